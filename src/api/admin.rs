@@ -0,0 +1,343 @@
+use std::net::SocketAddr;
+use std::path::{ Path, PathBuf };
+use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+use std::sync::{ Arc, OnceLock };
+use std::time::Instant;
+use axum::extract::{ ConnectInfo, FromRequestParts, State };
+use axum::http::{ header, request::Parts, StatusCode };
+use axum::response::{ IntoResponse, Response };
+use axum::routing::{ get, post };
+use axum::serve::ListenerExt;
+use axum::{ Json, Router };
+use chrono::Utc;
+use log::{ info, warn };
+use serde::Serialize;
+use crate::managment::{ MessageClient, PoolStats };
+use crate::network::auth::Principal;
+use crate::statement::ErrorCode;
+use crate::storage::{ CompactionStats, Executor, SnapshotStats };
+use crate::utils::config::{ AdminConfig, Config, StorageConfig };
+use crate::utils::metrics;
+use crate::utils::metrics::CONTENT_TYPE;
+use crate::utils::reload::{ ConfigReloader, ReloadReport };
+use super::auth::{ authenticate, parse_basic };
+use super::listener::ServerListener;
+use super::rest::ApiError;
+
+/// Directory under the storage path snapshots go to by default.
+pub const SNAPSHOT_DIRECTORY: &str = "snapshots";
+
+/// Snapshot directory configured under `[admin]`.
+pub fn snapshot_directory(admin: &AdminConfig, storage: &StorageConfig) -> PathBuf {
+    return match &admin.snapshot_directory {
+        Some(directory) if !directory.is_empty() => PathBuf::from(directory),
+        _ => Path::new(&storage.path).join(SNAPSHOT_DIRECTORY),
+    };
+}
+
+/// Startup progress of the node, updated by whoever owns each step and
+/// read by `/readyz`.
+#[derive(Debug)]
+pub struct NodeStatus {
+    started: Instant,
+    storage_open: AtomicBool,
+}
+
+impl Default for NodeStatus {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            storage_open: AtomicBool::new(false),
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl NodeStatus {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn uptime_secs(&self) -> u64 {
+        return self.started.elapsed().as_secs();
+    }
+
+    pub fn set_storage_open(&self, open: bool) {
+        self.storage_open.store(open, Ordering::SeqCst);
+    }
+
+    pub fn storage_open(&self) -> bool {
+        return self.storage_open.load(Ordering::SeqCst);
+    }
+}
+
+/// Everything the admin endpoints look at or act on. The management client
+/// and listener are optional so the surface can run on nodes without them;
+/// the client may also be set once it has connected.
+#[derive(Clone)]
+pub struct AdminState {
+    executor: Arc<Executor>,
+    status: Arc<NodeStatus>,
+    snapshot_directory: PathBuf,
+    client: Arc<OnceLock<MessageClient>>,
+    active_connections: Arc<AtomicUsize>,
+    draining: Arc<AtomicBool>,
+    config: Option<Arc<ConfigReloader>>,
+}
+
+#[allow(dead_code)]
+impl AdminState {
+    pub fn new(executor: Arc<Executor>, status: Arc<NodeStatus>, snapshot_directory: impl Into<PathBuf>) -> Self {
+        return Self {
+            executor,
+            status,
+            snapshot_directory: snapshot_directory.into(),
+            client: Arc::new(OnceLock::new()),
+            active_connections: Arc::new(AtomicUsize::new(0)),
+            draining: Arc::new(AtomicBool::new(false)),
+            config: None,
+        };
+    }
+
+    pub fn with_client(self, client: MessageClient) -> Self {
+        self.set_client(client);
+        return self;
+    }
+
+    /// Sets the management client of this state and every clone of it.
+    /// Only the first call has an effect.
+    pub fn set_client(&self, client: MessageClient) {
+        let _ = self.client.set(client);
+    }
+
+    /// Takes the connection counter and drain flag of a `NodeListener`.
+    pub fn with_listener(mut self, active_connections: Arc<AtomicUsize>, draining: Arc<AtomicBool>) -> Self {
+        self.active_connections = active_connections;
+        self.draining = draining;
+        return self;
+    }
+
+    pub fn with_config(mut self, config: Arc<ConfigReloader>) -> Self {
+        self.config = Some(config);
+        return self;
+    }
+
+    fn reloader(&self) -> Result<&Arc<ConfigReloader>, ApiError> {
+        return self.config.as_ref().ok_or_else(|| {
+            ApiError::new(StatusCode::NOT_FOUND, ErrorCode::UnsupportedMessage, "no configuration file is loaded".to_string())
+        });
+    }
+
+    fn management_authenticated(&self) -> bool {
+        return self.client.get().is_some_and(|client| client.is_authenticated());
+    }
+}
+
+/// A superuser, from HTTP basic credentials. Health probes are open; every
+/// `/admin` endpoint requires one.
+pub struct Operator(pub Principal);
+
+impl FromRequestParts<AdminState> for Operator {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AdminState) -> Result<Self, Self::Rejection> {
+        let (username, password) = parts.headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_basic)
+            .ok_or_else(|| {
+                ApiError::new(StatusCode::UNAUTHORIZED, ErrorCode::AuthenticationRequired, "basic credentials required".to_string())
+            })?;
+        let peer_address = parts.extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(address)| address.to_string())
+            .unwrap_or_default();
+
+        let principal = authenticate(&state.executor, username.clone(), password, peer_address).await.map_err(|e| {
+            ApiError::new(StatusCode::UNAUTHORIZED, ErrorCode::AuthenticationFailed, e.to_string())
+        })?;
+        if !state.executor.catalog().is_superuser(&username) {
+            return Err(
+                ApiError::new(StatusCode::FORBIDDEN, ErrorCode::PermissionDenied, format!("{} is not a superuser", principal))
+            );
+        }
+        return Ok(Operator(principal));
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthResponse {
+    pub status: String,
+    pub uptime_secs: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReadinessResponse {
+    pub ready: bool,
+    pub storage_open: bool,
+    pub management_authenticated: bool,
+    pub draining: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatusResponse {
+    pub uptime_secs: u64,
+    pub active_connections: usize,
+    pub draining: bool,
+    /// `None` when the node has no management client.
+    pub pool: Option<PoolStats>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SnapshotResponse {
+    pub path: String,
+    #[serde(flatten)]
+    pub stats: SnapshotStats,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConfigResponse {
+    pub path: String,
+    /// The config in effect, cluster tokens redacted.
+    pub config: Config,
+    /// Keys changed in the file that need a restart to take effect.
+    pub pending_restart: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DrainResponse {
+    pub draining: bool,
+    pub active_connections: usize,
+}
+
+/// Liveness: the process is up and serving HTTP.
+async fn healthz(State(state): State<AdminState>) -> Json<HealthResponse> {
+    return Json(HealthResponse { status: "ok".to_string(), uptime_secs: state.status.uptime_secs() });
+}
+
+/// Readiness: storage is open, the management connection is authenticated
+/// and the node is not draining.
+async fn readyz(State(state): State<AdminState>) -> Response {
+    let readiness = ReadinessResponse {
+        ready: false,
+        storage_open: state.status.storage_open(),
+        management_authenticated: state.management_authenticated(),
+        draining: state.draining.load(Ordering::SeqCst),
+    };
+    let ready = readiness.storage_open &&
+        readiness.management_authenticated &&
+        !readiness.draining;
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    return (status, Json(ReadinessResponse { ready, ..readiness })).into_response();
+}
+
+async fn admin_status(State(state): State<AdminState>, _operator: Operator) -> Json<StatusResponse> {
+    return Json(StatusResponse {
+        uptime_secs: state.status.uptime_secs(),
+        active_connections: state.active_connections.load(Ordering::SeqCst),
+        draining: state.draining.load(Ordering::SeqCst),
+        pool: state.client.get().map(|client| client.pool_stats()),
+    });
+}
+
+async fn compact(State(state): State<AdminState>, Operator(principal): Operator) -> Json<CompactionStats> {
+    let stats = state.executor.engine().compact();
+    info!("Compaction requested by {}: {:?}", principal, stats);
+    return Json(stats);
+}
+
+async fn snapshot(
+    State(state): State<AdminState>,
+    Operator(principal): Operator
+) -> Result<Json<SnapshotResponse>, ApiError> {
+    let path = state.snapshot_directory.join(format!("snapshot-{}.json", Utc::now().format("%Y%m%dT%H%M%S%.3fZ")));
+    let engine = state.executor.engine().clone();
+    let target = path.clone();
+    let stats = tokio::task
+        ::spawn_blocking(move || engine.snapshot(&target)).await
+        .unwrap_or_else(|e| Err(std::io::Error::other(e)))
+        .map_err(|e| {
+            warn!("Snapshot to {} failed: {}", path.display(), e);
+            ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, ErrorCode::Internal, e.to_string())
+        })?;
+    info!("Snapshot {} taken by {}", path.display(), principal);
+    return Ok(Json(SnapshotResponse { path: path.display().to_string(), stats }));
+}
+
+fn drain_response(state: &AdminState) -> Json<DrainResponse> {
+    return Json(DrainResponse {
+        draining: state.draining.load(Ordering::SeqCst),
+        active_connections: state.active_connections.load(Ordering::SeqCst),
+    });
+}
+
+/// Stops accepting connections and fails readiness so the node is taken
+/// out of rotation; open sessions are left to finish.
+async fn drain(State(state): State<AdminState>, Operator(principal): Operator) -> Json<DrainResponse> {
+    state.draining.store(true, Ordering::SeqCst);
+    info!("Node drained by {}", principal);
+    return drain_response(&state);
+}
+
+async fn resume(State(state): State<AdminState>, Operator(principal): Operator) -> Json<DrainResponse> {
+    state.draining.store(false, Ordering::SeqCst);
+    info!("Node resumed by {}", principal);
+    return drain_response(&state);
+}
+
+async fn active_config(
+    State(state): State<AdminState>,
+    _operator: Operator
+) -> Result<Json<ConfigResponse>, ApiError> {
+    let reloader = state.reloader()?;
+    return Ok(
+        Json(ConfigResponse {
+            path: reloader.path().display().to_string(),
+            config: reloader.active().redacted(),
+            pending_restart: reloader.pending_restart(),
+        })
+    );
+}
+
+async fn reload_config(
+    State(state): State<AdminState>,
+    Operator(principal): Operator
+) -> Result<Json<ReloadReport>, ApiError> {
+    let reloader = state.reloader()?;
+    let report = reloader.reload().map_err(|e| {
+        ApiError::new(StatusCode::BAD_REQUEST, ErrorCode::InvalidStatement, e.to_string())
+    })?;
+    info!("Configuration reloaded by {}: {:?}", principal, report);
+    return Ok(Json(report));
+}
+
+/// Prometheus scrape endpoint; open like the health probes.
+async fn prometheus_metrics(State(state): State<AdminState>) -> Response {
+    let body = metrics().render(Some(state.executor.engine()));
+    return ([(header::CONTENT_TYPE, CONTENT_TYPE)], body).into_response();
+}
+
+pub fn router(state: AdminState) -> Router {
+    return Router::new()
+        .route("/healthz", get(healthz))
+        .route("/metrics", get(prometheus_metrics))
+        .route("/readyz", get(readyz))
+        .route("/admin/status", get(admin_status))
+        .route("/admin/config", get(active_config))
+        .route("/admin/config/reload", post(reload_config))
+        .route("/admin/compact", post(compact))
+        .route("/admin/snapshot", post(snapshot))
+        .route("/admin/drain", post(drain))
+        .route("/admin/resume", post(resume))
+        .with_state(state);
+}
+
+/// Serves the health, metrics and admin endpoints on `listener`.
+pub async fn serve(listener: ServerListener, state: AdminState) -> std::io::Result<()> {
+    info!("Admin endpoints listening on {}", listener.local_addr());
+    let app = router(state).into_make_service_with_connect_info::<SocketAddr>();
+    let listener = listener.tap_io(|stream| {
+        let _ = stream.set_nodelay(true);
+    });
+    return axum::serve(listener, app).await;
+}
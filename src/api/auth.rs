@@ -0,0 +1,46 @@
+use std::sync::Arc;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use crate::network::auth::Principal;
+use crate::network::listener::next_connection_id;
+use crate::storage::catalog::CatalogError;
+use crate::storage::Executor;
+use crate::utils::{ metrics, AuditEvent };
+
+/// Username and password of a `Basic` authorization value.
+pub fn parse_basic(value: &str) -> Option<(String, String)> {
+    let encoded = value.strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    return Some((username.to_string(), password.to_string()));
+}
+
+/// Checks a user's password against the system catalog. Failures go to the
+/// audit log like failed logins on the binary protocol.
+pub async fn authenticate(
+    executor: &Arc<Executor>,
+    username: String,
+    password: String,
+    peer_address: String
+) -> Result<Principal, CatalogError> {
+    // Password hashing is deliberately slow; keep it off the runtime threads.
+    let catalog_executor = executor.clone();
+    let name = username.clone();
+    let result = tokio::task
+        ::spawn_blocking(move || catalog_executor.catalog().authenticate(&name, &password)).await
+        .unwrap_or(Err(CatalogError::InvalidCredentials));
+
+    if let Err(e) = result {
+        metrics().auth_failures.with_label_values(&["http"]).inc();
+        if let Some(audit) = executor.audit_log() {
+            audit.record(AuditEvent::AuthenticationFailed {
+                connection_id: next_connection_id(),
+                peer_address,
+                identity: username,
+                reason: e.to_string(),
+            });
+        }
+        return Err(e);
+    }
+    return Ok(Principal::User(username));
+}
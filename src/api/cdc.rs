@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use futures::{ SinkExt, StreamExt };
+use log::{ info, warn };
+use tokio::sync::broadcast::error::RecvError;
+use tokio_tungstenite::tungstenite::handshake::server::{ Request, Response };
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use crate::storage::catalog::SYSTEM_DATABASE;
+use crate::storage::{ ChangeEvent, ChangeFeed, ExecutionContext, Executor, Privilege };
+use crate::storage::engine::DEFAULT_DATABASE;
+use super::auth::{ authenticate, parse_basic };
+use super::listener::{ ServerListener, ServerStream };
+
+/// What a subscriber asked for in the query string of the upgrade request:
+/// `?database=shop&table=orders&from=<position>`. Without `database` or
+/// `table` every database or table the user may read is included.
+#[derive(Debug, Default)]
+struct Subscription {
+    database: Option<String>,
+    table: Option<String>,
+    from: Option<String>,
+    authorization: Option<String>,
+}
+
+impl Subscription {
+    fn from_request(request: &Request) -> Self {
+        let query: HashMap<String, String> = request
+            .uri()
+            .query()
+            .map(|query| url::form_urlencoded::parse(query.as_bytes()).into_owned().collect())
+            .unwrap_or_default();
+        let authorization = request
+            .headers()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string());
+
+        return Self {
+            database: query.get("database").cloned(),
+            table: query.get("table").cloned(),
+            from: query.get("from").cloned(),
+            authorization,
+        };
+    }
+
+    fn matches(&self, event: &ChangeEvent) -> bool {
+        return self.database.as_ref().is_none_or(|database| database == &event.database) &&
+            self.table.as_ref().is_none_or(|table| table == &event.table);
+    }
+}
+
+type Socket = WebSocketStream<ServerStream>;
+
+async fn close(socket: &mut Socket, code: CloseCode, reason: String) {
+    let frame = CloseFrame { code, reason: reason.into() };
+    let _ = socket.close(Some(frame)).await;
+}
+
+/// Pushes row changes to WebSocket subscribers as JSON `ChangeEvent`s.
+struct Subscriber {
+    executor: Arc<Executor>,
+    feed: Arc<ChangeFeed>,
+    subscription: Subscription,
+    ctx: ExecutionContext,
+    /// Sequence of the last event looked at, sent or filtered out.
+    last: u64,
+}
+
+impl Subscriber {
+    fn visible(&self, event: &ChangeEvent) -> bool {
+        // The catalog holds password hashes; it is never part of the feed.
+        return event.database != SYSTEM_DATABASE &&
+            self.subscription.matches(event) &&
+            self.executor.authorize(&self.ctx, Privilege::Select, &event.database, &event.table).is_ok();
+    }
+
+    async fn send(&mut self, socket: &mut Socket, events: &[Arc<ChangeEvent>]) -> Result<(), String> {
+        for event in events {
+            if event.sequence() <= self.last {
+                continue;
+            }
+            self.last = event.sequence();
+            if !self.visible(event) {
+                continue;
+            }
+            let text = serde_json::to_string(event.as_ref()).map_err(|e| e.to_string())?;
+            socket.send(Message::text(text)).await.map_err(|e| e.to_string())?;
+        }
+        return Ok(());
+    }
+
+    async fn run(mut self, socket: &mut Socket) -> Result<(), String> {
+        // Subscribe before reading the backlog so nothing falls in between;
+        // events seen twice are skipped by sequence.
+        let mut receiver = self.feed.subscribe();
+        match self.subscription.from.clone() {
+            Some(from) => {
+                let backlog = self.feed.since(&from).map_err(|e| e.to_string())?;
+                self.last = self.feed.sequence_of(&from).map_err(|e| e.to_string())?;
+                self.send(socket, &backlog).await?;
+            }
+            None => {
+                self.last = self.feed.sequence();
+            }
+        }
+
+        loop {
+            tokio::select! {
+                received = receiver.recv() => match received {
+                    Ok(event) => self.send(socket, &[event]).await?,
+                    Err(RecvError::Lagged(_)) => {
+                        let backlog = self.feed
+                            .since_sequence(self.last)
+                            .ok_or_else(|| "subscriber fell behind the retained changes".to_string())?;
+                        self.send(socket, &backlog).await?;
+                    }
+                    Err(RecvError::Closed) => {
+                        return Ok(());
+                    }
+                },
+                incoming = socket.next() => match incoming {
+                    None | Some(Err(_)) | Some(Ok(Message::Close(_))) => {
+                        return Ok(());
+                    }
+                    // Pings are answered by the next write.
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+    }
+}
+
+async fn handle_connection(
+    stream: ServerStream,
+    peer_address: SocketAddr,
+    executor: Arc<Executor>,
+    feed: Arc<ChangeFeed>
+) {
+    let mut subscription = Subscription::default();
+    // The callback signature, error type included, is fixed by tungstenite.
+    #[allow(clippy::result_large_err)]
+    let callback = |request: &Request, response: Response| {
+        subscription = Subscription::from_request(request);
+        Ok(response)
+    };
+    let mut socket = match tokio_tungstenite::accept_hdr_async(stream, callback).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            warn!("CDC handshake with {} failed: {}", peer_address, e);
+            return;
+        }
+    };
+
+    let credentials = subscription.authorization.as_deref().and_then(parse_basic);
+    let (username, password) = match credentials {
+        Some(credentials) => credentials,
+        None => {
+            close(&mut socket, CloseCode::Policy, "basic credentials required".to_string()).await;
+            return;
+        }
+    };
+    let principal = match authenticate(&executor, username, password, peer_address.to_string()).await {
+        Ok(principal) => principal,
+        Err(e) => {
+            close(&mut socket, CloseCode::Policy, e.to_string()).await;
+            return;
+        }
+    };
+
+    info!("CDC subscriber {} connected from {}", principal, peer_address);
+    let subscriber = Subscriber {
+        executor,
+        feed,
+        subscription,
+        ctx: ExecutionContext { principal, database: DEFAULT_DATABASE.to_string() },
+        last: 0,
+    };
+    match subscriber.run(&mut socket).await {
+        Ok(()) => close(&mut socket, CloseCode::Normal, String::new()).await,
+        Err(reason) => close(&mut socket, CloseCode::Error, reason).await,
+    }
+}
+
+/// Accepts CDC subscribers on `listener`. The executor's storage engine
+/// must have been built with a change feed.
+pub async fn serve(mut listener: ServerListener, executor: Arc<Executor>) -> io::Result<()> {
+    let feed = executor
+        .engine()
+        .change_feed()
+        .cloned()
+        .ok_or_else(|| io::Error::other("the storage engine has no change feed"))?;
+    info!("CDC feed listening on {}", listener.local_addr());
+
+    loop {
+        let (stream, peer_address) = listener.accept().await;
+        tokio::spawn(handle_connection(stream, peer_address, executor.clone(), feed.clone()));
+    }
+}
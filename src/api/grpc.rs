@@ -0,0 +1,484 @@
+use std::collections::{ BTreeMap, HashMap };
+use std::pin::Pin;
+use std::sync::Arc;
+use futures::{ stream, Stream };
+use log::info;
+use prost_types::value::Kind;
+use prost_types::{ ListValue, Struct };
+use serde_json::{ Map, Number, Value };
+use tonic::{ Request, Response, Status };
+use validator::ValidationErrors;
+use crate::network::listener::next_connection_id;
+use crate::protocol::MessageType;
+use crate::statement::{ self, ColumnDefinition, EmptyStatement, Statement };
+use crate::storage::catalog::CatalogError;
+use crate::storage::engine::DEFAULT_DATABASE;
+use crate::storage::executor::ROWS_PER_CHUNK;
+use crate::storage::{ ExecutionContext, ExecutionError, ExecutionResult, Executor, StorageError };
+use crate::transport::{ Message, Row };
+use super::auth::{ authenticate, parse_basic };
+use super::listener::ServerListener;
+
+pub mod proto {
+    tonic::include_proto!("zenith.v1");
+}
+
+use proto::zenith_store_server::{ ZenithStore, ZenithStoreServer };
+use proto::{ ExecuteResponse, RowsResponse };
+
+/// Metadata entry naming the database a call runs against.
+pub const DATABASE_METADATA: &str = "zenith-database";
+
+fn json_value(value: prost_types::Value) -> Value {
+    match value.kind {
+        None | Some(Kind::NullValue(_)) => Value::Null,
+        Some(Kind::BoolValue(b)) => Value::Bool(b),
+        // Protobuf only has doubles; whole numbers go back to integers.
+        Some(Kind::NumberValue(n)) if n.fract() == 0.0 && n.abs() < (i64::MAX as f64) => Value::from(n as i64),
+        Some(Kind::NumberValue(n)) => Number::from_f64(n).map(Value::Number).unwrap_or(Value::Null),
+        Some(Kind::StringValue(s)) => Value::String(s),
+        Some(Kind::ListValue(list)) => Value::Array(list.values.into_iter().map(json_value).collect()),
+        Some(Kind::StructValue(fields)) => Value::Object(json_object(fields).into_iter().collect::<Map<_, _>>()),
+    }
+}
+
+fn json_object(fields: Struct) -> HashMap<String, Value> {
+    return fields.fields
+        .into_iter()
+        .map(|(name, value)| (name, json_value(value)))
+        .collect();
+}
+
+fn proto_value(value: Value) -> prost_types::Value {
+    let kind = match value {
+        Value::Null => Kind::NullValue(0),
+        Value::Bool(b) => Kind::BoolValue(b),
+        Value::Number(n) => Kind::NumberValue(n.as_f64().unwrap_or_default()),
+        Value::String(s) => Kind::StringValue(s),
+        Value::Array(values) => Kind::ListValue(ListValue { values: values.into_iter().map(proto_value).collect() }),
+        Value::Object(fields) => Kind::StructValue(proto_struct(fields.into_iter())),
+    };
+    return prost_types::Value { kind: Some(kind) };
+}
+
+fn proto_struct(fields: impl Iterator<Item = (String, Value)>) -> Struct {
+    let fields: BTreeMap<String, prost_types::Value> = fields
+        .map(|(name, value)| (name, proto_value(value)))
+        .collect();
+    return Struct { fields };
+}
+
+fn proto_rows(rows: Vec<Row>) -> Vec<Struct> {
+    return rows
+        .into_iter()
+        .map(|row| proto_struct(row.into_iter()))
+        .collect();
+}
+
+fn execution_status(e: ExecutionError) -> Status {
+    let message = e.to_string();
+    match e {
+        ExecutionError::PermissionDenied(_) => Status::permission_denied(message),
+        ExecutionError::InvalidStatement(_) => Status::invalid_argument(message),
+        ExecutionError::Unsupported(_) => Status::unimplemented(message),
+        ExecutionError::Storage(error) =>
+            match error {
+                StorageError::DatabaseNotFound(_) |
+                StorageError::TableNotFound(_) |
+                StorageError::IndexNotFound(_) => Status::not_found(message),
+                StorageError::DatabaseExists(_) |
+                StorageError::TableExists(_) |
+                StorageError::IndexExists(_) => Status::already_exists(message),
+                StorageError::ReadOnly(_) => Status::permission_denied(message),
+                StorageError::Locked(_) => Status::aborted(message),
+                StorageError::Spill(_) => Status::internal(message),
+                StorageError::Full(_) => Status::resource_exhausted(message),
+                StorageError::Cancelled => Status::cancelled(message),
+                StorageError::UnknownColumn { .. } |
+                StorageError::AmbiguousColumn(_) |
+                StorageError::NotNumeric { .. } =>
+                    Status::invalid_argument(message),
+            }
+        ExecutionError::Catalog(error) =>
+            match error {
+                CatalogError::UserExists(_) | CatalogError::RoleExists(_) => Status::already_exists(message),
+                CatalogError::UserNotFound(_) |
+                CatalogError::RoleNotFound(_) |
+                CatalogError::GranteeNotFound(_) => Status::not_found(message),
+                _ => Status::invalid_argument(message),
+            }
+    }
+}
+
+/// Every call runs on a connection of its own, which a transaction could
+/// not outlive.
+fn no_transactions() -> Status {
+    return Status::unimplemented("transactions are only available on the binary protocol");
+}
+
+fn aggregate_function(function: proto::aggregate::Function) -> statement::AggregateFunction {
+    match function {
+        proto::aggregate::Function::Count => statement::AggregateFunction::Count,
+        proto::aggregate::Function::Sum => statement::AggregateFunction::Sum,
+        proto::aggregate::Function::Avg => statement::AggregateFunction::Avg,
+        proto::aggregate::Function::Min => statement::AggregateFunction::Min,
+        proto::aggregate::Function::Max => statement::AggregateFunction::Max,
+        proto::aggregate::Function::CountDistinct => statement::AggregateFunction::CountDistinct,
+    }
+}
+
+fn affected(result: ExecutionResult) -> Response<ExecuteResponse> {
+    let affected_rows = match result {
+        ExecutionResult::Affected(affected) => affected,
+        _ => 0,
+    };
+    return Response::new(ExecuteResponse { affected_rows });
+}
+
+fn rows(result: ExecutionResult) -> Response<RowsResponse> {
+    let (rows, continuation) = match result {
+        ExecutionResult::Rows(rows) => (proto_rows(rows), None),
+        ExecutionResult::Page { rows, continuation } => (proto_rows(rows), continuation),
+        _ => (Vec::new(), None),
+    };
+    return Response::new(RowsResponse { rows, continuation });
+}
+
+/// Runs the `ZenithStore` service against the executor, with the same
+/// catalog users and privileges as the binary protocol.
+pub struct GrpcService {
+    executor: Arc<Executor>,
+}
+
+#[allow(dead_code)]
+impl GrpcService {
+    pub fn new(executor: Arc<Executor>) -> Self {
+        return Self { executor };
+    }
+
+    async fn context<T>(&self, request: &Request<T>) -> Result<ExecutionContext, Status> {
+        let metadata = request.metadata();
+        let (username, password) = metadata
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_basic)
+            .ok_or_else(|| Status::unauthenticated("basic credentials required"))?;
+        let database = metadata
+            .get(DATABASE_METADATA)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or(DEFAULT_DATABASE)
+            .to_string();
+        let peer_address = request
+            .remote_addr()
+            .map(|address| address.to_string())
+            .unwrap_or_default();
+
+        let principal = authenticate(&self.executor, username, password, peer_address).await.map_err(|e|
+            Status::unauthenticated(e.to_string())
+        )?;
+        return Ok(ExecutionContext { principal, database });
+    }
+
+    async fn execute<T>(
+        &self,
+        request: &Request<T>,
+        message_type: MessageType,
+        stmt: Result<impl Statement, ValidationErrors>
+    ) -> Result<ExecutionResult, Status> {
+        let ctx = self.context(request).await?;
+        let stmt = stmt.map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let message = Message::new(message_type, &stmt);
+        return self.executor.run(next_connection_id(), &ctx, &message).map_err(execution_status);
+    }
+}
+
+fn columns(columns: Vec<proto::ColumnDefinition>) -> Vec<ColumnDefinition> {
+    return columns
+        .into_iter()
+        .map(|column| ColumnDefinition {
+            name: column.name,
+            col_type: column.r#type,
+            length: column.length,
+            primary_key: column.primary_key,
+            index: column.index,
+            default_value: column.default_value,
+        })
+        .collect();
+}
+
+type SelectStream = Pin<Box<dyn Stream<Item = Result<RowsResponse, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl ZenithStore for GrpcService {
+    type SelectStream = SelectStream;
+
+    async fn create_database(
+        &self,
+        request: Request<proto::CreateDatabaseStatement>
+    ) -> Result<Response<ExecuteResponse>, Status> {
+        let stmt = statement::CreateDatabaseStatement::new(request.get_ref().database_name.clone());
+        return Ok(affected(self.execute(&request, MessageType::CreateDatabase, stmt).await?));
+    }
+
+    async fn drop_database(
+        &self,
+        request: Request<proto::DropDatabaseStatement>
+    ) -> Result<Response<ExecuteResponse>, Status> {
+        let stmt = statement::DropDatabaseStatement::new(request.get_ref().database_name.clone());
+        return Ok(affected(self.execute(&request, MessageType::DropDatabase, stmt).await?));
+    }
+
+    async fn show_databases(
+        &self,
+        request: Request<proto::ShowDatabasesStatement>
+    ) -> Result<Response<RowsResponse>, Status> {
+        let stmt = Ok(EmptyStatement::new(MessageType::ShowDatabases));
+        return Ok(rows(self.execute(&request, MessageType::ShowDatabases, stmt).await?));
+    }
+
+    async fn create_table(
+        &self,
+        request: Request<proto::CreateTableStatement>
+    ) -> Result<Response<ExecuteResponse>, Status> {
+        let body = request.get_ref().clone();
+        let stmt = statement::CreateTableStatement::new(body.table_name, columns(body.columns), body.storage);
+        return Ok(affected(self.execute(&request, MessageType::CreateTable, stmt).await?));
+    }
+
+    async fn drop_table(&self, request: Request<proto::DropTableStatement>) -> Result<Response<ExecuteResponse>, Status> {
+        let stmt = statement::DropTableStatement::new(request.get_ref().table_name.clone());
+        return Ok(affected(self.execute(&request, MessageType::DropTable, stmt).await?));
+    }
+
+    async fn alter_table(
+        &self,
+        request: Request<proto::AlterTableStatement>
+    ) -> Result<Response<ExecuteResponse>, Status> {
+        let body = request.get_ref().clone();
+        let stmt = statement::AlterTableStatement::new(body.table_name, body.changes);
+        return Ok(affected(self.execute(&request, MessageType::AlterTable, stmt).await?));
+    }
+
+    async fn rename_table(
+        &self,
+        request: Request<proto::RenameTableStatement>
+    ) -> Result<Response<ExecuteResponse>, Status> {
+        let body = request.get_ref().clone();
+        let stmt = statement::RenameTableStatement::new(body.old_table_name, body.new_table_name);
+        return Ok(affected(self.execute(&request, MessageType::RenameTable, stmt).await?));
+    }
+
+    async fn truncate_table(
+        &self,
+        request: Request<proto::TruncateTableStatement>
+    ) -> Result<Response<ExecuteResponse>, Status> {
+        let stmt = statement::TruncateTableStatement::new(request.get_ref().table_name.clone());
+        return Ok(affected(self.execute(&request, MessageType::TruncateTable, stmt).await?));
+    }
+
+    async fn show_tables(&self, request: Request<proto::ShowTablesStatement>) -> Result<Response<RowsResponse>, Status> {
+        let stmt = Ok(EmptyStatement::new(MessageType::ShowTables));
+        return Ok(rows(self.execute(&request, MessageType::ShowTables, stmt).await?));
+    }
+
+    async fn describe_table(
+        &self,
+        request: Request<proto::DescribeTableStatement>
+    ) -> Result<Response<RowsResponse>, Status> {
+        let stmt = statement::DescribeTableStatement::new(request.get_ref().table_name.clone());
+        return Ok(rows(self.execute(&request, MessageType::DescribeTable, stmt).await?));
+    }
+
+    async fn create_index(
+        &self,
+        request: Request<proto::CreateIndexStatement>
+    ) -> Result<Response<ExecuteResponse>, Status> {
+        let body = request.get_ref().clone();
+        let stmt = statement::CreateIndexStatement::new(body.index_name, body.table_name, body.columns);
+        return Ok(affected(self.execute(&request, MessageType::CreateIndex, stmt).await?));
+    }
+
+    async fn drop_index(&self, request: Request<proto::DropIndexStatement>) -> Result<Response<ExecuteResponse>, Status> {
+        let body = request.get_ref().clone();
+        let stmt = statement::DropIndexStatement::new(body.index_name, body.table_name);
+        return Ok(affected(self.execute(&request, MessageType::DropIndex, stmt).await?));
+    }
+
+    async fn show_indexes(
+        &self,
+        request: Request<proto::ShowIndexesStatement>
+    ) -> Result<Response<RowsResponse>, Status> {
+        let stmt = statement::ShowIndexesStatement::new(request.get_ref().table_name.clone());
+        return Ok(rows(self.execute(&request, MessageType::ShowIndexes, stmt).await?));
+    }
+
+    async fn insert(&self, request: Request<proto::InsertStatement>) -> Result<Response<ExecuteResponse>, Status> {
+        let body = request.get_ref().clone();
+        let stmt = statement::InsertStatement::new(body.table_name, json_object(body.values.unwrap_or_default()));
+        return Ok(affected(self.execute(&request, MessageType::Insert, stmt).await?));
+    }
+
+    async fn select(&self, request: Request<proto::SelectStatement>) -> Result<Response<Self::SelectStream>, Status> {
+        let body = request.get_ref().clone();
+        let order_by = body.order_by
+            .into_iter()
+            .map(|key| statement::OrderBy {
+                column: key.column,
+                descending: key.descending,
+                nulls_first: key.nulls_first,
+            })
+            .collect();
+        let aggregates = body.aggregates
+            .into_iter()
+            .map(|aggregate| statement::Aggregate {
+                function: aggregate_function(aggregate.function()),
+                column: aggregate.column,
+                alias: aggregate.alias,
+            })
+            .collect();
+        let joins = body.joins
+            .into_iter()
+            .map(|join| statement::Join {
+                kind: match join.kind() {
+                    proto::join::Kind::Inner => statement::JoinKind::Inner,
+                    proto::join::Kind::Left => statement::JoinKind::Left,
+                },
+                table_name: join.table_name,
+                on: join.on,
+            })
+            .collect();
+        let stmt = statement::SelectStatement
+            ::new(body.table_name, body.columns, body.r#where)
+            .and_then(|stmt| stmt.with_joins(joins))
+            .and_then(|stmt| stmt.with_order_by(order_by))
+            .and_then(|stmt| stmt.with_aggregates(aggregates))
+            .and_then(|stmt| stmt.with_group_by(body.group_by))
+            .map(|mut stmt| {
+                stmt.limit = body.limit;
+                stmt.offset = body.offset;
+                stmt.after = body.after;
+                stmt.having = body.having;
+                stmt
+            });
+        let result = self.execute(&request, MessageType::Select, stmt).await?;
+
+        let (rows, continuation) = match result {
+            ExecutionResult::Rows(rows) => (rows, None),
+            ExecutionResult::Page { rows, continuation } => (rows, continuation),
+            _ => (Vec::new(), None),
+        };
+        let mut batches: Vec<RowsResponse> = rows
+            .chunks(ROWS_PER_CHUNK)
+            .map(|batch| RowsResponse { rows: proto_rows(batch.to_vec()), continuation: None })
+            .collect();
+        // Tokens are only issued for full, so non-empty, pages.
+        if let Some(last) = batches.last_mut() {
+            last.continuation = continuation;
+        }
+        return Ok(Response::new(Box::pin(stream::iter(batches.into_iter().map(Ok)))));
+    }
+
+    async fn update(&self, request: Request<proto::UpdateStatement>) -> Result<Response<ExecuteResponse>, Status> {
+        let body = request.get_ref().clone();
+        let updates = json_object(body.updates.unwrap_or_default());
+        let stmt = statement::UpdateStatement::new(body.table_name, updates, body.r#where);
+        return Ok(affected(self.execute(&request, MessageType::Update, stmt).await?));
+    }
+
+    async fn delete(&self, request: Request<proto::DeleteStatement>) -> Result<Response<ExecuteResponse>, Status> {
+        let body = request.get_ref().clone();
+        let stmt = statement::DeleteStatement::new(body.table_name, body.r#where);
+        return Ok(affected(self.execute(&request, MessageType::Delete, stmt).await?));
+    }
+
+    async fn bulk_insert(
+        &self,
+        request: Request<proto::BulkInsertStatement>
+    ) -> Result<Response<ExecuteResponse>, Status> {
+        let body = request.get_ref().clone();
+        let rows = body.rows.into_iter().map(json_object).collect();
+        let stmt = statement::BulkInsertStatement::new(body.table_name, rows);
+        return Ok(affected(self.execute(&request, MessageType::BulkInsert, stmt).await?));
+    }
+
+    async fn upsert(&self, request: Request<proto::UpsertStatement>) -> Result<Response<ExecuteResponse>, Status> {
+        let body = request.get_ref().clone();
+        let values = json_object(body.values.unwrap_or_default());
+        let stmt = statement::UpsertStatement::new(body.table_name, values, body.unique_key);
+        return Ok(affected(self.execute(&request, MessageType::Upsert, stmt).await?));
+    }
+
+    async fn begin_transaction(
+        &self,
+        _request: Request<proto::BeginTransactionStatement>
+    ) -> Result<Response<ExecuteResponse>, Status> {
+        return Err(no_transactions());
+    }
+
+    async fn commit(&self, _request: Request<proto::CommitStatement>) -> Result<Response<ExecuteResponse>, Status> {
+        return Err(no_transactions());
+    }
+
+    async fn rollback(&self, _request: Request<proto::RollbackStatement>) -> Result<Response<ExecuteResponse>, Status> {
+        return Err(no_transactions());
+    }
+
+    async fn savepoint(&self, _request: Request<proto::SavepointStatement>) -> Result<Response<ExecuteResponse>, Status> {
+        return Err(no_transactions());
+    }
+
+    async fn release_savepoint(
+        &self,
+        _request: Request<proto::ReleaseSavepointStatement>
+    ) -> Result<Response<ExecuteResponse>, Status> {
+        return Err(no_transactions());
+    }
+
+    async fn create_user(
+        &self,
+        request: Request<proto::CreateUserStatement>
+    ) -> Result<Response<ExecuteResponse>, Status> {
+        let body = request.get_ref().clone();
+        let stmt = statement::CreateUserStatement::new(body.username, body.password, body.superuser, body.roles);
+        return Ok(affected(self.execute(&request, MessageType::CreateUser, stmt).await?));
+    }
+
+    async fn drop_user(&self, request: Request<proto::DropUserStatement>) -> Result<Response<ExecuteResponse>, Status> {
+        let stmt = statement::DropUserStatement::new(request.get_ref().username.clone());
+        return Ok(affected(self.execute(&request, MessageType::DropUser, stmt).await?));
+    }
+
+    async fn create_role(
+        &self,
+        request: Request<proto::CreateRoleStatement>
+    ) -> Result<Response<ExecuteResponse>, Status> {
+        let stmt = statement::CreateRoleStatement::new(request.get_ref().role_name.clone());
+        return Ok(affected(self.execute(&request, MessageType::CreateRole, stmt).await?));
+    }
+
+    async fn grant(&self, request: Request<proto::GrantStatement>) -> Result<Response<ExecuteResponse>, Status> {
+        let body = request.get_ref().clone();
+        let stmt = statement::GrantStatement::new(body.privileges, body.database, body.table, body.roles, body.grantee);
+        return Ok(affected(self.execute(&request, MessageType::Grant, stmt).await?));
+    }
+
+    async fn revoke(&self, request: Request<proto::RevokeStatement>) -> Result<Response<ExecuteResponse>, Status> {
+        let body = request.get_ref().clone();
+        let stmt = statement::RevokeStatement::new(body.privileges, body.database, body.table, body.roles, body.grantee);
+        return Ok(affected(self.execute(&request, MessageType::Revoke, stmt).await?));
+    }
+}
+
+/// Serves the gRPC API on `listener` until the task is dropped.
+pub async fn serve(listener: ServerListener, executor: Arc<Executor>) -> Result<(), tonic::transport::Error> {
+    info!("gRPC API listening on {}", listener.local_addr());
+    let incoming = stream::unfold(listener, |mut listener| async move {
+        let (stream, _) = listener.accept().await;
+        Some((Ok::<_, std::io::Error>(stream), listener))
+    });
+
+    return tonic::transport::Server
+        ::builder()
+        .add_service(ZenithStoreServer::new(GrpcService::new(executor)))
+        .serve_with_incoming(incoming).await;
+}
@@ -0,0 +1,165 @@
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{ Context, Poll };
+use std::time::Duration;
+use log::warn;
+use tokio::io::{ AsyncRead, AsyncWrite, ReadBuf };
+use tokio::net::{ TcpListener, TcpStream };
+use tokio::sync::mpsc;
+use tokio_rustls::server::TlsStream;
+use tonic::transport::server::{ Connected, TcpConnectInfo };
+use crate::network::ServerTls;
+
+/// How long a client may take to complete the TLS handshake.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A connection accepted by a `ServerListener`.
+pub enum ServerStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+#[allow(dead_code)]
+impl ServerStream {
+    pub fn tcp(&self) -> &TcpStream {
+        return match self {
+            ServerStream::Plain(stream) => stream,
+            ServerStream::Tls(stream) => stream.get_ref().0,
+        };
+    }
+
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        return self.tcp().set_nodelay(nodelay);
+    }
+}
+
+impl AsyncRead for ServerStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        return match self.get_mut() {
+            ServerStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            ServerStream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        };
+    }
+}
+
+impl AsyncWrite for ServerStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        return match self.get_mut() {
+            ServerStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            ServerStream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        };
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        return match self.get_mut() {
+            ServerStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            ServerStream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        };
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        return match self.get_mut() {
+            ServerStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            ServerStream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        };
+    }
+}
+
+impl Connected for ServerStream {
+    type ConnectInfo = TcpConnectInfo;
+
+    fn connect_info(&self) -> Self::ConnectInfo {
+        return self.tcp().connect_info();
+    }
+}
+
+/// Accepts connections for the REST, gRPC, CDC and admin servers, over TLS
+/// when the node has it enabled. Handshakes run in their own tasks, so a
+/// slow client does not hold up the others.
+pub struct ServerListener {
+    local_addr: SocketAddr,
+    accepted: mpsc::Receiver<(ServerStream, SocketAddr)>,
+}
+
+#[allow(dead_code)]
+impl ServerListener {
+    pub fn new(listener: TcpListener, tls: Option<ServerTls>) -> io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let (sender, accepted) = mpsc::channel(64);
+        tokio::spawn(accept_connections(listener, tls, sender));
+        return Ok(Self { local_addr, accepted });
+    }
+
+    pub fn plain(listener: TcpListener) -> io::Result<Self> {
+        return Self::new(listener, None);
+    }
+
+    /// The next connection, with its TLS handshake done.
+    pub async fn accept(&mut self) -> (ServerStream, SocketAddr) {
+        return match self.accepted.recv().await {
+            Some(accepted) => accepted,
+            // The accept loop only stops once the listener is dropped.
+            None => std::future::pending().await,
+        };
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        return self.local_addr;
+    }
+}
+
+impl axum::serve::Listener for ServerListener {
+    type Io = ServerStream;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        return ServerListener::accept(self).await;
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        return Ok(self.local_addr);
+    }
+}
+
+async fn accept_connections(
+    listener: TcpListener,
+    tls: Option<ServerTls>,
+    sender: mpsc::Sender<(ServerStream, SocketAddr)>
+) {
+    loop {
+        let (tcp, peer_address) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    // Usually out of file descriptors; give some a chance to close.
+                    warn!("Cannot accept a connection on {:?}: {}", listener.local_addr(), e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            },
+            _ = sender.closed() => {
+                return;
+            }
+        };
+
+        let Some(tls) = &tls else {
+            if sender.send((ServerStream::Plain(tcp), peer_address)).await.is_err() {
+                return;
+            }
+            continue;
+        };
+
+        let acceptor = tls.acceptor();
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(tcp)).await {
+                Ok(Ok(stream)) => {
+                    let _ = sender.send((ServerStream::Tls(Box::new(stream)), peer_address)).await;
+                }
+                Ok(Err(e)) => warn!("TLS handshake with {} failed: {}", peer_address, e),
+                Err(_) => warn!("TLS handshake with {} timed out", peer_address),
+            }
+        });
+    }
+}
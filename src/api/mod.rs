@@ -1 +1,8 @@
-pub mod grpc;
\ No newline at end of file
+pub mod admin;
+pub mod auth;
+pub mod cdc;
+pub mod grpc;
+pub mod listener;
+pub mod rest;
+pub use listener::{ ServerListener, ServerStream };
+pub use rest::{ router, serve, ApiDoc };
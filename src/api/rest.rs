@@ -0,0 +1,534 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use axum::extract::{ ConnectInfo, FromRequestParts, Path, Query, State };
+use axum::http::{ header, request::Parts, StatusCode };
+use axum::response::{ IntoResponse, Response };
+use axum::routing::{ delete, get, post };
+use axum::serve::ListenerExt;
+use axum::{ Json, Router };
+use log::info;
+use serde::{ Deserialize, Serialize };
+use serde_json::Value;
+use utoipa::openapi::security::{ Http, HttpAuthScheme, SecurityScheme };
+use utoipa::{ IntoParams, Modify, OpenApi, ToSchema };
+use validator::ValidationErrors;
+use crate::network::auth::Principal;
+use crate::network::listener::next_connection_id;
+use crate::protocol::MessageType;
+use crate::statement::*;
+use crate::storage::catalog::CatalogError;
+use crate::storage::engine::DEFAULT_DATABASE;
+use crate::storage::{ ExecutionContext, ExecutionError, ExecutionResult, Executor, StorageError };
+use crate::transport::{ Message, Row };
+use super::auth::{ authenticate, parse_basic };
+use super::listener::ServerListener;
+
+/// JSON body of every error response; the same code and message an
+/// `ErrorStatement` carries on the binary protocol.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ApiErrorBody {
+    pub code: u32,
+    pub message: String,
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub body: ApiErrorBody,
+}
+
+#[allow(dead_code)]
+impl ApiError {
+    pub fn new(status: StatusCode, code: ErrorCode, message: String) -> Self {
+        let error = ErrorStatement::new(code, message);
+        return Self { status, body: ApiErrorBody { code: error.code, message: error.message } };
+    }
+
+    fn unauthorized(message: &str) -> Self {
+        return Self::new(StatusCode::UNAUTHORIZED, ErrorCode::AuthenticationRequired, message.to_string());
+    }
+
+    fn invalid(e: ValidationErrors) -> Self {
+        return Self::new(StatusCode::BAD_REQUEST, ErrorCode::InvalidStatement, e.to_string());
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut response = (self.status, Json(self.body)).into_response();
+        if self.status == StatusCode::UNAUTHORIZED {
+            response.headers_mut().insert(header::WWW_AUTHENTICATE, "Basic realm=\"zenith\"".parse().unwrap());
+        }
+        return response;
+    }
+}
+
+impl From<ExecutionError> for ApiError {
+    fn from(e: ExecutionError) -> Self {
+        let status = match &e {
+            ExecutionError::PermissionDenied(_) => StatusCode::FORBIDDEN,
+            ExecutionError::InvalidStatement(_) => StatusCode::BAD_REQUEST,
+            ExecutionError::Unsupported(_) => StatusCode::NOT_IMPLEMENTED,
+            ExecutionError::Storage(error) =>
+                match error {
+                    StorageError::DatabaseNotFound(_) |
+                    StorageError::TableNotFound(_) |
+                    StorageError::IndexNotFound(_) => StatusCode::NOT_FOUND,
+                    StorageError::DatabaseExists(_) |
+                    StorageError::TableExists(_) |
+                    StorageError::IndexExists(_) |
+                    StorageError::Locked(_) => StatusCode::CONFLICT,
+                    StorageError::ReadOnly(_) => StatusCode::FORBIDDEN,
+                    StorageError::Spill(_) | StorageError::Cancelled => StatusCode::INTERNAL_SERVER_ERROR,
+                    StorageError::Full(_) => StatusCode::INSUFFICIENT_STORAGE,
+                    StorageError::UnknownColumn { .. } |
+                    StorageError::AmbiguousColumn(_) |
+                    StorageError::NotNumeric { .. } => StatusCode::BAD_REQUEST,
+                }
+            ExecutionError::Catalog(error) =>
+                match error {
+                    CatalogError::UserExists(_) | CatalogError::RoleExists(_) => StatusCode::CONFLICT,
+                    CatalogError::UserNotFound(_) |
+                    CatalogError::RoleNotFound(_) |
+                    CatalogError::GranteeNotFound(_) => StatusCode::NOT_FOUND,
+                    _ => StatusCode::BAD_REQUEST,
+                }
+        };
+        return Self::new(status, e.error_code(), e.to_string());
+    }
+}
+
+#[derive(Clone)]
+pub struct ApiState {
+    executor: Arc<Executor>,
+}
+
+/// The user a request runs as, from HTTP basic credentials checked
+/// against the system catalog.
+pub struct Caller(pub Principal);
+
+impl FromRequestParts<ApiState> for Caller {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &ApiState) -> Result<Self, Self::Rejection> {
+        let (username, password) = parts.headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_basic)
+            .ok_or_else(|| ApiError::unauthorized("basic credentials required"))?;
+        let peer_address = parts.extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(address)| address.to_string())
+            .unwrap_or_default();
+
+        return match authenticate(&state.executor, username, password, peer_address).await {
+            Ok(principal) => Ok(Caller(principal)),
+            Err(e) => Err(ApiError::new(StatusCode::UNAUTHORIZED, ErrorCode::AuthenticationFailed, e.to_string())),
+        };
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AffectedResponse {
+    pub affected_rows: u64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RowsResponse {
+    #[schema(value_type = Vec<Object>)]
+    pub rows: Vec<Row>,
+    /// Passed as `after` to read the next page of a paged select; absent on
+    /// the last page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub continuation: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateDatabaseRequest {
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateTableRequest {
+    pub name: String,
+    pub columns: Vec<ColumnDefinition>,
+    pub storage: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct InsertRequest {
+    #[schema(value_type = Object)]
+    pub values: HashMap<String, Value>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BulkInsertRequest {
+    #[schema(value_type = Vec<Object>)]
+    pub rows: Vec<HashMap<String, Value>>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpsertRequest {
+    #[schema(value_type = Object)]
+    pub values: HashMap<String, Value>,
+    pub unique_key: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdateRequest {
+    #[schema(value_type = Object)]
+    pub updates: HashMap<String, Value>,
+    #[serde(default, rename = "where")]
+    pub where_clause: String,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct SelectQuery {
+    /// Comma separated column names; every column when empty.
+    pub columns: Option<String>,
+    /// Filter in the same syntax as `SelectStatement.where`.
+    #[serde(rename = "where")]
+    #[param(rename = "where")]
+    pub where_clause: Option<String>,
+    /// Sort keys such as `age desc nulls last, name`.
+    pub order_by: Option<String>,
+    pub limit: Option<u64>,
+    pub offset: Option<u64>,
+    /// Continuation token of the previous page; needs `order_by`.
+    pub after: Option<String>,
+    /// Aggregates such as `count(*), avg(age) as mean`.
+    pub aggregates: Option<String>,
+    /// Comma separated columns to group by.
+    pub group_by: Option<String>,
+    /// Filter on the groups, over the columns of the result.
+    pub having: Option<String>,
+    /// Tables joined to this one, separated by semicolons, such as
+    /// `left orders on users.id = orders.user_id`.
+    pub joins: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct DeleteQuery {
+    #[serde(rename = "where")]
+    #[param(rename = "where")]
+    pub where_clause: Option<String>,
+}
+
+fn run(
+    state: &ApiState,
+    caller: Caller,
+    database: &str,
+    message_type: MessageType,
+    stmt: Result<impl Statement, ValidationErrors>
+) -> Result<ExecutionResult, ApiError> {
+    let stmt = stmt.map_err(ApiError::invalid)?;
+    let ctx = ExecutionContext { principal: caller.0, database: database.to_string() };
+    let message = Message::new(message_type, &stmt);
+    return Ok(state.executor.run(next_connection_id(), &ctx, &message)?);
+}
+
+/// The non-empty names of a comma separated list.
+fn comma_list(list: String) -> Vec<String> {
+    return list
+        .split(',')
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect();
+}
+
+fn affected(result: ExecutionResult) -> Json<AffectedResponse> {
+    let affected_rows = match result {
+        ExecutionResult::Affected(affected) => affected,
+        _ => 0,
+    };
+    return Json(AffectedResponse { affected_rows });
+}
+
+fn rows(result: ExecutionResult) -> Json<RowsResponse> {
+    let (rows, continuation) = match result {
+        ExecutionResult::Rows(rows) => (rows, None),
+        ExecutionResult::Page { rows, continuation } => (rows, continuation),
+        _ => (Vec::new(), None),
+    };
+    return Json(RowsResponse { rows, continuation });
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/databases",
+    tag = "databases",
+    responses((status = 200, body = RowsResponse))
+)]
+async fn list_databases(State(state): State<ApiState>, caller: Caller) -> Result<Json<RowsResponse>, ApiError> {
+    let stmt = Ok(EmptyStatement::new(MessageType::ShowDatabases));
+    return Ok(rows(run(&state, caller, DEFAULT_DATABASE, MessageType::ShowDatabases, stmt)?));
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/databases",
+    tag = "databases",
+    request_body = CreateDatabaseRequest,
+    responses((status = 200, body = AffectedResponse), (status = 409, body = ApiErrorBody))
+)]
+async fn create_database(
+    State(state): State<ApiState>,
+    caller: Caller,
+    Json(request): Json<CreateDatabaseRequest>
+) -> Result<Json<AffectedResponse>, ApiError> {
+    let stmt = CreateDatabaseStatement::new(request.name);
+    return Ok(affected(run(&state, caller, DEFAULT_DATABASE, MessageType::CreateDatabase, stmt)?));
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/databases/{database}",
+    tag = "databases",
+    params(("database" = String, Path)),
+    responses((status = 200, body = AffectedResponse), (status = 404, body = ApiErrorBody))
+)]
+async fn drop_database(
+    State(state): State<ApiState>,
+    caller: Caller,
+    Path(database): Path<String>
+) -> Result<Json<AffectedResponse>, ApiError> {
+    let stmt = DropDatabaseStatement::new(database);
+    return Ok(affected(run(&state, caller, DEFAULT_DATABASE, MessageType::DropDatabase, stmt)?));
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/databases/{database}/tables",
+    tag = "tables",
+    params(("database" = String, Path)),
+    responses((status = 200, body = RowsResponse))
+)]
+async fn list_tables(
+    State(state): State<ApiState>,
+    caller: Caller,
+    Path(database): Path<String>
+) -> Result<Json<RowsResponse>, ApiError> {
+    let stmt = Ok(EmptyStatement::new(MessageType::ShowTables));
+    return Ok(rows(run(&state, caller, &database, MessageType::ShowTables, stmt)?));
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/databases/{database}/tables",
+    tag = "tables",
+    params(("database" = String, Path)),
+    request_body = CreateTableRequest,
+    responses((status = 200, body = AffectedResponse), (status = 409, body = ApiErrorBody))
+)]
+async fn create_table(
+    State(state): State<ApiState>,
+    caller: Caller,
+    Path(database): Path<String>,
+    Json(request): Json<CreateTableRequest>
+) -> Result<Json<AffectedResponse>, ApiError> {
+    let stmt = CreateTableStatement::new(request.name, request.columns, request.storage);
+    return Ok(affected(run(&state, caller, &database, MessageType::CreateTable, stmt)?));
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/databases/{database}/tables/{table}",
+    tag = "tables",
+    params(("database" = String, Path), ("table" = String, Path)),
+    responses((status = 200, body = AffectedResponse), (status = 404, body = ApiErrorBody))
+)]
+async fn drop_table(
+    State(state): State<ApiState>,
+    caller: Caller,
+    Path((database, table)): Path<(String, String)>
+) -> Result<Json<AffectedResponse>, ApiError> {
+    let stmt = DropTableStatement::new(table);
+    return Ok(affected(run(&state, caller, &database, MessageType::DropTable, stmt)?));
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/databases/{database}/tables/{table}/rows",
+    tag = "rows",
+    params(("database" = String, Path), ("table" = String, Path), SelectQuery),
+    responses((status = 200, body = RowsResponse), (status = 400, body = ApiErrorBody))
+)]
+async fn select_rows(
+    State(state): State<ApiState>,
+    caller: Caller,
+    Path((database, table)): Path<(String, String)>,
+    Query(query): Query<SelectQuery>
+) -> Result<Json<RowsResponse>, ApiError> {
+    let columns = comma_list(query.columns.unwrap_or_default());
+    let invalid = |e: String| ApiError::new(StatusCode::BAD_REQUEST, ErrorCode::InvalidStatement, e);
+    let order_by = OrderBy::parse_list(query.order_by.as_deref().unwrap_or_default()).map_err(invalid)?;
+    let aggregates = Aggregate::parse_list(query.aggregates.as_deref().unwrap_or_default()).map_err(invalid)?;
+    let group_by = comma_list(query.group_by.unwrap_or_default());
+    let joins = Join::parse_list(query.joins.as_deref().unwrap_or_default()).map_err(invalid)?;
+    let stmt = SelectStatement::new(table, columns, query.where_clause.unwrap_or_default())
+        .and_then(|stmt| stmt.with_joins(joins))
+        .and_then(|stmt| stmt.with_order_by(order_by))
+        .and_then(|stmt| stmt.with_aggregates(aggregates))
+        .and_then(|stmt| stmt.with_group_by(group_by))
+        .map(|mut stmt| {
+            stmt.limit = query.limit;
+            stmt.offset = query.offset.unwrap_or_default();
+            stmt.after = query.after;
+            stmt.having = query.having.unwrap_or_default();
+            stmt
+        });
+    return Ok(rows(run(&state, caller, &database, MessageType::Select, stmt)?));
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/databases/{database}/tables/{table}/rows",
+    tag = "rows",
+    params(("database" = String, Path), ("table" = String, Path)),
+    request_body = InsertRequest,
+    responses((status = 200, body = AffectedResponse), (status = 400, body = ApiErrorBody))
+)]
+async fn insert_row(
+    State(state): State<ApiState>,
+    caller: Caller,
+    Path((database, table)): Path<(String, String)>,
+    Json(request): Json<InsertRequest>
+) -> Result<Json<AffectedResponse>, ApiError> {
+    let stmt = InsertStatement::new(table, request.values);
+    return Ok(affected(run(&state, caller, &database, MessageType::Insert, stmt)?));
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/databases/{database}/tables/{table}/rows/bulk",
+    tag = "rows",
+    params(("database" = String, Path), ("table" = String, Path)),
+    request_body = BulkInsertRequest,
+    responses((status = 200, body = AffectedResponse), (status = 400, body = ApiErrorBody))
+)]
+async fn bulk_insert_rows(
+    State(state): State<ApiState>,
+    caller: Caller,
+    Path((database, table)): Path<(String, String)>,
+    Json(request): Json<BulkInsertRequest>
+) -> Result<Json<AffectedResponse>, ApiError> {
+    let stmt = BulkInsertStatement::new(table, request.rows);
+    return Ok(affected(run(&state, caller, &database, MessageType::BulkInsert, stmt)?));
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/databases/{database}/tables/{table}/rows/upsert",
+    tag = "rows",
+    params(("database" = String, Path), ("table" = String, Path)),
+    request_body = UpsertRequest,
+    responses((status = 200, body = AffectedResponse), (status = 400, body = ApiErrorBody))
+)]
+async fn upsert_row(
+    State(state): State<ApiState>,
+    caller: Caller,
+    Path((database, table)): Path<(String, String)>,
+    Json(request): Json<UpsertRequest>
+) -> Result<Json<AffectedResponse>, ApiError> {
+    let stmt = UpsertStatement::new(table, request.values, request.unique_key);
+    return Ok(affected(run(&state, caller, &database, MessageType::Upsert, stmt)?));
+}
+
+#[utoipa::path(
+    patch,
+    path = "/v1/databases/{database}/tables/{table}/rows",
+    tag = "rows",
+    params(("database" = String, Path), ("table" = String, Path)),
+    request_body = UpdateRequest,
+    responses((status = 200, body = AffectedResponse), (status = 400, body = ApiErrorBody))
+)]
+async fn update_rows(
+    State(state): State<ApiState>,
+    caller: Caller,
+    Path((database, table)): Path<(String, String)>,
+    Json(request): Json<UpdateRequest>
+) -> Result<Json<AffectedResponse>, ApiError> {
+    let stmt = UpdateStatement::new(table, request.updates, request.where_clause);
+    return Ok(affected(run(&state, caller, &database, MessageType::Update, stmt)?));
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/databases/{database}/tables/{table}/rows",
+    tag = "rows",
+    params(("database" = String, Path), ("table" = String, Path), DeleteQuery),
+    responses((status = 200, body = AffectedResponse), (status = 400, body = ApiErrorBody))
+)]
+async fn delete_rows(
+    State(state): State<ApiState>,
+    caller: Caller,
+    Path((database, table)): Path<(String, String)>,
+    Query(query): Query<DeleteQuery>
+) -> Result<Json<AffectedResponse>, ApiError> {
+    let stmt = DeleteStatement::new(table, query.where_clause);
+    return Ok(affected(run(&state, caller, &database, MessageType::Delete, stmt)?));
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "ZenithStore REST API", description = "JSON front end for the ZenithStore statements."),
+    paths(
+        list_databases,
+        create_database,
+        drop_database,
+        list_tables,
+        create_table,
+        drop_table,
+        select_rows,
+        insert_row,
+        bulk_insert_rows,
+        upsert_row,
+        update_rows,
+        delete_rows
+    ),
+    components(schemas(ApiErrorBody, AffectedResponse, RowsResponse, ColumnDefinition)),
+    modifiers(&BasicAuth),
+    security(("basic" = []))
+)]
+pub struct ApiDoc;
+
+struct BasicAuth;
+
+impl Modify for BasicAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme("basic", SecurityScheme::Http(Http::new(HttpAuthScheme::Basic)));
+    }
+}
+
+async fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    return Json(ApiDoc::openapi());
+}
+
+/// Routes of the REST API, served under `/v1`.
+pub fn router(executor: Arc<Executor>) -> Router {
+    let tables = "/v1/databases/{database}/tables";
+    let rows = "/v1/databases/{database}/tables/{table}/rows";
+
+    return Router::new()
+        .route("/v1/openapi.json", get(openapi))
+        .route("/v1/databases", get(list_databases).post(create_database))
+        .route("/v1/databases/{database}", delete(drop_database))
+        .route(tables, get(list_tables).post(create_table))
+        .route(&format!("{}/{{table}}", tables), delete(drop_table))
+        .route(rows, get(select_rows).post(insert_row).patch(update_rows).delete(delete_rows))
+        .route(&format!("{}/bulk", rows), post(bulk_insert_rows))
+        .route(&format!("{}/upsert", rows), post(upsert_row))
+        .with_state(ApiState { executor });
+}
+
+/// Serves the REST API on `listener` until the task is dropped.
+pub async fn serve(listener: ServerListener, executor: Arc<Executor>) -> std::io::Result<()> {
+    info!("REST API listening on {}", listener.local_addr());
+    let app = router(executor).into_make_service_with_connect_info::<SocketAddr>();
+    let listener = listener.tap_io(|stream| {
+        let _ = stream.set_nodelay(true);
+    });
+    return axum::serve(listener, app).await;
+}
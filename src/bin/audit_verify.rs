@@ -0,0 +1,47 @@
+//! Checks the hash chain of an audit directory offline.
+//!
+//! Usage: `audit_verify [DIRECTORY]`, defaulting to the `[audit]` directory
+//! of `config.toml`.
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+use zenith_store::utils::audit::{ verify_chain, AUDIT_DIRECTORY };
+use zenith_store::utils::config::Config;
+
+fn audit_directory() -> Result<PathBuf, String> {
+    if let Some(directory) = std::env::args().nth(1) {
+        return Ok(PathBuf::from(directory));
+    }
+    let config = Config::load("config.toml").map_err(|e| format!("cannot read config.toml: {}", e))?;
+    return Ok(match config.audit.directory {
+        Some(directory) if !directory.is_empty() => PathBuf::from(directory),
+        _ => PathBuf::from(config.storage.path).join(AUDIT_DIRECTORY),
+    });
+}
+
+fn main() -> ExitCode {
+    let directory = match audit_directory() {
+        Ok(directory) => directory,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(2);
+        }
+    };
+
+    match verify_chain(&directory) {
+        Ok(summary) => {
+            println!(
+                "{}: {} records in {} files, chain intact, last hash {}",
+                directory.display(),
+                summary.records,
+                summary.files,
+                summary.last_hash
+            );
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("{}: audit chain broken: {}", directory.display(), e);
+            return ExitCode::FAILURE;
+        }
+    }
+}
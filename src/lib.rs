@@ -0,0 +1,10 @@
+pub mod network;
+pub mod storage;
+pub mod utils;
+pub mod protocol;
+pub mod statement;
+pub mod sql;
+pub mod managment;
+pub mod transport;
+pub mod api;
+pub mod node;
@@ -1,23 +1,57 @@
-#[allow(unused_imports)]
-mod network;
-#[allow(unused_imports)]
-mod storage;
-#[allow(unused_imports)]
-mod utils;
-#[allow(unused_imports)]
-mod protocol;
-#[allow(unused_imports)]
-mod statement;
-#[allow(unused_imports)]
-mod managment;
-#[allow(unused_imports)]
-mod transport;
-#[allow(unused_imports)]
-mod example;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use clap::Parser;
+use zenith_store::node;
+use zenith_store::utils::config::{ Config, Overrides };
+use zenith_store::utils::logger;
+use zenith_store::utils::ConfigReloader;
 
-use example::start_server;
+/// Runs a ZenithStore node. Any config value can also be set with a
+/// `ZENITH_<SECTION>__<KEY>` environment variable; the options below take
+/// precedence over both.
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Cli {
+    /// Path of the TOML config file.
+    #[arg(short, long, env = "ZENITH_CONFIG", default_value = "config.toml")]
+    config: PathBuf,
+    /// Overrides `management.node_id`.
+    #[arg(long)]
+    node_id: Option<String>,
+    /// Overrides `storage.path`.
+    #[arg(long)]
+    data_dir: Option<String>,
+    /// Overrides `listener.address`.
+    #[arg(long)]
+    listen: Option<String>,
+}
 
 #[tokio::main]
-async fn main() {
-    start_server().await;
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let overrides = Overrides {
+        node_id: cli.node_id,
+        data_dir: cli.data_dir,
+        listen_address: cli.listen,
+    };
+    let config = match Config::load_with(&cli.config, &overrides) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(2);
+        }
+    };
+
+    if let Err(e) = logger::init(&config.logging) {
+        eprintln!("cannot set up logging: {}", e);
+        return ExitCode::from(2);
+    }
+    logger::set_node_id(&config.management.node_id);
+    let reloader = Arc::new(ConfigReloader::new(cli.config, overrides, config));
+    if let Err(e) = node::run(reloader).await {
+        eprintln!("{}", e);
+        return ExitCode::FAILURE;
+    }
+    return ExitCode::SUCCESS;
 }
@@ -1,25 +1,47 @@
 use std::collections::BinaryHeap;
-use std::sync::{ Arc, Mutex };
+use std::sync::{ Arc, Mutex, RwLock };
 use std::time::Duration;
 use log::{ info, warn };
-use crate::network::{ ZenithConnection, dial_timeout };
-use crate::protocol::{ self, MessageType };
+use serde::Serialize;
+use crate::network::{ ZenithConnection, ClientTls, DialOptions, dial_timeout };
+use crate::protocol::{ self, MessageType, Capabilities };
+use crate::protocol::handshake::{ self, NegotiatedProtocol };
 use crate::transport::Message;
-use crate::statement::LoginStatement;
+use crate::statement::{ ErrorStatement, LegacyLoginStatement, LoginStatement, WelcomeStatement };
+use crate::utils::{ metrics, KeyRing };
+use crate::utils::keyring::DEFAULT_KEY_ID;
 
 const RECONNECT_INTERVAL: Duration = Duration::from_secs(3);
 
 #[derive(Debug, Clone)]
 pub struct MessageClient {
     server_addr: String,
-    token: String,
+    keys: KeyRing,
     node_id: String,
     address: String,
     tags: Vec<String>,
     connections: Arc<Mutex<BinaryHeap<ConnectionPool>>>,
-    max_conn: usize,
-    min_conn: usize,
-    timeout: Duration,
+    limits: Arc<RwLock<PoolLimits>>,
+    max_frame_size: u32,
+    compression: Vec<String>,
+    compression_threshold: usize,
+    tls: Option<ClientTls>,
+}
+
+/// Pool size and dial timeout; these can change while the client runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolLimits {
+    pub min_conn: usize,
+    pub max_conn: usize,
+    pub timeout: Duration,
+}
+
+impl PoolLimits {
+    /// At least one connection, and `max_conn` never below `min_conn`.
+    pub fn new(min_conn: usize, max_conn: usize, timeout: Duration) -> Self {
+        let min_conn = min_conn.max(1);
+        return Self { min_conn, max_conn: max_conn.max(min_conn), timeout };
+    }
 }
 
 #[derive(Debug, Eq, PartialEq, Clone)]
@@ -40,16 +62,34 @@ impl PartialOrd for ConnectionPool {
     }
 }
 
+/// Connection pool state, for the admin endpoints.
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolStats {
+    pub server_addr: String,
+    pub min_conn: usize,
+    pub max_conn: usize,
+    pub connections: usize,
+    /// Requests currently borrowing a pooled connection.
+    pub loans: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct MessageConfig {
     pub server_addr: String,
-    pub token: String,
+    /// Cluster keys; logins are signed with the active one.
+    pub keys: KeyRing,
     pub node_id: String,
     pub address: String,
     pub tags: Vec<String>,
     pub min_conn: usize,
     pub max_conn: usize,
     pub timeout: Duration,
+    pub max_frame_size: u32,
+    /// Codecs to offer in order of preference; empty disables compression.
+    pub compression: Vec<String>,
+    pub compression_threshold: usize,
+    /// Connect to the server over TLS; see `ClientTls::client`.
+    pub tls: Option<ClientTls>,
 }
 
 #[allow(dead_code)]
@@ -57,30 +97,74 @@ impl MessageClient {
     pub async fn new(
         config: MessageConfig
     ) -> Result<MessageClient, Box<dyn std::error::Error + Send + Sync>> {
-        let min_conn = config.min_conn.max(1);
-        let max_conn = config.max_conn.max(min_conn);
+        let limits = PoolLimits::new(config.min_conn, config.max_conn, config.timeout);
 
         let client = Self {
             server_addr: config.server_addr,
-            token: config.token,
+            keys: config.keys,
             node_id: config.node_id,
             address: config.address,
             tags: config.tags,
             connections: Arc::new(Mutex::new(BinaryHeap::new())),
-            max_conn,
-            min_conn,
-            timeout: config.timeout,
+            limits: Arc::new(RwLock::new(limits)),
+            max_frame_size: config.max_frame_size,
+            compression: config.compression,
+            compression_threshold: config.compression_threshold,
+            tls: config.tls,
         };
 
         client.init_connections().await;
+        client.watch_key_rotation();
 
         return Ok(client);
     }
 
+    pub fn pool_stats(&self) -> PoolStats {
+        let connections = self.connections.lock().unwrap();
+        return PoolStats {
+            server_addr: self.server_addr.clone(),
+            min_conn: self.limits().min_conn,
+            max_conn: self.limits().max_conn,
+            connections: connections.len(),
+            loans: connections.iter().map(|cp| cp.loan_count).sum(),
+        };
+    }
+
+    fn record_pool(connections: &BinaryHeap<ConnectionPool>) {
+        metrics().pool_connections.set(connections.len() as i64);
+        metrics().pool_loans.set(connections.iter().map(|cp| cp.loan_count as i64).sum());
+    }
+
+    /// Connections only join the pool once they have logged in, so any
+    /// pooled connection means the node is authenticated with the server.
+    pub fn limits(&self) -> PoolLimits {
+        return *self.limits.read().unwrap();
+    }
+
+    /// Applies new limits to the running pool. Connections missing to reach
+    /// the new minimum are opened in the background; surplus idle ones are
+    /// closed as they are freed.
+    pub fn set_limits(&self, limits: PoolLimits) {
+        let limits = PoolLimits::new(limits.min_conn, limits.max_conn, limits.timeout);
+        *self.limits.write().unwrap() = limits;
+        let missing = limits.min_conn.saturating_sub(self.connections.lock().unwrap().len());
+        info!("Pool limits set to {:?}, opening {} connections", limits, missing);
+        for _ in 0..missing {
+            let self_clone = Arc::new(self.clone());
+            tokio::spawn(async move {
+                let _ = self_clone.retry_create_connection().await;
+            });
+        }
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        return !self.connections.lock().unwrap().is_empty();
+    }
+
     async fn init_connections(&self) {
         let mut handles: Vec<tokio::task::JoinHandle<()>> = Vec::new();
 
-        for _ in 0..self.min_conn {
+        for _ in 0..self.limits().min_conn {
             let self_clone = Arc::new(self.clone());
             let handle = tokio::spawn(async move {
                 let _ = self_clone.retry_create_connection().await;
@@ -91,10 +175,45 @@ impl MessageClient {
         futures::future::join_all(handles).await;
     }
 
+    /// Makes every pooled connection log in again when the key ring
+    /// changes, so they move to the new active key without reconnecting.
+    fn watch_key_rotation(&self) {
+        let mut changes = self.keys.subscribe();
+        let connections = Arc::downgrade(&self.connections);
+
+        tokio::spawn(async move {
+            while changes.changed().await.is_ok() {
+                let connections = match connections.upgrade() {
+                    Some(connections) => connections,
+                    None => {
+                        return;
+                    }
+                };
+                let pooled: Vec<ZenithConnection> = connections
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|cp| cp.conn.clone())
+                    .collect();
+
+                info!("Cluster key changed, re-authenticating {} connections", pooled.len());
+                for conn in pooled {
+                    conn.require_auth().await;
+                }
+            }
+        });
+    }
+
     async fn create_connection(
         &self
     ) -> Result<ZenithConnection, Box<dyn std::error::Error + Send + Sync>> {
-        let result = dial_timeout(&self.server_addr, self.timeout).await;
+        let options = DialOptions {
+            timeout: self.limits().timeout,
+            max_body_size: self.max_frame_size,
+            compression_threshold: self.compression_threshold,
+            tls: self.tls.clone(),
+        };
+        let result = dial_timeout(&self.server_addr, options).await;
         let mut conn = match result {
             Ok(conn) => conn,
             Err(e) => {
@@ -108,14 +227,25 @@ impl MessageClient {
         tokio::spawn(async move {
             loop {
                 conn_clone.on_require_auth().await;
-                println!("Re-authenticating with the server...");
+                info!(connection_id = conn_clone.id; "Re-authenticating with the server...");
+                if let Err(e) = self_clone.handshake(&conn_clone).await {
+                    warn!(connection_id = conn_clone.id; "Failed to negotiate protocol with the server: {:?}", e);
+                    let _ = conn_clone.close().await;
+                    continue;
+                }
                 if let Err(e) = self_clone.authenticate(&mut conn_clone).await {
-                    println!("Failed to authenticate with the server: {:?}", e);
+                    warn!(connection_id = conn_clone.id; "Failed to authenticate with the server: {:?}", e);
                     let _ = conn_clone.close().await;
                 }
             }
         });
 
+        if let Err(e) = self.handshake(&conn).await {
+            warn!("Failed to negotiate protocol with the server: {:?}", e);
+            let _ = conn.close().await;
+            return Err(e);
+        }
+
         match self.authenticate(&mut conn).await {
             Ok(_) => {
                 info!("Successfully authenticated with the server.");
@@ -142,9 +272,11 @@ impl MessageClient {
         for item in temp {
             connections.push(item);
         }
+        Self::record_pool(&connections);
 
         drop(connections);
 
+        metrics().reconnects.inc();
         let self_clone = Arc::new(self.clone());
         tokio::spawn(async move {
             let _ = self_clone.retry_create_connection().await;
@@ -161,6 +293,7 @@ impl MessageClient {
                 Ok(conn) => {
                     let mut conn_pool = self.connections.lock().unwrap();
                     conn_pool.push(ConnectionPool { conn, loan_count: 0 });
+                    Self::record_pool(&conn_pool);
                     return Ok(());
                 }
                 Err(_) => {
@@ -176,7 +309,7 @@ impl MessageClient {
     ) -> Result<ZenithConnection, Box<dyn std::error::Error + Send + Sync>> {
         let mut conn_pool = self.connections.lock().unwrap();
 
-        if conn_pool.is_empty() && conn_pool.len() < self.max_conn {
+        if conn_pool.is_empty() && conn_pool.len() < self.limits().max_conn {
             let self_clone = Arc::new(self.clone());
             tokio::spawn(async move {
                 let _ = self_clone.retry_create_connection().await;
@@ -190,6 +323,7 @@ impl MessageClient {
         let mut selected: ConnectionPool = conn_pool.pop().unwrap();
         selected.loan_count += 1;
         conn_pool.push(selected.clone());
+        Self::record_pool(&conn_pool);
 
         return Ok(selected.conn);
     }
@@ -198,6 +332,7 @@ impl MessageClient {
         &self,
         conn: ZenithConnection
     ) {
+        let min_conn = self.limits().min_conn;
         let mut connections = self.connections.lock().unwrap();
         let mut temp: Vec<_> = connections.drain().collect();
 
@@ -209,7 +344,7 @@ impl MessageClient {
         }
 
         for item in temp {
-            if item.loan_count > 0 || connections.len() < self.min_conn {
+            if item.loan_count > 0 || connections.len() < min_conn {
                 connections.push(item);
             } else {
                 tokio::spawn(async move {
@@ -217,14 +352,16 @@ impl MessageClient {
                 });
             }
         }
+        Self::record_pool(&connections);
     }
 
     async fn cleanup_idle_connections(&self, connections: &mut BinaryHeap<ConnectionPool>) {
+        let min_conn = self.limits().min_conn;
         let temp: Vec<_> = connections.drain().collect();
         let mut retained = Vec::new();
 
         for conn_pool in temp.into_iter() {
-            if retained.len() < self.min_conn || conn_pool.loan_count > 0 {
+            if retained.len() < min_conn || conn_pool.loan_count > 0 {
                 retained.push(conn_pool);
             } else {
                 let _ = conn_pool.conn.close().await;
@@ -236,29 +373,87 @@ impl MessageClient {
         }
     }
 
+    /// Exchanges Greeting/Welcome before login so both sides agree on the
+    /// protocol version, message types, codecs and frame size.
+    pub async fn handshake(
+        &self,
+        conn: &ZenithConnection
+    ) -> Result<NegotiatedProtocol, Box<dyn std::error::Error + Send + Sync>> {
+        let capabilities = Capabilities::local(self.max_frame_size).with_compression(&self.compression);
+        let greeting = capabilities.to_greeting(&self.node_id);
+        let greeting_message = Message::new(MessageType::Greeting, &greeting);
+        // Peers that predate the handshake may never answer a Greeting.
+        let response = match tokio::time::timeout(self.limits().timeout, conn.send(&greeting_message)).await {
+            Ok(response) => Some(response?),
+            Err(_) => None,
+        };
+
+        let negotiated = if let Some(response) = response.filter(|r| r.header.message_type == MessageType::Welcome) {
+            let welcome = WelcomeStatement::decode(&response.body).map_err(|e|
+                handshake::HandshakeError::Decode(e.to_string())
+            )?;
+            handshake::accept_welcome(&capabilities, &welcome)?
+        } else {
+            info!("Server did not answer the greeting, falling back to the legacy protocol.");
+            NegotiatedProtocol::legacy(self.max_frame_size)
+        };
+
+        info!(
+            "Negotiated protocol version {} with max frame size {} and compression {}",
+            negotiated.protocol_version,
+            negotiated.max_frame_size,
+            negotiated.codec().to_name()
+        );
+        conn.set_protocol(negotiated.clone());
+
+        return Ok(negotiated);
+    }
+
     pub async fn authenticate(
         &self,
         conn: &mut ZenithConnection
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let stmt = LoginStatement::new(
-            self.token.clone(),
-            self.node_id.clone(),
-            self.node_id.clone(),
-            false,
-            self.address.clone(),
-            self.tags.clone()
-        )?;
-        let login_message = Message::new(protocol::MessageType::Login, &stmt);
+        let (key_id, token) = self.keys.active();
+        // Peers on the legacy protocol only know the older login.
+        let login_message = if conn.protocol().supports_login_nonce() {
+            let stmt = LoginStatement::new(
+                token,
+                key_id,
+                self.node_id.clone(),
+                self.node_id.clone(),
+                false,
+                self.address.clone(),
+                self.tags.clone()
+            )?;
+            Message::new(protocol::MessageType::Login, &stmt)
+        } else {
+            let token = self.keys.get(DEFAULT_KEY_ID).unwrap_or(token);
+            let stmt = LegacyLoginStatement::new(
+                token,
+                self.node_id.clone(),
+                self.node_id.clone(),
+                false,
+                self.address.clone(),
+                self.tags.clone()
+            )?;
+            Message::new(protocol::MessageType::Login, &stmt)
+        };
         let response = match conn.send(&login_message).await {
             Ok(response) => response,
             Err(e) => {
-                println!("Failed to send login message: {:?}", e);
+                warn!(connection_id = conn.id; "Failed to send login message: {:?}", e);
                 return Err(e);
             }
         };
 
+        if response.header.message_type == MessageType::Error {
+            let error = ErrorStatement::decode(&response.body)?;
+            warn!(connection_id = conn.id; "Authentication failed: {}", error.message);
+            return Err(format!("Authentication failed: {}", error.message).into());
+        }
+
         if response.header.message_type != MessageType::Login {
-            println!("Authentication failed");
+            warn!(connection_id = conn.id; "Authentication failed");
             return Err("Authentication failed".into());
         }
 
@@ -1,2 +1,2 @@
 pub mod client;
-pub use client::{ MessageClient, MessageConfig };
+pub use client::{ MessageClient, MessageConfig, PoolLimits, PoolStats };
@@ -0,0 +1,192 @@
+use std::collections::{ HashSet, VecDeque };
+use std::error::Error;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+use chrono::Utc;
+use crate::statement::{ ErrorCode, LegacyLoginStatement, LoginStatement };
+use crate::utils::keyring::DEFAULT_KEY_ID;
+use crate::utils::KeyRing;
+
+/// How far a login timestamp may be from the local clock, either way.
+pub const DEFAULT_MAX_CLOCK_SKEW: Duration = Duration::from_secs(30);
+/// Upper bound on remembered nonces, so a flood of logins cannot grow the
+/// cache without limit.
+pub const DEFAULT_MAX_TRACKED_NONCES: usize = 100_000;
+
+/// Identity a connection is authenticated as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Principal {
+    /// A cluster node, logged in with the cluster key.
+    Node(String),
+    /// An application user from the system catalog.
+    User(String),
+}
+
+impl fmt::Display for Principal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Principal::Node(node_id) => write!(f, "node:{}", node_id),
+            Principal::User(username) => write!(f, "user:{}", username),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoginError {
+    UnknownKey(String),
+    BadSignature,
+    Expired {
+        skew: Duration,
+    },
+    Replayed,
+    /// A login without a nonce while `allow_legacy_login` is off.
+    LegacyNotAllowed,
+}
+
+#[allow(dead_code)]
+impl LoginError {
+    pub fn error_code(&self) -> ErrorCode {
+        match self {
+            LoginError::UnknownKey(_) => ErrorCode::UnknownKey,
+            LoginError::BadSignature => ErrorCode::AuthenticationFailed,
+            LoginError::Expired { .. } => ErrorCode::LoginExpired,
+            LoginError::Replayed => ErrorCode::LoginReplayed,
+            LoginError::LegacyNotAllowed => ErrorCode::AuthenticationFailed,
+        }
+    }
+}
+
+impl fmt::Display for LoginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoginError::UnknownKey(key_id) => write!(f, "unknown cluster key {:?}", key_id),
+            LoginError::BadSignature => write!(f, "invalid signature"),
+            LoginError::Expired { skew } =>
+                write!(f, "login timestamp is {:?} away from the server clock", skew),
+            LoginError::Replayed => write!(f, "login nonce was already used"),
+            LoginError::LegacyNotAllowed => write!(f, "logins without a nonce are not allowed"),
+        }
+    }
+}
+
+impl Error for LoginError {}
+
+struct SeenNonce {
+    key: String,
+    expires_at: u64,
+}
+
+/// Nonces seen inside the timestamp window. Once a login's timestamp falls
+/// out of the window it is rejected as expired, so its nonce can be forgotten.
+struct NonceCache {
+    keys: HashSet<String>,
+    order: VecDeque<SeenNonce>,
+    capacity: usize,
+}
+
+impl NonceCache {
+    fn evict_expired(&mut self, now: u64) {
+        while self.order.front().is_some_and(|oldest| oldest.expires_at <= now) {
+            self.evict_oldest();
+        }
+    }
+
+    fn evict_oldest(&mut self) {
+        if let Some(oldest) = self.order.pop_front() {
+            self.keys.remove(&oldest.key);
+        }
+    }
+
+    /// Returns false if the nonce was already seen. The replay check comes
+    /// before making room, so a full cache cannot forget the nonce it is
+    /// being asked about.
+    fn insert(&mut self, key: String, expires_at: u64, now: u64) -> bool {
+        self.evict_expired(now);
+        if self.keys.contains(&key) {
+            return false;
+        }
+        while self.order.len() >= self.capacity {
+            self.evict_oldest();
+        }
+        self.keys.insert(key.clone());
+        self.order.push_back(SeenNonce { key, expires_at });
+        return true;
+    }
+}
+
+/// Server side check of `LoginStatement`: signature, freshness and replay.
+pub struct LoginVerifier {
+    max_clock_skew: Duration,
+    nonces: Mutex<NonceCache>,
+}
+
+#[allow(dead_code)]
+impl LoginVerifier {
+    pub fn new(max_clock_skew: Duration, max_tracked_nonces: usize) -> Self {
+        Self {
+            max_clock_skew,
+            nonces: Mutex::new(NonceCache {
+                keys: HashSet::new(),
+                order: VecDeque::new(),
+                capacity: max_tracked_nonces.max(1),
+            }),
+        }
+    }
+
+    pub fn verify(&self, stmt: &LoginStatement, keys: &KeyRing) -> Result<(), LoginError> {
+        let now = Utc::now().timestamp_nanos_opt().unwrap() as u64;
+        return self.verify_at(stmt, keys, now);
+    }
+
+    /// Same as `verify` with `now` in nanoseconds since the epoch.
+    pub fn verify_at(&self, stmt: &LoginStatement, keys: &KeyRing, now: u64) -> Result<(), LoginError> {
+        let token = keys.get(&stmt.key_id).ok_or_else(|| LoginError::UnknownKey(stmt.key_id.clone()))?;
+
+        // The signature is checked first so that unsigned logins never reach
+        // the nonce cache.
+        if !stmt.validate_hash(&token) {
+            return Err(LoginError::BadSignature);
+        }
+
+        return self.check_fresh(stmt.timestamp, format!("{}/{}", stmt.node_id, stmt.nonce), now);
+    }
+
+    /// Checks a login from a peer on the legacy protocol. Those only sign with
+    /// the `DEFAULT_KEY_ID` key and send no nonce, so the timestamp, which has
+    /// nanosecond resolution, stands in for it.
+    pub fn verify_legacy(&self, stmt: &LegacyLoginStatement, keys: &KeyRing) -> Result<(), LoginError> {
+        let now = Utc::now().timestamp_nanos_opt().unwrap() as u64;
+        return self.verify_legacy_at(stmt, keys, now);
+    }
+
+    pub fn verify_legacy_at(&self, stmt: &LegacyLoginStatement, keys: &KeyRing, now: u64) -> Result<(), LoginError> {
+        let token = keys.get(DEFAULT_KEY_ID).ok_or_else(|| LoginError::UnknownKey(DEFAULT_KEY_ID.to_string()))?;
+        if !stmt.validate_hash(&token) {
+            return Err(LoginError::BadSignature);
+        }
+
+        return self.check_fresh(stmt.timestamp, format!("{}@{}", stmt.node_id, stmt.timestamp), now);
+    }
+
+    fn check_fresh(&self, timestamp: u64, nonce_key: String, now: u64) -> Result<(), LoginError> {
+        let window = self.max_clock_skew.as_nanos() as u64;
+        let skew = now.abs_diff(timestamp);
+        if skew > window {
+            return Err(LoginError::Expired { skew: Duration::from_nanos(skew) });
+        }
+
+        let expires_at = timestamp.saturating_add(window);
+        if !self.nonces.lock().unwrap().insert(nonce_key, expires_at, now) {
+            return Err(LoginError::Replayed);
+        }
+
+        return Ok(());
+    }
+}
+
+impl Default for LoginVerifier {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_CLOCK_SKEW, DEFAULT_MAX_TRACKED_NONCES)
+    }
+}
@@ -0,0 +1,8 @@
+use tokio::io::{ AsyncRead, AsyncWrite };
+
+/// Anything a connection can run over: a plain TCP stream or a TLS session.
+pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> AsyncStream for T {}
+
+pub type BoxedStream = Box<dyn AsyncStream>;
@@ -0,0 +1,653 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+use std::sync::{ Arc, Mutex };
+use std::time::Duration;
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use log::{ info, warn, error };
+use tracing::{ Instrument, Span };
+use rustls::pki_types::CertificateDer;
+use sha2::{ Digest, Sha256 };
+use uuid::Uuid;
+use tokio::io::{ ReadHalf, WriteHalf };
+use tokio::net::{ TcpListener, TcpStream };
+use tokio::sync::mpsc;
+use crate::protocol::{ handshake, Capabilities, MessageType, NegotiatedProtocol };
+use crate::protocol::handshake::DEFAULT_MAX_FRAME_SIZE;
+use crate::statement::{
+    EmptyStatement,
+    ErrorCode,
+    ErrorStatement,
+    LegacyLoginStatement,
+    LoginStatement,
+    Statement,
+    StreamCreditStatement,
+    UserLoginStatement,
+};
+use crate::transport::{ ChunkKind, FlowControl, Framing, Message, MessageTypeFlag };
+use crate::transport::compression::DEFAULT_COMPRESSION_THRESHOLD;
+use crate::transport::stream::INITIAL_STREAM_WINDOW;
+use crate::utils::{ metrics, telemetry, AuditEvent, AuditLog, KeyRing };
+use crate::storage::engine::DEFAULT_DATABASE;
+use super::auth::{ LoginError, LoginVerifier, Principal, DEFAULT_MAX_CLOCK_SKEW, DEFAULT_MAX_TRACKED_NONCES };
+use super::io::BoxedStream;
+use super::tls::ServerTls;
+
+static NEXT_CONNECTION_ID: AtomicUsize = AtomicUsize::new(0);
+const MAX_CONSECUTIVE_FRAME_ERRORS: usize = 8;
+
+/// Ids are shared with the other front ends (REST, gRPC) so that audit
+/// records from all of them can be told apart.
+pub fn next_connection_id() -> usize {
+    return NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed);
+}
+
+#[derive(Debug, Clone)]
+pub struct ListenerConfig {
+    pub address: String,
+    pub node_id: String,
+    /// Cluster keys that peers sign their `LoginStatement` with.
+    pub keys: KeyRing,
+    /// How far a login timestamp may drift from the local clock.
+    pub max_clock_skew: Duration,
+    /// Whether peers on the legacy protocol may log in without a nonce or
+    /// key id. Such logins can be replayed inside the timestamp window.
+    pub allow_legacy_login: bool,
+    pub max_body_size: u32,
+    pub compression: Vec<String>,
+    pub compression_threshold: usize,
+    pub tls: Option<ServerTls>,
+    /// Trail that logins and failed logins are recorded in.
+    pub audit: Option<Arc<AuditLog>>,
+}
+
+#[allow(dead_code)]
+impl ListenerConfig {
+    pub fn new(address: String, node_id: String, keys: KeyRing) -> Self {
+        Self {
+            address,
+            node_id,
+            keys,
+            max_clock_skew: DEFAULT_MAX_CLOCK_SKEW,
+            allow_legacy_login: false,
+            max_body_size: DEFAULT_MAX_FRAME_SIZE,
+            compression: Vec::new(),
+            compression_threshold: DEFAULT_COMPRESSION_THRESHOLD,
+            tls: None,
+            audit: None,
+        }
+    }
+}
+
+/// State of one accepted connection, shared with the handler.
+#[derive(Debug)]
+pub struct Session {
+    pub connection_id: usize,
+    pub peer_address: SocketAddr,
+    /// Chain presented by the client during a mutual TLS handshake.
+    pub peer_certificates: Vec<CertificateDer<'static>>,
+    protocol: Mutex<NegotiatedProtocol>,
+    /// Header layout the client framed its first message with.
+    framing: Mutex<Framing>,
+    principal: Mutex<Option<Principal>>,
+    database: Mutex<String>,
+}
+
+#[allow(dead_code)]
+impl Session {
+    fn new(peer_address: SocketAddr, peer_certificates: Vec<CertificateDer<'static>>, max_body_size: u32) -> Self {
+        Self {
+            connection_id: next_connection_id(),
+            peer_address,
+            peer_certificates,
+            protocol: Mutex::new(NegotiatedProtocol::legacy(max_body_size)),
+            framing: Mutex::new(Framing::Current),
+            principal: Mutex::new(None),
+            database: Mutex::new(DEFAULT_DATABASE.to_string()),
+        }
+    }
+
+    pub fn protocol(&self) -> NegotiatedProtocol {
+        return self.protocol.lock().unwrap().clone();
+    }
+
+    pub fn framing(&self) -> Framing {
+        return *self.framing.lock().unwrap();
+    }
+
+    /// The node or user that logged in on this connection, if any.
+    pub fn principal(&self) -> Option<Principal> {
+        return self.principal.lock().unwrap().clone();
+    }
+
+    fn set_principal(&self, principal: Option<Principal>) {
+        *self.principal.lock().unwrap() = principal;
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        return self.principal.lock().unwrap().is_some();
+    }
+
+    /// Database statements without one run against.
+    pub fn database(&self) -> String {
+        return self.database.lock().unwrap().clone();
+    }
+
+    pub fn set_database(&self, database: &str) {
+        *self.database.lock().unwrap() = database.to_string();
+    }
+
+    /// SHA-256 of the client certificate, for logging and auditing.
+    pub fn peer_fingerprint(&self) -> Option<String> {
+        return self.peer_certificates.first().map(|cert| hex::encode(Sha256::digest(cert.as_ref())));
+    }
+}
+
+/// What the handler sends back for a request.
+pub enum HandlerResponse {
+    Reply(Message),
+    /// Frames of a chunked response; chunk frames are paced by the client's
+    /// stream credits.
+    Stream(BoxStream<'static, Message>),
+    None,
+}
+
+#[async_trait]
+pub trait MessageHandler: Send + Sync + 'static {
+    /// Handles one authenticated request frame. Chunks of an upload are
+    /// delivered in order, one at a time, and only the last one is expected
+    /// to produce a reply.
+    async fn handle(self: Arc<Self>, session: Arc<Session>, message: Message) -> HandlerResponse;
+
+    /// Checks the credentials of an application user. On success the
+    /// session is authenticated as that user.
+    async fn authenticate_user(&self, _session: Arc<Session>, _stmt: &UserLoginStatement) -> Result<(), ErrorStatement> {
+        return Err(ErrorStatement::new(ErrorCode::AuthenticationFailed, "user logins are not supported".to_string()));
+    }
+
+    /// Called once the connection of `session` is closed, to drop what the
+    /// handler kept for it.
+    async fn disconnected(&self, _session: Arc<Session>) {}
+}
+
+/// Accepts node and client connections, optionally over TLS, and runs the
+/// Greeting/Login exchange before handing requests to the handler.
+pub struct NodeListener {
+    config: ListenerConfig,
+    listener: TcpListener,
+    handler: Arc<dyn MessageHandler>,
+    verifier: Arc<LoginVerifier>,
+    active_connections: Arc<AtomicUsize>,
+    /// While set, new connections are closed right after being accepted;
+    /// sessions already open carry on until their peers disconnect.
+    draining: Arc<AtomicBool>,
+}
+
+#[allow(dead_code)]
+impl NodeListener {
+    pub async fn bind(
+        config: ListenerConfig,
+        handler: Arc<dyn MessageHandler>
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let listener = TcpListener::bind(&config.address).await?;
+        let verifier = Arc::new(LoginVerifier::new(config.max_clock_skew, DEFAULT_MAX_TRACKED_NONCES));
+        return Ok(Self {
+            config,
+            listener,
+            handler,
+            verifier,
+            active_connections: Arc::new(AtomicUsize::new(0)),
+            draining: Arc::new(AtomicBool::new(false)),
+        });
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        return self.listener.local_addr();
+    }
+
+    pub fn active_connections(&self) -> Arc<AtomicUsize> {
+        return self.active_connections.clone();
+    }
+
+    pub fn draining(&self) -> Arc<AtomicBool> {
+        return self.draining.clone();
+    }
+
+    pub async fn serve(self) {
+        let config = Arc::new(self.config);
+        info!("Listening on {}", config.address);
+
+        loop {
+            let (tcp, peer_address) = match self.listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    error!("Error accepting connection: {:?}", e);
+                    continue;
+                }
+            };
+            if self.draining.load(Ordering::SeqCst) {
+                info!("Draining, refused connection from {}", peer_address);
+                continue;
+            }
+            let _ = tcp.set_nodelay(true);
+
+            let config = config.clone();
+            let handler = self.handler.clone();
+            let verifier = self.verifier.clone();
+            let active_connections = self.active_connections.clone();
+            tokio::spawn(async move {
+                let (stream, peer_certificates) = match accept_stream(&config, tcp).await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!("Rejected connection from {}: {}", peer_address, e);
+                        return;
+                    }
+                };
+
+                let session = Arc::new(
+                    Session::new(peer_address, peer_certificates, config.max_body_size)
+                );
+                active_connections.fetch_add(1, Ordering::SeqCst);
+                serve_connection(stream, session.clone(), config, handler.clone(), verifier).await;
+                handler.disconnected(session).await;
+                active_connections.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    }
+}
+
+async fn accept_stream(
+    config: &ListenerConfig,
+    tcp: TcpStream
+) -> Result<(BoxedStream, Vec<CertificateDer<'static>>), Box<dyn std::error::Error + Send + Sync>> {
+    let tls = match &config.tls {
+        Some(tls) => tls,
+        None => {
+            return Ok((Box::new(tcp), Vec::new()));
+        }
+    };
+
+    let stream = tls.acceptor().accept(tcp).await?;
+    let peer_certificates = stream
+        .get_ref()
+        .1.peer_certificates()
+        .map(|certs| certs.to_vec())
+        .unwrap_or_default();
+
+    return Ok((Box::new(stream), peer_certificates));
+}
+
+type StreamWindows = Arc<Mutex<HashMap<[u8; 16], FlowControl>>>;
+
+struct Connection {
+    session: Arc<Session>,
+    config: Arc<ListenerConfig>,
+    handler: Arc<dyn MessageHandler>,
+    verifier: Arc<LoginVerifier>,
+    frames: mpsc::Sender<Message>,
+    windows: StreamWindows,
+}
+
+async fn serve_connection(
+    stream: BoxedStream,
+    session: Arc<Session>,
+    config: Arc<ListenerConfig>,
+    handler: Arc<dyn MessageHandler>,
+    verifier: Arc<LoginVerifier>
+) {
+    let (mut reader, writer) = tokio::io::split(stream);
+    let (frames, frame_receiver) = mpsc::channel::<Message>(100);
+
+    tokio::spawn(write_frames(writer, frame_receiver, session.clone(), config.compression_threshold));
+
+    let connection = Arc::new(Connection {
+        session,
+        config,
+        handler,
+        verifier,
+        frames,
+        windows: Arc::new(Mutex::new(HashMap::new())),
+    });
+
+    read_frames(&mut reader, connection).await;
+}
+
+async fn write_frames(
+    mut writer: WriteHalf<BoxedStream>,
+    mut frames: mpsc::Receiver<Message>,
+    session: Arc<Session>,
+    compression_threshold: usize
+) {
+    while let Some(mut message) = frames.recv().await {
+        let codec = session.protocol().codec();
+        if let Err(e) = message.compress(codec, compression_threshold) {
+            warn!(
+                connection_id = session.connection_id,
+                message_id:% = message.header.message_id_string();
+                "Sending frame uncompressed: {}",
+                e
+            );
+        }
+        match message.write_to(&mut writer, session.framing()).await {
+            Ok(()) => {}
+            Err(e) if e.is_recoverable() => {
+                warn!(
+                    connection_id = session.connection_id,
+                    message_id:% = message.header.message_id_string();
+                    "Dropping frame for {}: {}",
+                    session.peer_address,
+                    e
+                );
+            }
+            Err(e) => {
+                error!(connection_id = session.connection_id; "Error writing to {}: {}", session.peer_address, e);
+                return;
+            }
+        }
+    }
+}
+
+async fn read_frames(reader: &mut ReadHalf<BoxedStream>, connection: Arc<Connection>) {
+    let mut consecutive_errors: usize = 0;
+    // Clients that predate the handshake open with a legacy frame instead of
+    // a Greeting; the first frame decides the layout for the connection.
+    let mut detected = false;
+
+    loop {
+        let read = if detected {
+            Message::read_from(reader, connection.config.max_body_size, connection.session.framing()).await.map(
+                |message| (message, connection.session.framing())
+            )
+        } else {
+            Message::read_with(reader, connection.config.max_body_size, Framing::detect).await
+        };
+        let message = match read {
+            Ok((message, framing)) => {
+                if !detected {
+                    detected = true;
+                    *connection.session.framing.lock().unwrap() = framing;
+                }
+                consecutive_errors = 0;
+                message
+            }
+            Err(e) if e.is_recoverable() && consecutive_errors < MAX_CONSECUTIVE_FRAME_ERRORS => {
+                consecutive_errors += 1;
+                warn!(
+                    connection_id = connection.session.connection_id;
+                    "Discarding corrupt frame from {}: {}",
+                    connection.session.peer_address,
+                    e
+                );
+                continue;
+            }
+            Err(e) => {
+                info!(connection_id = connection.session.connection_id; "Connection closed: {}", e);
+                return;
+            }
+        };
+
+        match message.header.message_type {
+            MessageType::Greeting => {
+                let capabilities = Capabilities::local(connection.config.max_body_size).with_compression(
+                    &connection.config.compression
+                );
+                let (response, negotiated) = handshake::handle_greeting(&capabilities, &message);
+                let _ = connection.frames.send(response).await;
+                if let Some(negotiated) = negotiated {
+                    *connection.session.protocol.lock().unwrap() = negotiated;
+                }
+            }
+            MessageType::StreamCredit => {
+                apply_stream_credit(&connection.windows, &message);
+            }
+            MessageType::Login => {
+                let response = login(&connection, &message);
+                let _ = connection.frames.send(response).await;
+            }
+            MessageType::UserLogin => {
+                let response = user_login(&connection, &message).await;
+                let _ = connection.frames.send(response).await;
+            }
+            message_type if !connection.session.is_authenticated() => {
+                let response = error_response(
+                    &message,
+                    ErrorCode::AuthenticationRequired,
+                    format!("{} requires a login first", message_type.to_name())
+                );
+                let _ = connection.frames.send(response).await;
+            }
+            message_type if !connection.session.protocol().supports(message_type) => {
+                let response = error_response(
+                    &message,
+                    ErrorCode::UnsupportedMessage,
+                    format!("{} was not negotiated on this connection", message_type.to_name())
+                );
+                let _ = connection.frames.send(response).await;
+            }
+            _ if message.header.chunk_kind() != ChunkKind::Single => {
+                // Upload chunks are handled in order, and credit is only
+                // handed back once the handler is done with a chunk.
+                let stream_id = message.header.message_id;
+                let kind = message.header.chunk_kind();
+                let span = handle_span(&connection, &message);
+                let response = connection.handler.clone().handle(connection.session.clone(), message).instrument(span).await;
+                if !kind.is_final() {
+                    grant_stream_credit(&connection.frames, stream_id, 1).await;
+                }
+                dispatch(connection.clone(), stream_id, response).await;
+            }
+            _ => {
+                let connection = connection.clone();
+                let span = handle_span(&connection, &message);
+                tokio::spawn(
+                    async move {
+                        let stream_id = message.header.message_id;
+                        let response = connection.handler.clone().handle(connection.session.clone(), message).await;
+                        dispatch(connection, stream_id, response).await;
+                    }.instrument(span)
+                );
+            }
+        }
+    }
+}
+
+/// Span for handling `message`, continuing the sender's trace when the
+/// frame carries one.
+fn handle_span(connection: &Connection, message: &Message) -> Span {
+    let span = tracing::info_span!(
+        "zenith.handle",
+        message_type = message.header.message_type.to_name(),
+        connection_id = connection.session.connection_id
+    );
+    if let Some(traceparent) = &message.trace_context {
+        telemetry::set_remote_parent(&span, traceparent);
+    }
+    return span;
+}
+
+fn audit(connection: &Connection, event: AuditEvent) {
+    if let Some(audit) = &connection.config.audit {
+        audit.record(event);
+    }
+}
+
+fn login(connection: &Connection, message: &Message) -> Message {
+    // Peers on the legacy protocol never sent a Greeting and log in without
+    // a nonce or key id.
+    let keys = &connection.config.keys;
+    let decoded = if connection.session.protocol().supports_login_nonce() {
+        LoginStatement::decode(&message.body).map(|stmt| {
+            let verified = connection.verifier.verify(&stmt, keys);
+            (stmt, verified)
+        })
+    } else {
+        LegacyLoginStatement::decode(&message.body).map(|stmt| {
+            let verified = if connection.config.allow_legacy_login {
+                connection.verifier.verify_legacy(&stmt, keys)
+            } else {
+                Err(LoginError::LegacyNotAllowed)
+            };
+            (LoginStatement::from(stmt), verified)
+        })
+    };
+    let (stmt, verified) = match decoded {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            return error_response(message, ErrorCode::InvalidStatement, e.to_string());
+        }
+    };
+
+    if let Err(e) = verified {
+        // A failed re-login must not leave the earlier login in place.
+        connection.session.set_principal(None);
+        warn!(
+            connection_id = connection.session.connection_id;
+            "Authentication failed for node {} from {}: {}",
+            stmt.node_id,
+            connection.session.peer_address,
+            e
+        );
+        audit(connection, AuditEvent::AuthenticationFailed {
+            connection_id: connection.session.connection_id,
+            peer_address: connection.session.peer_address.to_string(),
+            identity: stmt.node_id.clone(),
+            reason: e.to_string(),
+        });
+        metrics().auth_failures.with_label_values(&["node"]).inc();
+        return error_response(message, e.error_code(), e.to_string());
+    }
+
+    info!(
+        connection_id = connection.session.connection_id;
+        "Node {} authenticated from {}",
+        stmt.node_id,
+        connection.session.peer_address
+    );
+    audit(connection, AuditEvent::NodeLogin {
+        connection_id: connection.session.connection_id,
+        peer_address: connection.session.peer_address.to_string(),
+        node_id: stmt.node_id.clone(),
+        node_name: stmt.node_name.clone(),
+        address: stmt.address.clone(),
+        tags: stmt.tags.clone(),
+    });
+    connection.session.set_principal(Some(Principal::Node(stmt.node_id)));
+
+    return Message::new_response(message, MessageType::Login, &EmptyStatement::new(MessageType::Login));
+}
+
+async fn user_login(connection: &Connection, message: &Message) -> Message {
+    let stmt = match UserLoginStatement::decode(&message.body) {
+        Ok(stmt) => stmt,
+        Err(e) => {
+            return error_response(message, ErrorCode::InvalidStatement, e.to_string());
+        }
+    };
+
+    if let Err(error) = connection.handler.authenticate_user(connection.session.clone(), &stmt).await {
+        warn!(
+            connection_id = connection.session.connection_id;
+            "Login failed for user {} from {}",
+            stmt.username,
+            connection.session.peer_address
+        );
+        audit(connection, AuditEvent::AuthenticationFailed {
+            connection_id: connection.session.connection_id,
+            peer_address: connection.session.peer_address.to_string(),
+            identity: stmt.username.clone(),
+            reason: error.message.clone(),
+        });
+        metrics().auth_failures.with_label_values(&["user"]).inc();
+        connection.session.set_principal(None);
+        return Message::new_response(message, MessageType::Error, &error);
+    }
+
+    info!(
+        connection_id = connection.session.connection_id;
+        "User {} authenticated from {}",
+        stmt.username,
+        connection.session.peer_address
+    );
+    audit(connection, AuditEvent::UserLogin {
+        connection_id: connection.session.connection_id,
+        peer_address: connection.session.peer_address.to_string(),
+        username: stmt.username.clone(),
+        database: connection.session.database(),
+    });
+    connection.session.set_principal(Some(Principal::User(stmt.username)));
+
+    return Message::new_response(message, MessageType::UserLogin, &EmptyStatement::new(MessageType::UserLogin));
+}
+
+pub fn error_response(request: &Message, code: ErrorCode, message: String) -> Message {
+    return Message::new_response(request, MessageType::Error, &ErrorStatement::new(code, message));
+}
+
+async fn dispatch(connection: Arc<Connection>, stream_id: [u8; 16], response: HandlerResponse) {
+    match response {
+        HandlerResponse::Reply(message) => {
+            let _ = connection.frames.send(message).await;
+        }
+        HandlerResponse::Stream(frames) if connection.session.framing() == Framing::Legacy => {
+            // The legacy header cannot mark chunks, so the stream goes out
+            // joined into one frame, as `send` would have joined it.
+            let frames: Vec<Message> = frames.collect().await;
+            if let Some(message) = Message::assemble(frames) {
+                let _ = connection.frames.send(message).await;
+            }
+        }
+        HandlerResponse::Stream(mut frames) => {
+            let window = FlowControl::new(INITIAL_STREAM_WINDOW);
+            connection.windows.lock().unwrap().insert(stream_id, window.clone());
+
+            while let Some(frame) = frames.next().await {
+                if frame.header.chunk_kind() != ChunkKind::Single && window.acquire().await.is_err() {
+                    info!(
+                        connection_id = connection.session.connection_id,
+                        message_id:% = Uuid::from_bytes(stream_id);
+                        "Stream cancelled by {}",
+                        connection.session.peer_address
+                    );
+                    break;
+                }
+                if connection.frames.send(frame).await.is_err() {
+                    break;
+                }
+            }
+
+            connection.windows.lock().unwrap().remove(&stream_id);
+        }
+        HandlerResponse::None => {}
+    }
+}
+
+fn apply_stream_credit(windows: &StreamWindows, message: &Message) {
+    let credit = match StreamCreditStatement::decode(&message.body) {
+        Ok(credit) => credit,
+        Err(e) => {
+            warn!(message_id:% = message.header.message_id_string(); "Invalid stream credit: {:?}", e);
+            return;
+        }
+    };
+
+    if let Some(window) = windows.lock().unwrap().get(&message.header.message_id) {
+        if credit.cancel {
+            window.cancel();
+        } else {
+            window.grant(credit.credits);
+        }
+    }
+}
+
+async fn grant_stream_credit(frames: &mpsc::Sender<Message>, stream_id: [u8; 16], credits: u32) {
+    let message = Message::chunk(
+        MessageType::StreamCredit,
+        MessageTypeFlag::ResponseMessage,
+        stream_id,
+        ChunkKind::Single,
+        StreamCreditStatement::new(credits).to_bytes().unwrap()
+    );
+    let _ = frames.send(message).await;
+}
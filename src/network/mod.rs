@@ -1,2 +1,17 @@
+pub mod auth;
+pub use auth::{ LoginError, LoginVerifier, Principal };
+
+pub mod io;
+pub use io::BoxedStream;
+
 pub mod zenith_connection;
-pub use zenith_connection::{ ZenithConnection, dial_timeout };
+pub use zenith_connection::{ ZenithConnection, DialOptions, dial_timeout };
+
+pub mod response_stream;
+pub use response_stream::ResponseStream;
+
+pub mod tls;
+pub use tls::{ ClientTls, ServerTls };
+
+pub mod listener;
+pub use listener::{ HandlerResponse, ListenerConfig, MessageHandler, NodeListener, Session };
@@ -0,0 +1,101 @@
+use std::pin::Pin;
+use std::task::{ Context, Poll };
+use futures::Stream;
+use tokio::sync::mpsc;
+use crate::protocol::MessageType;
+use crate::statement::{ Statement, StreamCreditStatement };
+use crate::transport::{ ChunkKind, Message, MessageTypeFlag };
+use crate::transport::stream::INITIAL_STREAM_WINDOW;
+use super::zenith_connection::{ MessageWithResponse, ResponseSink };
+
+/// The chunks of a streamed response, in arrival order. Credit is handed back
+/// to the peer as chunks are consumed, so a slow consumer slows the sender
+/// down instead of buffering the whole result.
+pub struct ResponseStream {
+    stream_id: [u8; 16],
+    receiver: mpsc::Receiver<Message>,
+    message_sender: mpsc::Sender<MessageWithResponse>,
+    consumed: u32,
+    finished: bool,
+}
+
+#[allow(dead_code)]
+impl ResponseStream {
+    pub(crate) fn new(
+        stream_id: [u8; 16],
+        receiver: mpsc::Receiver<Message>,
+        message_sender: mpsc::Sender<MessageWithResponse>
+    ) -> Self {
+        Self {
+            stream_id,
+            receiver,
+            message_sender,
+            consumed: 0,
+            finished: false,
+        }
+    }
+
+    fn post_credit(&self, stmt: StreamCreditStatement) {
+        let message = Message::chunk(
+            MessageType::StreamCredit,
+            MessageTypeFlag::RequestMessage,
+            self.stream_id,
+            ChunkKind::Single,
+            stmt.to_bytes().unwrap()
+        );
+        let message_with_response = MessageWithResponse {
+            message,
+            response_sender: ResponseSink::None,
+        };
+
+        if let Err(mpsc::error::TrySendError::Full(message_with_response)) =
+            self.message_sender.try_send(message_with_response)
+        {
+            if let Ok(handle) = tokio::runtime::Handle::try_current() {
+                let message_sender = self.message_sender.clone();
+                handle.spawn(async move {
+                    let _ = message_sender.send(message_with_response).await;
+                });
+            }
+        }
+    }
+}
+
+impl Stream for ResponseStream {
+    type Item = Result<Message, Box<dyn std::error::Error + Send + Sync>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.finished {
+            return Poll::Ready(None);
+        }
+
+        match self.receiver.poll_recv(cx) {
+            Poll::Ready(Some(message)) => {
+                if message.header.chunk_kind().is_final() {
+                    self.finished = true;
+                } else {
+                    self.consumed += 1;
+                    if self.consumed >= INITIAL_STREAM_WINDOW / 2 {
+                        let credits = self.consumed;
+                        self.consumed = 0;
+                        self.post_credit(StreamCreditStatement::new(credits));
+                    }
+                }
+                return Poll::Ready(Some(Ok(message)));
+            }
+            Poll::Ready(None) => {
+                self.finished = true;
+                return Poll::Ready(Some(Err("stream interrupted before its last chunk".into())));
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for ResponseStream {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.post_credit(StreamCreditStatement::cancel());
+        }
+    }
+}
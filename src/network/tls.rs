@@ -0,0 +1,244 @@
+use std::fs::{ self, File };
+use std::io::BufReader;
+use std::sync::{ Arc, RwLock };
+use std::time::{ Duration, SystemTime };
+use log::{ info, error };
+use rustls::{ ClientConfig, RootCertStore, ServerConfig };
+use rustls::crypto::{ self, CryptoProvider };
+use rustls::pki_types::{ CertificateDer, PrivateKeyDer, ServerName };
+use rustls::server::WebPkiClientVerifier;
+use tokio_rustls::{ TlsAcceptor, TlsConnector };
+use crate::utils::config::TlsConfig;
+
+type TlsResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(crypto::ring::default_provider())
+}
+
+fn load_certs(path: &str) -> TlsResult<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certs = rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(format!("no certificates found in {}", path).into());
+    }
+    return Ok(certs);
+}
+
+fn load_key(path: &str) -> TlsResult<PrivateKeyDer<'static>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    match rustls_pemfile::private_key(&mut reader)? {
+        Some(key) => Ok(key),
+        None => Err(format!("no private key found in {}", path).into()),
+    }
+}
+
+fn load_roots(path: &str) -> TlsResult<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots.add(cert)?;
+    }
+    return Ok(roots);
+}
+
+fn required<'a>(value: &'a Option<String>, name: &str) -> TlsResult<&'a str> {
+    match value {
+        Some(value) if !value.is_empty() => Ok(value),
+        _ => Err(format!("tls.{} is required", name).into()),
+    }
+}
+
+/// Builds the listener side configuration. With `require_client_cert` every
+/// client must present a certificate signed by the CA.
+pub fn build_server_config(config: &TlsConfig) -> TlsResult<Arc<ServerConfig>> {
+    let certs = load_certs(required(&config.cert_path, "cert_path")?)?;
+    let key = load_key(required(&config.key_path, "key_path")?)?;
+
+    let builder = ServerConfig::builder_with_provider(provider()).with_safe_default_protocol_versions()?;
+
+    let server_config = match &config.ca_path {
+        Some(ca_path) if !ca_path.is_empty() => {
+            let roots = Arc::new(load_roots(ca_path)?);
+            let verifier = WebPkiClientVerifier::builder_with_provider(roots, provider());
+            let verifier = if config.require_client_cert {
+                verifier.build()?
+            } else {
+                verifier.allow_unauthenticated().build()?
+            };
+            builder.with_client_cert_verifier(verifier).with_single_cert(certs, key)?
+        }
+        _ if config.require_client_cert => {
+            return Err("tls.ca_path is required to verify client certificates".into());
+        }
+        _ => builder.with_no_client_auth().with_single_cert(certs, key)?,
+    };
+
+    return Ok(Arc::new(server_config));
+}
+
+/// Builds the dialer side configuration. When a certificate and key are set
+/// they are presented to servers that ask for a client certificate.
+pub fn build_client_config(config: &TlsConfig) -> TlsResult<Arc<ClientConfig>> {
+    let roots = load_roots(required(&config.ca_path, "ca_path")?)?;
+
+    let builder = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots);
+
+    let client_config = match (&config.cert_path, &config.key_path) {
+        (Some(cert_path), Some(key_path)) if !cert_path.is_empty() && !key_path.is_empty() => {
+            builder.with_client_auth_cert(load_certs(cert_path)?, load_key(key_path)?)?
+        }
+        _ => builder.with_no_client_auth(),
+    };
+
+    return Ok(Arc::new(client_config));
+}
+
+/// A TLS configuration that is rebuilt whenever the certificate, key or CA
+/// files change on disk. New connections pick up the current one; existing
+/// connections keep the configuration they were established with.
+pub struct ReloadableTls<T> {
+    config: TlsConfig,
+    current: Arc<RwLock<Arc<T>>>,
+    build: fn(&TlsConfig) -> TlsResult<Arc<T>>,
+}
+
+impl<T> Clone for ReloadableTls<T> {
+    fn clone(&self) -> Self {
+        Self {
+            config: self.config.clone(),
+            current: self.current.clone(),
+            build: self.build,
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for ReloadableTls<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReloadableTls").field("config", &self.config).finish()
+    }
+}
+
+pub type ServerTls = ReloadableTls<ServerConfig>;
+pub type ClientTls = ReloadableTls<ClientConfig>;
+
+#[allow(dead_code)]
+impl<T: Send + Sync + 'static> ReloadableTls<T> {
+    fn new(config: &TlsConfig, build: fn(&TlsConfig) -> TlsResult<Arc<T>>) -> TlsResult<Self> {
+        let current = build(config)?;
+        let tls = Self {
+            config: config.clone(),
+            current: Arc::new(RwLock::new(current)),
+            build,
+        };
+
+        if config.reload_interval_secs > 0 {
+            tls.spawn_watcher(Duration::from_secs(config.reload_interval_secs));
+        }
+
+        return Ok(tls);
+    }
+
+    pub fn current(&self) -> Arc<T> {
+        return self.current.read().unwrap().clone();
+    }
+
+    /// Rebuilds the configuration from disk. On failure the previous
+    /// configuration stays active.
+    pub fn reload(&self) -> TlsResult<()> {
+        let rebuilt = (self.build)(&self.config)?;
+        *self.current.write().unwrap() = rebuilt;
+        return Ok(());
+    }
+
+    fn watched_files(&self) -> Vec<String> {
+        return [&self.config.ca_path, &self.config.cert_path, &self.config.key_path]
+            .into_iter()
+            .flatten()
+            .filter(|path| !path.is_empty())
+            .cloned()
+            .collect();
+    }
+
+    fn last_modified(&self) -> Option<SystemTime> {
+        return self
+            .watched_files()
+            .iter()
+            .filter_map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+            .max();
+    }
+
+    /// Polls the certificate files and reloads them when they change. The
+    /// task only keeps a weak reference to the configuration, and stops once
+    /// every clone of this `ReloadableTls` is dropped.
+    fn spawn_watcher(&self, interval: Duration) {
+        let handle = match tokio::runtime::Handle::try_current() {
+            Ok(handle) => handle,
+            Err(_) => {
+                error!("Certificate reload needs a tokio runtime, reload disabled");
+                return;
+            }
+        };
+
+        let config = self.config.clone();
+        let build = self.build;
+        let current = Arc::downgrade(&self.current);
+        let mut seen = self.last_modified();
+
+        handle.spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let tls = match current.upgrade() {
+                    Some(current) => Self { config: config.clone(), current, build },
+                    None => {
+                        return;
+                    }
+                };
+
+                let modified = tls.last_modified();
+                if modified == seen {
+                    continue;
+                }
+                match tls.reload() {
+                    Ok(_) => {
+                        info!("Reloaded TLS certificates from {:?}", tls.watched_files());
+                        seen = modified;
+                    }
+                    Err(e) => error!("Failed to reload TLS certificates: {}", e),
+                }
+            }
+        });
+    }
+}
+
+#[allow(dead_code)]
+impl ReloadableTls<ServerConfig> {
+    pub fn server(config: &TlsConfig) -> TlsResult<Self> {
+        return Self::new(config, build_server_config);
+    }
+
+    pub fn acceptor(&self) -> TlsAcceptor {
+        return TlsAcceptor::from(self.current());
+    }
+}
+
+#[allow(dead_code)]
+impl ReloadableTls<ClientConfig> {
+    pub fn client(config: &TlsConfig) -> TlsResult<Self> {
+        return Self::new(config, build_client_config);
+    }
+
+    pub fn connector(&self) -> TlsConnector {
+        return TlsConnector::from(self.current());
+    }
+
+    /// Name checked against the server certificate, defaulting to `host`.
+    pub fn server_name(&self, host: &str) -> TlsResult<ServerName<'static>> {
+        let name = match &self.config.server_name {
+            Some(name) if !name.is_empty() => name.clone(),
+            _ => host.to_string(),
+        };
+        return Ok(ServerName::try_from(name)?);
+    }
+}
@@ -3,21 +3,107 @@ use std::sync::atomic::{ AtomicUsize, Ordering };
 use std::sync::{ Arc, Mutex };
 use tokio::net::TcpStream;
 use tokio::sync::Mutex as TokioMutex;
-use std::thread::sleep;
-use std::time::{ self, Duration };
-use tokio::io::{ AsyncReadExt, AsyncWriteExt, ReadHalf };
-use tokio::sync::{ mpsc, oneshot, Notify };
-use log::{ info, warn, error };
-use crate::transport::{ response, Message };
-use std::{ ptr, usize };
+use std::time::Duration;
+use tokio::io::{ AsyncWriteExt, ReadHalf, WriteHalf };
+use tokio::sync::{ mpsc, oneshot };
+use std::pin::Pin;
+use futures::{ stream, Stream, StreamExt };
+use log::{ warn, error };
+use tracing::{ instrument, Span };
+use rmp_serde::decode;
+use uuid::Uuid;
+use crate::transport::{
+    ChunkAssembler,
+    ChunkKind,
+    FlowControl,
+    FrameError,
+    Framing,
+    Message,
+    MessageTypeFlag,
+    Row,
+    RowBatch,
+};
+use crate::transport::stream::{ INITIAL_STREAM_WINDOW, MAX_OPEN_STREAMS };
+use crate::transport::compression::DEFAULT_COMPRESSION_THRESHOLD;
+use crate::protocol::{ MessageType, NegotiatedProtocol };
+use crate::protocol::handshake::DEFAULT_MAX_FRAME_SIZE;
+use crate::utils::{ metrics, telemetry };
+use crate::statement::{
+    validate_alphanumunderscore,
+    BulkInsertStatement,
+    SelectStatement,
+    Statement,
+    StreamCreditStatement,
+};
+use super::{ BoxedStream, ClientTls, ResponseStream };
 
 static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+/// Corrupt frames in a row after which the stream is considered lost.
+const MAX_CONSECUTIVE_FRAME_ERRORS: usize = 8;
+
+/// Where the reader task delivers the frames answering a request.
+#[derive(Debug)]
+pub enum ResponseSink {
+    /// One response; chunked responses are joined before delivery.
+    Single(oneshot::Sender<Message>),
+    /// Every chunk of a streamed response, as it arrives.
+    Stream(mpsc::Sender<Message>),
+    /// Fire and forget, e.g. credit updates and upload continuation chunks.
+    None,
+}
 
 #[allow(dead_code)]
 #[derive(Debug)]
 pub struct MessageWithResponse {
     pub message: Message,
-    pub response_sender: oneshot::Sender<Message>,
+    pub response_sender: ResponseSink,
+}
+
+/// Settings for a dialed connection.
+#[derive(Debug, Clone)]
+pub struct DialOptions {
+    /// Delay between reconnection attempts.
+    pub timeout: Duration,
+    /// Largest frame body accepted from the peer.
+    pub max_body_size: u32,
+    /// Bodies below this size are never compressed.
+    pub compression_threshold: usize,
+    /// Dial over TLS with this configuration instead of plain TCP.
+    pub tls: Option<ClientTls>,
+}
+
+impl Default for DialOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(3),
+            max_body_size: DEFAULT_MAX_FRAME_SIZE,
+            compression_threshold: DEFAULT_COMPRESSION_THRESHOLD,
+            tls: None,
+        }
+    }
+}
+
+type ResponseMap = Arc<Mutex<HashMap<String, ResponseSink>>>;
+type UploadWindows = Arc<Mutex<HashMap<String, FlowControl>>>;
+/// Header layout of the connection, shared with its reader and writer tasks.
+/// `None` until the handshake settled it: frames are then written in the
+/// current layout, which the Greeting needs, and read in whichever layout the
+/// peer answers with.
+type SharedFraming = Arc<Mutex<Option<Framing>>>;
+
+/// Registers the sink awaiting `message_id`, counting it as in flight.
+fn track_response(response_map: &ResponseMap, message_id: String, sink: ResponseSink) {
+    if response_map.lock().unwrap().insert(message_id, sink).is_none() {
+        metrics().in_flight.inc();
+    }
+}
+
+fn untrack_response(response_map: &ResponseMap, message_id: &str) -> Option<ResponseSink> {
+    let sink = response_map.lock().unwrap().remove(message_id);
+    if sink.is_some() {
+        metrics().in_flight.dec();
+    }
+    return sink;
 }
 
 #[derive(Debug)]
@@ -26,6 +112,10 @@ pub struct ZenithConnection {
     require_auth_sender: mpsc::Sender<()>,
     require_auth_receiver: Arc<TokioMutex<mpsc::Receiver<()>>>,
     message_sender: mpsc::Sender<MessageWithResponse>,
+    protocol: Arc<Mutex<NegotiatedProtocol>>,
+    framing: SharedFraming,
+    upload_windows: UploadWindows,
+    compression_threshold: usize,
 }
 
 impl PartialEq for ZenithConnection {
@@ -43,6 +133,10 @@ impl Clone for ZenithConnection {
             message_sender: self.message_sender.clone(),
             require_auth_sender: self.require_auth_sender.clone(),
             require_auth_receiver: self.require_auth_receiver.clone(),
+            protocol: self.protocol.clone(),
+            framing: self.framing.clone(),
+            upload_windows: self.upload_windows.clone(),
+            compression_threshold: self.compression_threshold,
         };
     }
 }
@@ -57,23 +151,80 @@ impl ZenithConnection {
             message_sender,
             require_auth_sender: sender,
             require_auth_receiver: Arc::new(TokioMutex::new(receiver)),
+            protocol: Arc::new(Mutex::new(NegotiatedProtocol::legacy(DEFAULT_MAX_FRAME_SIZE))),
+            framing: Arc::new(Mutex::new(None)),
+            upload_windows: Arc::new(Mutex::new(HashMap::new())),
+            compression_threshold: DEFAULT_COMPRESSION_THRESHOLD,
         };
     }
 
-    pub async fn send(
+    pub fn protocol(&self) -> NegotiatedProtocol {
+        return self.protocol.lock().unwrap().clone();
+    }
+
+    /// Also switches the connection to the header layout of the protocol.
+    pub fn set_protocol(&self, protocol: NegotiatedProtocol) {
+        *self.framing.lock().unwrap() = Some(protocol.framing());
+        *self.protocol.lock().unwrap() = protocol;
+    }
+
+    pub fn framing(&self) -> Framing {
+        return self.framing.lock().unwrap().unwrap_or(Framing::Current);
+    }
+
+    fn check_outgoing(&self, message: &Message) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let message_type = message.header.message_type;
+        let protocol = self.protocol();
+        if !protocol.supports(message_type) {
+            return Err(
+                format!("message type {} was not negotiated with the peer", message_type.to_name()).into()
+            );
+        }
+        if message.header.chunk_kind() != ChunkKind::Single && self.framing() == Framing::Legacy {
+            return Err(Box::new(FrameError::UnsupportedByLegacyFraming("a chunked message")));
+        }
+        if message.body.len() > (protocol.max_frame_size as usize) {
+            return Err(
+                Box::new(FrameError::BodyTooLarge {
+                    body_size: message.body.len() as u32,
+                    max_body_size: protocol.max_frame_size,
+                })
+            );
+        }
+        return Ok(());
+    }
+
+    async fn enqueue(
         &self,
-        message: &Message
-    ) -> Result<Message, Box<dyn std::error::Error + Send + Sync>> {
-        let (response_sender, response_receiver) = oneshot::channel();
+        mut message: Message,
+        response_sender: ResponseSink
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let protocol = self.protocol();
+        if protocol.supports_trace_context() {
+            message.trace_context = telemetry::traceparent(&Span::current());
+        }
+        message.compress(protocol.codec(), self.compression_threshold)?;
 
         let message_with_response = MessageWithResponse {
-            message: message.clone(),
+            message,
             response_sender,
         };
 
         if let Err(e) = self.message_sender.send(message_with_response).await {
             return Err(Box::new(e));
         }
+        return Ok(());
+    }
+
+    #[instrument(name = "zenith.send", skip_all, fields(message_type = message.header.message_type.to_name()))]
+    pub async fn send(
+        &self,
+        message: &Message
+    ) -> Result<Message, Box<dyn std::error::Error + Send + Sync>> {
+        self.check_outgoing(message)?;
+
+        let (response_sender, response_receiver) = oneshot::channel();
+        self.enqueue(message.clone(), ResponseSink::Single(response_sender)).await?;
 
         match response_receiver.await {
             Ok(response) => Ok(response),
@@ -81,6 +232,184 @@ impl ZenithConnection {
         }
     }
 
+    /// Sends a request whose response may be chunked and yields each chunk as
+    /// it arrives.
+    #[instrument(
+        name = "zenith.send_streaming",
+        skip_all,
+        fields(message_type = message.header.message_type.to_name())
+    )]
+    pub async fn send_streaming(
+        &self,
+        message: &Message
+    ) -> Result<ResponseStream, Box<dyn std::error::Error + Send + Sync>> {
+        self.check_outgoing(message)?;
+
+        // The window bounds what the peer may send ahead; the extra room only
+        // absorbs the final chunk racing a credit update.
+        let (chunk_sender, chunk_receiver) = mpsc::channel((INITIAL_STREAM_WINDOW as usize) * 2);
+        self.enqueue(message.clone(), ResponseSink::Stream(chunk_sender)).await?;
+
+        return Ok(
+            ResponseStream::new(message.header.message_id, chunk_receiver, self.message_sender.clone())
+        );
+    }
+
+    /// Uploads a request as a sequence of chunks sharing one message id and
+    /// waits for its response. Each chunk needs a credit from the peer, which
+    /// starts out with `INITIAL_STREAM_WINDOW` of them.
+    #[instrument(name = "zenith.send_chunked", skip_all, fields(message_type = message_type.to_name()))]
+    pub async fn send_chunked<S>(
+        &self,
+        message_type: MessageType,
+        bodies: S
+    ) -> Result<Message, Box<dyn std::error::Error + Send + Sync>>
+        where S: Stream<Item = Vec<u8>> + Unpin
+    {
+        let message_id = *Uuid::new_v4().as_bytes();
+        let stream_key = Uuid::from_bytes(message_id).to_string();
+        let window = FlowControl::new(INITIAL_STREAM_WINDOW);
+        self.upload_windows.lock().unwrap().insert(stream_key.clone(), window.clone());
+        let upload_windows = self.upload_windows.clone();
+        let _cleanup = scopeguard::guard((), move |_| {
+            upload_windows.lock().unwrap().remove(&stream_key);
+        });
+
+        let (response_sender, response_receiver) = oneshot::channel();
+        let mut response_sender = Some(response_sender);
+        let mut bodies = bodies.peekable();
+        let mut sent: usize = 0;
+
+        while let Some(body) = bodies.next().await {
+            let is_last = Pin::new(&mut bodies).peek().await.is_none();
+            let kind = match (sent == 0, is_last) {
+                (true, true) => ChunkKind::Single,
+                (true, false) => ChunkKind::First,
+                (false, false) => ChunkKind::Continuation,
+                (false, true) => ChunkKind::Last,
+            };
+
+            let chunk = Message::chunk(
+                message_type,
+                MessageTypeFlag::RequestMessage,
+                message_id,
+                kind,
+                body
+            );
+            self.check_outgoing(&chunk)?;
+            window.acquire().await?;
+
+            let sink = match response_sender.take() {
+                Some(sender) => ResponseSink::Single(sender),
+                None => ResponseSink::None,
+            };
+            self.enqueue(chunk, sink).await?;
+            sent += 1;
+        }
+
+        if sent == 0 {
+            return Err("nothing to upload".into());
+        }
+
+        match response_receiver.await {
+            Ok(response) => Ok(response),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    /// Runs a `Select` and yields its rows as the result chunks arrive. Each
+    /// chunk of the response carries a MessagePack encoded batch of rows.
+    pub async fn select_rows(
+        &self,
+        stmt: &SelectStatement
+    ) -> Result<
+        impl Stream<Item = Result<Row, Box<dyn std::error::Error + Send + Sync>>>,
+        Box<dyn std::error::Error + Send + Sync>
+    > {
+        let message = Message::new(MessageType::Select, stmt);
+        let responses = self.send_streaming(&message).await?;
+
+        return Ok(
+            responses.flat_map(|response| {
+                let rows: Vec<Result<Row, Box<dyn std::error::Error + Send + Sync>>> = match response {
+                    Ok(message) if message.header.message_type == MessageType::Select => {
+                        match decode::from_slice::<RowBatch>(&message.body) {
+                            Ok(batch) => batch.into_parts().0.into_iter().map(Ok).collect(),
+                            Err(e) => vec![Err(Box::new(e))],
+                        }
+                    }
+                    Ok(message) => {
+                        vec![
+                            Err(
+                                format!(
+                                    "unexpected {} response to a select",
+                                    message.header.message_type.to_name()
+                                ).into()
+                            )
+                        ]
+                    }
+                    Err(e) => vec![Err(e)],
+                };
+                stream::iter(rows)
+            })
+        );
+    }
+
+    /// Runs a paged `Select` and returns one page of rows with the token to
+    /// pass to `SelectStatement::with_after` for the next page; `None` once
+    /// the last page was read.
+    pub async fn select_page(
+        &self,
+        stmt: &SelectStatement
+    ) -> Result<(Vec<Row>, Option<String>), Box<dyn std::error::Error + Send + Sync>> {
+        let message = Message::new(MessageType::Select, stmt);
+        let mut responses = self.send_streaming(&message).await?;
+
+        let mut rows = Vec::new();
+        let mut continuation = None;
+        while let Some(response) = responses.next().await {
+            let message = response?;
+            if message.header.message_type != MessageType::Select {
+                return Err(
+                    format!("unexpected {} response to a select", message.header.message_type.to_name()).into()
+                );
+            }
+            let (batch, token) = decode::from_slice::<RowBatch>(&message.body)?.into_parts();
+            rows.extend(batch);
+            continuation = token.or(continuation);
+        }
+        return Ok((rows, continuation));
+    }
+
+    /// Uploads rows as a chunked `BulkInsert`, `rows_per_chunk` rows per frame,
+    /// without holding the whole upload in memory.
+    pub async fn bulk_insert_stream<S>(
+        &self,
+        table_name: &str,
+        rows: S,
+        rows_per_chunk: usize
+    ) -> Result<Message, Box<dyn std::error::Error + Send + Sync>>
+        where S: Stream<Item = Row> + Unpin
+    {
+        validate_alphanumunderscore(table_name)?;
+
+        let table_name = table_name.to_string();
+        let bodies = rows.chunks(rows_per_chunk.max(1)).map(move |rows| {
+            let stmt = BulkInsertStatement {
+                table_name: table_name.clone(),
+                rows,
+            };
+            stmt.to_bytes().unwrap()
+        });
+
+        return self.send_chunked(MessageType::BulkInsert, bodies).await;
+    }
+
+    /// Asks the connection to log in again, e.g. after the cluster key rotated.
+    pub async fn require_auth(&self) {
+        let _ = self.require_auth_sender.send(()).await;
+    }
+
     pub async fn on_require_auth(&self) {
         let mut require_auth_receiver = self.require_auth_receiver.lock().await;
         require_auth_receiver.recv().await;
@@ -95,18 +424,28 @@ impl ZenithConnection {
 
 pub async fn dial_timeout(
     address: &str,
-    timeout: Duration
+    options: DialOptions
 ) -> Result<ZenithConnection, Box<dyn std::error::Error + Send + Sync>> {
+    let timeout = options.timeout;
+    let max_body_size = options.max_body_size;
     let address_cloned = address.to_string();
     let (message_sender, message_receiver) = mpsc::channel(100);
     let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
     let (sender, receiver) = mpsc::channel(1);
+    let upload_windows: UploadWindows = Arc::new(Mutex::new(HashMap::new()));
+    let framing: SharedFraming = Arc::new(Mutex::new(None));
 
     let config = StartServerConfig {
+        connection_id: id,
         address: address_cloned.clone(),
         timeout,
+        max_body_size,
         message_receiver,
+        message_sender: message_sender.clone(),
         require_auth_sender: sender.clone(),
+        upload_windows: upload_windows.clone(),
+        framing: framing.clone(),
+        tls: options.tls,
     };
 
     tokio::spawn(start_server(config));
@@ -116,37 +455,72 @@ pub async fn dial_timeout(
         message_sender,
         require_auth_sender: sender,
         require_auth_receiver: Arc::new(TokioMutex::new(receiver)),
+        protocol: Arc::new(Mutex::new(NegotiatedProtocol::legacy(max_body_size))),
+        framing,
+        upload_windows: upload_windows.clone(),
+        compression_threshold: options.compression_threshold,
     };
 
     return Ok(conn);
 }
 
 struct StartServerConfig {
+    connection_id: usize,
     address: String,
     timeout: Duration,
+    max_body_size: u32,
     message_receiver: mpsc::Receiver<MessageWithResponse>,
+    message_sender: mpsc::Sender<MessageWithResponse>,
     require_auth_sender: mpsc::Sender<()>,
+    upload_windows: UploadWindows,
+    framing: SharedFraming,
+    tls: Option<ClientTls>,
+}
+
+async fn connect(
+    address: &str,
+    tls: &Option<ClientTls>
+) -> Result<BoxedStream, Box<dyn std::error::Error + Send + Sync>> {
+    let conn = TcpStream::connect(address).await?;
+    let _ = conn.set_nodelay(true);
+
+    let tls = match tls {
+        Some(tls) => tls,
+        None => {
+            return Ok(Box::new(conn));
+        }
+    };
+
+    let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+    let server_name = tls.server_name(host.trim_start_matches('[').trim_end_matches(']'))?;
+    let stream = tls.connector().connect(server_name, conn).await?;
+    return Ok(Box::new(stream));
 }
 
 async fn start_server(config: StartServerConfig) {
+    let connection_id = config.connection_id;
     let address = config.address;
     let timeout = config.timeout;
+    let max_body_size = config.max_body_size;
     let message_receiver: Arc<TokioMutex<mpsc::Receiver<MessageWithResponse>>> = Arc::new(
         TokioMutex::new(config.message_receiver)
     );
-    let response_map = Arc::new(Mutex::new(HashMap::<String, oneshot::Sender<Message>>::new()));
+    let response_map: ResponseMap = Arc::new(Mutex::new(HashMap::new()));
     let require_auth_sender = config.require_auth_sender;
+    let message_sender = config.message_sender;
+    let upload_windows = config.upload_windows;
+    let framing = config.framing;
+    let tls = config.tls;
 
     loop {
-        let conn = match TcpStream::connect(address.to_string()).await {
+        let conn = match connect(&address, &tls).await {
             Ok(conn) => conn,
             Err(e) => {
-                error!("Error connecting to server: {:?}", e);
-                sleep(Duration::from_secs(timeout.as_secs() as u64));
+                error!(connection_id; "Error connecting to server: {:?}", e);
+                tokio::time::sleep(timeout).await;
                 continue;
             }
         };
-        let _ = conn.set_nodelay(true);
 
         let (mut reader, mut writer) = tokio::io::split(conn);
         let (tx_close, rx_close) = oneshot::channel::<()>();
@@ -154,22 +528,38 @@ async fn start_server(config: StartServerConfig) {
         let message_receiver_clone: Arc<TokioMutex<mpsc::Receiver<MessageWithResponse>>> =
             Arc::clone(&message_receiver);
 
-        let response_map_clone = response_map.clone();
+        let reader_context = ReaderContext {
+            connection_id,
+            response_map: response_map.clone(),
+            upload_windows: upload_windows.clone(),
+            message_sender: message_sender.clone(),
+            max_body_size,
+            framing: framing.clone(),
+        };
         tokio::spawn(async move {
-            read_dump(&mut reader, response_map_clone, tx_close).await;
+            read_dump(&mut reader, reader_context, tx_close).await;
         });
 
         let response_map_clone = response_map.clone();
 
-        write_dump(&mut writer, message_receiver_clone, response_map_clone, rx_close).await;
+        write_dump(
+            connection_id,
+            &mut writer,
+            message_receiver_clone,
+            response_map_clone,
+            framing.clone(),
+            rx_close
+        ).await;
         let _ = require_auth_sender.send(()).await;
     }
 }
 
 async fn write_dump(
-    writer: &mut tokio::io::WriteHalf<TcpStream>,
+    connection_id: usize,
+    writer: &mut WriteHalf<BoxedStream>,
     message_receiver: Arc<TokioMutex<mpsc::Receiver<MessageWithResponse>>>,
-    response_map: Arc<Mutex<HashMap<String, oneshot::Sender<Message>>>>,
+    response_map: ResponseMap,
+    framing: SharedFraming,
     rx_close: oneshot::Receiver<()>
 ) {
     let mut message_receiver = message_receiver.lock().await;
@@ -182,51 +572,207 @@ async fn write_dump(
                 break;
             }
             Some(message_with_response) = message_receiver.recv() => {
-                response_map
-                    .lock()
-                    .unwrap()
-                    .insert(
+                let framing = framing.lock().unwrap().unwrap_or(Framing::Current);
+                let serialized = match message_with_response.message.serialize_for(framing) {
+                    Ok(serialized) => serialized,
+                    Err(e) => {
+                        let message_id = message_with_response.message.header.message_id_string();
+                        error!(connection_id, message_id:%; "Dropping message: {}", e);
+                        continue;
+                    }
+                };
+
+                if !matches!(message_with_response.response_sender, ResponseSink::None) {
+                    track_response(
+                        &response_map,
                         message_with_response.message.header.message_id_string(),
                         message_with_response.response_sender
                     );
+                }
 
-                if let Err(e) = writer.write_all(&message_with_response.message.serialize()).await {
-                    error!("Error writing message: {:?}", e);
+                if let Err(e) = writer.write_all(&serialized).await {
+                    let message_id = message_with_response.message.header.message_id_string();
+                    error!(connection_id, message_id:%; "Error writing message: {:?}", e);
                     return;
                 }
+                metrics().bytes_out.inc_by(serialized.len() as u64);
             }
         }
     }
 }
 
+struct ReaderContext {
+    connection_id: usize,
+    response_map: ResponseMap,
+    upload_windows: UploadWindows,
+    message_sender: mpsc::Sender<MessageWithResponse>,
+    max_body_size: u32,
+    framing: SharedFraming,
+}
+
 async fn read_dump(
-    reader: &mut ReadHalf<TcpStream>,
-    response_map: Arc<Mutex<HashMap<String, oneshot::Sender<Message>>>>,
+    reader: &mut ReadHalf<BoxedStream>,
+    context: ReaderContext,
     tx_close: oneshot::Sender<()>
 ) {
+    let mut consecutive_errors: usize = 0;
+    // Chunks of responses awaited through `send`, joined once complete.
+    let mut partial_responses = ChunkAssembler::new(context.max_body_size, MAX_OPEN_STREAMS);
+
     loop {
-        let message = match Message::read_from(reader).await {
-            Ok(message) => message,
+        // The layout is looked up once the frame starts arriving, so a switch
+        // to the legacy framing applies to a read that is already waiting.
+        let framing = context.framing.clone();
+        let read = Message::read_with(reader, context.max_body_size, move |prefix| {
+            framing.lock().unwrap().unwrap_or_else(|| Framing::detect(prefix))
+        }).await;
+        let message = match read {
+            Ok((message, _)) => {
+                consecutive_errors = 0;
+                message
+            }
+            Err(e) if e.is_recoverable() && consecutive_errors < MAX_CONSECUTIVE_FRAME_ERRORS => {
+                consecutive_errors += 1;
+                warn!(connection_id = context.connection_id; "Discarding corrupt frame: {}", e);
+
+                // Fail the waiting request right away instead of letting it hang.
+                if let FrameError::ChecksumMismatch { message_id, .. } = e {
+                    partial_responses.discard(&message_id);
+                    if let Ok(message_id) = Uuid::from_slice(&message_id) {
+                        untrack_response(&context.response_map, &message_id.to_string());
+                    }
+                }
+                continue;
+            }
             Err(e) => {
-                error!("Error reading message: {:?}", e);
+                error!(connection_id = context.connection_id; "Error reading message: {:?}", e);
                 if let Err(e) = tx_close.send(()) {
-                    error!("Error sending close signal: {:?}", e);
+                    error!(connection_id = context.connection_id; "Error sending close signal: {:?}", e);
                 }
                 return;
             }
         };
 
         let message_id = message.header.message_id_string();
-        let response_sender = match response_map.lock().unwrap().remove(&message_id) {
-            Some(sender) => sender,
-            None => {
-                error!("No response sender for message: {:?}", message_id);
+
+        let connection_id = context.connection_id;
+        if message.header.message_type == MessageType::StreamCredit {
+            apply_stream_credit(&context.upload_windows, &message_id, &message);
+            continue;
+        }
+
+        let kind = message.header.chunk_kind();
+        let response_sender = if kind.is_final() {
+            untrack_response(&context.response_map, &message_id)
+        } else {
+            let abandoned = matches!(
+                context.response_map.lock().unwrap().get(&message_id),
+                Some(ResponseSink::Single(sender)) if sender.is_closed()
+            );
+            if abandoned {
+                // The `send` caller gave up: stop the peer instead of buffering for nobody.
+                warn!(connection_id, message_id:%; "Cancelling a response nobody waits for");
+                untrack_response(&context.response_map, &message_id);
+                partial_responses.discard(&message.header.message_id);
+                cancel_stream(&context.message_sender, message.header.message_id);
                 continue;
             }
+
+            let response_map = context.response_map.lock().unwrap();
+            // Borrow a handle to the sink, it stays registered until the last chunk.
+            match response_map.get(&message_id) {
+                Some(ResponseSink::Single(_)) => Some(ResponseSink::None),
+                Some(ResponseSink::Stream(sender)) => Some(ResponseSink::Stream(sender.clone())),
+                _ => None,
+            }
         };
 
-        if let Err(e) = response_sender.send(message.clone()) {
-            error!("Error sending response: {:?}", e);
+        match response_sender {
+            Some(ResponseSink::Single(sender)) => {
+                let message = match partial_responses.push(message) {
+                    Ok(chunks) => chunks.and_then(Message::assemble),
+                    Err(e) => {
+                        error!(connection_id, message_id:%; "Dropping chunked response: {}", e);
+                        None
+                    }
+                };
+                // Dropping the sender fails the waiting request.
+                if let Some(message) = message {
+                    if let Err(e) = sender.send(message) {
+                        error!(connection_id, message_id:%; "Error sending response: {:?}", e);
+                    }
+                }
+            }
+            Some(ResponseSink::None) => {
+                // Intermediate chunk for a `send` caller: buffer it and keep
+                // the peer going, the assembler bounds what is held.
+                let stream_id = message.header.message_id;
+                if let Err(e) = partial_responses.push(message) {
+                    error!(connection_id, message_id:%; "Dropping chunked response: {}", e);
+                    untrack_response(&context.response_map, &message_id);
+                    cancel_stream(&context.message_sender, stream_id);
+                    continue;
+                }
+                grant_stream_credit(&context.message_sender, stream_id, 1);
+            }
+            Some(ResponseSink::Stream(sender)) => {
+                if let Err(e) = sender.try_send(message) {
+                    error!(connection_id, message_id:%; "Dropping stream after a flow control violation: {:?}", e);
+                    untrack_response(&context.response_map, &message_id);
+                }
+            }
+            None => {
+                error!(connection_id, message_id:%; "No response sender for message");
+            }
+        }
+    }
+}
+
+fn apply_stream_credit(upload_windows: &UploadWindows, message_id: &str, message: &Message) {
+    let credit = match StreamCreditStatement::decode(&message.body) {
+        Ok(credit) => credit,
+        Err(e) => {
+            warn!(message_id; "Invalid stream credit: {:?}", e);
+            return;
         }
+    };
+
+    match upload_windows.lock().unwrap().get(message_id) {
+        Some(window) if credit.cancel => window.cancel(),
+        Some(window) => window.grant(credit.credits),
+        None => warn!(message_id; "Stream credit for unknown upload"),
     }
 }
+
+fn grant_stream_credit(
+    message_sender: &mpsc::Sender<MessageWithResponse>,
+    stream_id: [u8; 16],
+    credits: u32
+) {
+    post_stream_credit(message_sender, stream_id, StreamCreditStatement::new(credits));
+}
+
+fn cancel_stream(message_sender: &mpsc::Sender<MessageWithResponse>, stream_id: [u8; 16]) {
+    post_stream_credit(message_sender, stream_id, StreamCreditStatement::cancel());
+}
+
+fn post_stream_credit(
+    message_sender: &mpsc::Sender<MessageWithResponse>,
+    stream_id: [u8; 16],
+    credit: StreamCreditStatement
+) {
+    let message = Message::chunk(
+        MessageType::StreamCredit,
+        MessageTypeFlag::RequestMessage,
+        stream_id,
+        ChunkKind::Single,
+        credit.to_bytes().unwrap()
+    );
+    let message_sender = message_sender.clone();
+    tokio::spawn(async move {
+        let _ = message_sender.send(MessageWithResponse {
+            message,
+            response_sender: ResponseSink::None,
+        }).await;
+    });
+}
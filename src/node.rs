@@ -0,0 +1,232 @@
+use std::sync::{ Arc, OnceLock };
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use log::{ error, info, warn };
+use tokio::net::TcpListener;
+use crate::api::{ admin, cdc, grpc, rest };
+use crate::api::admin::{ AdminState, NodeStatus };
+use crate::api::ServerListener;
+use crate::managment::{ MessageClient, MessageConfig, PoolLimits };
+use crate::network::{ ClientTls, ListenerConfig, NodeListener, ServerTls };
+use crate::storage::{ ChangeFeed, Executor, SlowQueryLog, SpillConfig, StorageEngine };
+use crate::utils::config::Config;
+use crate::utils::{ telemetry, AuditLog, ConfigReloader, KeyRing };
+
+type NodeResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+fn management_client_config(config: &Config, keys: KeyRing) -> NodeResult<MessageConfig> {
+    let management = &config.management;
+    let limits = pool_limits(config);
+    let tls = if config.tls.enabled { Some(ClientTls::client(&config.tls)?) } else { None };
+    return Ok(MessageConfig {
+        server_addr: management.server_addr.clone(),
+        keys,
+        node_id: management.node_id.clone(),
+        address: config.listener.address.clone(),
+        tags: management.tags.clone(),
+        min_conn: limits.min_conn,
+        max_conn: limits.max_conn,
+        timeout: limits.timeout,
+        max_frame_size: config.listener.max_frame_size,
+        compression: config.listener.compression.clone(),
+        compression_threshold: config.listener.compression_threshold,
+        tls,
+    });
+}
+
+fn listener_config(config: &Config, keys: KeyRing, audit: Option<Arc<AuditLog>>) -> NodeResult<ListenerConfig> {
+    let settings = &config.listener;
+    let mut listener = ListenerConfig::new(settings.address.clone(), config.management.node_id.clone(), keys);
+    listener.max_clock_skew = Duration::from_secs(settings.max_clock_skew_secs);
+    listener.allow_legacy_login = settings.allow_legacy_login;
+    listener.max_body_size = settings.max_frame_size;
+    listener.compression = settings.compression.clone();
+    listener.compression_threshold = settings.compression_threshold;
+    listener.tls = if config.tls.enabled { Some(ServerTls::server(&config.tls)?) } else { None };
+    listener.audit = audit;
+    return Ok(listener);
+}
+
+/// Binds `address` and runs `serve` on it in the background, over TLS when
+/// `tls` is set.
+async fn spawn_server<F, Fut, E>(
+    name: &'static str,
+    address: &str,
+    tls: &Option<ServerTls>,
+    serve: F
+) -> NodeResult<()>
+    where
+        F: FnOnce(ServerListener) -> Fut,
+        Fut: std::future::Future<Output = Result<(), E>> + Send + 'static,
+        E: std::fmt::Display
+{
+    let listener = TcpListener::bind(address).await.map_err(|e| format!("cannot bind {} on {}: {}", name, address, e))?;
+    let server = serve(ServerListener::new(listener, tls.clone())?);
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            error!("{} server stopped: {}", name, e);
+        }
+    });
+    return Ok(());
+}
+
+fn spawn_compaction(executor: Arc<Executor>, interval_secs: u64) {
+    if interval_secs == 0 {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+        // The first tick completes immediately; there is nothing to compact yet.
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let stats = executor.engine().compact();
+            info!("Periodic compaction: {:?}", stats);
+        }
+    });
+}
+
+fn pool_limits(config: &Config) -> PoolLimits {
+    let pool = &config.management.pool;
+    return PoolLimits::new(pool.min_connections, pool.max_connections, Duration::from_secs(pool.connect_timeout_secs));
+}
+
+/// Applies reloaded cluster keys, pool limits and slow query settings, and
+/// reloads `reloader` on SIGHUP and, if configured, when its file changes.
+pub fn watch_reloads(
+    reloader: &Arc<ConfigReloader>,
+    keys: KeyRing,
+    client: Arc<OnceLock<MessageClient>>,
+    slow_queries: Arc<SlowQueryLog>
+) -> NodeResult<()> {
+    reloader.on_reload(
+        Box::new(move |config| {
+            slow_queries.configure(&config.slow_query_log);
+            if let Err(e) = keys.update_from_management(&config.management) {
+                warn!("Keeping the current cluster keys: {}", e);
+            }
+            if let Some(client) = client.get() {
+                let limits = pool_limits(config);
+                if client.limits() != limits {
+                    client.set_limits(limits);
+                }
+            }
+        })
+    );
+
+    let interval = reloader.active().reload.watch_interval_secs;
+    if interval > 0 {
+        reloader.watch(Duration::from_secs(interval));
+    }
+    reloader.watch_signals()?;
+    return Ok(());
+}
+
+/// Starts every component the active config of `reloader` enables and
+/// serves the binary protocol until the process is interrupted. The config
+/// file is watched and reloaded while the node runs.
+pub async fn run(reloader: Arc<ConfigReloader>) -> NodeResult<()> {
+    let config = reloader.active();
+    let provider = telemetry::init(&config.telemetry).map_err(|e| format!("cannot start telemetry: {}", e))?;
+    let status = Arc::new(NodeStatus::new());
+
+    let mut engine = StorageEngine::new().with_max_size((config.storage.max_size_mb * 1024 * 1024) as usize);
+    if config.cdc.enabled {
+        engine = engine.with_change_feed(Arc::new(ChangeFeed::new(config.cdc.retained_changes)));
+    }
+
+    let audit = AuditLog::from_config(&config.audit, &config.storage)
+        .map_err(|e| format!("cannot open the audit log: {}", e))?
+        .map(Arc::new);
+    let keys = KeyRing::from_management(&config.management)?;
+    let slow_queries = Arc::new(SlowQueryLog::new(config.slow_query_log.clone()));
+    let mut executor = Executor::new(Arc::new(engine))
+        .with_slow_query_log(slow_queries.clone())
+        .with_spill(SpillConfig::from_config(&config.query, &config.storage))
+        .with_token_keys(keys.clone());
+    if let Some(audit) = &audit {
+        executor = executor.with_audit(audit.clone());
+    }
+    let executor = Arc::new(executor);
+    // Storage is in memory and starts empty: it is open once the catalog
+    // and the audit log are. There is no write-ahead log to replay yet.
+    status.set_storage_open(true);
+
+    let management = &config.management;
+    if let Some(url) = management.keys_url.as_ref().filter(|url| !url.is_empty()) {
+        if management.key_refresh_interval_secs > 0 {
+            keys.watch_management(url.clone(), Duration::from_secs(management.key_refresh_interval_secs));
+        }
+    }
+
+    let listener_config = listener_config(&config, keys.clone(), audit)?;
+    // The API servers share the certificates of the binary protocol.
+    let tls = listener_config.tls.clone();
+    let listener = NodeListener::bind(listener_config, executor.clone()).await.map_err(|e| {
+        format!("cannot bind the listener on {}: {}", config.listener.address, e)
+    })?;
+    info!("Node {} listening on {}", config.management.node_id, listener.local_addr()?);
+    let draining = listener.draining();
+    let admin_state = AdminState::new(
+        executor.clone(),
+        status.clone(),
+        admin::snapshot_directory(&config.admin, &config.storage)
+    )
+        .with_listener(listener.active_connections(), draining.clone())
+        .with_config(reloader.clone());
+
+    let client = Arc::new(OnceLock::new());
+    watch_reloads(&reloader, keys.clone(), client.clone(), slow_queries)?;
+    let client_config = management_client_config(&config, keys)?;
+    let client_state = admin_state.clone();
+    let client_reloader = reloader.clone();
+    tokio::spawn(async move {
+        match MessageClient::new(client_config).await {
+            Ok(started) => {
+                // Limits may have been reloaded while the pool was filling.
+                let limits = pool_limits(&client_reloader.active());
+                if started.limits() != limits {
+                    started.set_limits(limits);
+                }
+                client_state.set_client(started.clone());
+                let _ = client.set(started);
+            }
+            Err(e) => error!("Management client failed to start: {}", e),
+        }
+    });
+
+    if config.api.enabled {
+        let executor = executor.clone();
+        spawn_server("REST API", &config.api.address, &tls, move |listener| rest::serve(listener, executor)).await?;
+    }
+    if config.grpc.enabled {
+        let executor = executor.clone();
+        spawn_server("gRPC", &config.grpc.address, &tls, move |listener| grpc::serve(listener, executor)).await?;
+    }
+    if config.cdc.enabled {
+        let executor = executor.clone();
+        spawn_server("CDC", &config.cdc.address, &tls, move |listener| cdc::serve(listener, executor)).await?;
+    }
+    if config.admin.enabled {
+        spawn_server("admin", &config.admin.address, &tls, move |listener| admin::serve(listener, admin_state)).await?;
+    }
+    spawn_compaction(executor, config.storage.compaction_interval_secs);
+
+    tokio::select! {
+        _ = listener.serve() => {}
+        signal = tokio::signal::ctrl_c() => {
+            if let Err(e) = signal {
+                warn!("Cannot listen for shutdown signals: {}", e);
+            }
+            info!("Shutting down node {}", config.management.node_id);
+            draining.store(true, Ordering::SeqCst);
+        }
+    }
+
+    if let Some(provider) = provider {
+        if let Err(e) = provider.shutdown() {
+            warn!("Failed to flush pending spans: {}", e);
+        }
+    }
+    return Ok(());
+}
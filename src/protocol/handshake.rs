@@ -0,0 +1,286 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use crate::protocol::MessageType;
+use crate::statement::{ GreetingStatement, WelcomeStatement };
+use crate::transport::{ Codec, Framing, Message };
+use crate::transport::compression::SUPPORTED_CODECS;
+
+/// Highest protocol version spoken by this node.
+pub const PROTOCOL_VERSION: u32 = 3;
+/// Oldest protocol version this node still accepts from a peer.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+/// Version assumed for peers that predate the Greeting/Welcome handshake.
+pub const LEGACY_PROTOCOL_VERSION: u32 = 1;
+/// First version framed with the versioned, checksummed header. A Greeting is
+/// already sent in that layout, so a handshake never settles below it.
+pub const FRAMED_PROTOCOL_VERSION: u32 = 2;
+/// First version whose logins carry a nonce and a key id under their
+/// signature; see `LoginStatement` and `LegacyLoginStatement`.
+pub const LOGIN_NONCE_VERSION: u32 = 2;
+/// First version whose frames may carry a trace context extension.
+pub const TRACE_CONTEXT_VERSION: u32 = 3;
+pub const DEFAULT_MAX_FRAME_SIZE: u32 = 16 * 1024 * 1024;
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum HandshakeError {
+    IncompatibleVersion {
+        local_min: u32,
+        local_max: u32,
+        remote_min: u32,
+        remote_max: u32,
+    },
+    Rejected(String),
+    InvalidWelcome(String),
+    Decode(String),
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandshakeError::IncompatibleVersion { local_min, local_max, remote_min, remote_max } =>
+                write!(
+                    f,
+                    "incompatible protocol versions: local {}..={}, remote {}..={}",
+                    local_min,
+                    local_max,
+                    remote_min,
+                    remote_max
+                ),
+            HandshakeError::Rejected(reason) => write!(f, "handshake rejected: {}", reason),
+            HandshakeError::InvalidWelcome(reason) => write!(f, "invalid welcome: {}", reason),
+            HandshakeError::Decode(reason) => write!(f, "invalid handshake payload: {}", reason),
+        }
+    }
+}
+
+impl Error for HandshakeError {}
+
+/// What one side of a connection is able to speak.
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    pub protocol_version: u32,
+    pub min_protocol_version: u32,
+    pub message_types: Vec<MessageType>,
+    pub compression: Vec<String>,
+    pub max_frame_size: u32,
+}
+
+/// The common ground both peers agreed on for the lifetime of a connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NegotiatedProtocol {
+    pub protocol_version: u32,
+    pub message_types: HashSet<MessageType>,
+    pub compression: Vec<String>,
+    pub max_frame_size: u32,
+}
+
+#[allow(dead_code)]
+impl Capabilities {
+    pub fn local(max_frame_size: u32) -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+            message_types: MessageType::all().to_vec(),
+            compression: SUPPORTED_CODECS.iter()
+                .map(|codec| codec.to_string())
+                .collect(),
+            max_frame_size,
+        }
+    }
+
+    /// Restricts the offered codecs to `codecs`, keeping their order as the
+    /// preference. Unknown names are ignored; an empty list disables compression.
+    pub fn with_compression(mut self, codecs: &[String]) -> Self {
+        self.compression = codecs
+            .iter()
+            .filter(|codec| SUPPORTED_CODECS.contains(&codec.as_str()))
+            .cloned()
+            .collect();
+        return self;
+    }
+
+    pub fn to_greeting(&self, node_id: &str) -> GreetingStatement {
+        GreetingStatement {
+            protocol_version: self.protocol_version,
+            min_protocol_version: self.min_protocol_version,
+            message_types: self.message_types
+                .iter()
+                .map(|t| t.to_u32())
+                .collect(),
+            compression: self.compression.clone(),
+            max_frame_size: self.max_frame_size,
+            node_id: node_id.to_string(),
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl NegotiatedProtocol {
+    /// Settings used when the peer does not answer the Greeting with a Welcome,
+    /// so that nodes on the new version can still talk to ones that are not
+    /// upgraded yet during a rolling restart.
+    pub fn legacy(max_frame_size: u32) -> Self {
+        Self {
+            protocol_version: LEGACY_PROTOCOL_VERSION,
+            message_types: MessageType::all().iter().cloned().collect(),
+            compression: Vec::new(),
+            max_frame_size,
+        }
+    }
+
+    /// The codec to compress outgoing bodies with: the first agreed one.
+    pub fn codec(&self) -> Codec {
+        return self.compression
+            .iter()
+            .find_map(|name| Codec::from_name(name))
+            .unwrap_or(Codec::None);
+    }
+
+    /// Header layout to frame messages with. Only peers that never answered
+    /// a Greeting are on the legacy protocol, and they only read the legacy
+    /// header.
+    pub fn framing(&self) -> Framing {
+        if self.protocol_version < FRAMED_PROTOCOL_VERSION {
+            return Framing::Legacy;
+        }
+        return Framing::Current;
+    }
+
+    /// Whether logins are sent as `LoginStatement`, with a nonce and key id,
+    /// rather than as `LegacyLoginStatement`.
+    pub fn supports_login_nonce(&self) -> bool {
+        return self.protocol_version >= LOGIN_NONCE_VERSION;
+    }
+
+    /// Whether requests may carry `Message::trace_context`; older peers
+    /// would not know where the body starts.
+    pub fn supports_trace_context(&self) -> bool {
+        return self.protocol_version >= TRACE_CONTEXT_VERSION;
+    }
+
+    pub fn supports(&self, message_type: MessageType) -> bool {
+        if is_handshake_type(message_type) {
+            return true;
+        }
+        return self.message_types.contains(&message_type);
+    }
+}
+
+/// Message types that must always be accepted, whatever was negotiated.
+pub fn is_handshake_type(message_type: MessageType) -> bool {
+    matches!(
+        message_type,
+        MessageType::Greeting |
+            MessageType::Welcome |
+            MessageType::Login |
+            MessageType::Error |
+            MessageType::UnknownCommand
+    )
+}
+
+/// Server side: picks the common settings for an incoming Greeting.
+pub fn negotiate(
+    local: &Capabilities,
+    greeting: &GreetingStatement
+) -> Result<WelcomeStatement, HandshakeError> {
+    let version = local.protocol_version.min(greeting.protocol_version);
+    if
+        version < FRAMED_PROTOCOL_VERSION ||
+        version < local.min_protocol_version ||
+        version < greeting.min_protocol_version
+    {
+        return Err(HandshakeError::IncompatibleVersion {
+            local_min: local.min_protocol_version,
+            local_max: local.protocol_version,
+            remote_min: greeting.min_protocol_version,
+            remote_max: greeting.protocol_version,
+        });
+    }
+
+    let message_types: Vec<u32> = local.message_types
+        .iter()
+        .map(|t| t.to_u32())
+        .filter(|id| greeting.message_types.contains(id))
+        .collect();
+
+    // The client lists codecs in order of preference, so keep its ordering.
+    let compression: Vec<String> = greeting.compression
+        .iter()
+        .filter(|codec| local.compression.contains(codec))
+        .cloned()
+        .collect();
+
+    let max_frame_size = local.max_frame_size.min(greeting.max_frame_size);
+
+    return Ok(WelcomeStatement::accept(version, message_types, compression, max_frame_size));
+}
+
+/// Server side: builds the Welcome response for a Greeting request message.
+pub fn handle_greeting(local: &Capabilities, request: &Message) -> (Message, Option<NegotiatedProtocol>) {
+    let welcome = match GreetingStatement::decode(&request.body) {
+        Ok(greeting) =>
+            match negotiate(local, &greeting) {
+                Ok(welcome) => welcome,
+                Err(e) => WelcomeStatement::reject(e.to_string()),
+            }
+        Err(e) => WelcomeStatement::reject(HandshakeError::Decode(e.to_string()).to_string()),
+    };
+
+    let negotiated = if welcome.accepted {
+        accept_welcome(local, &welcome).ok()
+    } else {
+        None
+    };
+
+    return (Message::new_response(request, MessageType::Welcome, &welcome), negotiated);
+}
+
+/// Client side: checks that the Welcome only contains settings we offered.
+pub fn accept_welcome(
+    local: &Capabilities,
+    welcome: &WelcomeStatement
+) -> Result<NegotiatedProtocol, HandshakeError> {
+    if !welcome.accepted {
+        return Err(HandshakeError::Rejected(welcome.reason.clone()));
+    }
+
+    if
+        welcome.protocol_version < FRAMED_PROTOCOL_VERSION ||
+        welcome.protocol_version < local.min_protocol_version ||
+        welcome.protocol_version > local.protocol_version
+    {
+        return Err(HandshakeError::IncompatibleVersion {
+            local_min: local.min_protocol_version,
+            local_max: local.protocol_version,
+            remote_min: welcome.protocol_version,
+            remote_max: welcome.protocol_version,
+        });
+    }
+
+    if welcome.max_frame_size == 0 || welcome.max_frame_size > local.max_frame_size {
+        return Err(
+            HandshakeError::InvalidWelcome(
+                format!("max frame size {} not acceptable", welcome.max_frame_size)
+            )
+        );
+    }
+
+    if let Some(codec) = welcome.compression.iter().find(|c| !local.compression.contains(c)) {
+        return Err(HandshakeError::InvalidWelcome(format!("codec {} was not offered", codec)));
+    }
+
+    let message_types = welcome.message_types
+        .iter()
+        .map(|id| MessageType::from_id(*id))
+        .filter(|t| local.message_types.contains(t))
+        .collect();
+
+    return Ok(NegotiatedProtocol {
+        protocol_version: welcome.protocol_version,
+        message_types,
+        compression: welcome.compression.clone(),
+        max_frame_size: welcome.max_frame_size,
+    });
+}
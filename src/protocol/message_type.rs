@@ -8,6 +8,7 @@ pub enum MessageType {
     CreateDatabase = 1,
     DropDatabase = 2,
     ShowDatabases = 3,
+    UseDatabase = 4,
 
     // Table Operations
     CreateTable = 10,
@@ -40,24 +41,43 @@ pub enum MessageType {
 
     // Authentication & User Management
     Login = 50,
+    CreateUser = 51,
+    DropUser = 52,
+    CreateRole = 53,
+    Grant = 54,
+    Revoke = 55,
+    UserLogin = 56,
+
+    // Diagnostics
+    ShowSlowQueries = 60,
+    Explain = 61,
+
+    // SQL
+    Query = 70,
+    Prepare = 71,
+    Execute = 72,
+    Deallocate = 73,
 
     // Utility Commands
     Ping = 90,
     Pong = 91,
     Greeting = 92,
     Welcome = 93,
+    StreamCredit = 94,
+    Error = 95,
     UnknownCommand = 255,
 }
 
 impl MessageType {
-    pub fn to_u32(&self) -> u32 {
-        *self as u32
+    pub fn to_u32(self) -> u32 {
+        self as u32
     }
     pub fn from_id(id: u32) -> Self {
         match id {
             1 => MessageType::CreateDatabase,
             2 => MessageType::DropDatabase,
             3 => MessageType::ShowDatabases,
+            4 => MessageType::UseDatabase,
 
             10 => MessageType::CreateTable,
             11 => MessageType::DropTable,
@@ -85,21 +105,38 @@ impl MessageType {
             44 => MessageType::ReleaseSavepoint,
 
             50 => MessageType::Login,
+            51 => MessageType::CreateUser,
+            52 => MessageType::DropUser,
+            53 => MessageType::CreateRole,
+            54 => MessageType::Grant,
+            55 => MessageType::Revoke,
+            56 => MessageType::UserLogin,
+
+            60 => MessageType::ShowSlowQueries,
+            61 => MessageType::Explain,
+
+            70 => MessageType::Query,
+            71 => MessageType::Prepare,
+            72 => MessageType::Execute,
+            73 => MessageType::Deallocate,
 
             90 => MessageType::Ping,
             91 => MessageType::Pong,
             92 => MessageType::Greeting,
             93 => MessageType::Welcome,
+            94 => MessageType::StreamCredit,
+            95 => MessageType::Error,
 
             _ => MessageType::UnknownCommand,
         }
     }
 
-    pub fn to_name(&self) -> &'static str {
+    pub fn to_name(self) -> &'static str {
         match self {
             MessageType::CreateDatabase => "CreateDatabase",
             MessageType::DropDatabase => "DropDatabase",
             MessageType::ShowDatabases => "ShowDatabases",
+            MessageType::UseDatabase => "UseDatabase",
 
             MessageType::CreateTable => "CreateTable",
             MessageType::DropTable => "DropTable",
@@ -127,23 +164,96 @@ impl MessageType {
             MessageType::ReleaseSavepoint => "ReleaseSavepoint",
 
             MessageType::Login => "Login",
+            MessageType::CreateUser => "CreateUser",
+            MessageType::DropUser => "DropUser",
+            MessageType::CreateRole => "CreateRole",
+            MessageType::Grant => "Grant",
+            MessageType::Revoke => "Revoke",
+            MessageType::UserLogin => "UserLogin",
+
+            MessageType::ShowSlowQueries => "ShowSlowQueries",
+            MessageType::Explain => "Explain",
+
+            MessageType::Query => "Query",
+            MessageType::Prepare => "Prepare",
+            MessageType::Execute => "Execute",
+            MessageType::Deallocate => "Deallocate",
 
             MessageType::Ping => "Ping",
             MessageType::Pong => "Pong",
             MessageType::Greeting => "Greeting",
             MessageType::Welcome => "Welcome",
+            MessageType::StreamCredit => "StreamCredit",
+            MessageType::Error => "Error",
 
             MessageType::UnknownCommand => "UnknownCommand",
         }
     }
 }
 
+const ALL_MESSAGE_TYPES: [MessageType; 45] = [
+    MessageType::CreateDatabase,
+    MessageType::DropDatabase,
+    MessageType::ShowDatabases,
+    MessageType::UseDatabase,
+
+    MessageType::CreateTable,
+    MessageType::DropTable,
+    MessageType::AlterTable,
+    MessageType::RenameTable,
+    MessageType::TruncateTable,
+    MessageType::ShowTables,
+    MessageType::DescribeTable,
+
+    MessageType::CreateIndex,
+    MessageType::DropIndex,
+    MessageType::ShowIndexes,
+
+    MessageType::Insert,
+    MessageType::Select,
+    MessageType::Update,
+    MessageType::Delete,
+    MessageType::BulkInsert,
+    MessageType::Upsert,
+
+    MessageType::BeginTransaction,
+    MessageType::Commit,
+    MessageType::Rollback,
+    MessageType::Savepoint,
+    MessageType::ReleaseSavepoint,
+
+    MessageType::Login,
+    MessageType::CreateUser,
+    MessageType::DropUser,
+    MessageType::CreateRole,
+    MessageType::Grant,
+    MessageType::Revoke,
+    MessageType::UserLogin,
+
+    MessageType::ShowSlowQueries,
+    MessageType::Explain,
+
+    MessageType::Query,
+    MessageType::Prepare,
+    MessageType::Execute,
+    MessageType::Deallocate,
+
+    MessageType::Ping,
+    MessageType::Pong,
+    MessageType::Greeting,
+    MessageType::Welcome,
+    MessageType::StreamCredit,
+    MessageType::Error,
+    MessageType::UnknownCommand,
+];
+
 lazy_static! {
     static ref MESSAGE_TYPE_LOOKUP: HashMap<&'static str, MessageType> = {
         let mut map = HashMap::new();
         map.insert("CreateDatabase", MessageType::CreateDatabase);
         map.insert("DropDatabase", MessageType::DropDatabase);
         map.insert("ShowDatabases", MessageType::ShowDatabases);
+        map.insert("UseDatabase", MessageType::UseDatabase);
 
         map.insert("CreateTable", MessageType::CreateTable);
         map.insert("DropTable", MessageType::DropTable);
@@ -171,11 +281,27 @@ lazy_static! {
         map.insert("ReleaseSavepoint", MessageType::ReleaseSavepoint);
 
         map.insert("Login", MessageType::Login);
+        map.insert("CreateUser", MessageType::CreateUser);
+        map.insert("DropUser", MessageType::DropUser);
+        map.insert("CreateRole", MessageType::CreateRole);
+        map.insert("Grant", MessageType::Grant);
+        map.insert("Revoke", MessageType::Revoke);
+        map.insert("UserLogin", MessageType::UserLogin);
+
+        map.insert("ShowSlowQueries", MessageType::ShowSlowQueries);
+        map.insert("Explain", MessageType::Explain);
+
+        map.insert("Query", MessageType::Query);
+        map.insert("Prepare", MessageType::Prepare);
+        map.insert("Execute", MessageType::Execute);
+        map.insert("Deallocate", MessageType::Deallocate);
 
         map.insert("Ping", MessageType::Ping);
         map.insert("Pong", MessageType::Pong);
         map.insert("Greeting", MessageType::Greeting);
         map.insert("Welcome", MessageType::Welcome);
+        map.insert("StreamCredit", MessageType::StreamCredit);
+        map.insert("Error", MessageType::Error);
 
         map.insert("UnknownCommand", MessageType::UnknownCommand);
         map
@@ -183,6 +309,10 @@ lazy_static! {
 }
 
 impl MessageType {
+    pub fn all() -> &'static [MessageType] {
+        &ALL_MESSAGE_TYPES
+    }
+
     pub fn from_name(name: &str) -> Self {
         MESSAGE_TYPE_LOOKUP.get(name).cloned().unwrap_or(MessageType::UnknownCommand)
     }
@@ -1,2 +1,5 @@
 pub mod message_type;
-pub use message_type::MessageType;
\ No newline at end of file
+pub use message_type::MessageType;
+
+pub mod handshake;
+pub use handshake::{ Capabilities, NegotiatedProtocol, HandshakeError };
@@ -0,0 +1,30 @@
+use std::error::Error;
+use std::fmt;
+
+/// A statement that could not be compiled, with the position, counted from
+/// 1, of the token where it went wrong.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SqlError {
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl SqlError {
+    /// An error at byte `offset` of `sql`.
+    pub fn at(sql: &str, offset: usize, message: String) -> Self {
+        let before = &sql[..offset.min(sql.len())];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        let column = before[line_start..].chars().count() + 1;
+        return Self { message, line, column };
+    }
+}
+
+impl fmt::Display for SqlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at line {}, column {}", self.message, self.line, self.column)
+    }
+}
+
+impl Error for SqlError {}
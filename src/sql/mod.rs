@@ -0,0 +1,5 @@
+pub mod error;
+pub use error::SqlError;
+
+pub mod parser;
+pub use parser::{ parse, parse_prepared, PreparedSql, ValueSlot };
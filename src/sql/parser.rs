@@ -0,0 +1,776 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use serde_json::Value;
+use uuid::Uuid;
+use crate::protocol::MessageType;
+use crate::statement::*;
+use crate::statement::explain_statement::EXPLAINABLE;
+use crate::storage::expression::{ tokenize_with_spans, Expression, ExpressionError, Parser, Span, Token };
+use super::SqlError;
+
+/// A value written by a prepared statement that comes from a placeholder.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValueSlot {
+    /// Row of an insert; 0 for the other statements.
+    pub row: usize,
+    pub column: String,
+    pub parameter: usize,
+}
+
+/// One statement compiled for Prepare. Placeholders in its conditions are
+/// written `$n`; the values it writes that are placeholders are NULL in
+/// `statement` and listed in `slots`.
+pub struct PreparedSql {
+    pub statement: Box<dyn Statement>,
+    /// Values an Execute binds, one per placeholder number.
+    pub parameters: usize,
+    pub slots: Vec<ValueSlot>,
+}
+
+/// Compiles a script of `;` separated statements into the statements a
+/// client would otherwise build by hand. Clauses such as WHERE, ON and
+/// HAVING are checked here and carried over as written.
+pub fn parse(sql: &str) -> Result<Vec<Box<dyn Statement>>, SqlError> {
+    let (tokens, spans) = tokenize(sql)?;
+    return Compiler::new(sql, &tokens, spans, false).script();
+}
+
+/// Compiles the single statement of a Prepare, which may have `?` or `$n`
+/// placeholders wherever a value goes.
+pub fn parse_prepared(sql: &str) -> Result<PreparedSql, SqlError> {
+    let (tokens, spans) = tokenize(sql)?;
+    let mut compiler = Compiler::new(sql, &tokens, spans, true);
+    let mut statements = compiler.script()?;
+    if statements.len() > 1 {
+        return Err(SqlError::at(sql, 0, "a prepared statement must be a single statement".to_string()));
+    }
+    let parameters = tokens
+        .iter()
+        .filter_map(|token| match token {
+            Token::Parameter(n) => Some(n + 1),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0);
+    return Ok(PreparedSql { statement: statements.remove(0), parameters, slots: compiler.slots });
+}
+
+fn tokenize(sql: &str) -> Result<(Vec<Token>, Vec<Span>), SqlError> {
+    return Ok(
+        tokenize_with_spans(sql)
+            .map_err(|(e, offset)| SqlError::at(sql, offset, e.0))?
+            .into_iter()
+            .unzip()
+    );
+}
+
+struct Compiler<'a> {
+    sql: &'a str,
+    tokens: &'a [Token],
+    spans: Vec<Span>,
+    parser: Parser<'a>,
+    /// Transaction opened by a BEGIN earlier in the script and not ended
+    /// yet; the default of the statements that need one.
+    transaction: Option<String>,
+    /// Whether values may be placeholders, collected in `slots`.
+    prepared: bool,
+    slots: Vec<ValueSlot>,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(sql: &'a str, tokens: &'a [Token], spans: Vec<Span>, prepared: bool) -> Self {
+        let parser = Parser::new(tokens);
+        return Self { sql, tokens, spans, parser, transaction: None, prepared, slots: Vec::new() };
+    }
+
+    fn script(&mut self) -> Result<Vec<Box<dyn Statement>>, SqlError> {
+        let mut statements = Vec::new();
+        while !self.parser.is_done() {
+            if self.parser.eat_symbol(";") {
+                continue;
+            }
+            statements.push(self.statement()?);
+            if !self.parser.is_done() {
+                self.symbol(";")?;
+            }
+        }
+        if statements.is_empty() {
+            return Err(self.error("expected a statement"));
+        }
+        return Ok(statements);
+    }
+
+    fn statement(&mut self) -> Result<Box<dyn Statement>, SqlError> {
+        let start = self.parser.position();
+        let keyword = match self.parser.peek() {
+            Some(Token::Identifier(word)) => word.to_ascii_uppercase(),
+            _ => {
+                return Err(self.error("expected a statement"));
+            }
+        };
+        self.parser.next_token();
+
+        let statement: Box<dyn Statement> = match keyword.as_str() {
+            "CREATE" => self.create(start)?,
+            "DROP" => self.drop(start)?,
+            "ALTER" => self.alter(start)?,
+            "USE" => {
+                let database = self.identifier()?;
+                Box::new(self.checked(start, UseDatabaseStatement::new(database))?)
+            }
+            "TRUNCATE" => {
+                self.parser.eat_keyword("TABLE");
+                let table = self.identifier()?;
+                Box::new(self.checked(start, TruncateTableStatement::new(table))?)
+            }
+            "SHOW" => self.show(start)?,
+            "DESCRIBE" | "DESC" => {
+                let table = self.identifier()?;
+                Box::new(self.checked(start, DescribeTableStatement::new(table))?)
+            }
+            "EXPLAIN" => {
+                let analyze = self.parser.eat_keyword("ANALYZE");
+                let explained = self.parser.position();
+                let statement = self.statement()?;
+                if !EXPLAINABLE.contains(&statement.protocol()) {
+                    return Err(self.error_at(explained, "only data statements can be explained"));
+                }
+                Box::new(self.checked(explained, ExplainStatement::new(statement.as_ref(), analyze))?)
+            }
+            "SELECT" => Box::new(self.select(start)?),
+            "INSERT" => self.insert(start)?,
+            "UPDATE" => self.update(start)?,
+            "DELETE" => {
+                self.keyword("FROM")?;
+                let table = self.identifier()?;
+                let r#where = if self.parser.eat_keyword("WHERE") { Some(self.clause()?) } else { None };
+                Box::new(self.checked(start, DeleteStatement::new(table, r#where))?)
+            }
+            "BEGIN" | "START" => {
+                if keyword == "START" {
+                    self.keyword("TRANSACTION")?;
+                } else if !self.parser.eat_keyword("TRANSACTION") {
+                    self.parser.eat_keyword("WORK");
+                }
+                let transaction = match self.parser.peek() {
+                    Some(Token::Identifier(_)) => self.identifier()?,
+                    _ => Uuid::new_v4().simple().to_string(),
+                };
+                self.transaction = Some(transaction.clone());
+                Box::new(self.checked(start, BeginTransactionStatement::new(transaction))?)
+            }
+            "COMMIT" => {
+                let transaction = self.transaction_end(start)?;
+                Box::new(self.checked(start, CommitStatement::new(transaction))?)
+            }
+            "ROLLBACK" => {
+                if self.parser.eat_keyword("TO") {
+                    self.parser.eat_keyword("SAVEPOINT");
+                    let savepoint = self.identifier()?;
+                    let transaction = self.open_transaction(start)?;
+                    Box::new(self.checked(start, RollbackStatement::to_savepoint(transaction, savepoint))?)
+                } else {
+                    let transaction = self.transaction_end(start)?;
+                    Box::new(self.checked(start, RollbackStatement::new(transaction))?)
+                }
+            }
+            "SAVEPOINT" => {
+                let savepoint = self.identifier()?;
+                let transaction = self.open_transaction(start)?;
+                Box::new(self.checked(start, SavepointStatement::new(transaction, savepoint))?)
+            }
+            "RELEASE" => {
+                self.parser.eat_keyword("SAVEPOINT");
+                let savepoint = self.identifier()?;
+                let transaction = self.open_transaction(start)?;
+                Box::new(self.checked(start, ReleaseSavepointStatement::new(transaction, savepoint))?)
+            }
+            "GRANT" => self.grant(start, false)?,
+            "REVOKE" => self.grant(start, true)?,
+            "PREPARE" => {
+                let name = self.identifier()?;
+                self.keyword("AS")?;
+                let body = self.parser.position();
+                while !self.parser.is_done() && !matches!(self.parser.peek(), Some(Token::Symbol(";"))) {
+                    self.parser.next_token();
+                }
+                if self.parser.position() == body {
+                    return Err(self.error("expected the statement to prepare"));
+                }
+                let sql = self.text(body).to_string();
+                Box::new(self.checked(start, PrepareStatement::new(name, sql))?)
+            }
+            "EXECUTE" => {
+                let name = self.identifier()?;
+                let mut parameters = Vec::new();
+                if self.parser.eat_symbol("(") {
+                    parameters.push(self.literal()?);
+                    while self.parser.eat_symbol(",") {
+                        parameters.push(self.literal()?);
+                    }
+                    self.symbol(")")?;
+                }
+                Box::new(self.checked(start, ExecuteStatement::new(name, parameters))?)
+            }
+            "DEALLOCATE" => {
+                self.parser.eat_keyword("PREPARE");
+                let name = if self.parser.eat_keyword("ALL") { None } else { Some(self.identifier()?) };
+                Box::new(self.checked(start, DeallocateStatement::new(name))?)
+            }
+            _ => {
+                return Err(self.error_at(start, format!("unknown statement {}", keyword)));
+            }
+        };
+        return Ok(statement);
+    }
+
+    fn create(&mut self, start: usize) -> Result<Box<dyn Statement>, SqlError> {
+        if self.parser.eat_keyword("DATABASE") {
+            let database = self.identifier()?;
+            return Ok(Box::new(self.checked(start, CreateDatabaseStatement::new(database))?));
+        }
+        if self.parser.eat_keyword("TABLE") {
+            return Ok(Box::new(self.create_table(start)?));
+        }
+        if self.parser.eat_keyword("INDEX") {
+            let index = self.identifier()?;
+            self.keyword("ON")?;
+            let table = self.identifier()?;
+            let columns = self.name_list()?;
+            return Ok(Box::new(self.checked(start, CreateIndexStatement::new(index, table, columns))?));
+        }
+        if self.parser.eat_keyword("USER") {
+            let user = self.identifier()?;
+            self.parser.eat_keyword("WITH");
+            self.keyword("PASSWORD")?;
+            let password = self.string()?;
+            let superuser = self.parser.eat_keyword("SUPERUSER");
+            let mut roles = Vec::new();
+            if self.parser.eat_keyword("IN") {
+                self.keyword("ROLE")?;
+                roles = self.identifiers()?;
+            }
+            return Ok(Box::new(self.checked(start, CreateUserStatement::new(user, password, superuser, roles))?));
+        }
+        if self.parser.eat_keyword("ROLE") {
+            let role = self.identifier()?;
+            return Ok(Box::new(self.checked(start, CreateRoleStatement::new(role))?));
+        }
+        return Err(self.error("expected DATABASE, TABLE, INDEX, USER or ROLE"));
+    }
+
+    /// `name (column type[(length)] [PRIMARY KEY] [INDEX] [DEFAULT value], ...
+    /// [, PRIMARY KEY (columns)])`
+    fn create_table(&mut self, start: usize) -> Result<CreateTableStatement, SqlError> {
+        let table = self.identifier()?;
+        self.symbol("(")?;
+        let mut columns: Vec<ColumnDefinition> = Vec::new();
+        loop {
+            if self.parser.eat_keyword("PRIMARY") {
+                self.keyword("KEY")?;
+                let key = self.parser.position();
+                for name in self.name_list()? {
+                    match columns.iter_mut().find(|column| column.name == name) {
+                        Some(column) => {
+                            column.primary_key = true;
+                        }
+                        None => {
+                            return Err(self.error_at(key, format!("unknown column {}", name)));
+                        }
+                    }
+                }
+            } else {
+                columns.push(self.column_definition()?);
+            }
+            if !self.parser.eat_symbol(",") {
+                break;
+            }
+        }
+        self.symbol(")")?;
+        return self.checked(start, CreateTableStatement::new(table, columns, None));
+    }
+
+    fn column_definition(&mut self) -> Result<ColumnDefinition, SqlError> {
+        let name = self.identifier()?;
+        let col_type = self.identifier()?.to_ascii_lowercase();
+        let mut length = 0;
+        if self.parser.eat_symbol("(") {
+            let at = self.parser.position();
+            length = i32::try_from(self.number()?).map_err(|_| self.error_at(at, "length out of range"))?;
+            self.symbol(")")?;
+        }
+        let mut column = ColumnDefinition {
+            name,
+            col_type,
+            length,
+            primary_key: false,
+            index: false,
+            default_value: String::new(),
+        };
+        loop {
+            if self.parser.eat_keyword("PRIMARY") {
+                self.keyword("KEY")?;
+                column.primary_key = true;
+            } else if self.parser.eat_keyword("INDEX") {
+                column.index = true;
+            } else if self.parser.eat_keyword("DEFAULT") {
+                column.default_value = match self.literal()? {
+                    Value::String(text) => text,
+                    Value::Null => String::new(),
+                    value => value.to_string(),
+                };
+            } else {
+                return Ok(column);
+            }
+        }
+    }
+
+    fn drop(&mut self, start: usize) -> Result<Box<dyn Statement>, SqlError> {
+        if self.parser.eat_keyword("DATABASE") {
+            let database = self.identifier()?;
+            return Ok(Box::new(self.checked(start, DropDatabaseStatement::new(database))?));
+        }
+        if self.parser.eat_keyword("TABLE") {
+            let table = self.identifier()?;
+            return Ok(Box::new(self.checked(start, DropTableStatement::new(table))?));
+        }
+        if self.parser.eat_keyword("INDEX") {
+            let index = self.identifier()?;
+            self.keyword("ON")?;
+            let table = self.identifier()?;
+            return Ok(Box::new(self.checked(start, DropIndexStatement::new(index, table))?));
+        }
+        if self.parser.eat_keyword("USER") {
+            let user = self.identifier()?;
+            return Ok(Box::new(self.checked(start, DropUserStatement::new(user))?));
+        }
+        return Err(self.error("expected DATABASE, TABLE, INDEX or USER"));
+    }
+
+    /// `ALTER TABLE name RENAME TO new_name`; any other change is passed on
+    /// as written.
+    fn alter(&mut self, start: usize) -> Result<Box<dyn Statement>, SqlError> {
+        self.keyword("TABLE")?;
+        let table = self.identifier()?;
+        if self.parser.eat_keyword("RENAME") {
+            self.keyword("TO")?;
+            let new_table = self.identifier()?;
+            return Ok(Box::new(self.checked(start, RenameTableStatement::new(table, new_table))?));
+        }
+        let changes = self.parser.position();
+        while !self.parser.is_done() && !matches!(self.parser.peek(), Some(Token::Symbol(";"))) {
+            self.parser.next_token();
+        }
+        if self.parser.position() == changes {
+            return Err(self.error("expected the changes to the table"));
+        }
+        let changes = self.text(changes).to_string();
+        return Ok(Box::new(self.checked(start, AlterTableStatement::new(table, changes))?));
+    }
+
+    fn show(&mut self, start: usize) -> Result<Box<dyn Statement>, SqlError> {
+        if self.parser.eat_keyword("DATABASES") {
+            return Ok(Box::new(EmptyStatement::new(MessageType::ShowDatabases)));
+        }
+        if self.parser.eat_keyword("TABLES") {
+            return Ok(Box::new(EmptyStatement::new(MessageType::ShowTables)));
+        }
+        if self.parser.eat_keyword("INDEXES") || self.parser.eat_keyword("INDEX") {
+            if !self.parser.eat_keyword("FROM") {
+                self.keyword("ON")?;
+            }
+            let table = self.identifier()?;
+            return Ok(Box::new(self.checked(start, ShowIndexesStatement::new(table))?));
+        }
+        if self.parser.eat_keyword("SLOW") {
+            self.keyword("QUERIES")?;
+            let mut limit = 0;
+            if self.parser.eat_keyword("LIMIT") {
+                let at = self.parser.position();
+                limit = u32::try_from(self.number()?).map_err(|_| self.error_at(at, "limit out of range"))?;
+            }
+            return Ok(Box::new(self.checked(start, ShowSlowQueriesStatement::new(limit))?));
+        }
+        return Err(self.error("expected DATABASES, TABLES, INDEXES or SLOW QUERIES"));
+    }
+
+    /// `SELECT items FROM table [joins] [WHERE] [GROUP BY] [HAVING]
+    /// [ORDER BY] [LIMIT] [OFFSET]`, where an item is `*`, a column or an
+    /// aggregate.
+    fn select(&mut self, start: usize) -> Result<SelectStatement, SqlError> {
+        let mut columns = Vec::new();
+        let mut aggregates = Vec::new();
+        loop {
+            if self.parser.eat_symbol("*") {
+                columns.push("*".to_string());
+            } else if let Some(aggregate) = self.aggregate()? {
+                columns.push(aggregate.output_name());
+                aggregates.push(aggregate);
+            } else {
+                columns.push(self.column_name()?);
+            }
+            if !self.parser.eat_symbol(",") {
+                break;
+            }
+        }
+
+        self.keyword("FROM")?;
+        let table = self.identifier()?;
+        let mut joins = Vec::new();
+        while let Some(join) = self.join()? {
+            joins.push(join);
+        }
+        let r#where = if self.parser.eat_keyword("WHERE") { self.clause()? } else { String::new() };
+        let mut group_by = Vec::new();
+        if self.parser.eat_keyword("GROUP") {
+            self.keyword("BY")?;
+            group_by = self.column_names()?;
+        }
+        let having = if self.parser.eat_keyword("HAVING") { self.clause()? } else { String::new() };
+        let mut order_by = Vec::new();
+        if self.parser.eat_keyword("ORDER") {
+            self.keyword("BY")?;
+            loop {
+                order_by.push(self.order_key()?);
+                if !self.parser.eat_symbol(",") {
+                    break;
+                }
+            }
+        }
+        let limit = if self.parser.eat_keyword("LIMIT") { Some(self.number()?) } else { None };
+        let offset = if self.parser.eat_keyword("OFFSET") { self.number()? } else { 0 };
+
+        let stmt = SelectStatement::new(table, columns, r#where)
+            .and_then(|stmt| stmt.with_joins(joins))
+            .and_then(|stmt| stmt.with_aggregates(aggregates))
+            .and_then(|stmt| stmt.with_group_by(group_by))
+            .and_then(|stmt| stmt.with_order_by(order_by))
+            .map(|stmt| stmt.with_having(having).with_offset(offset));
+        let stmt = self.checked(start, stmt)?;
+        return Ok(match limit {
+            Some(limit) => stmt.with_limit(limit),
+            None => stmt,
+        });
+    }
+
+    /// `function([DISTINCT] column | *) [AS alias]`, or nothing if the next
+    /// item is not a call.
+    fn aggregate(&mut self) -> Result<Option<Aggregate>, SqlError> {
+        let start = self.parser.position();
+        let function = match (self.tokens.get(start), self.tokens.get(start + 1)) {
+            (Some(Token::Identifier(function)), Some(Token::Symbol("("))) => function.clone(),
+            _ => {
+                return Ok(None);
+            }
+        };
+        self.parser.next_token();
+        self.parser.next_token();
+        let distinct = if self.parser.eat_keyword("DISTINCT") { "DISTINCT " } else { "" };
+        let argument = if self.parser.eat_symbol("*") { "*".to_string() } else { self.column_name()? };
+        self.symbol(")")?;
+        let alias = if self.parser.eat_keyword("AS") { format!(" AS {}", self.identifier()?) } else { String::new() };
+        let aggregate = Aggregate::from_str(&format!("{}({}{}){}", function, distinct, argument, alias));
+        return Ok(Some(self.checked(start, aggregate)?));
+    }
+
+    /// `[INNER | LEFT [OUTER]] JOIN table [ON condition]`, or nothing if no
+    /// join follows.
+    fn join(&mut self) -> Result<Option<Join>, SqlError> {
+        let kind = if self.parser.eat_keyword("LEFT") {
+            self.parser.eat_keyword("OUTER");
+            self.keyword("JOIN")?;
+            JoinKind::Left
+        } else if self.parser.eat_keyword("INNER") {
+            self.keyword("JOIN")?;
+            JoinKind::Inner
+        } else if self.parser.eat_keyword("JOIN") {
+            JoinKind::Inner
+        } else {
+            return Ok(None);
+        };
+        let table_name = self.identifier()?;
+        let on = if self.parser.eat_keyword("ON") { self.clause()? } else { String::new() };
+        return Ok(Some(Join { kind, table_name, on }));
+    }
+
+    /// `column [ASC | DESC] [NULLS FIRST | NULLS LAST]`
+    fn order_key(&mut self) -> Result<OrderBy, SqlError> {
+        let column = self.column_name()?;
+        let key = if self.parser.eat_keyword("DESC") {
+            OrderBy::desc(&column)
+        } else {
+            self.parser.eat_keyword("ASC");
+            OrderBy::asc(&column)
+        };
+        if !self.parser.eat_keyword("NULLS") {
+            return Ok(key);
+        }
+        if self.parser.eat_keyword("FIRST") {
+            return Ok(key.nulls_first(true));
+        }
+        self.keyword("LAST")?;
+        return Ok(key.nulls_first(false));
+    }
+
+    /// `INSERT INTO table (columns) VALUES (values), ...`, an upsert with
+    /// `ON CONFLICT (column) DO UPDATE`.
+    fn insert(&mut self, start: usize) -> Result<Box<dyn Statement>, SqlError> {
+        self.keyword("INTO")?;
+        let table = self.identifier()?;
+        let list = self.parser.position();
+        let columns = self.name_list()?;
+        if let Some(duplicate) = columns.iter().enumerate().find(|(i, column)| columns[..*i].contains(column)) {
+            return Err(self.error_at(list, format!("column {} is listed twice", duplicate.1)));
+        }
+        self.keyword("VALUES")?;
+        let mut rows = Vec::new();
+        loop {
+            let row = self.parser.position();
+            self.symbol("(")?;
+            let mut values = vec![self.value(rows.len(), &columns[0])?];
+            while self.parser.eat_symbol(",") {
+                let column = columns.get(values.len()).map_or("", String::as_str);
+                values.push(self.value(rows.len(), column)?);
+            }
+            self.symbol(")")?;
+            if values.len() != columns.len() {
+                let message = format!("expected {} values but found {}", columns.len(), values.len());
+                return Err(self.error_at(row, message));
+            }
+            rows.push(columns.iter().cloned().zip(values).collect::<HashMap<String, Value>>());
+            if !self.parser.eat_symbol(",") {
+                break;
+            }
+        }
+
+        if self.parser.eat_keyword("ON") {
+            self.keyword("CONFLICT")?;
+            self.symbol("(")?;
+            let key = self.identifier()?;
+            self.symbol(")")?;
+            self.keyword("DO")?;
+            self.keyword("UPDATE")?;
+            if rows.len() != 1 {
+                return Err(self.error_at(start, "ON CONFLICT takes a single row"));
+            }
+            return Ok(Box::new(self.checked(start, UpsertStatement::new(table, rows.remove(0), key))?));
+        }
+        if rows.len() == 1 {
+            return Ok(Box::new(self.checked(start, InsertStatement::new(table, rows.remove(0)))?));
+        }
+        return Ok(Box::new(self.checked(start, BulkInsertStatement::new(table, rows))?));
+    }
+
+    fn update(&mut self, start: usize) -> Result<Box<dyn Statement>, SqlError> {
+        let table = self.identifier()?;
+        self.keyword("SET")?;
+        let mut updates = HashMap::new();
+        loop {
+            let column = self.identifier()?;
+            self.symbol("=")?;
+            let value = self.value(0, &column)?;
+            updates.insert(column, value);
+            if !self.parser.eat_symbol(",") {
+                break;
+            }
+        }
+        let r#where = if self.parser.eat_keyword("WHERE") { self.clause()? } else { String::new() };
+        return Ok(Box::new(self.checked(start, UpdateStatement::new(table, updates, r#where))?));
+    }
+
+    /// `GRANT privileges ON database.table TO grantee` or `GRANT roles TO
+    /// grantee`; REVOKE takes FROM instead of TO.
+    fn grant(&mut self, start: usize, revoke: bool) -> Result<Box<dyn Statement>, SqlError> {
+        let mut names = Vec::new();
+        loop {
+            names.push(self.identifier()?);
+            self.parser.eat_keyword("PRIVILEGES");
+            if !self.parser.eat_symbol(",") {
+                break;
+            }
+        }
+        let (privileges, database, table, roles) = if self.parser.eat_keyword("ON") {
+            let database = self.name_or_wildcard()?;
+            self.symbol(".")?;
+            let table = self.name_or_wildcard()?;
+            let privileges = names.iter().map(|name| name.to_ascii_uppercase()).collect();
+            (privileges, database, table, Vec::new())
+        } else {
+            (Vec::new(), "*".to_string(), "*".to_string(), names)
+        };
+        self.keyword(if revoke { "FROM" } else { "TO" })?;
+        let grantee = self.identifier()?;
+        if revoke {
+            let stmt = RevokeStatement::new(privileges, database, table, roles, grantee);
+            return Ok(Box::new(self.checked(start, stmt)?));
+        }
+        let stmt = GrantStatement::new(privileges, database, table, roles, grantee);
+        return Ok(Box::new(self.checked(start, stmt)?));
+    }
+
+    /// The transaction a COMMIT or ROLLBACK ends: the one it names, or the
+    /// one the script opened.
+    fn transaction_end(&mut self, start: usize) -> Result<String, SqlError> {
+        if !self.parser.eat_keyword("TRANSACTION") {
+            self.parser.eat_keyword("WORK");
+        }
+        let transaction = match self.parser.peek() {
+            Some(Token::Identifier(_)) => self.identifier()?,
+            _ => self.open_transaction(start)?,
+        };
+        if self.transaction.as_ref() == Some(&transaction) {
+            self.transaction = None;
+        }
+        return Ok(transaction);
+    }
+
+    fn open_transaction(&self, start: usize) -> Result<String, SqlError> {
+        return self.transaction.clone().ok_or_else(|| self.error_at(start, "no transaction was started"));
+    }
+
+    /// A literal value, such as `42`, `-1.5`, `'text'`, `TRUE` or `NULL`.
+    fn literal(&mut self) -> Result<Value, SqlError> {
+        let start = self.parser.position();
+        match self.parser.parse_expression().map_err(|e| self.expression_error(e))? {
+            Expression::Literal(value) => Ok(value),
+            _ => Err(self.error_at(start, "expected a literal value")),
+        }
+    }
+
+    /// A value written to `column` of the `row`th row: a literal, or in a
+    /// prepared statement a placeholder, which reads NULL until bound.
+    fn value(&mut self, row: usize, column: &str) -> Result<Value, SqlError> {
+        if let Some(Token::Parameter(parameter)) = self.parser.peek() {
+            if !self.prepared {
+                return Err(self.placeholder_error(self.parser.position()));
+            }
+            self.slots.push(ValueSlot { row, column: column.to_string(), parameter: *parameter });
+            self.parser.next_token();
+            return Ok(Value::Null);
+        }
+        return self.literal();
+    }
+
+    /// A condition, checked and returned as written; with placeholders, as
+    /// parsed, so that each one keeps its number.
+    fn clause(&mut self) -> Result<String, SqlError> {
+        let start = self.parser.position();
+        let expr = self.parser.parse_expression().map_err(|e| self.expression_error(e))?;
+        if expr.has_parameters() {
+            if !self.prepared {
+                let at = (start..self.parser.position())
+                    .find(|&token| matches!(self.tokens[token], Token::Parameter(_)))
+                    .unwrap_or(start);
+                return Err(self.placeholder_error(at));
+            }
+            return Ok(expr.to_string());
+        }
+        return Ok(self.text(start).to_string());
+    }
+
+    /// The source of the tokens from `start` up to the current one.
+    fn text(&self, start: usize) -> &'a str {
+        let end = self.spans[self.parser.position() - 1].1;
+        return &self.sql[self.spans[start].0..end];
+    }
+
+    fn number(&mut self) -> Result<u64, SqlError> {
+        match self.parser.peek() {
+            Some(Token::Number(n)) if n.fract() == 0.0 && *n >= 0.0 => {
+                let n = *n as u64;
+                self.parser.next_token();
+                Ok(n)
+            }
+            _ => Err(self.error("expected a whole number")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, SqlError> {
+        match self.parser.peek() {
+            Some(Token::String(text)) => {
+                let text = text.clone();
+                self.parser.next_token();
+                Ok(text)
+            }
+            _ => Err(self.error("expected a quoted string")),
+        }
+    }
+
+    fn identifier(&mut self) -> Result<String, SqlError> {
+        return self.parser.expect_identifier().map_err(|e| self.expression_error(e));
+    }
+
+    fn identifiers(&mut self) -> Result<Vec<String>, SqlError> {
+        let mut names = vec![self.identifier()?];
+        while self.parser.eat_symbol(",") {
+            names.push(self.identifier()?);
+        }
+        return Ok(names);
+    }
+
+    /// `(name, ...)`
+    fn name_list(&mut self) -> Result<Vec<String>, SqlError> {
+        self.symbol("(")?;
+        let names = self.identifiers()?;
+        self.symbol(")")?;
+        return Ok(names);
+    }
+
+    /// A column, optionally qualified by its table.
+    fn column_name(&mut self) -> Result<String, SqlError> {
+        let name = self.identifier()?;
+        if self.parser.eat_symbol(".") {
+            return Ok(format!("{}.{}", name, self.identifier()?));
+        }
+        return Ok(name);
+    }
+
+    fn column_names(&mut self) -> Result<Vec<String>, SqlError> {
+        let mut names = vec![self.column_name()?];
+        while self.parser.eat_symbol(",") {
+            names.push(self.column_name()?);
+        }
+        return Ok(names);
+    }
+
+    fn name_or_wildcard(&mut self) -> Result<String, SqlError> {
+        if self.parser.eat_symbol("*") {
+            return Ok("*".to_string());
+        }
+        return self.identifier();
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<(), SqlError> {
+        return self.parser.expect_keyword(keyword).map_err(|e| self.expression_error(e));
+    }
+
+    fn symbol(&mut self, symbol: &str) -> Result<(), SqlError> {
+        return self.parser.expect_symbol(symbol).map_err(|e| self.expression_error(e));
+    }
+
+    /// `result`, with a failure reported at the token `start`.
+    fn checked<T, E: fmt::Display>(&self, start: usize, result: Result<T, E>) -> Result<T, SqlError> {
+        return result.map_err(|e| self.error_at(start, e.to_string()));
+    }
+
+    fn placeholder_error(&self, token: usize) -> SqlError {
+        return self.error_at(token, "placeholders are only allowed in prepared statements");
+    }
+
+    fn expression_error(&self, e: ExpressionError) -> SqlError {
+        return self.error(e.0);
+    }
+
+    /// An error at the current token.
+    fn error(&self, message: impl Into<String>) -> SqlError {
+        return self.error_at(self.parser.position(), message);
+    }
+
+    /// An error at the token `token`, or at the end of the input past the
+    /// last one.
+    fn error_at(&self, token: usize, message: impl Into<String>) -> SqlError {
+        let offset = self.spans.get(token).map_or(self.sql.len(), |span| span.0);
+        return SqlError::at(self.sql, offset, message.into());
+    }
+}
@@ -0,0 +1,164 @@
+use std::fmt;
+use std::str::FromStr;
+use serde::{ Deserialize, Serialize };
+use validator::{ Validate, ValidationError };
+use crate::statement::{ validate_alphanumunderscore, validate_column_name };
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AggregateFunction {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+    CountDistinct,
+}
+
+impl AggregateFunction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AggregateFunction::Count => "count",
+            AggregateFunction::Sum => "sum",
+            AggregateFunction::Avg => "avg",
+            AggregateFunction::Min => "min",
+            AggregateFunction::Max => "max",
+            AggregateFunction::CountDistinct => "count_distinct",
+        }
+    }
+}
+
+/// One aggregate of a select, such as `SUM(age) AS total`. NULLs are
+/// skipped by every function but `COUNT(*)`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Validate)]
+#[validate(schema(function = "validate_aggregate"))]
+pub struct Aggregate {
+    #[serde(rename = "function")]
+    pub function: AggregateFunction,
+
+    /// `None` only for `COUNT(*)`.
+    #[validate(custom(function = "validate_column_name"))]
+    #[serde(rename = "column", default)]
+    pub column: Option<String>,
+
+    #[validate(custom(function = "validate_alphanumunderscore"))]
+    #[serde(rename = "alias", default)]
+    pub alias: Option<String>,
+}
+
+fn validate_aggregate(aggregate: &Aggregate) -> Result<(), ValidationError> {
+    if aggregate.column.is_none() && aggregate.function != AggregateFunction::Count {
+        return Err(ValidationError::new("aggregate_column"));
+    }
+    Ok(())
+}
+
+#[allow(dead_code)]
+impl Aggregate {
+    fn of(function: AggregateFunction, column: &str) -> Self {
+        Aggregate { function, column: Some(column.to_string()), alias: None }
+    }
+
+    pub fn count_all() -> Self {
+        Aggregate { function: AggregateFunction::Count, column: None, alias: None }
+    }
+
+    pub fn count(column: &str) -> Self {
+        Aggregate::of(AggregateFunction::Count, column)
+    }
+
+    pub fn count_distinct(column: &str) -> Self {
+        Aggregate::of(AggregateFunction::CountDistinct, column)
+    }
+
+    pub fn sum(column: &str) -> Self {
+        Aggregate::of(AggregateFunction::Sum, column)
+    }
+
+    pub fn avg(column: &str) -> Self {
+        Aggregate::of(AggregateFunction::Avg, column)
+    }
+
+    pub fn min(column: &str) -> Self {
+        Aggregate::of(AggregateFunction::Min, column)
+    }
+
+    pub fn max(column: &str) -> Self {
+        Aggregate::of(AggregateFunction::Max, column)
+    }
+
+    pub fn with_alias(mut self, alias: &str) -> Self {
+        self.alias = Some(alias.to_string());
+        self
+    }
+
+    /// Column of the result rows: the alias, else `count` for `COUNT(*)`
+    /// and `<function>_<column>` otherwise, e.g. `avg_age` or, for a
+    /// qualified column, `avg_users_age`.
+    pub fn output_name(&self) -> String {
+        match (&self.alias, &self.column) {
+            (Some(alias), _) => alias.clone(),
+            (None, None) => self.function.name().to_string(),
+            (None, Some(column)) => format!("{}_{}", self.function.name(), column.replace('.', "_")),
+        }
+    }
+
+    /// Parses a comma separated list such as `count(*), avg(age) as mean`.
+    pub fn parse_list(input: &str) -> Result<Vec<Aggregate>, String> {
+        input
+            .split(',')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+            .map(Aggregate::from_str)
+            .collect()
+    }
+}
+
+impl FromStr for Aggregate {
+    type Err = String;
+
+    /// `function(column | *) [AS alias]`, with `COUNT(DISTINCT column)`;
+    /// case-insensitive.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let error = || format!("cannot parse aggregate {:?}", input);
+        let (call, alias) = match input.to_ascii_lowercase().find(" as ") {
+            Some(at) => (input[..at].trim(), Some(input[at + 4..].trim())),
+            None => (input.trim(), None),
+        };
+        let (function, argument) = call.strip_suffix(')').and_then(|call| call.split_once('(')).ok_or_else(error)?;
+        let argument = argument.trim();
+        let (distinct, argument) = match argument.split_once(char::is_whitespace) {
+            Some((word, rest)) if word.eq_ignore_ascii_case("distinct") => (true, rest.trim()),
+            _ => (false, argument),
+        };
+        let function = match (function.trim().to_ascii_lowercase().as_str(), distinct) {
+            ("count", false) => AggregateFunction::Count,
+            ("count", true) => AggregateFunction::CountDistinct,
+            ("sum", false) => AggregateFunction::Sum,
+            ("avg", false) => AggregateFunction::Avg,
+            ("min", false) => AggregateFunction::Min,
+            ("max", false) => AggregateFunction::Max,
+            _ => {
+                return Err(error());
+            }
+        };
+        let column = if argument == "*" { None } else { Some(argument.to_string()) };
+        let aggregate = Aggregate { function, column, alias: alias.map(str::to_string) };
+        aggregate.validate().map_err(|e| e.to_string())?;
+        Ok(aggregate)
+    }
+}
+
+impl fmt::Display for Aggregate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let argument = self.column.as_deref().unwrap_or("*");
+        match self.function {
+            AggregateFunction::CountDistinct => write!(f, "COUNT(DISTINCT {})", argument)?,
+            function => write!(f, "{}({})", function.name().to_ascii_uppercase(), argument)?,
+        }
+        if let Some(alias) = &self.alias {
+            write!(f, " AS {}", alias)?;
+        }
+        Ok(())
+    }
+}
@@ -1,8 +1,10 @@
+use std::fmt;
 use serde::{ Deserialize, Serialize };
+use utoipa::ToSchema;
 use validator::Validate;
 use crate::statement::validate_alphanumunderscore;
 
-#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
+#[derive(Debug, Serialize, Deserialize, Clone, Validate, ToSchema)]
 pub struct ColumnDefinition {
     #[validate(custom(function = "validate_alphanumunderscore"))]
     #[serde(rename = "name")]
@@ -34,7 +36,14 @@ impl ColumnsDefinition {
         self.0.len()
     }
 
-    pub fn to_string(&self) -> String {
-        format!("{:?}", self.0)
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+}
+
+impl fmt::Display for ColumnsDefinition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0)
     }
 }
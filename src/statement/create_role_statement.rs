@@ -0,0 +1,44 @@
+use serde::{ Deserialize, Serialize };
+use validator::{ Validate, ValidationErrors };
+use rmp_serde::{ encode, decode };
+use crate::statement::{ Statement, validate_alphanumunderscore };
+use crate::protocol::MessageType;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
+pub struct CreateRoleStatement {
+    #[validate(custom(function = "validate_alphanumunderscore"))]
+    #[serde(rename = "role_name")]
+    pub role_name: String,
+}
+
+#[allow(dead_code)]
+impl CreateRoleStatement {
+    pub fn new(role_name: String) -> Result<Self, ValidationErrors> {
+        let stmt = CreateRoleStatement { role_name };
+        stmt.validate()?;
+        Ok(stmt)
+    }
+}
+
+impl Statement for CreateRoleStatement {
+    fn clone_box(&self) -> Box<dyn Statement> {
+        Box::new(self.clone())
+    }
+
+    fn protocol(&self) -> MessageType {
+        MessageType::CreateRole
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, encode::Error> {
+        encode::to_vec(self)
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<Box<dyn Statement>, decode::Error> {
+        let stmt: CreateRoleStatement = decode::from_slice(data)?;
+        Ok(Box::new(stmt))
+    }
+
+    fn to_string(&self) -> String {
+        format!("CreateRoleStatement{{RoleName: {}}}", self.role_name)
+    }
+}
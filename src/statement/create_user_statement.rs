@@ -0,0 +1,66 @@
+use serde::{ Deserialize, Serialize };
+use validator::{ Validate, ValidationErrors };
+use rmp_serde::{ encode, decode };
+use crate::statement::{ Statement, validate_alphanumunderscore };
+use crate::protocol::MessageType;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
+pub struct CreateUserStatement {
+    #[validate(custom(function = "validate_alphanumunderscore"))]
+    #[serde(rename = "username")]
+    pub username: String,
+
+    #[validate(length(min = 8))]
+    #[serde(rename = "password")]
+    pub password: String,
+
+    /// Superusers bypass every privilege check.
+    #[serde(rename = "superuser")]
+    pub superuser: bool,
+
+    /// Roles granted to the user right away.
+    #[serde(rename = "roles")]
+    pub roles: Vec<String>,
+}
+
+#[allow(dead_code)]
+impl CreateUserStatement {
+    pub fn new(
+        username: String,
+        password: String,
+        superuser: bool,
+        roles: Vec<String>
+    ) -> Result<Self, ValidationErrors> {
+        let stmt = CreateUserStatement { username, password, superuser, roles };
+        stmt.validate()?;
+        Ok(stmt)
+    }
+}
+
+impl Statement for CreateUserStatement {
+    fn clone_box(&self) -> Box<dyn Statement> {
+        Box::new(self.clone())
+    }
+
+    fn protocol(&self) -> MessageType {
+        MessageType::CreateUser
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, encode::Error> {
+        encode::to_vec(self)
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<Box<dyn Statement>, decode::Error> {
+        let stmt: CreateUserStatement = decode::from_slice(data)?;
+        Ok(Box::new(stmt))
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "CreateUserStatement{{Username: {}, Superuser: {}, Roles: {:?}}}",
+            self.username,
+            self.superuser,
+            self.roles
+        )
+    }
+}
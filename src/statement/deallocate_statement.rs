@@ -0,0 +1,46 @@
+use serde::{ Deserialize, Serialize };
+use validator::{ Validate, ValidationErrors };
+use rmp_serde::{ encode, decode };
+use crate::protocol::MessageType;
+use crate::statement::{ Statement, validate_alphanumunderscore };
+
+/// Drops the prepared statement `name`, or every one of the connection's
+/// when there is no name.
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
+pub struct DeallocateStatement {
+    #[validate(custom(function = "validate_alphanumunderscore"))]
+    #[serde(rename = "name")]
+    pub name: Option<String>,
+}
+
+#[allow(dead_code)]
+impl DeallocateStatement {
+    pub fn new(name: Option<String>) -> Result<Self, ValidationErrors> {
+        let stmt = DeallocateStatement { name };
+        stmt.validate()?;
+        Ok(stmt)
+    }
+}
+
+impl Statement for DeallocateStatement {
+    fn clone_box(&self) -> Box<dyn Statement> {
+        Box::new(self.clone())
+    }
+
+    fn protocol(&self) -> MessageType {
+        MessageType::Deallocate
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, encode::Error> {
+        encode::to_vec(self)
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<Box<dyn Statement>, decode::Error> {
+        let stmt: DeallocateStatement = decode::from_slice(data)?;
+        Ok(Box::new(stmt))
+    }
+
+    fn to_string(&self) -> String {
+        format!("DeallocateStatement{{Name: {}}}", self.name.as_deref().unwrap_or("ALL"))
+    }
+}
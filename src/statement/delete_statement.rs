@@ -2,7 +2,7 @@ use serde::{Deserialize, Serialize};
 use validator::{Validate, ValidationErrors};
 use rmp_serde::{encode, decode};
 use crate::protocol::MessageType;
-use crate::statement::{ validate_alphanumunderscore, Statement };
+use crate::statement::{ redact_filter, validate_alphanumunderscore, Statement };
 
 #[derive(Debug, Serialize, Deserialize, Clone, Validate)]
 pub struct DeleteStatement {
@@ -48,4 +48,12 @@ impl Statement for DeleteStatement {
             self.r#where.clone().unwrap_or_default()
         )
     }
+
+    fn to_redacted_string(&self) -> String {
+        format!(
+            "DeleteStatement{{TableName: {}, Where: {}}}",
+            self.table_name,
+            self.r#where.as_deref().map(redact_filter).unwrap_or_default()
+        )
+    }
 }
@@ -37,7 +37,7 @@ impl Statement for DropDatabaseStatement {
 
 #[allow(dead_code)]
 impl DropDatabaseStatement {
-    fn new(database_name: String) -> Result<Self, ValidationErrors> {
+    pub fn new(database_name: String) -> Result<Self, ValidationErrors> {
         let stmt = DropDatabaseStatement { database_name };
         stmt.validate()?;
         Ok(stmt)
@@ -0,0 +1,44 @@
+use serde::{ Deserialize, Serialize };
+use validator::{ Validate, ValidationErrors };
+use rmp_serde::{ encode, decode };
+use crate::statement::{ Statement, validate_alphanumunderscore };
+use crate::protocol::MessageType;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
+pub struct DropUserStatement {
+    #[validate(custom(function = "validate_alphanumunderscore"))]
+    #[serde(rename = "username")]
+    pub username: String,
+}
+
+#[allow(dead_code)]
+impl DropUserStatement {
+    pub fn new(username: String) -> Result<Self, ValidationErrors> {
+        let stmt = DropUserStatement { username };
+        stmt.validate()?;
+        Ok(stmt)
+    }
+}
+
+impl Statement for DropUserStatement {
+    fn clone_box(&self) -> Box<dyn Statement> {
+        Box::new(self.clone())
+    }
+
+    fn protocol(&self) -> MessageType {
+        MessageType::DropUser
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, encode::Error> {
+        encode::to_vec(self)
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<Box<dyn Statement>, decode::Error> {
+        let stmt: DropUserStatement = decode::from_slice(data)?;
+        Ok(Box::new(stmt))
+    }
+
+    fn to_string(&self) -> String {
+        format!("DropUserStatement{{Username: {}}}", self.username)
+    }
+}
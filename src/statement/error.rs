@@ -19,8 +19,8 @@ impl fmt::Display for UnsupportedStatementError {
 impl UnsupportedStatementError {
     pub fn new(message_type: MessageType, message: String) -> Self {
         return Self {
-            message_type: message_type,
-            message: message,
+            message_type,
+            message,
         }
     }
 }
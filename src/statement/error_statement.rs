@@ -0,0 +1,94 @@
+use serde::{ Deserialize, Serialize };
+use rmp_serde::{ encode, decode };
+use crate::protocol::MessageType;
+use crate::statement::Statement;
+
+/// Error codes carried by `ErrorStatement` responses.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    Internal = 1,
+    UnsupportedMessage = 2,
+    InvalidStatement = 3,
+    HandshakeFailed = 4,
+    AuthenticationRequired = 10,
+    /// The login signature does not match the cluster token.
+    AuthenticationFailed = 11,
+    /// The login timestamp is outside the accepted clock skew.
+    LoginExpired = 12,
+    /// The login nonce was already used.
+    LoginReplayed = 13,
+    /// The login was signed with a key id this node does not know.
+    UnknownKey = 14,
+    PermissionDenied = 20,
+    ExecutionFailed = 21,
+}
+
+#[allow(dead_code)]
+impl ErrorCode {
+    pub fn from_id(id: u32) -> Self {
+        match id {
+            2 => ErrorCode::UnsupportedMessage,
+            3 => ErrorCode::InvalidStatement,
+            4 => ErrorCode::HandshakeFailed,
+            10 => ErrorCode::AuthenticationRequired,
+            11 => ErrorCode::AuthenticationFailed,
+            12 => ErrorCode::LoginExpired,
+            13 => ErrorCode::LoginReplayed,
+            14 => ErrorCode::UnknownKey,
+            20 => ErrorCode::PermissionDenied,
+            21 => ErrorCode::ExecutionFailed,
+            _ => ErrorCode::Internal,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ErrorStatement {
+    #[serde(rename = "code")]
+    pub code: u32,
+
+    #[serde(rename = "message")]
+    pub message: String,
+}
+
+#[allow(dead_code)]
+impl ErrorStatement {
+    pub fn new(code: ErrorCode, message: String) -> Self {
+        Self {
+            code: code as u32,
+            message,
+        }
+    }
+
+    pub fn error_code(&self) -> ErrorCode {
+        ErrorCode::from_id(self.code)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, decode::Error> {
+        decode::from_slice(data)
+    }
+}
+
+impl Statement for ErrorStatement {
+    fn clone_box(&self) -> Box<dyn Statement> {
+        Box::new(self.clone())
+    }
+
+    fn protocol(&self) -> MessageType {
+        MessageType::Error
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, encode::Error> {
+        encode::to_vec(self)
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<Box<dyn Statement>, decode::Error> {
+        let stmt: ErrorStatement = decode::from_slice(data)?;
+        Ok(Box::new(stmt))
+    }
+
+    fn to_string(&self) -> String {
+        format!("ErrorStatement{{Code: {}, Message: {}}}", self.code, self.message)
+    }
+}
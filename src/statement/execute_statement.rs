@@ -0,0 +1,55 @@
+use serde::{ Deserialize, Serialize };
+use validator::{ Validate, ValidationErrors };
+use rmp_serde::{ encode, decode };
+use crate::protocol::MessageType;
+use crate::statement::{ Statement, validate_alphanumunderscore };
+use crate::utils::config::REDACTED;
+
+/// Runs the prepared statement `name` with `parameters` bound to its
+/// placeholders in order.
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
+pub struct ExecuteStatement {
+    #[validate(custom(function = "validate_alphanumunderscore"))]
+    #[serde(rename = "name")]
+    pub name: String,
+
+    #[serde(rename = "parameters")]
+    pub parameters: Vec<serde_json::Value>,
+}
+
+#[allow(dead_code)]
+impl ExecuteStatement {
+    pub fn new(name: String, parameters: Vec<serde_json::Value>) -> Result<Self, ValidationErrors> {
+        let stmt = ExecuteStatement { name, parameters };
+        stmt.validate()?;
+        Ok(stmt)
+    }
+}
+
+impl Statement for ExecuteStatement {
+    fn clone_box(&self) -> Box<dyn Statement> {
+        Box::new(self.clone())
+    }
+
+    fn protocol(&self) -> MessageType {
+        MessageType::Execute
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, encode::Error> {
+        encode::to_vec(self)
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<Box<dyn Statement>, decode::Error> {
+        let stmt: ExecuteStatement = decode::from_slice(data)?;
+        Ok(Box::new(stmt))
+    }
+
+    fn to_string(&self) -> String {
+        format!("ExecuteStatement{{Name: {}, Parameters: {:?}}}", self.name, self.parameters)
+    }
+
+    fn to_redacted_string(&self) -> String {
+        let parameters = vec![REDACTED; self.parameters.len()];
+        format!("ExecuteStatement{{Name: {}, Parameters: {:?}}}", self.name, parameters)
+    }
+}
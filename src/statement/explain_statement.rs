@@ -0,0 +1,92 @@
+use serde::{ Deserialize, Serialize };
+use validator::{ Validate, ValidationErrors };
+use rmp_serde::{ encode, decode };
+use crate::protocol::MessageType;
+use crate::statement::Statement;
+use crate::statement::statement::deserialize_statement;
+
+/// Data statements that can be explained.
+pub const EXPLAINABLE: [MessageType; 6] = [
+    MessageType::Select,
+    MessageType::Update,
+    MessageType::Delete,
+    MessageType::Insert,
+    MessageType::BulkInsert,
+    MessageType::Upsert,
+];
+
+/// Asks for the plan of a data statement instead of its result. With
+/// `analyze` the statement is run, writes included, and the plan comes back
+/// with actual row counts and timings.
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
+pub struct ExplainStatement {
+    #[serde(rename = "analyze")]
+    pub analyze: bool,
+
+    /// `MessageType` of the wrapped statement.
+    #[serde(rename = "message_type")]
+    pub message_type: u32,
+
+    /// The wrapped statement, encoded as it would be in a frame body.
+    #[serde(rename = "statement")]
+    pub statement: Vec<u8>,
+}
+
+#[allow(dead_code)]
+impl ExplainStatement {
+    pub fn new(statement: &dyn Statement, analyze: bool) -> Result<Self, ValidationErrors> {
+        let message_type = statement.protocol();
+        if !EXPLAINABLE.contains(&message_type) {
+            return Err(ValidationErrors::new());
+        }
+        let statement = statement.to_bytes().map_err(|_| ValidationErrors::new())?;
+        let stmt = ExplainStatement { analyze, message_type: message_type.to_u32(), statement };
+        stmt.validate()?;
+        Ok(stmt)
+    }
+
+    pub fn explained_type(&self) -> MessageType {
+        MessageType::from_id(self.message_type)
+    }
+
+    fn explained(&self) -> Option<Box<dyn Statement>> {
+        deserialize_statement(self.explained_type(), &self.statement).ok()
+    }
+}
+
+impl Statement for ExplainStatement {
+    fn clone_box(&self) -> Box<dyn Statement> {
+        Box::new(self.clone())
+    }
+
+    fn protocol(&self) -> MessageType {
+        MessageType::Explain
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, encode::Error> {
+        encode::to_vec(self)
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<Box<dyn Statement>, decode::Error> {
+        let stmt: ExplainStatement = decode::from_slice(data)?;
+        Ok(Box::new(stmt))
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "ExplainStatement{{Analyze: {}, MessageType: {}, Statement: {}}}",
+            self.analyze,
+            self.explained_type().to_name(),
+            self.explained().map(|stmt| stmt.to_string()).unwrap_or_default()
+        )
+    }
+
+    fn to_redacted_string(&self) -> String {
+        format!(
+            "ExplainStatement{{Analyze: {}, MessageType: {}, Statement: {}}}",
+            self.analyze,
+            self.explained_type().to_name(),
+            self.explained().map(|stmt| stmt.to_redacted_string()).unwrap_or_default()
+        )
+    }
+}
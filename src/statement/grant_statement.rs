@@ -0,0 +1,74 @@
+use serde::{ Deserialize, Serialize };
+use validator::{ Validate, ValidationErrors };
+use rmp_serde::{ encode, decode };
+use crate::statement::{ Statement, validate_alphanumunderscore, validate_name_or_wildcard };
+use crate::protocol::MessageType;
+
+/// Grants privileges on a database or table, and/or roles, to a user or role.
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
+pub struct GrantStatement {
+    /// SELECT, INSERT, UPDATE, DELETE, DDL or ALL.
+    #[serde(rename = "privileges")]
+    pub privileges: Vec<String>,
+
+    #[validate(custom(function = "validate_name_or_wildcard"))]
+    #[serde(rename = "database")]
+    pub database: String,
+
+    /// `*` for every table of the database.
+    #[validate(custom(function = "validate_name_or_wildcard"))]
+    #[serde(rename = "table")]
+    pub table: String,
+
+    #[serde(rename = "roles")]
+    pub roles: Vec<String>,
+
+    #[validate(custom(function = "validate_alphanumunderscore"))]
+    #[serde(rename = "grantee")]
+    pub grantee: String,
+}
+
+#[allow(dead_code)]
+impl GrantStatement {
+    pub fn new(
+        privileges: Vec<String>,
+        database: String,
+        table: String,
+        roles: Vec<String>,
+        grantee: String
+    ) -> Result<Self, ValidationErrors> {
+        let stmt = GrantStatement { privileges, database, table, roles, grantee };
+        stmt.validate()?;
+        Ok(stmt)
+    }
+}
+
+impl Statement for GrantStatement {
+    fn clone_box(&self) -> Box<dyn Statement> {
+        Box::new(self.clone())
+    }
+
+    fn protocol(&self) -> MessageType {
+        MessageType::Grant
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, encode::Error> {
+        encode::to_vec(self)
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<Box<dyn Statement>, decode::Error> {
+        let stmt: GrantStatement = decode::from_slice(data)?;
+        Ok(Box::new(stmt))
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "GrantStatement{{Privileges: {:?}, Database: {}, Table: {}, Roles: {:?}, Grantee: {}}}",
+            self.privileges,
+            self.database,
+            self.table,
+            self.roles,
+            self.grantee
+        )
+    }
+}
@@ -0,0 +1,87 @@
+use serde::{ Deserialize, Serialize };
+use validator::{ Validate, ValidationErrors };
+use rmp_serde::{ encode, decode };
+use crate::protocol::MessageType;
+use crate::statement::Statement;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
+pub struct GreetingStatement {
+    #[validate(range(min = 1))]
+    #[serde(rename = "protocol_version")]
+    pub protocol_version: u32,
+
+    #[validate(range(min = 1))]
+    #[serde(rename = "min_protocol_version")]
+    pub min_protocol_version: u32,
+
+    #[serde(rename = "message_types")]
+    pub message_types: Vec<u32>,
+
+    #[serde(rename = "compression")]
+    pub compression: Vec<String>,
+
+    #[validate(range(min = 1))]
+    #[serde(rename = "max_frame_size")]
+    pub max_frame_size: u32,
+
+    #[serde(rename = "node_id")]
+    pub node_id: String,
+}
+
+#[allow(dead_code)]
+impl GreetingStatement {
+    pub fn new(
+        protocol_version: u32,
+        min_protocol_version: u32,
+        message_types: Vec<u32>,
+        compression: Vec<String>,
+        max_frame_size: u32,
+        node_id: String
+    ) -> Result<Self, ValidationErrors> {
+        let stmt = GreetingStatement {
+            protocol_version,
+            min_protocol_version,
+            message_types,
+            compression,
+            max_frame_size,
+            node_id,
+        };
+        stmt.validate()?;
+        Ok(stmt)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, decode::Error> {
+        decode::from_slice(data)
+    }
+}
+
+impl Statement for GreetingStatement {
+    fn clone_box(&self) -> Box<dyn Statement> {
+        Box::new(self.clone())
+    }
+
+    fn protocol(&self) -> MessageType {
+        MessageType::Greeting
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, encode::Error> {
+        encode::to_vec(self)
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<Box<dyn Statement>, decode::Error> {
+        let stmt: GreetingStatement = decode::from_slice(data)?;
+        Ok(Box::new(stmt))
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "GreetingStatement{{ProtocolVersion: {}, MinProtocolVersion: {}, MessageTypes: {:?}, Compression: {:?}, MaxFrameSize: {}, NodeID: {}}}",
+            self.protocol_version,
+            self.min_protocol_version,
+            self.message_types,
+            self.compression,
+            self.max_frame_size,
+            self.node_id
+        )
+    }
+}
@@ -4,7 +4,7 @@ use validator::{Validate, ValidationErrors};
 use rmp_serde::{encode, decode};
 use crate::statement::validate_alphanumunderscore;
 use crate::protocol::MessageType;
-use crate::statement::Statement;
+use crate::statement::{ redact_values, Statement };
 
 #[derive(Debug, Serialize, Deserialize, Clone, Validate)]
 pub struct InsertStatement {
@@ -47,4 +47,8 @@ impl Statement for InsertStatement {
     fn to_string(&self) -> String {
         format!("InsertStatement{{TableName: {}, Values: {:?}}}", self.table_name, self.values)
     }
+
+    fn to_redacted_string(&self) -> String {
+        format!("InsertStatement{{TableName: {}, Values: {:?}}}", self.table_name, redact_values(&self.values))
+    }
 }
@@ -0,0 +1,99 @@
+use std::fmt;
+use std::str::FromStr;
+use serde::{ Deserialize, Serialize };
+use validator::Validate;
+use crate::statement::validate_alphanumunderscore;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum JoinKind {
+    #[default]
+    Inner,
+    /// Keeps every row of the tables joined so far, with NULLs for the
+    /// columns of this table when nothing matches.
+    Left,
+}
+
+/// One table joined to a select, such as `LEFT JOIN orders ON users.id =
+/// orders.user_id`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Validate)]
+pub struct Join {
+    #[serde(rename = "kind", default)]
+    pub kind: JoinKind,
+
+    #[validate(custom(function = "validate_alphanumunderscore"))]
+    #[serde(rename = "table_name")]
+    pub table_name: String,
+
+    /// Condition in the same syntax as `where`; empty for a cross join.
+    #[serde(rename = "on", default)]
+    pub on: String,
+}
+
+#[allow(dead_code)]
+impl Join {
+    pub fn inner(table_name: &str, on: &str) -> Self {
+        Join { kind: JoinKind::Inner, table_name: table_name.to_string(), on: on.to_string() }
+    }
+
+    pub fn left(table_name: &str, on: &str) -> Self {
+        Join { kind: JoinKind::Left, table_name: table_name.to_string(), on: on.to_string() }
+    }
+
+    /// Parses a semicolon separated list such as
+    /// `orders on users.id = orders.user_id; left payments on ...`.
+    pub fn parse_list(input: &str) -> Result<Vec<Join>, String> {
+        input
+            .split(';')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+            .map(Join::from_str)
+            .collect()
+    }
+}
+
+/// `input` after its first word if that is `keyword`, case-insensitively.
+fn strip_keyword<'a>(input: &'a str, keyword: &str) -> Option<&'a str> {
+    let (word, rest) = input.split_once(char::is_whitespace).unwrap_or((input, ""));
+    word.eq_ignore_ascii_case(keyword).then(|| rest.trim_start())
+}
+
+impl FromStr for Join {
+    type Err = String;
+
+    /// `[INNER | LEFT [OUTER]] [JOIN] table [ON condition]`; keywords are
+    /// case-insensitive.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut rest = input.trim();
+        let mut kind = JoinKind::Inner;
+        if let Some(after) = strip_keyword(rest, "left") {
+            kind = JoinKind::Left;
+            rest = strip_keyword(after, "outer").unwrap_or(after);
+        } else if let Some(after) = strip_keyword(rest, "inner") {
+            rest = after;
+        }
+        rest = strip_keyword(rest, "join").unwrap_or(rest);
+        let (table_name, rest) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let on = match rest.trim() {
+            "" => "",
+            rest => strip_keyword(rest, "on").ok_or_else(|| format!("cannot parse join {:?}", input))?,
+        };
+        let join = Join { kind, table_name: table_name.to_string(), on: on.to_string() };
+        join.validate().map_err(|e| e.to_string())?;
+        Ok(join)
+    }
+}
+
+impl fmt::Display for Join {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            JoinKind::Inner => "JOIN",
+            JoinKind::Left => "LEFT JOIN",
+        };
+        write!(f, "{} {}", kind, self.table_name)?;
+        if !self.on.trim().is_empty() {
+            write!(f, " ON {}", self.on)?;
+        }
+        Ok(())
+    }
+}
@@ -3,23 +3,35 @@ use validator::{ Validate, ValidationErrors };
 use rmp_serde::{ encode, decode };
 use crate::protocol::MessageType;
 use crate::utils;
+use crate::utils::config::REDACTED;
 use regex::Regex;
 use subtle::ConstantTimeEq;
 use chrono::Utc;
+use uuid::Uuid;
 
 use super::Statement;
 
+/// Node login on protocol version 2 and later.
 #[derive(Debug, Serialize, Deserialize, Clone, Validate)]
 pub struct LoginStatement {
     #[serde(rename = "timestamp")]
     pub timestamp: u64,
 
+    /// Random value that makes every login unique, so a captured frame
+    /// cannot be replayed inside the timestamp window.
+    #[serde(rename = "nonce")]
+    pub nonce: String,
+
     #[serde(rename = "is_replica")]
     pub is_replica: bool,
 
     #[serde(rename = "hash")]
     pub hash: String,
 
+    /// Id of the cluster key the hash was made with.
+    #[serde(rename = "key_id", default)]
+    pub key_id: String,
+
     #[serde(rename = "node_name")]
     pub node_name: String,
 
@@ -37,35 +49,175 @@ pub struct LoginStatement {
 impl LoginStatement {
     pub fn new(
         token: String,
+        key_id: String,
         node_id: String,
         node_name: String,
         is_replica: bool,
         address: String,
         tags: Vec<String>
     ) -> Result<Self, ValidationErrors> {
-        if token.is_empty() {
-            return Err(ValidationErrors::new());
-        }
+        check_login_fields(&token, &node_id, &node_name, &tags)?;
 
-        let re_node_id = Regex::new(r"^[a-zA-Z0-9_-]+$").unwrap();
-        let re_node_name = Regex::new(r"^[a-zA-Z0-9_]+$").unwrap();
+        let mut stmt = LoginStatement {
+            timestamp: Utc::now().timestamp_nanos_opt().unwrap() as u64,
+            nonce: Uuid::new_v4().simple().to_string(),
+            is_replica,
+            hash: String::new(),
+            key_id,
+            node_name,
+            node_id,
+            address,
+            tags,
+        };
+        stmt.hash = stmt.sign(&token);
 
-        if node_id.is_empty() || !re_node_id.is_match(&node_id) {
-            return Err(ValidationErrors::new());
-        }
+        stmt.validate()?;
+        Ok(stmt)
+    }
 
-        if node_name.is_empty() || !re_node_name.is_match(&node_name) {
-            return Err(ValidationErrors::new());
-        }
+    /// Signature over every field of the login except the hash itself. The
+    /// tags go in as their count followed by each tag, every one length
+    /// prefixed by `generate_hash`, so no two tag lists sign the same.
+    pub fn sign(&self, token: &str) -> String {
+        let timestamp = self.timestamp.to_string();
+        let is_replica = self.is_replica.to_string();
+        let tag_count = self.tags.len().to_string();
+        let mut fields = vec![
+            timestamp.as_str(),
+            &self.nonce,
+            &self.key_id,
+            &self.node_id,
+            &self.node_name,
+            &is_replica,
+            &self.address,
+            &tag_count
+        ];
+        fields.extend(self.tags.iter().map(String::as_str));
+        utils::generate_hash(token, &fields)
+    }
 
-        if tags.is_empty() || tags.iter().any(|t| (t.is_empty() || !re_node_id.is_match(t))) {
-            return Err(ValidationErrors::new());
-        }
+    pub fn validate_hash(&self, token: &str) -> bool {
+        let expected = self.sign(token);
+        self.hash.as_bytes().ct_eq(expected.as_bytes()).unwrap_u8() == 1
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, decode::Error> {
+        decode::from_slice(data)
+    }
+}
+
+fn check_login_fields(token: &str, node_id: &str, node_name: &str, tags: &[String]) -> Result<(), ValidationErrors> {
+    if token.is_empty() {
+        return Err(ValidationErrors::new());
+    }
+
+    let re_node_id = Regex::new(r"^[a-zA-Z0-9_-]+$").unwrap();
+    let re_node_name = Regex::new(r"^[a-zA-Z0-9_]+$").unwrap();
+
+    if node_id.is_empty() || !re_node_id.is_match(node_id) {
+        return Err(ValidationErrors::new());
+    }
+
+    if node_name.is_empty() || !re_node_name.is_match(node_name) {
+        return Err(ValidationErrors::new());
+    }
+
+    if tags.is_empty() || tags.iter().any(|t| t.is_empty() || !re_node_id.is_match(t)) {
+        return Err(ValidationErrors::new());
+    }
+
+    return Ok(());
+}
+
+impl Statement for LoginStatement {
+    fn clone_box(&self) -> Box<dyn Statement> {
+        Box::new(self.clone())
+    }
+
+    fn protocol(&self) -> MessageType {
+        MessageType::Login
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, encode::Error> {
+        encode::to_vec(self)
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<Box<dyn Statement>, decode::Error> {
+        let stmt: LoginStatement = decode::from_slice(data)?;
+        Ok(Box::new(stmt))
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "LoginStatement{{Timestamp: {}, Nonce: {}, KeyID: {}, NodeID: {}, NodeName: {}, IsReplica: {}, Tags: {:?}}}",
+            self.timestamp,
+            self.nonce,
+            self.key_id,
+            self.node_id,
+            self.node_name,
+            self.is_replica,
+            self.tags
+        )
+    }
+
+    /// The nonce is what keeps a captured login from being replayed.
+    fn to_redacted_string(&self) -> String {
+        format!(
+            "LoginStatement{{Timestamp: {}, Nonce: {}, KeyID: {}, NodeID: {}, NodeName: {}, IsReplica: {}, Tags: {:?}}}",
+            self.timestamp,
+            REDACTED,
+            self.key_id,
+            self.node_id,
+            self.node_name,
+            self.is_replica,
+            self.tags
+        )
+    }
+}
+
+/// Node login as sent on the legacy protocol, by peers that predate the
+/// handshake: no nonce or key id, and the hash leaves out the node name and
+/// address. See `generate_legacy_hash`.
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
+pub struct LegacyLoginStatement {
+    #[serde(rename = "timestamp")]
+    pub timestamp: u64,
+
+    #[serde(rename = "is_replica")]
+    pub is_replica: bool,
+
+    #[serde(rename = "hash")]
+    pub hash: String,
+
+    #[serde(rename = "node_name")]
+    pub node_name: String,
+
+    #[serde(rename = "node_id")]
+    pub node_id: String,
+
+    #[serde(rename = "address")]
+    pub address: String,
+
+    #[serde(rename = "tags")]
+    pub tags: Vec<String>,
+}
+
+#[allow(dead_code)]
+impl LegacyLoginStatement {
+    pub fn new(
+        token: String,
+        node_id: String,
+        node_name: String,
+        is_replica: bool,
+        address: String,
+        tags: Vec<String>
+    ) -> Result<Self, ValidationErrors> {
+        check_login_fields(&token, &node_id, &node_name, &tags)?;
 
         let timestamp = Utc::now().timestamp_nanos_opt().unwrap() as u64;
-        let hash = utils::generate_hash(&token, timestamp, &node_id, is_replica, &tags);
+        let hash = utils::generate_legacy_hash(&token, timestamp, &node_id, is_replica, &tags);
 
-        let stmt = LoginStatement {
+        let stmt = LegacyLoginStatement {
             timestamp,
             is_replica,
             hash,
@@ -79,19 +231,34 @@ impl LoginStatement {
         Ok(stmt)
     }
 
-    fn validate_hash(&self, token: &str) -> bool {
-        let expected = utils::generate_hash(
-            token,
-            self.timestamp,
-            &self.node_id,
-            self.is_replica,
-            &self.tags
-        );
+    pub fn validate_hash(&self, token: &str) -> bool {
+        let expected = utils::generate_legacy_hash(token, self.timestamp, &self.node_id, self.is_replica, &self.tags);
         self.hash.as_bytes().ct_eq(expected.as_bytes()).unwrap_u8() == 1
     }
+
+    pub fn decode(data: &[u8]) -> Result<Self, decode::Error> {
+        decode::from_slice(data)
+    }
 }
 
-impl Statement for LoginStatement {
+/// The same login without a nonce or key id, once its own hash was checked.
+impl From<LegacyLoginStatement> for LoginStatement {
+    fn from(stmt: LegacyLoginStatement) -> Self {
+        LoginStatement {
+            timestamp: stmt.timestamp,
+            nonce: String::new(),
+            is_replica: stmt.is_replica,
+            hash: stmt.hash,
+            key_id: String::new(),
+            node_name: stmt.node_name,
+            node_id: stmt.node_id,
+            address: stmt.address,
+            tags: stmt.tags,
+        }
+    }
+}
+
+impl Statement for LegacyLoginStatement {
     fn clone_box(&self) -> Box<dyn Statement> {
         Box::new(self.clone())
     }
@@ -105,13 +272,13 @@ impl Statement for LoginStatement {
     }
 
     fn from_bytes(data: &[u8]) -> Result<Box<dyn Statement>, decode::Error> {
-        let stmt: LoginStatement = decode::from_slice(data)?;
+        let stmt: LegacyLoginStatement = decode::from_slice(data)?;
         Ok(Box::new(stmt))
     }
 
     fn to_string(&self) -> String {
         format!(
-            "LoginStatement{{Timestamp: {}, NodeID: {}, NodeName: {}, IsReplica: {}, Tags: {:?}}}",
+            "LegacyLoginStatement{{Timestamp: {}, NodeID: {}, NodeName: {}, IsReplica: {}, Tags: {:?}}}",
             self.timestamp,
             self.node_id,
             self.node_name,
@@ -1,11 +1,21 @@
 pub mod validate;
-pub use validate::validate_alphanumunderscore;
+pub use validate::{ validate_alphanumunderscore, validate_column_name, validate_column_names, validate_name_or_wildcard };
 
 pub mod column_definition;
 pub use column_definition::ColumnDefinition;
 
+pub mod order_by;
+pub use order_by::OrderBy;
+
+pub mod aggregate;
+pub use aggregate::{ Aggregate, AggregateFunction };
+
+pub mod join;
+pub use join::{ Join, JoinKind };
+
+#[allow(clippy::module_inception)]
 pub mod statement;
-pub use statement::Statement;
+pub use statement::{ redact_filter, redact_values, Statement };
 
 pub mod alter_table_statement;
 pub use alter_table_statement::AlterTableStatement;
@@ -25,9 +35,18 @@ pub use create_database_statement::CreateDatabaseStatement;
 pub mod create_index_statement;
 pub use create_index_statement::CreateIndexStatement;
 
+pub mod create_role_statement;
+pub use create_role_statement::CreateRoleStatement;
+
 pub mod create_table_statement;
 pub use create_table_statement::CreateTableStatement;
 
+pub mod create_user_statement;
+pub use create_user_statement::CreateUserStatement;
+
+pub mod deallocate_statement;
+pub use deallocate_statement::DeallocateStatement;
+
 pub mod delete_statement;
 pub use delete_statement::DeleteStatement;
 
@@ -43,14 +62,38 @@ pub use drop_index_statement::DropIndexStatement;
 pub mod drop_table_statement;
 pub use drop_table_statement::DropTableStatement;
 
+pub mod drop_user_statement;
+pub use drop_user_statement::DropUserStatement;
+
 pub mod empty_statement;
 pub use empty_statement::EmptyStatement;
 
+pub mod error_statement;
+pub use error_statement::{ ErrorCode, ErrorStatement };
+
+pub mod execute_statement;
+pub use execute_statement::ExecuteStatement;
+
+pub mod explain_statement;
+pub use explain_statement::ExplainStatement;
+
+pub mod grant_statement;
+pub use grant_statement::GrantStatement;
+
+pub mod greeting_statement;
+pub use greeting_statement::GreetingStatement;
+
 pub mod insert_statement;
 pub use insert_statement::InsertStatement;
 
 pub mod login_statement;
-pub use login_statement::LoginStatement;
+pub use login_statement::{ LegacyLoginStatement, LoginStatement };
+
+pub mod prepare_statement;
+pub use prepare_statement::PrepareStatement;
+
+pub mod query_statement;
+pub use query_statement::QueryStatement;
 
 pub mod release_savepoint_statement;
 pub use release_savepoint_statement::ReleaseSavepointStatement;
@@ -58,6 +101,12 @@ pub use release_savepoint_statement::ReleaseSavepointStatement;
 pub mod rename_table_statement;
 pub use rename_table_statement::RenameTableStatement;
 
+pub mod result_statement;
+pub use result_statement::ResultStatement;
+
+pub mod revoke_statement;
+pub use revoke_statement::RevokeStatement;
+
 pub mod rollback_statement;
 pub use rollback_statement::RollbackStatement;
 
@@ -70,6 +119,12 @@ pub use select_statement::SelectStatement;
 pub mod show_indexes_statement;
 pub use show_indexes_statement::ShowIndexesStatement;
 
+pub mod show_slow_queries_statement;
+pub use show_slow_queries_statement::ShowSlowQueriesStatement;
+
+pub mod stream_credit_statement;
+pub use stream_credit_statement::StreamCreditStatement;
+
 pub mod truncate_table_statement;
 pub use truncate_table_statement::TruncateTableStatement;
 
@@ -79,4 +134,13 @@ pub use update_statement::UpdateStatement;
 pub mod upsert_statement;
 pub use upsert_statement::UpsertStatement;
 
+pub mod use_database_statement;
+pub use use_database_statement::UseDatabaseStatement;
+
+pub mod user_login_statement;
+pub use user_login_statement::UserLoginStatement;
+
+pub mod welcome_statement;
+pub use welcome_statement::WelcomeStatement;
+
 pub mod error;
\ No newline at end of file
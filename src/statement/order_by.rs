@@ -0,0 +1,95 @@
+use std::fmt;
+use std::str::FromStr;
+use serde::{ Deserialize, Serialize };
+use validator::Validate;
+use crate::statement::validate_column_name;
+
+/// One key of an `order_by`. Without `nulls_first`, NULL sorts above every
+/// other value: last ascending, first descending.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Validate)]
+pub struct OrderBy {
+    #[validate(custom(function = "validate_column_name"))]
+    #[serde(rename = "column")]
+    pub column: String,
+
+    #[serde(rename = "descending", default)]
+    pub descending: bool,
+
+    #[serde(rename = "nulls_first", default)]
+    pub nulls_first: Option<bool>,
+}
+
+#[allow(dead_code)]
+impl OrderBy {
+    pub fn asc(column: &str) -> Self {
+        OrderBy { column: column.to_string(), descending: false, nulls_first: None }
+    }
+
+    pub fn desc(column: &str) -> Self {
+        OrderBy { column: column.to_string(), descending: true, nulls_first: None }
+    }
+
+    pub fn nulls_first(mut self, nulls_first: bool) -> Self {
+        self.nulls_first = Some(nulls_first);
+        self
+    }
+
+    pub fn places_nulls_first(&self) -> bool {
+        self.nulls_first.unwrap_or(self.descending)
+    }
+
+    /// Parses a comma separated list such as `age desc nulls last, name`.
+    pub fn parse_list(input: &str) -> Result<Vec<OrderBy>, String> {
+        input
+            .split(',')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+            .map(OrderBy::from_str)
+            .collect()
+    }
+}
+
+impl FromStr for OrderBy {
+    type Err = String;
+
+    /// `column [ASC | DESC] [NULLS FIRST | NULLS LAST]`, case-insensitive.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let words: Vec<String> = input
+            .split_whitespace()
+            .map(|word| word.to_ascii_uppercase())
+            .collect();
+        let column = input.split_whitespace().next().ok_or("empty sort key")?;
+        let mut order = OrderBy::asc(column);
+        let mut rest = &words[1..];
+        match rest.first().map(String::as_str) {
+            Some("ASC") => rest = &rest[1..],
+            Some("DESC") => {
+                order.descending = true;
+                rest = &rest[1..];
+            }
+            _ => {}
+        }
+        match rest {
+            [] => {}
+            [nulls, first] if nulls == "NULLS" && first == "FIRST" => order.nulls_first = Some(true),
+            [nulls, last] if nulls == "NULLS" && last == "LAST" => order.nulls_first = Some(false),
+            _ => {
+                return Err(format!("cannot parse sort key {:?}", input));
+            }
+        }
+        order.validate().map_err(|e| e.to_string())?;
+        Ok(order)
+    }
+}
+
+impl fmt::Display for OrderBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} NULLS {}",
+            self.column,
+            if self.descending { "DESC" } else { "ASC" },
+            if self.places_nulls_first() { "FIRST" } else { "LAST" }
+        )
+    }
+}
@@ -0,0 +1,60 @@
+use serde::{ Deserialize, Serialize };
+use validator::{ Validate, ValidationErrors };
+use rmp_serde::{ encode, decode };
+use crate::protocol::MessageType;
+use crate::sql;
+use crate::statement::{ Statement, validate_alphanumunderscore };
+
+/// Compiles and plans one SQL statement with `?` or `$n` placeholders under
+/// `name`, for the connection that sent it.
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
+pub struct PrepareStatement {
+    #[validate(custom(function = "validate_alphanumunderscore"))]
+    #[serde(rename = "name")]
+    pub name: String,
+
+    #[validate(length(min = 1))]
+    #[serde(rename = "sql")]
+    pub sql: String,
+}
+
+#[allow(dead_code)]
+impl PrepareStatement {
+    pub fn new(name: String, sql: String) -> Result<Self, ValidationErrors> {
+        let stmt = PrepareStatement { name, sql };
+        stmt.validate()?;
+        Ok(stmt)
+    }
+}
+
+impl Statement for PrepareStatement {
+    fn clone_box(&self) -> Box<dyn Statement> {
+        Box::new(self.clone())
+    }
+
+    fn protocol(&self) -> MessageType {
+        MessageType::Prepare
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, encode::Error> {
+        encode::to_vec(self)
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<Box<dyn Statement>, decode::Error> {
+        let stmt: PrepareStatement = decode::from_slice(data)?;
+        Ok(Box::new(stmt))
+    }
+
+    fn to_string(&self) -> String {
+        format!("PrepareStatement{{Name: {}, Sql: {}}}", self.name, self.sql)
+    }
+
+    fn to_redacted_string(&self) -> String {
+        match sql::parse_prepared(&self.sql) {
+            Ok(prepared) => {
+                format!("PrepareStatement{{Name: {}, Statement: {}}}", self.name, prepared.statement.to_redacted_string())
+            }
+            Err(e) => format!("PrepareStatement{{Name: {}, Error: {}}}", self.name, e),
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use serde::{ Deserialize, Serialize };
+use validator::{ Validate, ValidationErrors };
+use rmp_serde::{ encode, decode };
+use crate::protocol::MessageType;
+use crate::sql;
+use crate::statement::Statement;
+
+/// Raw SQL text: one statement, or several separated by `;` that run in
+/// order.
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
+pub struct QueryStatement {
+    #[validate(length(min = 1))]
+    #[serde(rename = "sql")]
+    pub sql: String,
+}
+
+#[allow(dead_code)]
+impl QueryStatement {
+    pub fn new(sql: String) -> Result<Self, ValidationErrors> {
+        let stmt = QueryStatement { sql };
+        stmt.validate()?;
+        Ok(stmt)
+    }
+}
+
+impl Statement for QueryStatement {
+    fn clone_box(&self) -> Box<dyn Statement> {
+        Box::new(self.clone())
+    }
+
+    fn protocol(&self) -> MessageType {
+        MessageType::Query
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, encode::Error> {
+        encode::to_vec(self)
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<Box<dyn Statement>, decode::Error> {
+        let stmt: QueryStatement = decode::from_slice(data)?;
+        Ok(Box::new(stmt))
+    }
+
+    fn to_string(&self) -> String {
+        format!("QueryStatement{{Sql: {}}}", self.sql)
+    }
+
+    /// The compiled statements, redacted; literals in the text may be
+    /// sensitive, so text that does not compile is left out.
+    fn to_redacted_string(&self) -> String {
+        match sql::parse(&self.sql) {
+            Ok(statements) => {
+                let statements: Vec<String> = statements.iter().map(|stmt| stmt.to_redacted_string()).collect();
+                format!("QueryStatement{{Statements: [{}]}}", statements.join(", "))
+            }
+            Err(e) => format!("QueryStatement{{Error: {}}}", e),
+        }
+    }
+}
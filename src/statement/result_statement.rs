@@ -0,0 +1,55 @@
+use serde::{ Deserialize, Serialize };
+use rmp_serde::{ encode, decode };
+use crate::protocol::MessageType;
+use crate::statement::Statement;
+
+/// Response to a statement that does not return rows.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ResultStatement {
+    #[serde(rename = "message_type")]
+    pub message_type: u32,
+
+    #[serde(rename = "affected_rows")]
+    pub affected_rows: u64,
+}
+
+#[allow(dead_code)]
+impl ResultStatement {
+    pub fn new(message_type: MessageType, affected_rows: u64) -> Self {
+        Self {
+            message_type: message_type as u32,
+            affected_rows,
+        }
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, decode::Error> {
+        decode::from_slice(data)
+    }
+}
+
+impl Statement for ResultStatement {
+    fn clone_box(&self) -> Box<dyn Statement> {
+        Box::new(self.clone())
+    }
+
+    fn protocol(&self) -> MessageType {
+        MessageType::from_id(self.message_type)
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, encode::Error> {
+        encode::to_vec(self)
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<Box<dyn Statement>, decode::Error> {
+        let stmt: ResultStatement = decode::from_slice(data)?;
+        Ok(Box::new(stmt))
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "ResultStatement{{MessageType: {}, AffectedRows: {}}}",
+            self.protocol().to_name(),
+            self.affected_rows
+        )
+    }
+}
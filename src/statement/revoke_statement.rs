@@ -0,0 +1,72 @@
+use serde::{ Deserialize, Serialize };
+use validator::{ Validate, ValidationErrors };
+use rmp_serde::{ encode, decode };
+use crate::statement::{ Statement, validate_alphanumunderscore, validate_name_or_wildcard };
+use crate::protocol::MessageType;
+
+/// Reverses a `GrantStatement` with the same privileges, object and roles.
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
+pub struct RevokeStatement {
+    #[serde(rename = "privileges")]
+    pub privileges: Vec<String>,
+
+    #[validate(custom(function = "validate_name_or_wildcard"))]
+    #[serde(rename = "database")]
+    pub database: String,
+
+    #[validate(custom(function = "validate_name_or_wildcard"))]
+    #[serde(rename = "table")]
+    pub table: String,
+
+    #[serde(rename = "roles")]
+    pub roles: Vec<String>,
+
+    #[validate(custom(function = "validate_alphanumunderscore"))]
+    #[serde(rename = "grantee")]
+    pub grantee: String,
+}
+
+#[allow(dead_code)]
+impl RevokeStatement {
+    pub fn new(
+        privileges: Vec<String>,
+        database: String,
+        table: String,
+        roles: Vec<String>,
+        grantee: String
+    ) -> Result<Self, ValidationErrors> {
+        let stmt = RevokeStatement { privileges, database, table, roles, grantee };
+        stmt.validate()?;
+        Ok(stmt)
+    }
+}
+
+impl Statement for RevokeStatement {
+    fn clone_box(&self) -> Box<dyn Statement> {
+        Box::new(self.clone())
+    }
+
+    fn protocol(&self) -> MessageType {
+        MessageType::Revoke
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, encode::Error> {
+        encode::to_vec(self)
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<Box<dyn Statement>, decode::Error> {
+        let stmt: RevokeStatement = decode::from_slice(data)?;
+        Ok(Box::new(stmt))
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "RevokeStatement{{Privileges: {:?}, Database: {}, Table: {}, Roles: {:?}, Grantee: {}}}",
+            self.privileges,
+            self.database,
+            self.table,
+            self.roles,
+            self.grantee
+        )
+    }
+}
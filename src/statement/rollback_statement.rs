@@ -9,12 +9,23 @@ pub struct RollbackStatement {
     #[validate(custom(function = "validate_alphanumunderscore"))]
     #[serde(rename = "transaction_id")]
     pub transaction_id: String,
+
+    /// Rolls back only what was done since this savepoint, which stays.
+    #[validate(custom(function = "validate_alphanumunderscore"))]
+    #[serde(rename = "savepoint_name", default, skip_serializing_if = "Option::is_none")]
+    pub savepoint_name: Option<String>,
 }
 
 #[allow(dead_code)]
 impl RollbackStatement {
     pub fn new(transaction_id: String) -> Result<Self, ValidationErrors> {
-        let stmt = RollbackStatement { transaction_id };
+        let stmt = RollbackStatement { transaction_id, savepoint_name: None };
+        stmt.validate()?;
+        Ok(stmt)
+    }
+
+    pub fn to_savepoint(transaction_id: String, savepoint_name: String) -> Result<Self, ValidationErrors> {
+        let stmt = RollbackStatement { transaction_id, savepoint_name: Some(savepoint_name) };
         stmt.validate()?;
         Ok(stmt)
     }
@@ -39,6 +50,10 @@ impl Statement for RollbackStatement {
     }
 
     fn to_string(&self) -> String {
-        format!("RollbackStatement{{TransactionID: {}}}", self.transaction_id)
+        match &self.savepoint_name {
+            Some(savepoint) =>
+                format!("RollbackStatement{{TransactionID: {}, SavepointName: {}}}", self.transaction_id, savepoint),
+            None => format!("RollbackStatement{{TransactionID: {}}}", self.transaction_id),
+        }
     }
 }
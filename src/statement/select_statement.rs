@@ -2,7 +2,15 @@ use serde::{Deserialize, Serialize};
 use validator::{Validate, ValidationErrors};
 use rmp_serde::{encode, decode};
 use crate::protocol::MessageType;
-use crate::statement::{ validate_alphanumunderscore, Statement };
+use crate::statement::{
+    redact_filter,
+    validate_alphanumunderscore,
+    validate_column_names,
+    Aggregate,
+    Join,
+    OrderBy,
+    Statement,
+};
 
 #[derive(Debug, Serialize, Deserialize, Clone, Validate)]
 pub struct SelectStatement {
@@ -15,15 +23,149 @@ pub struct SelectStatement {
 
     #[serde(rename = "where")]
     pub r#where: String,
+
+    #[validate(nested)]
+    #[serde(rename = "order_by", default)]
+    pub order_by: Vec<OrderBy>,
+
+    #[serde(rename = "limit", default)]
+    pub limit: Option<u64>,
+
+    #[serde(rename = "offset", default)]
+    pub offset: u64,
+
+    /// Continuation token from the previous page; the next page starts
+    /// right after the last row of that one. Needs `order_by` and `limit`.
+    #[serde(rename = "after", default)]
+    pub after: Option<String>,
+
+    /// With aggregates or `group_by` the result has one row per group, with
+    /// the `group_by` columns and one column per aggregate.
+    #[validate(nested)]
+    #[serde(rename = "aggregates", default)]
+    pub aggregates: Vec<Aggregate>,
+
+    #[validate(custom(function = "validate_column_names"))]
+    #[serde(rename = "group_by", default)]
+    pub group_by: Vec<String>,
+
+    /// Filter on the groups, over the columns of the result.
+    #[serde(rename = "having", default)]
+    pub having: String,
+
+    /// Tables joined to `table_name`, in the order written. Joined rows
+    /// name their columns `table.column`; a bare name that only one of the
+    /// tables has can be used anywhere a column is read.
+    #[validate(nested)]
+    #[serde(rename = "joins", default)]
+    pub joins: Vec<Join>,
 }
 
 #[allow(dead_code)]
 impl SelectStatement {
     pub fn new(table_name: String, columns: Vec<String>, r#where: String) -> Result<Self, ValidationErrors> {
-        let stmt = SelectStatement { table_name, columns, r#where };
+        let stmt = SelectStatement {
+            table_name,
+            columns,
+            r#where,
+            order_by: Vec::new(),
+            limit: None,
+            offset: 0,
+            after: None,
+            aggregates: Vec::new(),
+            group_by: Vec::new(),
+            having: String::new(),
+            joins: Vec::new(),
+        };
         stmt.validate()?;
         Ok(stmt)
     }
+
+    pub fn with_order_by(mut self, order_by: Vec<OrderBy>) -> Result<Self, ValidationErrors> {
+        self.order_by = order_by;
+        self.validate()?;
+        Ok(self)
+    }
+
+    pub fn with_limit(mut self, limit: u64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn with_offset(mut self, offset: u64) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn with_after(mut self, token: String) -> Self {
+        self.after = Some(token);
+        self
+    }
+
+    pub fn with_aggregates(mut self, aggregates: Vec<Aggregate>) -> Result<Self, ValidationErrors> {
+        self.aggregates = aggregates;
+        self.validate()?;
+        Ok(self)
+    }
+
+    pub fn with_group_by(mut self, group_by: Vec<String>) -> Result<Self, ValidationErrors> {
+        self.group_by = group_by;
+        self.validate()?;
+        Ok(self)
+    }
+
+    pub fn with_having(mut self, having: String) -> Self {
+        self.having = having;
+        self
+    }
+
+    /// The statement, with the filters and join conditions passed through
+    /// `filter`.
+    fn describe(&self, filter: impl Fn(&str) -> String) -> String {
+        let order_by: Vec<String> = self.order_by.iter().map(|order| order.to_string()).collect();
+        let aggregates: Vec<String> = self.aggregates.iter().map(|aggregate| aggregate.to_string()).collect();
+        let joins: Vec<String> = self.joins
+            .iter()
+            .map(|join| Join { on: filter(&join.on), ..join.clone() }.to_string())
+            .collect();
+        format!(
+            "SelectStatement{{TableName: {}, Joins: {:?}, Columns: {:?}, Aggregates: {:?}, Where: {}, GroupBy: {:?}, Having: {}, OrderBy: {:?}, Limit: {:?}, Offset: {}, After: {}}}",
+            self.table_name,
+            joins,
+            self.columns,
+            aggregates,
+            filter(&self.r#where),
+            self.group_by,
+            filter(&self.having),
+            order_by,
+            self.limit,
+            self.offset,
+            self.after.is_some()
+        )
+    }
+
+    pub fn with_joins(mut self, joins: Vec<Join>) -> Result<Self, ValidationErrors> {
+        self.joins = joins;
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// Every table read: `table_name`, then the joined ones.
+    pub fn tables(&self) -> Vec<&str> {
+        let mut tables = vec![self.table_name.as_str()];
+        tables.extend(self.joins.iter().map(|join| join.table_name.as_str()));
+        tables
+    }
+
+    /// Whether rows are grouped: with aggregates, `group_by`, or both.
+    pub fn is_aggregate(&self) -> bool {
+        !self.aggregates.is_empty() || !self.group_by.is_empty()
+    }
+
+    /// Whether the response carries a continuation token.
+    pub fn is_paged(&self) -> bool {
+        !self.order_by.is_empty() && self.limit.is_some()
+    }
 }
 
 impl Statement for SelectStatement {
@@ -45,6 +187,11 @@ impl Statement for SelectStatement {
     }
 
     fn to_string(&self) -> String {
-        format!("SelectStatement{{TableName: {}, Columns: {:?}, Where: {}}}", self.table_name, self.columns, self.r#where)
+        self.describe(|filter| filter.to_string())
+    }
+
+    /// Filters and join conditions may compare columns to sensitive values.
+    fn to_redacted_string(&self) -> String {
+        self.describe(redact_filter)
     }
 }
@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationErrors};
+use rmp_serde::{encode, decode};
+use crate::protocol::MessageType;
+use crate::statement::Statement;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, Validate)]
+pub struct ShowSlowQueriesStatement {
+    /// Most recent entries to return; 0 returns every entry kept.
+    #[serde(rename = "limit", default)]
+    pub limit: u32,
+}
+
+#[allow(dead_code)]
+impl ShowSlowQueriesStatement {
+    pub fn new(limit: u32) -> Result<Self, ValidationErrors> {
+        let stmt = ShowSlowQueriesStatement { limit };
+        stmt.validate()?;
+        Ok(stmt)
+    }
+}
+
+impl Statement for ShowSlowQueriesStatement {
+    fn clone_box(&self) -> Box<dyn Statement> {
+        Box::new(self.clone())
+    }
+
+    fn protocol(&self) -> MessageType {
+        MessageType::ShowSlowQueries
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, encode::Error> {
+        encode::to_vec(self)
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<Box<dyn Statement>, decode::Error> {
+        let stmt: ShowSlowQueriesStatement = decode::from_slice(data)?;
+        Ok(Box::new(stmt))
+    }
+
+    fn to_string(&self) -> String {
+        format!("ShowSlowQueriesStatement{{Limit: {}}}", self.limit)
+    }
+}
@@ -1,6 +1,9 @@
+use std::collections::{ BTreeMap, HashMap };
 use crate::protocol::MessageType;
 use crate::statement::*;
 use crate::statement::error::UnsupportedStatementError;
+use crate::storage::expression::{ tokenize_with_spans, Token };
+use crate::utils::config::REDACTED;
 
 pub trait Statement {
     fn clone_box(&self) -> Box<dyn Statement>;
@@ -11,6 +14,38 @@ pub trait Statement {
         where Self: Sized;
 
     fn to_string(&self) -> String;
+
+    /// `to_string()` without values that may be sensitive, for logs.
+    fn to_redacted_string(&self) -> String {
+        self.to_string()
+    }
+}
+
+/// Column names of `values` with every value replaced by `REDACTED`.
+pub fn redact_values(values: &HashMap<String, serde_json::Value>) -> BTreeMap<&str, &'static str> {
+    values.keys().map(|column| (column.as_str(), REDACTED)).collect()
+}
+
+/// A filter with every literal replaced by `REDACTED`; all of it when it
+/// does not tokenize.
+pub fn redact_filter(filter: &str) -> String {
+    let tokens = match tokenize_with_spans(filter) {
+        Ok(tokens) => tokens,
+        Err(_) => {
+            return REDACTED.to_string();
+        }
+    };
+    let mut redacted = String::new();
+    let mut copied = 0;
+    for (token, (start, end)) in tokens {
+        if matches!(token, Token::Number(_) | Token::String(_)) {
+            redacted.push_str(&filter[copied..start]);
+            redacted.push_str(REDACTED);
+            copied = end;
+        }
+    }
+    redacted.push_str(&filter[copied..]);
+    redacted
 }
 
 impl Clone for Box<dyn Statement> {
@@ -36,6 +71,11 @@ pub fn deserialize_statement(
                 message_type: MessageType::DropDatabase,
                 message: "Unsupported statement".to_string(),
             }),
+        MessageType::UseDatabase =>
+            UseDatabaseStatement::from_bytes(data).map_err(|_| UnsupportedStatementError {
+                message_type: MessageType::UseDatabase,
+                message: "Unsupported statement".to_string(),
+            }),
 
         // Table Operations
         MessageType::CreateTable =>
@@ -156,6 +196,70 @@ pub fn deserialize_statement(
                 message_type: MessageType::Login,
                 message: "Unsupported statement".to_string(),
             }),
+        MessageType::CreateUser =>
+            CreateUserStatement::from_bytes(data).map_err(|_| UnsupportedStatementError {
+                message_type: MessageType::CreateUser,
+                message: "Unsupported statement".to_string(),
+            }),
+        MessageType::DropUser =>
+            DropUserStatement::from_bytes(data).map_err(|_| UnsupportedStatementError {
+                message_type: MessageType::DropUser,
+                message: "Unsupported statement".to_string(),
+            }),
+        MessageType::CreateRole =>
+            CreateRoleStatement::from_bytes(data).map_err(|_| UnsupportedStatementError {
+                message_type: MessageType::CreateRole,
+                message: "Unsupported statement".to_string(),
+            }),
+        MessageType::Grant =>
+            GrantStatement::from_bytes(data).map_err(|_| UnsupportedStatementError {
+                message_type: MessageType::Grant,
+                message: "Unsupported statement".to_string(),
+            }),
+        MessageType::Revoke =>
+            RevokeStatement::from_bytes(data).map_err(|_| UnsupportedStatementError {
+                message_type: MessageType::Revoke,
+                message: "Unsupported statement".to_string(),
+            }),
+        MessageType::UserLogin =>
+            UserLoginStatement::from_bytes(data).map_err(|_| UnsupportedStatementError {
+                message_type: MessageType::UserLogin,
+                message: "Unsupported statement".to_string(),
+            }),
+
+        // Diagnostics
+        MessageType::ShowSlowQueries =>
+            ShowSlowQueriesStatement::from_bytes(data).map_err(|_| UnsupportedStatementError {
+                message_type: MessageType::ShowSlowQueries,
+                message: "Unsupported statement".to_string(),
+            }),
+        MessageType::Explain =>
+            ExplainStatement::from_bytes(data).map_err(|_| UnsupportedStatementError {
+                message_type: MessageType::Explain,
+                message: "Unsupported statement".to_string(),
+            }),
+
+        // SQL
+        MessageType::Query =>
+            QueryStatement::from_bytes(data).map_err(|_| UnsupportedStatementError {
+                message_type: MessageType::Query,
+                message: "Unsupported statement".to_string(),
+            }),
+        MessageType::Prepare =>
+            PrepareStatement::from_bytes(data).map_err(|_| UnsupportedStatementError {
+                message_type: MessageType::Prepare,
+                message: "Unsupported statement".to_string(),
+            }),
+        MessageType::Execute =>
+            ExecuteStatement::from_bytes(data).map_err(|_| UnsupportedStatementError {
+                message_type: MessageType::Execute,
+                message: "Unsupported statement".to_string(),
+            }),
+        MessageType::Deallocate =>
+            DeallocateStatement::from_bytes(data).map_err(|_| UnsupportedStatementError {
+                message_type: MessageType::Deallocate,
+                message: "Unsupported statement".to_string(),
+            }),
 
         // Utility Commands
         MessageType::Ping =>
@@ -169,22 +273,32 @@ pub fn deserialize_statement(
                 message: "Unsupported statement".to_string(),
             }),
         MessageType::Greeting =>
-            EmptyStatement::from_bytes(data).map_err(|_| UnsupportedStatementError {
+            GreetingStatement::from_bytes(data).map_err(|_| UnsupportedStatementError {
                 message_type: MessageType::Greeting,
                 message: "Unsupported statement".to_string(),
             }),
         MessageType::Welcome =>
-            EmptyStatement::from_bytes(data).map_err(|_| UnsupportedStatementError {
+            WelcomeStatement::from_bytes(data).map_err(|_| UnsupportedStatementError {
                 message_type: MessageType::Welcome,
                 message: "Unsupported statement".to_string(),
             }),
+        MessageType::StreamCredit =>
+            StreamCreditStatement::from_bytes(data).map_err(|_| UnsupportedStatementError {
+                message_type: MessageType::StreamCredit,
+                message: "Unsupported statement".to_string(),
+            }),
+        MessageType::Error =>
+            ErrorStatement::from_bytes(data).map_err(|_| UnsupportedStatementError {
+                message_type: MessageType::Error,
+                message: "Unsupported statement".to_string(),
+            }),
 
         // Unsupported
         _ => Err(UnsupportedStatementError {
-            message_type: message_type,
+            message_type,
             message: "Unsupported statement".to_string(),
         }),
     };
 
-    result
+    return result;
 }
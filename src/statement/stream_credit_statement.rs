@@ -0,0 +1,53 @@
+use serde::{ Deserialize, Serialize };
+use rmp_serde::{ encode, decode };
+use crate::protocol::MessageType;
+use crate::statement::Statement;
+
+/// Grants the peer permission to send `credits` more chunks on the stream
+/// identified by the message id, or asks it to stop when `cancel` is set.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StreamCreditStatement {
+    #[serde(rename = "credits")]
+    pub credits: u32,
+
+    #[serde(rename = "cancel")]
+    pub cancel: bool,
+}
+
+#[allow(dead_code)]
+impl StreamCreditStatement {
+    pub fn new(credits: u32) -> Self {
+        Self { credits, cancel: false }
+    }
+
+    pub fn cancel() -> Self {
+        Self { credits: 0, cancel: true }
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, decode::Error> {
+        decode::from_slice(data)
+    }
+}
+
+impl Statement for StreamCreditStatement {
+    fn clone_box(&self) -> Box<dyn Statement> {
+        Box::new(self.clone())
+    }
+
+    fn protocol(&self) -> MessageType {
+        MessageType::StreamCredit
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, encode::Error> {
+        encode::to_vec(self)
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<Box<dyn Statement>, decode::Error> {
+        let stmt: StreamCreditStatement = decode::from_slice(data)?;
+        Ok(Box::new(stmt))
+    }
+
+    fn to_string(&self) -> String {
+        format!("StreamCreditStatement{{Credits: {}, Cancel: {}}}", self.credits, self.cancel)
+    }
+}
@@ -3,7 +3,7 @@ use validator::{Validate, ValidationErrors};
 use rmp_serde::{encode, decode};
 use std::collections::HashMap;
 use crate::protocol::MessageType;
-use crate::statement::{ validate_alphanumunderscore, Statement };
+use crate::statement::{ redact_filter, redact_values, validate_alphanumunderscore, Statement };
 
 #[derive(Debug, Serialize, Deserialize, Clone, Validate)]
 pub struct UpdateStatement {
@@ -49,4 +49,13 @@ impl Statement for UpdateStatement {
     fn to_string(&self) -> String {
         format!("UpdateStatement{{TableName: {}, Updates: {:?}, Where: {}}}", self.table_name, self.updates, self.where_clause)
     }
+
+    fn to_redacted_string(&self) -> String {
+        format!(
+            "UpdateStatement{{TableName: {}, Updates: {:?}, Where: {}}}",
+            self.table_name,
+            redact_values(&self.updates),
+            redact_filter(&self.where_clause)
+        )
+    }
 }
@@ -2,7 +2,7 @@ use serde::{ Deserialize, Serialize };
 use validator::{ Validate, ValidationErrors };
 use rmp_serde::{ encode, decode };
 use std::collections::HashMap;
-use crate::statement::{ Statement, validate_alphanumunderscore };
+use crate::statement::{ redact_values, Statement, validate_alphanumunderscore };
 use crate::protocol::MessageType;
 
 #[derive(Debug, Serialize, Deserialize, Clone, Validate)]
@@ -58,4 +58,13 @@ impl Statement for UpsertStatement {
             self.unique_key
         )
     }
+
+    fn to_redacted_string(&self) -> String {
+        format!(
+            "UpsertStatement{{TableName: {}, Values: {:?}, UniqueKey: {}}}",
+            self.table_name,
+            redact_values(&self.values),
+            self.unique_key
+        )
+    }
 }
@@ -0,0 +1,61 @@
+use serde::{ Deserialize, Serialize };
+use validator::{ Validate, ValidationErrors };
+use rmp_serde::{ encode, decode };
+use crate::statement::{ Statement, validate_alphanumunderscore };
+use crate::protocol::MessageType;
+
+/// Login of an application user, as opposed to `LoginStatement` for nodes.
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
+pub struct UserLoginStatement {
+    #[validate(custom(function = "validate_alphanumunderscore"))]
+    #[serde(rename = "username")]
+    pub username: String,
+
+    #[validate(length(min = 1))]
+    #[serde(rename = "password")]
+    pub password: String,
+
+    /// Database selected after login; empty keeps the default one.
+    #[serde(rename = "database", default)]
+    pub database: String,
+}
+
+#[allow(dead_code)]
+impl UserLoginStatement {
+    pub fn new(
+        username: String,
+        password: String,
+        database: String
+    ) -> Result<Self, ValidationErrors> {
+        let stmt = UserLoginStatement { username, password, database };
+        stmt.validate()?;
+        Ok(stmt)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, decode::Error> {
+        decode::from_slice(data)
+    }
+}
+
+impl Statement for UserLoginStatement {
+    fn clone_box(&self) -> Box<dyn Statement> {
+        Box::new(self.clone())
+    }
+
+    fn protocol(&self) -> MessageType {
+        MessageType::UserLogin
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, encode::Error> {
+        encode::to_vec(self)
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<Box<dyn Statement>, decode::Error> {
+        let stmt: UserLoginStatement = decode::from_slice(data)?;
+        Ok(Box::new(stmt))
+    }
+
+    fn to_string(&self) -> String {
+        format!("UserLoginStatement{{Username: {}, Database: {}}}", self.username, self.database)
+    }
+}
@@ -9,3 +9,32 @@ pub fn validate_alphanumunderscore(value: &str) -> Result<(), ValidationError> {
       return Err(ValidationError::new("alphanumunderscore"));
   }
 }
+
+/// Like `validate_alphanumunderscore`, but also accepts `*` for "any".
+pub fn validate_name_or_wildcard(value: &str) -> Result<(), ValidationError> {
+  if value == "*" {
+      return Ok(());
+  }
+  return validate_alphanumunderscore(value);
+}
+
+/// A column, optionally qualified by its table as in `users.id`.
+pub fn validate_column_name(value: &str) -> Result<(), ValidationError> {
+  match value.split_once('.') {
+      Some((table, column)) => {
+          validate_alphanumunderscore(table)?;
+          return validate_alphanumunderscore(column);
+      }
+      None => {
+          return validate_alphanumunderscore(value);
+      }
+  }
+}
+
+/// `validate_column_name` for each name of a list.
+pub fn validate_column_names(values: &[String]) -> Result<(), ValidationError> {
+  for value in values {
+      validate_column_name(value)?;
+  }
+  return Ok(());
+}
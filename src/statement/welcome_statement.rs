@@ -0,0 +1,90 @@
+use serde::{ Deserialize, Serialize };
+use rmp_serde::{ encode, decode };
+use crate::protocol::MessageType;
+use crate::statement::Statement;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WelcomeStatement {
+    #[serde(rename = "accepted")]
+    pub accepted: bool,
+
+    #[serde(rename = "protocol_version")]
+    pub protocol_version: u32,
+
+    #[serde(rename = "message_types")]
+    pub message_types: Vec<u32>,
+
+    #[serde(rename = "compression")]
+    pub compression: Vec<String>,
+
+    #[serde(rename = "max_frame_size")]
+    pub max_frame_size: u32,
+
+    #[serde(rename = "reason")]
+    pub reason: String,
+}
+
+#[allow(dead_code)]
+impl WelcomeStatement {
+    pub fn accept(
+        protocol_version: u32,
+        message_types: Vec<u32>,
+        compression: Vec<String>,
+        max_frame_size: u32
+    ) -> Self {
+        Self {
+            accepted: true,
+            protocol_version,
+            message_types,
+            compression,
+            max_frame_size,
+            reason: String::new(),
+        }
+    }
+
+    pub fn reject(reason: String) -> Self {
+        Self {
+            accepted: false,
+            protocol_version: 0,
+            message_types: Vec::new(),
+            compression: Vec::new(),
+            max_frame_size: 0,
+            reason,
+        }
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, decode::Error> {
+        decode::from_slice(data)
+    }
+}
+
+impl Statement for WelcomeStatement {
+    fn clone_box(&self) -> Box<dyn Statement> {
+        Box::new(self.clone())
+    }
+
+    fn protocol(&self) -> MessageType {
+        MessageType::Welcome
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, encode::Error> {
+        encode::to_vec(self)
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<Box<dyn Statement>, decode::Error> {
+        let stmt: WelcomeStatement = decode::from_slice(data)?;
+        Ok(Box::new(stmt))
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "WelcomeStatement{{Accepted: {}, ProtocolVersion: {}, MessageTypes: {:?}, Compression: {:?}, MaxFrameSize: {}, Reason: {}}}",
+            self.accepted,
+            self.protocol_version,
+            self.message_types,
+            self.compression,
+            self.max_frame_size,
+            self.reason
+        )
+    }
+}
@@ -0,0 +1,398 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{ HashMap, HashSet };
+use std::fmt;
+use std::hash::{ Hash, Hasher };
+use serde::{ Deserialize, Serialize };
+use serde_json::{ json, Value };
+use crate::statement::{ Aggregate, AggregateFunction };
+use crate::transport::Row;
+use super::engine::StorageError;
+use super::expression::lookup;
+use super::index::value_key;
+use super::sort::compare_for_sort;
+use super::spill::{ value_size, SpillConfig, SpillReader, SpillWriter };
+
+/// Files the groups are spread over once the hash table outgrows its budget.
+const SPILL_PARTITIONS: usize = 16;
+
+/// Levels of partitions groups can be spread over. A partition that still
+/// outgrows the budget at the last level, or holds a single group with many
+/// distinct values, is merged in memory.
+const MAX_SPILL_DEPTH: usize = 4;
+
+/// Rough size of a group besides its key, and of one aggregate state.
+const GROUP_OVERHEAD: usize = 64;
+const STATE_SIZE: usize = 48;
+
+/// Key of a value for grouping and `COUNT(DISTINCT)`: values equal under
+/// `compare_values` share it, and NULLs group together.
+fn group_key(value: &Value) -> String {
+    return value_key(value).unwrap_or_else(|| format!("j:{}", value));
+}
+
+fn number(column: &str, value: &Value) -> Result<Option<f64>, StorageError> {
+    match value {
+        Value::Null => Ok(None),
+        Value::Number(n) => Ok(n.as_f64()),
+        value => Err(StorageError::NotNumeric { column: column.to_string(), value: value.to_string() }),
+    }
+}
+
+/// Running value of one aggregate over the rows of a group so far.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AggregateState {
+    Count(u64),
+    /// Integers are summed exactly while they fit; `exact` is cleared by
+    /// the first float or overflow.
+    Sum {
+        seen: bool,
+        exact: bool,
+        int: i64,
+        float: f64,
+    },
+    Avg {
+        sum: f64,
+        count: u64,
+    },
+    Min(Option<Value>),
+    Max(Option<Value>),
+    CountDistinct(HashSet<String>),
+}
+
+#[allow(dead_code)]
+impl AggregateState {
+    pub fn new(function: AggregateFunction) -> Self {
+        match function {
+            AggregateFunction::Count => AggregateState::Count(0),
+            AggregateFunction::Sum => AggregateState::Sum { seen: false, exact: true, int: 0, float: 0.0 },
+            AggregateFunction::Avg => AggregateState::Avg { sum: 0.0, count: 0 },
+            AggregateFunction::Min => AggregateState::Min(None),
+            AggregateFunction::Max => AggregateState::Max(None),
+            AggregateFunction::CountDistinct => AggregateState::CountDistinct(HashSet::new()),
+        }
+    }
+
+    /// Adds the value of the aggregate's column in one row, `None` for
+    /// `COUNT(*)`. Returns how many bytes the state grew by.
+    pub fn add(&mut self, column: &str, value: Option<&Value>) -> Result<usize, StorageError> {
+        let value = match value {
+            None => {
+                if let AggregateState::Count(count) = self {
+                    *count += 1;
+                }
+                return Ok(0);
+            }
+            Some(value) if value.is_null() => {
+                return Ok(0);
+            }
+            Some(value) => value,
+        };
+        match self {
+            AggregateState::Count(count) => {
+                *count += 1;
+            }
+            AggregateState::Sum { seen, exact, int, float } => {
+                *float += number(column, value)?.unwrap_or_default();
+                *seen = true;
+                match (value.as_i64(), *exact) {
+                    (Some(n), true) =>
+                        match int.checked_add(n) {
+                            Some(total) => {
+                                *int = total;
+                            }
+                            None => {
+                                *exact = false;
+                            }
+                        }
+                    _ => {
+                        *exact = false;
+                    }
+                }
+            }
+            AggregateState::Avg { sum, count } => {
+                *sum += number(column, value)?.unwrap_or_default();
+                *count += 1;
+            }
+            AggregateState::Min(min) => {
+                if min.as_ref().is_none_or(|min| compare_for_sort(value, min).is_lt()) {
+                    *min = Some(value.clone());
+                }
+            }
+            AggregateState::Max(max) => {
+                if max.as_ref().is_none_or(|max| compare_for_sort(value, max).is_gt()) {
+                    *max = Some(value.clone());
+                }
+            }
+            AggregateState::CountDistinct(seen) => {
+                let key = group_key(value);
+                let grown = key.len() + STATE_SIZE;
+                if seen.insert(key) {
+                    return Ok(grown);
+                }
+            }
+        }
+        return Ok(0);
+    }
+
+    /// Folds in the state of the same aggregate over other rows.
+    pub fn merge(&mut self, other: AggregateState) {
+        match (self, other) {
+            (AggregateState::Count(count), AggregateState::Count(other)) => {
+                *count += other;
+            }
+            (
+                AggregateState::Sum { seen, exact, int, float },
+                AggregateState::Sum { seen: other_seen, exact: other_exact, int: other_int, float: other_float },
+            ) => {
+                *seen |= other_seen;
+                *float += other_float;
+                match int.checked_add(other_int) {
+                    Some(total) if *exact && other_exact => {
+                        *int = total;
+                    }
+                    _ => {
+                        *exact = false;
+                    }
+                }
+            }
+            (AggregateState::Avg { sum, count }, AggregateState::Avg { sum: other_sum, count: other_count }) => {
+                *sum += other_sum;
+                *count += other_count;
+            }
+            (AggregateState::Min(min), AggregateState::Min(Some(other)))
+                if min.as_ref().is_none_or(|min| compare_for_sort(&other, min).is_lt()) => {
+                *min = Some(other);
+            }
+            (AggregateState::Max(max), AggregateState::Max(Some(other)))
+                if max.as_ref().is_none_or(|max| compare_for_sort(&other, max).is_gt()) => {
+                *max = Some(other);
+            }
+            (AggregateState::CountDistinct(seen), AggregateState::CountDistinct(other)) => {
+                seen.extend(other);
+            }
+            _ => {}
+        }
+    }
+
+    /// Rough size of the state in memory.
+    pub fn size(&self) -> usize {
+        return match self {
+            AggregateState::Min(Some(value)) | AggregateState::Max(Some(value)) => STATE_SIZE + value_size(value),
+            AggregateState::CountDistinct(seen) => STATE_SIZE + seen.iter().map(|key| key.len() + STATE_SIZE).sum::<usize>(),
+            _ => STATE_SIZE,
+        };
+    }
+
+    /// The aggregate's value; NULL for SUM, AVG, MIN and MAX of no values.
+    pub fn result(self) -> Value {
+        match self {
+            AggregateState::Count(count) => json!(count),
+            AggregateState::Sum { seen: false, .. } => Value::Null,
+            AggregateState::Sum { exact: true, int, .. } => json!(int),
+            AggregateState::Sum { float, .. } => json!(float),
+            AggregateState::Avg { count: 0, .. } => Value::Null,
+            AggregateState::Avg { sum, count } => json!(sum / (count as f64)),
+            AggregateState::Min(value) | AggregateState::Max(value) => value.unwrap_or(Value::Null),
+            AggregateState::CountDistinct(seen) => json!(seen.len()),
+        }
+    }
+}
+
+/// One group: its `group_by` values and the state of each aggregate.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Group {
+    values: Vec<Value>,
+    states: Vec<AggregateState>,
+}
+
+/// How an aggregation ran.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AggregateStrategy {
+    Hash {
+        groups: usize,
+    },
+    /// The hash table outgrew its budget and was spread over partitions
+    /// on disk, each aggregated on its own; `partitions` counts those of
+    /// every level.
+    SpilledHash {
+        groups: usize,
+        partitions: usize,
+    },
+}
+
+impl AggregateStrategy {
+    pub fn groups(&self) -> usize {
+        match self {
+            AggregateStrategy::Hash { groups } | AggregateStrategy::SpilledHash { groups, .. } => *groups,
+        }
+    }
+}
+
+impl fmt::Display for AggregateStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AggregateStrategy::Hash { groups } => write!(f, "hash ({} groups)", groups),
+            AggregateStrategy::SpilledHash { groups, partitions } =>
+                write!(f, "spilled hash ({} groups, {} partitions)", groups, partitions),
+        }
+    }
+}
+
+/// Hash aggregation within a memory budget. Once the groups held exceed
+/// it, their partial states are written to partitions by key hash and the
+/// table starts over; each partition is then merged on its own, so a group
+/// never spans two. A partition that outgrows the budget as it is merged is
+/// spread over partitions of its own, hashed differently.
+pub struct Aggregator<'a> {
+    group_by: &'a [String],
+    aggregates: &'a [Aggregate],
+    config: &'a SpillConfig,
+    /// How many times the groups were partitioned before reaching this one.
+    depth: usize,
+    groups: HashMap<Vec<String>, Group>,
+    held_bytes: usize,
+    partitions: Vec<SpillWriter>,
+}
+
+#[allow(dead_code)]
+impl<'a> Aggregator<'a> {
+    pub fn new(group_by: &'a [String], aggregates: &'a [Aggregate], config: &'a SpillConfig) -> Self {
+        return Self {
+            group_by,
+            aggregates,
+            config,
+            depth: 0,
+            groups: HashMap::new(),
+            held_bytes: 0,
+            partitions: Vec::new(),
+        };
+    }
+
+    /// Merges the groups of one of this aggregator's partitions.
+    fn nested(&self) -> Self {
+        return Self { depth: self.depth + 1, ..Self::new(self.group_by, self.aggregates, self.config) };
+    }
+
+    fn new_group(&self, values: Vec<Value>) -> Group {
+        let states = self.aggregates
+            .iter()
+            .map(|aggregate| AggregateState::new(aggregate.function))
+            .collect();
+        return Group { values, states };
+    }
+
+    fn group_size(&self, key: &[String], values: &[Value]) -> usize {
+        return GROUP_OVERHEAD +
+            STATE_SIZE * self.aggregates.len() +
+            key.iter().map(String::len).sum::<usize>() +
+            values.iter().map(value_size).sum::<usize>();
+    }
+
+    pub fn push(&mut self, row: &Row) -> Result<(), StorageError> {
+        let values: Vec<Value> = self.group_by
+            .iter()
+            .map(|column| lookup(row, column))
+            .collect();
+        let key: Vec<String> = values.iter().map(group_key).collect();
+        if !self.groups.contains_key(&key) {
+            self.held_bytes += self.group_size(&key, &values);
+            let group = self.new_group(values);
+            self.groups.insert(key.clone(), group);
+        }
+        let group = self.groups.get_mut(&key).unwrap();
+        for (aggregate, state) in self.aggregates.iter().zip(group.states.iter_mut()) {
+            let column = aggregate.column.as_deref();
+            let value = column.map(|column| lookup(row, column));
+            self.held_bytes += state.add(column.unwrap_or("*"), value.as_ref())?;
+        }
+        if self.held_bytes > self.config.memory_budget {
+            self.spill()?;
+        }
+        return Ok(());
+    }
+
+    /// Folds in the partial states of a group read back from a partition.
+    fn push_group(&mut self, group: Group) -> Result<(), StorageError> {
+        let key: Vec<String> = group.values.iter().map(group_key).collect();
+        match self.groups.get_mut(&key) {
+            Some(existing) => {
+                // Counts values the group already had again; an estimate.
+                self.held_bytes += group.states.iter().map(AggregateState::size).sum::<usize>();
+                for (state, other) in existing.states.iter_mut().zip(group.states) {
+                    state.merge(other);
+                }
+            }
+            None => {
+                self.held_bytes +=
+                    self.group_size(&key, &group.values) +
+                    group.states.iter().map(AggregateState::size).sum::<usize>();
+                self.groups.insert(key, group);
+            }
+        }
+        // A single group cannot be split up.
+        if self.held_bytes > self.config.memory_budget && self.groups.len() > 1 && self.depth < MAX_SPILL_DEPTH {
+            self.spill()?;
+        }
+        return Ok(());
+    }
+
+    fn spill(&mut self) -> Result<(), StorageError> {
+        while self.partitions.len() < SPILL_PARTITIONS {
+            self.partitions.push(SpillWriter::create(&self.config.directory)?);
+        }
+        for (key, group) in self.groups.drain() {
+            let mut hasher = DefaultHasher::new();
+            // Each level hashes differently, or a partition would land whole in one of its own.
+            self.depth.hash(&mut hasher);
+            key.hash(&mut hasher);
+            self.partitions[(hasher.finish() as usize) % SPILL_PARTITIONS].write(&group)?;
+        }
+        self.held_bytes = 0;
+        return Ok(());
+    }
+
+    fn output(&self, group: Group) -> Row {
+        let mut row: Row = self.group_by.iter().cloned().zip(group.values).collect();
+        for (aggregate, state) in self.aggregates.iter().zip(group.states) {
+            row.insert(aggregate.output_name(), state.result());
+        }
+        return row;
+    }
+
+    /// Passes one row per group to `emit`, a partition at a time, so only
+    /// the groups of one partition are held at once. Without `group_by`
+    /// there is a single group, even over no rows.
+    pub fn finish(
+        mut self,
+        emit: &mut dyn FnMut(Row) -> Result<(), StorageError>
+    ) -> Result<AggregateStrategy, StorageError> {
+        if self.partitions.is_empty() {
+            if self.groups.is_empty() && self.group_by.is_empty() && self.depth == 0 {
+                let group = self.new_group(Vec::new());
+                self.groups.insert(Vec::new(), group);
+            }
+            let groups = self.groups.len();
+            for (_, group) in std::mem::take(&mut self.groups) {
+                emit(self.output(group))?;
+            }
+            return Ok(AggregateStrategy::Hash { groups });
+        }
+
+        self.spill()?;
+        let mut groups = 0;
+        let mut partitions = self.partitions.len();
+        for partition in std::mem::take(&mut self.partitions) {
+            let reader: SpillReader<Group> = partition.finish()?;
+            let mut merged = self.nested();
+            for group in reader {
+                merged.push_group(group?)?;
+            }
+            let strategy = merged.finish(emit)?;
+            groups += strategy.groups();
+            if let AggregateStrategy::SpilledHash { partitions: nested, .. } = strategy {
+                partitions += nested;
+            }
+        }
+        return Ok(AggregateStrategy::SpilledHash { groups, partitions });
+    }
+}
@@ -0,0 +1,417 @@
+use std::collections::{ HashMap, HashSet };
+use std::error::Error;
+use std::fmt;
+use std::sync::{ Arc, Mutex };
+use std::time::{ Duration, Instant };
+use hmac::{ Hmac, Mac };
+use serde_json::{ json, Value };
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+use crate::transport::Row;
+use super::engine::{ StorageEngine, StorageError };
+use super::index::AccessPath;
+use crate::utils::metrics;
+
+/// Database holding the catalog tables below.
+pub const SYSTEM_DATABASE: &str = "system";
+const USERS_TABLE: &str = "users";
+const ROLES_TABLE: &str = "roles";
+const ROLE_MEMBERS_TABLE: &str = "role_members";
+const GRANTS_TABLE: &str = "grants";
+const PASSWORD_ROUNDS: u32 = 100_000;
+/// How long a verified password is trusted before it is hashed again.
+const VERIFIED_TTL: Duration = Duration::from_secs(300);
+const VERIFIED_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Privilege {
+    Select,
+    Insert,
+    Update,
+    Delete,
+    /// Creating, altering and dropping databases, tables and indexes.
+    Ddl,
+}
+
+const ALL_PRIVILEGES: [Privilege; 5] = [
+    Privilege::Select,
+    Privilege::Insert,
+    Privilege::Update,
+    Privilege::Delete,
+    Privilege::Ddl,
+];
+
+#[allow(dead_code)]
+impl Privilege {
+    pub fn to_name(self) -> &'static str {
+        match self {
+            Privilege::Select => "SELECT",
+            Privilege::Insert => "INSERT",
+            Privilege::Update => "UPDATE",
+            Privilege::Delete => "DELETE",
+            Privilege::Ddl => "DDL",
+        }
+    }
+
+    /// Parses a privilege name; `ALL` expands to every privilege.
+    pub fn parse(name: &str) -> Option<Vec<Privilege>> {
+        let name = name.to_ascii_uppercase();
+        if name == "ALL" {
+            return Some(ALL_PRIVILEGES.to_vec());
+        }
+        return ALL_PRIVILEGES.iter()
+            .find(|p| p.to_name() == name)
+            .map(|p| vec![*p]);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CatalogError {
+    UserExists(String),
+    RoleExists(String),
+    UserNotFound(String),
+    RoleNotFound(String),
+    GranteeNotFound(String),
+    InvalidPrivilege(String),
+    InvalidCredentials,
+    Storage(StorageError),
+}
+
+impl fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CatalogError::UserExists(name) => write!(f, "user {} already exists", name),
+            CatalogError::RoleExists(name) => write!(f, "role {} already exists", name),
+            CatalogError::UserNotFound(name) => write!(f, "user {} does not exist", name),
+            CatalogError::RoleNotFound(name) => write!(f, "role {} does not exist", name),
+            CatalogError::GranteeNotFound(name) => write!(f, "no user or role named {}", name),
+            CatalogError::InvalidPrivilege(name) => write!(f, "unknown privilege {}", name),
+            CatalogError::InvalidCredentials => write!(f, "invalid username or password"),
+            CatalogError::Storage(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for CatalogError {}
+
+impl From<StorageError> for CatalogError {
+    fn from(e: StorageError) -> Self {
+        CatalogError::Storage(e)
+    }
+}
+
+/// A password that passed `authenticate`. REST and gRPC clients send their
+/// password with every request, and the key derivation is too slow to
+/// repeat each time.
+#[derive(Debug)]
+struct Verified {
+    /// The stored hash it was checked against. Dropping or recreating the
+    /// user changes it, which invalidates the entry.
+    password_hash: String,
+    /// HMAC of the password under the cache key; the password is not kept.
+    proof: Vec<u8>,
+    at: Instant,
+}
+
+#[derive(Debug)]
+struct VerifiedCache {
+    key: [u8; 16],
+    entries: Mutex<HashMap<String, Verified>>,
+}
+
+impl VerifiedCache {
+    fn new() -> Self {
+        return Self { key: *Uuid::new_v4().as_bytes(), entries: Mutex::new(HashMap::new()) };
+    }
+
+    fn proof(&self, password: &str) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC can take key of any size");
+        mac.update(password.as_bytes());
+        return mac.finalize().into_bytes().to_vec();
+    }
+
+    fn contains(&self, name: &str, password_hash: &str, proof: &[u8]) -> bool {
+        let entries = self.entries.lock().unwrap();
+        return entries.get(name).is_some_and(|verified| {
+            verified.at.elapsed() < VERIFIED_TTL &&
+                verified.password_hash == password_hash &&
+                verified.proof.ct_eq(proof).unwrap_u8() == 1
+        });
+    }
+
+    fn insert(&self, name: &str, password_hash: &str, proof: Vec<u8>) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= VERIFIED_CAPACITY {
+            entries.retain(|_, verified| verified.at.elapsed() < VERIFIED_TTL);
+            if entries.len() >= VERIFIED_CAPACITY {
+                entries.clear();
+            }
+        }
+        entries.insert(name.to_string(), Verified { password_hash: password_hash.to_string(), proof, at: Instant::now() });
+    }
+
+    fn forget(&self, name: &str) {
+        self.entries.lock().unwrap().remove(name);
+    }
+}
+
+/// Users, roles and grants, kept as rows of tables in the `system` database.
+#[derive(Debug, Clone)]
+pub struct Catalog {
+    engine: Arc<StorageEngine>,
+    verified: Arc<VerifiedCache>,
+}
+
+fn text<'a>(row: &'a Row, column: &str) -> &'a str {
+    return row
+        .get(column)
+        .and_then(|v| v.as_str())
+        .unwrap_or("");
+}
+
+fn hash_password(password: &str, salt: &[u8]) -> String {
+    let mut hash = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, PASSWORD_ROUNDS, &mut hash);
+    return hex::encode(hash);
+}
+
+#[allow(dead_code)]
+impl Catalog {
+    /// Opens the catalog, creating the system tables if they are missing.
+    pub fn new(engine: Arc<StorageEngine>) -> Self {
+        if !engine.has_database(SYSTEM_DATABASE) {
+            let _ = engine.create_database(SYSTEM_DATABASE);
+        }
+        for table in [USERS_TABLE, ROLES_TABLE, ROLE_MEMBERS_TABLE, GRANTS_TABLE] {
+            let _ = engine.create_table(SYSTEM_DATABASE, table, Vec::new());
+        }
+        return Self { engine, verified: Arc::new(VerifiedCache::new()) };
+    }
+
+    fn rows(&self, table: &str, filter: impl Fn(&Row) -> bool) -> Vec<Row> {
+        return self.engine.scan(SYSTEM_DATABASE, table, &AccessPath::FullScan, filter).unwrap_or_default();
+    }
+
+    fn insert(&self, table: &str, row: Value) -> Result<(), CatalogError> {
+        let row: Row = serde_json::from_value(row).unwrap();
+        self.engine.insert(SYSTEM_DATABASE, table, vec![row])?;
+        return Ok(());
+    }
+
+    pub fn user_exists(&self, name: &str) -> bool {
+        return !self.rows(USERS_TABLE, |row| text(row, "name") == name).is_empty();
+    }
+
+    pub fn role_exists(&self, name: &str) -> bool {
+        return !self.rows(ROLES_TABLE, |row| text(row, "name") == name).is_empty();
+    }
+
+    pub fn create_user(&self, name: &str, password: &str, superuser: bool) -> Result<(), CatalogError> {
+        if self.user_exists(name) || self.role_exists(name) {
+            return Err(CatalogError::UserExists(name.to_string()));
+        }
+
+        let salt = Uuid::new_v4();
+        return self.insert(
+            USERS_TABLE,
+            json!({
+                "name": name,
+                "password_hash": hash_password(password, salt.as_bytes()),
+                "salt": hex::encode(salt.as_bytes()),
+                "superuser": superuser,
+            })
+        );
+    }
+
+    /// Drops the user together with its grants and role memberships.
+    pub fn drop_user(&self, name: &str) -> Result<(), CatalogError> {
+        let all = &AccessPath::FullScan;
+        if self.engine.delete(SYSTEM_DATABASE, USERS_TABLE, all, |row| text(row, "name") == name)? == 0 {
+            return Err(CatalogError::UserNotFound(name.to_string()));
+        }
+        self.verified.forget(name);
+        self.engine.delete(SYSTEM_DATABASE, ROLE_MEMBERS_TABLE, all, |row| text(row, "member") == name)?;
+        self.engine.delete(SYSTEM_DATABASE, GRANTS_TABLE, all, |row| text(row, "grantee") == name)?;
+        return Ok(());
+    }
+
+    pub fn create_role(&self, name: &str) -> Result<(), CatalogError> {
+        if self.role_exists(name) || self.user_exists(name) {
+            return Err(CatalogError::RoleExists(name.to_string()));
+        }
+        return self.insert(ROLES_TABLE, json!({ "name": name }));
+    }
+
+    fn parse_privileges(names: &[String]) -> Result<Vec<Privilege>, CatalogError> {
+        let mut privileges = Vec::new();
+        for name in names {
+            let parsed = Privilege::parse(name).ok_or_else(|| CatalogError::InvalidPrivilege(name.clone()))?;
+            privileges.extend(parsed);
+        }
+        return Ok(privileges);
+    }
+
+    pub fn grant(
+        &self,
+        grantee: &str,
+        privileges: &[String],
+        database: &str,
+        table: &str,
+        roles: &[String]
+    ) -> Result<(), CatalogError> {
+        if !self.user_exists(grantee) && !self.role_exists(grantee) {
+            return Err(CatalogError::GranteeNotFound(grantee.to_string()));
+        }
+        if let Some(role) = roles.iter().find(|role| !self.role_exists(role)) {
+            return Err(CatalogError::RoleNotFound(role.clone()));
+        }
+        let privileges = Self::parse_privileges(privileges)?;
+
+        for privilege in privileges {
+            if self.has_grant(grantee, privilege, database, table) {
+                continue;
+            }
+            self.insert(
+                GRANTS_TABLE,
+                json!({
+                    "grantee": grantee,
+                    "database": database,
+                    "table": table,
+                    "privilege": privilege.to_name(),
+                })
+            )?;
+        }
+
+        for role in roles {
+            let exists = !self
+                .rows(ROLE_MEMBERS_TABLE, |row| text(row, "member") == grantee && text(row, "role") == role)
+                .is_empty();
+            if !exists {
+                self.insert(ROLE_MEMBERS_TABLE, json!({ "member": grantee, "role": role }))?;
+            }
+        }
+
+        return Ok(());
+    }
+
+    pub fn revoke(
+        &self,
+        grantee: &str,
+        privileges: &[String],
+        database: &str,
+        table: &str,
+        roles: &[String]
+    ) -> Result<u64, CatalogError> {
+        let privileges: HashSet<&str> = Self::parse_privileges(privileges)?
+            .into_iter()
+            .map(|p| p.to_name())
+            .collect();
+
+        let mut revoked = self.engine.delete(SYSTEM_DATABASE, GRANTS_TABLE, &AccessPath::FullScan, |row| {
+            text(row, "grantee") == grantee &&
+                text(row, "database") == database &&
+                text(row, "table") == table &&
+                privileges.contains(text(row, "privilege"))
+        })?;
+        revoked += self.engine.delete(SYSTEM_DATABASE, ROLE_MEMBERS_TABLE, &AccessPath::FullScan, |row| {
+            text(row, "member") == grantee && roles.iter().any(|role| role == text(row, "role"))
+        })?;
+
+        return Ok(revoked);
+    }
+
+    /// Checks a user's password, in constant time once the user is found.
+    /// Passwords verified in the last few minutes are not hashed again.
+    pub fn authenticate(&self, name: &str, password: &str) -> Result<(), CatalogError> {
+        let users = self.rows(USERS_TABLE, |row| text(row, "name") == name);
+        let user = users.first().ok_or(CatalogError::InvalidCredentials)?;
+
+        let expected = text(user, "password_hash");
+        let proof = self.verified.proof(password);
+        let cached = self.verified.contains(name, expected, &proof);
+        metrics().record_cache("credentials", cached);
+        if cached {
+            return Ok(());
+        }
+
+        let salt = hex::decode(text(user, "salt")).map_err(|_| CatalogError::InvalidCredentials)?;
+        let actual = hash_password(password, &salt);
+        if actual.as_bytes().ct_eq(expected.as_bytes()).unwrap_u8() != 1 {
+            return Err(CatalogError::InvalidCredentials);
+        }
+        self.verified.insert(name, expected, proof);
+        return Ok(());
+    }
+
+    pub fn is_superuser(&self, name: &str) -> bool {
+        return self
+            .rows(USERS_TABLE, |row| text(row, "name") == name)
+            .iter()
+            .any(|row| row.get("superuser") == Some(&Value::Bool(true)));
+    }
+
+    /// The user plus every role it holds, directly or through other roles.
+    pub fn principals(&self, name: &str) -> HashSet<String> {
+        let memberships = self.rows(ROLE_MEMBERS_TABLE, |_| true);
+        let mut principals: HashSet<String> = HashSet::from([name.to_string()]);
+        let mut pending = vec![name.to_string()];
+
+        while let Some(member) = pending.pop() {
+            for row in memberships.iter().filter(|row| text(row, "member") == member) {
+                let role = text(row, "role").to_string();
+                if principals.insert(role.clone()) {
+                    pending.push(role);
+                }
+            }
+        }
+        return principals;
+    }
+
+    fn has_grant(&self, grantee: &str, privilege: Privilege, database: &str, table: &str) -> bool {
+        return !self
+            .rows(GRANTS_TABLE, |row| {
+                text(row, "grantee") == grantee &&
+                    text(row, "database") == database &&
+                    text(row, "table") == table &&
+                    text(row, "privilege") == privilege.to_name()
+            })
+            .is_empty();
+    }
+
+    /// Whether `user` may use `privilege` on `database.table`. A grant on
+    /// `*` covers every database or table; `table` is `*` for checks on the
+    /// database itself.
+    pub fn check(&self, user: &str, privilege: Privilege, database: &str, table: &str) -> bool {
+        if self.is_superuser(user) {
+            return true;
+        }
+
+        let principals = self.principals(user);
+        return !self
+            .rows(GRANTS_TABLE, |row| {
+                principals.contains(text(row, "grantee")) &&
+                    text(row, "privilege") == privilege.to_name() &&
+                    (text(row, "database") == "*" || text(row, "database") == database) &&
+                    (text(row, "table") == "*" || text(row, "table") == table)
+            })
+            .is_empty();
+    }
+
+    /// Whether `user` holds any privilege at all on the database, or on one
+    /// table when `table` is given. Used to filter listings.
+    pub fn can_see(&self, user: &str, database: &str, table: Option<&str>) -> bool {
+        if self.is_superuser(user) {
+            return true;
+        }
+
+        let principals = self.principals(user);
+        return !self
+            .rows(GRANTS_TABLE, |row| {
+                principals.contains(text(row, "grantee")) &&
+                    (text(row, "database") == "*" || text(row, "database") == database) &&
+                    table.is_none_or(|table| text(row, "table") == "*" || text(row, "table") == table)
+            })
+            .is_empty();
+    }
+}
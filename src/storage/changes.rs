@@ -0,0 +1,174 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::sync::{ Arc, Mutex };
+use chrono::{ SecondsFormat, Utc };
+use serde::{ Deserialize, Serialize };
+use tokio::sync::broadcast;
+use uuid::Uuid;
+use crate::transport::Row;
+
+/// Events kept for subscribers that resume from an earlier position.
+pub const DEFAULT_RETAINED_CHANGES: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeOperation {
+    Insert,
+    Update,
+    Delete,
+}
+
+/// One changed row. Inserts have no `before`, deletes no `after`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChangeEvent {
+    /// Token to resume the feed right after this event.
+    pub position: String,
+    pub database: String,
+    pub table: String,
+    pub operation: ChangeOperation,
+    pub before: Option<Row>,
+    pub after: Option<Row>,
+    /// Shared by every row changed by the same statement.
+    pub commit_timestamp: String,
+    #[serde(skip)]
+    sequence: u64,
+}
+
+#[allow(dead_code)]
+impl ChangeEvent {
+    pub fn sequence(&self) -> u64 {
+        return self.sequence;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChangeFeedError {
+    /// The token is malformed or comes from an earlier run of the node.
+    InvalidPosition(String),
+    /// The events after the token are no longer retained.
+    Expired(String),
+}
+
+impl fmt::Display for ChangeFeedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChangeFeedError::InvalidPosition(token) => write!(f, "invalid position {}", token),
+            ChangeFeedError::Expired(token) => write!(f, "position {} is no longer retained", token),
+        }
+    }
+}
+
+impl Error for ChangeFeedError {}
+
+#[derive(Debug, Default)]
+struct FeedState {
+    sequence: u64,
+    retained: VecDeque<Arc<ChangeEvent>>,
+}
+
+/// Row changes made through the storage engine, in commit order. Recent
+/// events are retained so subscribers can resume from a position token;
+/// tokens carry an epoch so that a token from before a restart is refused
+/// rather than silently skipping changes.
+#[derive(Debug)]
+pub struct ChangeFeed {
+    epoch: String,
+    capacity: usize,
+    state: Mutex<FeedState>,
+    sender: broadcast::Sender<Arc<ChangeEvent>>,
+}
+
+#[allow(dead_code)]
+impl ChangeFeed {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity.max(1));
+        return Self {
+            epoch: Uuid::new_v4().simple().to_string()[..8].to_string(),
+            capacity,
+            state: Mutex::new(FeedState::default()),
+            sender,
+        };
+    }
+
+    fn token(&self, sequence: u64) -> String {
+        return format!("{}:{}", self.epoch, sequence);
+    }
+
+    /// Token for "everything from now on".
+    pub fn position(&self) -> String {
+        return self.token(self.sequence());
+    }
+
+    /// Sequence number of the latest event.
+    pub fn sequence(&self) -> u64 {
+        return self.state.lock().unwrap().sequence;
+    }
+
+    pub fn sequence_of(&self, token: &str) -> Result<u64, ChangeFeedError> {
+        let invalid = || ChangeFeedError::InvalidPosition(token.to_string());
+        let (epoch, sequence) = token.split_once(':').ok_or_else(invalid)?;
+        if epoch != self.epoch {
+            return Err(invalid());
+        }
+        return sequence.parse().map_err(|_| invalid());
+    }
+
+    /// Records the row changes of one statement on `database.table`.
+    pub fn publish(&self, database: &str, table: &str, changes: Vec<(ChangeOperation, Option<Row>, Option<Row>)>) {
+        if changes.is_empty() {
+            return;
+        }
+        let commit_timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true);
+
+        let mut state = self.state.lock().unwrap();
+        for (operation, before, after) in changes {
+            state.sequence += 1;
+            let event = Arc::new(ChangeEvent {
+                position: self.token(state.sequence),
+                database: database.to_string(),
+                table: table.to_string(),
+                operation,
+                before,
+                after,
+                commit_timestamp: commit_timestamp.clone(),
+                sequence: state.sequence,
+            });
+            if state.retained.len() >= self.capacity.max(1) {
+                state.retained.pop_front();
+            }
+            state.retained.push_back(event.clone());
+            // No subscribers is not an error.
+            let _ = self.sender.send(event);
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<ChangeEvent>> {
+        return self.sender.subscribe();
+    }
+
+    /// Retained events after `token`, oldest first.
+    pub fn since(&self, token: &str) -> Result<Vec<Arc<ChangeEvent>>, ChangeFeedError> {
+        let sequence = self.sequence_of(token)?;
+        return self.since_sequence(sequence).ok_or_else(|| ChangeFeedError::Expired(token.to_string()));
+    }
+
+    /// Like `since`, for a sequence number taken from an event.
+    pub fn since_sequence(&self, sequence: u64) -> Option<Vec<Arc<ChangeEvent>>> {
+        let state = self.state.lock().unwrap();
+        if sequence > state.sequence {
+            return None;
+        }
+        let oldest = state.retained.front().map(|event| event.sequence).unwrap_or(state.sequence + 1);
+        if sequence + 1 < oldest {
+            return None;
+        }
+        return Some(
+            state.retained
+                .iter()
+                .filter(|event| event.sequence > sequence)
+                .cloned()
+                .collect()
+        );
+    }
+}
@@ -0,0 +1,955 @@
+use std::collections::{ BTreeMap, HashMap };
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{ self, Write };
+use std::path::Path;
+use std::sync::{ Arc, RwLock, RwLockReadGuard, RwLockWriteGuard };
+use std::sync::atomic::{ AtomicU64, Ordering };
+use std::time::Instant;
+use serde::Serialize;
+use serde_json::Value;
+use tracing::instrument;
+use crate::statement::ColumnDefinition;
+use crate::transport::Row;
+use crate::utils::metrics;
+use super::changes::{ ChangeFeed, ChangeOperation };
+use super::index::{ sort_key, AccessPath, Index, IndexOrder };
+use super::spill::{ column_size, row_size };
+use super::stats;
+use super::transaction::{ self, TableLock, Transaction };
+
+/// Database every session starts in.
+pub const DEFAULT_DATABASE: &str = "default";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StorageError {
+    DatabaseExists(String),
+    DatabaseNotFound(String),
+    TableExists(String),
+    TableNotFound(String),
+    UnknownColumn {
+        table: String,
+        column: String,
+    },
+    /// A column named without its table is in several joined tables.
+    AmbiguousColumn(String),
+    IndexExists(String),
+    IndexNotFound(String),
+    ReadOnly(String),
+    /// Writing or reading back rows spilled to disk failed.
+    Spill(String),
+    /// An aggregate that needs numbers met another value.
+    NotNumeric {
+        column: String,
+        value: String,
+    },
+    /// Another session's open transaction wrote the table.
+    Locked(String),
+    /// The rows would outgrow `storage.max_size_mb`, in bytes.
+    Full(u64),
+    /// The client stopped reading the rows of a select.
+    Cancelled,
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::DatabaseExists(name) => write!(f, "database {} already exists", name),
+            StorageError::DatabaseNotFound(name) => write!(f, "database {} does not exist", name),
+            StorageError::TableExists(name) => write!(f, "table {} already exists", name),
+            StorageError::TableNotFound(name) => write!(f, "table {} does not exist", name),
+            StorageError::UnknownColumn { table, column } =>
+                write!(f, "table {} has no column {}", table, column),
+            StorageError::AmbiguousColumn(name) =>
+                write!(f, "column {} is in more than one joined table; qualify it with its table", name),
+            StorageError::IndexExists(name) => write!(f, "index {} already exists", name),
+            StorageError::IndexNotFound(name) => write!(f, "index {} does not exist", name),
+            StorageError::ReadOnly(name) => write!(f, "database {} cannot be modified directly", name),
+            StorageError::Spill(reason) => write!(f, "cannot spill rows to disk: {}", reason),
+            StorageError::NotNumeric { column, value } =>
+                write!(f, "column {} holds {}, which is not a number", column, value),
+            StorageError::Locked(name) => write!(f, "table {} is locked by an open transaction", name),
+            StorageError::Full(limit) => write!(f, "storage is full: rows may take up at most {} bytes", limit),
+            StorageError::Cancelled => write!(f, "the client stopped reading the rows"),
+        }
+    }
+}
+
+impl Error for StorageError {}
+
+/// Storage itself is in memory; files are only used to spill rows.
+impl From<io::Error> for StorageError {
+    fn from(e: io::Error) -> Self {
+        StorageError::Spill(e.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Table {
+    pub columns: Vec<ColumnDefinition>,
+    pub rows: Vec<Row>,
+    pub indexes: Vec<Index>,
+    #[serde(skip)]
+    pub lock: Option<TableLock>,
+    /// Estimated size of the rows, counted against `max_size`.
+    #[serde(skip)]
+    pub bytes: usize,
+}
+
+/// Name of the index `CreateTable` builds for primary key columns.
+pub const PRIMARY_INDEX: &str = "primary";
+
+/// Rows looked at by `sample_row_size`.
+const ROW_SIZE_SAMPLE: usize = 64;
+
+/// Name and columns of an index, with its number of distinct keys.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IndexInfo {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub cardinality: usize,
+}
+
+#[allow(dead_code)]
+impl Table {
+    /// Tables created without columns accept any column.
+    fn check_columns<'a>(&self, name: &str, columns: impl Iterator<Item = &'a String>) -> Result<(), StorageError> {
+        if self.columns.is_empty() {
+            return Ok(());
+        }
+        for column in columns {
+            if !self.columns.iter().any(|c| &c.name == column) {
+                return Err(StorageError::UnknownColumn { table: name.to_string(), column: column.clone() });
+            }
+        }
+        return Ok(());
+    }
+
+    /// Fills columns missing from `row` with their default values.
+    fn with_defaults(&self, mut row: Row) -> Row {
+        for column in &self.columns {
+            if !row.contains_key(&column.name) {
+                let value = if column.default_value.is_empty() {
+                    Value::Null
+                } else {
+                    serde_json::from_str(&column.default_value).unwrap_or(Value::String(column.default_value.clone()))
+                };
+                row.insert(column.name.clone(), value);
+            }
+        }
+        return row;
+    }
+
+    /// Indexes declared on the columns: one over the primary key columns and
+    /// one per column flagged `index`, named `<column>_idx`.
+    fn declared_indexes(columns: &[ColumnDefinition]) -> Vec<Index> {
+        let mut indexes = Vec::new();
+        let primary: Vec<String> = columns
+            .iter()
+            .filter(|c| c.primary_key)
+            .map(|c| c.name.clone())
+            .collect();
+        if !primary.is_empty() {
+            indexes.push(Index::new(PRIMARY_INDEX, primary, &[]));
+        }
+        for column in columns.iter().filter(|c| c.index && !c.primary_key) {
+            indexes.push(Index::new(&format!("{}_idx", column.name), vec![column.name.clone()], &[]));
+        }
+        return indexes;
+    }
+
+    pub fn index(&self, name: &str) -> Option<&Index> {
+        return self.indexes.iter().find(|index| index.name == name);
+    }
+
+    /// An index over exactly `columns`, in any order.
+    fn index_on(&self, columns: &[&str]) -> Option<&Index> {
+        return self.indexes
+            .iter()
+            .find(|index| index.columns.len() == columns.len() && columns.iter().all(|c| index.covers(c)));
+    }
+
+    /// The table as the session on this thread sees it: as committed when
+    /// another session's transaction holds it.
+    fn visible(&self) -> &Table {
+        return match &self.lock {
+            Some(lock) if transaction::current_id() != Some(lock.owner) => &lock.committed,
+            _ => self,
+        };
+    }
+
+    /// Fails when another session's transaction holds the table.
+    fn check_unlocked(&self, name: &str) -> Result<(), StorageError> {
+        return match &self.lock {
+            Some(lock) if transaction::current_id() != Some(lock.owner) => Err(StorageError::Locked(name.to_string())),
+            _ => Ok(()),
+        };
+    }
+
+    fn rebuild_indexes(&mut self) {
+        for index in self.indexes.iter_mut() {
+            index.rebuild(&self.rows);
+        }
+    }
+
+    fn push_row(&mut self, row: Row) {
+        self.bytes += row_size(&row);
+        let position = self.rows.len();
+        for index in self.indexes.iter_mut() {
+            index.add(position, &row);
+        }
+        self.rows.push(row);
+    }
+
+    /// Positions of the rows `access` leads to that match `filter`. A named
+    /// index that no longer exists falls back to a full scan.
+    fn matching(&self, access: &AccessPath, filter: impl Fn(&Row) -> bool) -> Vec<usize> {
+        let started = Instant::now();
+        let candidates: Vec<usize> = match access {
+            AccessPath::IndexScan { index, key } if self.index(index).is_some() => {
+                stats::record_index(index);
+                self.index(index).unwrap().lookup(key)
+            }
+            _ => (0..self.rows.len()).collect(),
+        };
+        stats::record_scan(candidates.len());
+        let matched: Vec<usize> = candidates
+            .into_iter()
+            .filter(|position| filter(&self.rows[*position]))
+            .collect();
+        stats::record_matched(matched.len(), started.elapsed());
+        return matched;
+    }
+}
+
+/// Groups of positions of `ordered` with equal values, in the order `by`
+/// reads them.
+fn ordered_groups<'a>(
+    ordered: &'a BTreeMap<Vec<u8>, Vec<usize>>,
+    by: IndexOrder
+) -> Box<dyn Iterator<Item = &'a Vec<usize>> + 'a> {
+    let null = sort_key(&Value::Null);
+    let nulls_first = by.order.places_nulls_first();
+    let (start, skip_nulls) = match by.from {
+        None => (None, false),
+        // Every value sorts with or after the NULLs.
+        Some(Value::Null) if nulls_first => (None, false),
+        Some(Value::Null) => (Some(null.clone()), false),
+        Some(value) => (Some(sort_key(value)), nulls_first),
+    };
+    let values: Box<dyn Iterator<Item = (&Vec<u8>, &Vec<usize>)>> = match (start, by.order.descending) {
+        (Some(start), _) if start == null => Box::new(std::iter::empty()),
+        (None, false) => Box::new(ordered.range(..null.clone())),
+        (None, true) => Box::new(ordered.range(..null.clone()).rev()),
+        (Some(start), false) => Box::new(ordered.range(start..null.clone())),
+        (Some(start), true) => Box::new(ordered.range(..=start).rev()),
+    };
+    let values = values.map(|(_, positions)| positions);
+    let nulls = ordered.get(&null).filter(|_| !skip_nulls).into_iter();
+    if nulls_first {
+        return Box::new(nulls.chain(values));
+    }
+    return Box::new(values.chain(nulls));
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Database {
+    tables: BTreeMap<String, Table>,
+}
+
+/// A database as snapshots write it, without uncommitted rows.
+#[derive(Serialize)]
+struct CommittedDatabase<'a> {
+    tables: BTreeMap<&'a String, &'a Table>,
+}
+
+/// In-memory row store: databases of tables of rows.
+#[derive(Debug, Default)]
+pub struct StorageEngine {
+    databases: RwLock<Databases>,
+    changes: Option<Arc<ChangeFeed>>,
+    /// Bumped by every change to databases, tables or indexes.
+    schema_version: AtomicU64,
+    transactions: AtomicU64,
+    /// Most bytes of rows the databases may hold; `None` for no limit.
+    max_size: Option<usize>,
+}
+
+type Databases = BTreeMap<String, Database>;
+
+fn used_bytes(databases: &Databases) -> usize {
+    return databases
+        .values()
+        .flat_map(|db| db.tables.values())
+        .map(|t| t.bytes)
+        .sum();
+}
+
+/// How many bytes setting `updates` on `row` adds; negative when it shrinks.
+fn resized_by<'a>(row: &Row, updates: impl Iterator<Item = (&'a String, &'a Value)>) -> isize {
+    return updates
+        .map(|(column, value)| {
+            let old = row.get(column).map_or(0, |old| column_size(column, old));
+            column_size(column, value) as isize - old as isize
+        })
+        .sum();
+}
+
+pub type Changes = Vec<(ChangeOperation, Option<Row>, Option<Row>)>;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct CompactionStats {
+    pub tables: usize,
+    pub rows: usize,
+    /// Row slots released by shrinking the tables.
+    pub slots_released: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SnapshotStats {
+    pub databases: usize,
+    pub tables: usize,
+    pub rows: usize,
+    pub bytes: u64,
+}
+
+#[allow(dead_code)]
+impl StorageEngine {
+    pub fn new() -> Self {
+        let engine = Self::default();
+        engine.databases.write().unwrap().insert(DEFAULT_DATABASE.to_string(), Database::default());
+        return engine;
+    }
+
+    /// Takes the read lock, recording the wait in the statement stats.
+    fn read_databases(&self) -> RwLockReadGuard<'_, Databases> {
+        let started = Instant::now();
+        let databases = self.databases.read().unwrap();
+        stats::record_lock_wait(started.elapsed());
+        return databases;
+    }
+
+    fn write_databases(&self) -> RwLockWriteGuard<'_, Databases> {
+        let started = Instant::now();
+        let databases = self.databases.write().unwrap();
+        stats::record_lock_wait(started.elapsed());
+        return databases;
+    }
+
+    /// Publishes every row change to `feed`.
+    pub fn with_change_feed(mut self, feed: Arc<ChangeFeed>) -> Self {
+        self.changes = Some(feed);
+        return self;
+    }
+
+    pub fn change_feed(&self) -> Option<&Arc<ChangeFeed>> {
+        return self.changes.as_ref();
+    }
+
+    /// Refuses writes that would grow the rows of all tables past
+    /// `max_size` bytes, as estimated for memory budgets.
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = Some(max_size);
+        return self;
+    }
+
+    /// Estimated size of the rows of every table.
+    pub fn size(&self) -> usize {
+        return used_bytes(&self.read_databases());
+    }
+
+    /// Version of the schema, for caches of what was planned against it.
+    pub fn schema_version(&self) -> u64 {
+        return self.schema_version.load(Ordering::SeqCst);
+    }
+
+    /// Called with the databases still locked, so that nothing is planned
+    /// against the new schema under the old version.
+    fn schema_changed(&self) {
+        self.schema_version.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Called with the table still locked, so events are in commit order.
+    /// Inside a transaction they wait for the commit.
+    fn publish(&self, database: &str, table: &str, changes: Changes) {
+        if let Some(feed) = &self.changes {
+            let held = transaction::with_current(|transaction| transaction.hold(database, table, changes.clone()));
+            if held.is_none() {
+                feed.publish(database, table, changes);
+            }
+        }
+    }
+
+    /// Opens a transaction; statements run inside it with
+    /// `transaction::within` until `commit` or `rollback`.
+    pub fn begin(&self, name: &str) -> Transaction {
+        let id = self.transactions.fetch_add(1, Ordering::SeqCst) + 1;
+        return Transaction::new(id, name);
+    }
+
+    /// Unlocks the tables of `transaction` and publishes its changes.
+    pub fn commit(&self, transaction: Transaction) {
+        let mut databases = self.write_databases();
+        for (database, table) in transaction.tables() {
+            if let Some(t) = databases.get_mut(database).and_then(|db| db.tables.get_mut(table)) {
+                t.lock = None;
+            }
+        }
+        if let Some(feed) = &self.changes {
+            for (database, table, changes) in transaction.into_changes() {
+                feed.publish(&database, &table, changes);
+            }
+        }
+    }
+
+    /// Puts back the tables `transaction` wrote as they were before it.
+    pub fn rollback(&self, transaction: Transaction) {
+        let tables = transaction.tables().clone();
+        // Drops the copies kept for savepoints, which share the committed tables.
+        drop(transaction);
+        let mut databases = self.write_databases();
+        for (database, table) in tables {
+            if let Some(t) = databases.get_mut(&database).and_then(|db| db.tables.get_mut(&table)) {
+                if let Some(lock) = t.lock.take() {
+                    *t = Arc::try_unwrap(lock.committed).unwrap_or_else(|committed| (*committed).clone());
+                }
+            }
+        }
+    }
+
+    /// Puts back the tables written since savepoint `name`, which stays.
+    pub fn rollback_to_savepoint(&self, transaction: &mut Transaction, name: &str) {
+        let unwound = transaction.unwind(name);
+        let mut databases = self.write_databases();
+        for ((database, table), before) in unwound {
+            if let Some(t) = databases.get_mut(&database).and_then(|db| db.tables.get_mut(&table)) {
+                let lock = t.lock.take();
+                *t = before;
+                t.lock = lock;
+            }
+        }
+    }
+
+    pub fn create_database(&self, name: &str) -> Result<(), StorageError> {
+        let mut databases = self.write_databases();
+        if databases.contains_key(name) {
+            return Err(StorageError::DatabaseExists(name.to_string()));
+        }
+        databases.insert(name.to_string(), Database::default());
+        self.schema_changed();
+        return Ok(());
+    }
+
+    pub fn drop_database(&self, name: &str) -> Result<(), StorageError> {
+        let mut databases = self.write_databases();
+        if let Some(db) = databases.get(name) {
+            for (table, t) in &db.tables {
+                t.check_unlocked(table)?;
+            }
+        }
+        match databases.remove(name) {
+            Some(_) => {
+                self.schema_changed();
+                Ok(())
+            }
+            None => Err(StorageError::DatabaseNotFound(name.to_string())),
+        }
+    }
+
+    pub fn has_database(&self, name: &str) -> bool {
+        return self.read_databases().contains_key(name);
+    }
+
+    pub fn database_names(&self) -> Vec<String> {
+        return self.read_databases().keys().cloned().collect();
+    }
+
+    pub fn table_names(&self, database: &str) -> Result<Vec<String>, StorageError> {
+        let databases = self.read_databases();
+        let db = databases.get(database).ok_or_else(|| StorageError::DatabaseNotFound(database.to_string()))?;
+        return Ok(db.tables.keys().cloned().collect());
+    }
+
+    pub fn create_table(&self, database: &str, name: &str, columns: Vec<ColumnDefinition>) -> Result<(), StorageError> {
+        self.with_database(database, |db| {
+            if db.tables.contains_key(name) {
+                return Err(StorageError::TableExists(name.to_string()));
+            }
+            let indexes = Table::declared_indexes(&columns);
+            db.tables.insert(name.to_string(), Table { columns, rows: Vec::new(), indexes, lock: None, bytes: 0 });
+            self.schema_changed();
+            Ok(())
+        })
+    }
+
+    pub fn drop_table(&self, database: &str, name: &str) -> Result<(), StorageError> {
+        self.with_database(database, |db| {
+            db.tables.get(name).ok_or_else(|| StorageError::TableNotFound(name.to_string()))?.check_unlocked(name)?;
+            db.tables.remove(name);
+            self.schema_changed();
+            Ok(())
+        })
+    }
+
+    pub fn rename_table(&self, database: &str, name: &str, new_name: &str) -> Result<(), StorageError> {
+        self.with_database(database, |db| {
+            if db.tables.contains_key(new_name) {
+                return Err(StorageError::TableExists(new_name.to_string()));
+            }
+            db.tables.get(name).ok_or_else(|| StorageError::TableNotFound(name.to_string()))?.check_unlocked(name)?;
+            let table = db.tables.remove(name).unwrap();
+            db.tables.insert(new_name.to_string(), table);
+            self.schema_changed();
+            Ok(())
+        })
+    }
+
+    pub fn columns(&self, database: &str, table: &str) -> Result<Vec<ColumnDefinition>, StorageError> {
+        return self.read_table(database, table, |t| t.columns.clone());
+    }
+
+    /// Fails on the first of `columns` that `table` does not have.
+    pub fn check_columns(&self, database: &str, table: &str, columns: &[String]) -> Result<(), StorageError> {
+        return self.read_table(database, table, |t| t.check_columns(table, columns.iter()))?;
+    }
+
+    pub fn row_count(&self, database: &str, table: &str) -> Result<usize, StorageError> {
+        return self.read_table(database, table, |t| t.rows.len());
+    }
+
+    pub fn indexes(&self, database: &str, table: &str) -> Result<Vec<IndexInfo>, StorageError> {
+        return self.read_table(database, table, |t| {
+            t.indexes
+                .iter()
+                .map(|index| IndexInfo {
+                    name: index.name.clone(),
+                    columns: index.columns.clone(),
+                    cardinality: index.cardinality(),
+                })
+                .collect()
+        });
+    }
+
+    /// Rows an index lookup for `key` would return.
+    pub fn index_matches(&self, database: &str, table: &str, index: &str, key: &[Value]) -> Result<usize, StorageError> {
+        return self.read_table(database, table, |t| {
+            t.index(index)
+                .map(|i| i.lookup(key).len())
+                .ok_or_else(|| StorageError::IndexNotFound(index.to_string()))
+        })?;
+    }
+
+    pub fn create_index(&self, database: &str, table: &str, name: &str, columns: Vec<String>) -> Result<(), StorageError> {
+        self.with_table(database, table, |t| {
+            if t.index(name).is_some() {
+                return Err(StorageError::IndexExists(name.to_string()));
+            }
+            t.check_columns(table, columns.iter())?;
+            let index = Index::new(name, columns, &t.rows);
+            t.indexes.push(index);
+            self.schema_changed();
+            Ok(())
+        })
+    }
+
+    pub fn drop_index(&self, database: &str, table: &str, name: &str) -> Result<(), StorageError> {
+        self.with_table(database, table, |t| {
+            let before = t.indexes.len();
+            t.indexes.retain(|index| index.name != name);
+            if t.indexes.len() == before {
+                return Err(StorageError::IndexNotFound(name.to_string()));
+            }
+            self.schema_changed();
+            Ok(())
+        })
+    }
+
+    #[instrument(name = "storage.truncate", skip_all, fields(database = database, table = table))]
+    pub fn truncate(&self, database: &str, table: &str) -> Result<u64, StorageError> {
+        self.with_table(database, table, |t| {
+            let count = t.rows.len() as u64;
+            stats::record_scan(t.rows.len());
+            let removed = std::mem::take(&mut t.rows);
+            t.bytes = 0;
+            t.rebuild_indexes();
+            if self.changes.is_some() {
+                let changes = removed
+                    .into_iter()
+                    .map(|row| (ChangeOperation::Delete, Some(row), None))
+                    .collect();
+                self.publish(database, table, changes);
+            }
+            Ok(count)
+        })
+    }
+
+    #[instrument(name = "storage.insert", skip_all, fields(database = database, table = table))]
+    pub fn insert(&self, database: &str, table: &str, rows: Vec<Row>) -> Result<u64, StorageError> {
+        self.with_room(database, table, |t, room| {
+            for row in &rows {
+                t.check_columns(table, row.keys())?;
+            }
+            let rows: Vec<Row> = rows.into_iter().map(|row| t.with_defaults(row)).collect();
+            if rows.iter().map(row_size).sum::<usize>() > room {
+                return Err(self.full());
+            }
+            let count = rows.len() as u64;
+            let mut changes = Changes::new();
+            for row in rows {
+                if self.changes.is_some() {
+                    changes.push((ChangeOperation::Insert, None, Some(row.clone())));
+                }
+                t.push_row(row);
+            }
+            self.publish(database, table, changes);
+            Ok(count)
+        })
+    }
+
+    /// Returns clones of the rows `access` leads to that match `filter`.
+    #[instrument(name = "storage.scan", skip_all, fields(database = database, table = table))]
+    pub fn scan(
+        &self,
+        database: &str,
+        table: &str,
+        access: &AccessPath,
+        filter: impl Fn(&Row) -> bool
+    ) -> Result<Vec<Row>, StorageError> {
+        return self.read_table(database, table, |t| {
+            let started = Instant::now();
+            let positions = t.matching(access, filter);
+            stats::record_table(table, positions.len(), started.elapsed());
+            positions
+                .into_iter()
+                .map(|position| t.rows[position].clone())
+                .collect()
+        });
+    }
+
+    /// Passes the rows `access` leads to that match `filter` to `visit`
+    /// without copying them out first; stops at the first error. The table
+    /// stays read locked until it returns.
+    #[instrument(name = "storage.scan", skip_all, fields(database = database, table = table))]
+    pub fn visit(
+        &self,
+        database: &str,
+        table: &str,
+        access: &AccessPath,
+        filter: impl Fn(&Row) -> bool,
+        mut visit: impl FnMut(&Row) -> Result<(), StorageError>
+    ) -> Result<(), StorageError> {
+        return self.read_table(database, table, |t| {
+            let started = Instant::now();
+            let positions = t.matching(access, filter);
+            stats::record_table(table, positions.len(), started.elapsed());
+            for position in positions {
+                visit(&t.rows[position])?;
+            }
+            Ok(())
+        })?;
+    }
+
+    /// Passes the rows matching `filter` to `visit` in the order `by` reads
+    /// its index, one group of equal values at a time. `visit` returns whether it has all the rows it needs;
+    /// the rest of that group is still visited, as it sorts with them. A
+    /// named index that no longer exists falls back to a full scan.
+    #[instrument(name = "storage.scan", skip_all, fields(database = database, table = table))]
+    pub fn visit_ordered(
+        &self,
+        database: &str,
+        table: &str,
+        by: IndexOrder,
+        filter: impl Fn(&Row) -> bool,
+        mut visit: impl FnMut(&Row) -> Result<bool, StorageError>
+    ) -> Result<(), StorageError> {
+        return self.read_table(database, table, |t| {
+            let started = Instant::now();
+            let all: Vec<usize>;
+            let groups = match t.index(by.index) {
+                Some(index) => {
+                    stats::record_index(by.index);
+                    ordered_groups(index.ordered(), by)
+                }
+                // Dropped since the plan was made: the rows make one group.
+                None => {
+                    all = (0..t.rows.len()).collect();
+                    Box::new(std::iter::once(&all))
+                }
+            };
+
+            let mut scanned = 0;
+            let mut matched = 0;
+            for positions in groups {
+                let mut enough = false;
+                for position in positions {
+                    scanned += 1;
+                    let row = &t.rows[*position];
+                    if filter(row) {
+                        matched += 1;
+                        enough |= visit(row)?;
+                    }
+                }
+                if enough {
+                    break;
+                }
+            }
+            stats::record_scan(scanned);
+            stats::record_matched(matched, started.elapsed());
+            stats::record_table(table, matched, started.elapsed());
+            Ok(())
+        })?;
+    }
+
+    /// Average size of the first rows of a table, as estimated for memory
+    /// budgets; 0 for an empty table.
+    pub fn sample_row_size(&self, database: &str, table: &str) -> Result<usize, StorageError> {
+        return self.read_table(database, table, |t| {
+            let sample: Vec<usize> = t.rows.iter().take(ROW_SIZE_SAMPLE).map(row_size).collect();
+            sample.iter().sum::<usize>() / sample.len().max(1)
+        });
+    }
+
+    #[instrument(name = "storage.update", skip_all, fields(database = database, table = table))]
+    pub fn update(
+        &self,
+        database: &str,
+        table: &str,
+        updates: &HashMap<String, Value>,
+        access: &AccessPath,
+        filter: impl Fn(&Row) -> bool
+    ) -> Result<u64, StorageError> {
+        self.with_room(database, table, |t, room| {
+            t.check_columns(table, updates.keys())?;
+            let matched = t.matching(access, filter);
+            let resized: isize = matched
+                .iter()
+                .map(|position| resized_by(&t.rows[*position], updates.iter()))
+                .sum();
+            if resized > 0 && (resized as usize) > room {
+                return Err(self.full());
+            }
+            t.bytes = t.bytes.saturating_add_signed(resized);
+            let mut changes = Changes::new();
+            for position in &matched {
+                let row = &mut t.rows[*position];
+                let before = self.changes.as_ref().map(|_| row.clone());
+                for (column, value) in updates {
+                    row.insert(column.clone(), value.clone());
+                }
+                if before.is_some() {
+                    changes.push((ChangeOperation::Update, before, Some(row.clone())));
+                }
+            }
+            if !matched.is_empty() && t.indexes.iter().any(|index| updates.keys().any(|c| index.covers(c))) {
+                t.rebuild_indexes();
+            }
+            self.publish(database, table, changes);
+            Ok(matched.len() as u64)
+        })
+    }
+
+    #[instrument(name = "storage.delete", skip_all, fields(database = database, table = table))]
+    pub fn delete(
+        &self,
+        database: &str,
+        table: &str,
+        access: &AccessPath,
+        filter: impl Fn(&Row) -> bool
+    ) -> Result<u64, StorageError> {
+        self.with_table(database, table, |t| {
+            let mut doomed = vec![false; t.rows.len()];
+            for position in t.matching(access, filter) {
+                doomed[position] = true;
+            }
+            let mut removed = Vec::new();
+            for (position, row) in std::mem::take(&mut t.rows).into_iter().enumerate() {
+                if doomed[position] {
+                    removed.push(row);
+                } else {
+                    t.rows.push(row);
+                }
+            }
+            let count = removed.len() as u64;
+            if count > 0 {
+                t.bytes = t.bytes.saturating_sub(removed.iter().map(row_size).sum());
+                t.rebuild_indexes();
+            }
+            if self.changes.is_some() {
+                let changes = removed
+                    .into_iter()
+                    .map(|row| (ChangeOperation::Delete, Some(row), None))
+                    .collect();
+                self.publish(database, table, changes);
+            }
+            Ok(count)
+        })
+    }
+
+    /// Updates the rows whose `unique_key` equals the one in `row`, or
+    /// inserts `row` when there is none. An index on `unique_key` alone is
+    /// used to find them.
+    #[instrument(name = "storage.upsert", skip_all, fields(database = database, table = table))]
+    pub fn upsert(&self, database: &str, table: &str, row: Row, unique_key: &str) -> Result<u64, StorageError> {
+        self.with_room(database, table, |t, room| {
+            t.check_columns(table, row.keys())?;
+            let key = row.get(unique_key).cloned().unwrap_or(Value::Null);
+            let access = match t.index_on(&[unique_key]) {
+                Some(index) => AccessPath::IndexScan { index: index.name.clone(), key: vec![key.clone()] },
+                None => AccessPath::FullScan,
+            };
+            let matched = t.matching(&access, |r| !key.is_null() && r.get(unique_key) == Some(&key));
+            let resized: isize = matched
+                .iter()
+                .map(|position| resized_by(&t.rows[*position], row.iter()))
+                .sum();
+            if resized > 0 && (resized as usize) > room {
+                return Err(self.full());
+            }
+            t.bytes = t.bytes.saturating_add_signed(resized);
+            let mut changes = Changes::new();
+            for position in &matched {
+                let existing = &mut t.rows[*position];
+                let before = self.changes.as_ref().map(|_| existing.clone());
+                for (column, value) in &row {
+                    existing.insert(column.clone(), value.clone());
+                }
+                if before.is_some() {
+                    changes.push((ChangeOperation::Update, before, Some(existing.clone())));
+                }
+            }
+            if !matched.is_empty() {
+                t.rebuild_indexes();
+                self.publish(database, table, changes);
+                return Ok(matched.len() as u64);
+            }
+
+            let row = t.with_defaults(row);
+            if row_size(&row) > room {
+                return Err(self.full());
+            }
+            if self.changes.is_some() {
+                changes.push((ChangeOperation::Insert, None, Some(row.clone())));
+            }
+            t.push_row(row);
+            self.publish(database, table, changes);
+            Ok(1)
+        })
+    }
+
+    /// Releases the memory tables kept after rows were deleted.
+    pub fn compact(&self) -> CompactionStats {
+        let mut databases = self.write_databases();
+        let mut stats = CompactionStats::default();
+        for table in databases.values_mut().flat_map(|db| db.tables.values_mut()) {
+            stats.tables += 1;
+            stats.rows += table.rows.len();
+            stats.slots_released += table.rows.capacity() - table.rows.len();
+            table.rows.shrink_to_fit();
+        }
+        metrics().compactions.inc();
+        metrics().compaction_slots_released.inc_by(stats.slots_released as u64);
+        return stats;
+    }
+
+    /// Database, table and row count of every table.
+    pub fn row_counts(&self) -> Vec<(String, String, usize)> {
+        let databases = self.read_databases();
+        return databases
+            .iter()
+            .flat_map(|(database, db)| {
+                db.tables.iter().map(move |(table, t)| (database.clone(), table.clone(), t.visible().rows.len()))
+            })
+            .collect();
+    }
+
+    /// Writes every database as JSON to `path`. The file is written next to
+    /// it first and renamed into place, so a snapshot is never half written.
+    pub fn snapshot(&self, path: &Path) -> io::Result<SnapshotStats> {
+        let databases = self.read_databases();
+        let committed: BTreeMap<&String, CommittedDatabase> = databases
+            .iter()
+            .map(|(name, db)| {
+                let tables = db.tables
+                    .iter()
+                    .map(|(table, t)| (table, t.lock.as_ref().map_or(t, |lock| lock.committed.as_ref())))
+                    .collect();
+                (name, CommittedDatabase { tables })
+            })
+            .collect();
+        let body = serde_json::to_vec(&committed).map_err(io::Error::other)?;
+        let stats = SnapshotStats {
+            databases: databases.len(),
+            tables: databases.values().map(|db| db.tables.len()).sum(),
+            rows: committed
+                .values()
+                .flat_map(|db| db.tables.values())
+                .map(|table| table.rows.len())
+                .sum(),
+            bytes: body.len() as u64,
+        };
+        drop(databases);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Synced before the rename, so a crash never leaves a torn snapshot
+        // under the final name.
+        let partial = path.with_extension("partial");
+        let mut file = fs::File::create(&partial)?;
+        file.write_all(&body)?;
+        metrics().time_fsync("snapshot", || file.sync_all())?;
+        fs::rename(&partial, path)?;
+        return Ok(stats);
+    }
+
+    fn with_database<T>(
+        &self,
+        database: &str,
+        f: impl FnOnce(&mut Database) -> Result<T, StorageError>
+    ) -> Result<T, StorageError> {
+        let mut databases = self.write_databases();
+        let db = databases.get_mut(database).ok_or_else(|| StorageError::DatabaseNotFound(database.to_string()))?;
+        return f(db);
+    }
+
+    fn with_table<T>(
+        &self,
+        database: &str,
+        table: &str,
+        f: impl FnOnce(&mut Table) -> Result<T, StorageError>
+    ) -> Result<T, StorageError> {
+        return self.with_room(database, table, |t, _| f(t));
+    }
+
+    /// Like `with_table`, also passing how many bytes of rows may still be
+    /// added under `max_size`.
+    fn with_room<T>(
+        &self,
+        database: &str,
+        table: &str,
+        f: impl FnOnce(&mut Table, usize) -> Result<T, StorageError>
+    ) -> Result<T, StorageError> {
+        let mut databases = self.write_databases();
+        let room = match self.max_size {
+            Some(max_size) => max_size.saturating_sub(used_bytes(&databases)),
+            None => usize::MAX,
+        };
+        let db = databases.get_mut(database).ok_or_else(|| StorageError::DatabaseNotFound(database.to_string()))?;
+        let t = db.tables.get_mut(table).ok_or_else(|| StorageError::TableNotFound(table.to_string()))?;
+        t.check_unlocked(table)?;
+        transaction::with_current(|transaction| transaction.record(database, table, t));
+        return f(t, room);
+    }
+
+    fn full(&self) -> StorageError {
+        return StorageError::Full(self.max_size.unwrap_or_default() as u64);
+    }
+
+    fn read_table<T>(&self, database: &str, table: &str, f: impl FnOnce(&Table) -> T) -> Result<T, StorageError> {
+        let databases = self.read_databases();
+        let db = databases.get(database).ok_or_else(|| StorageError::DatabaseNotFound(database.to_string()))?;
+        let t = db.tables.get(table).ok_or_else(|| StorageError::TableNotFound(table.to_string()))?;
+        return Ok(f(t.visible()));
+    }
+}
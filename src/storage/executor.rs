@@ -0,0 +1,1693 @@
+use std::cell::{ Cell, RefCell };
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::{ Arc, Mutex };
+use std::time::{ Duration, Instant };
+use async_trait::async_trait;
+use chrono::{ SecondsFormat, Utc };
+use futures::{ stream, StreamExt };
+use log::warn;
+use tokio::sync::mpsc;
+use tracing::{ instrument, Span };
+use rmp_serde::decode;
+use serde::de::DeserializeOwned;
+use serde_json::{ json, Value };
+use uuid::Uuid;
+use validator::Validate;
+use crate::network::{ HandlerResponse, MessageHandler, Session };
+use crate::network::auth::Principal;
+use crate::network::listener::error_response;
+use crate::protocol::MessageType;
+use crate::sql;
+use crate::statement::*;
+use crate::statement::explain_statement::EXPLAINABLE;
+use crate::statement::statement::deserialize_statement;
+use crate::transport::{ ChunkKind, Message, MessageTypeFlag, Row, RowBatch };
+use crate::utils::{ metrics, AuditEvent, AuditLog, KeyRing };
+use crate::utils::audit::is_ddl;
+use super::catalog::{ Catalog, CatalogError, Privilege, SYSTEM_DATABASE };
+use super::engine::{ StorageEngine, StorageError, DEFAULT_DATABASE };
+use super::expression::{ lookup, parse_filter, BinaryOperator, Expression, ExpressionError };
+use super::aggregate::Aggregator;
+use super::index::{ AccessPath, IndexOrder };
+use super::join::{ plan_join, run_join, JoinPlan, JoinSchema };
+use super::planner::{ estimate_groups, plan_scan, plan_sort, AggregatePlan, Plan, PlanNode, ScanPlan, SortPlan };
+use super::prepared::PreparedPlan;
+use super::slow_query::{ SlowQuery, SlowQueryLog };
+use super::sort::{ compare_rows, Continuation, Sorter };
+use super::spill::SpillConfig;
+use super::stats::{ self, StatementStats };
+use super::transaction::{ self, Transaction };
+
+/// Rows per chunk of a streamed select response.
+pub const ROWS_PER_CHUNK: usize = 256;
+
+/// Who runs a statement and against which database.
+#[derive(Debug, Clone)]
+pub struct ExecutionContext {
+    pub principal: Principal,
+    pub database: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExecutionResult {
+    Affected(u64),
+    Rows(Vec<Row>),
+    /// One page of a paged select, with the token for the next page unless
+    /// it was the last.
+    Page {
+        rows: Vec<Row>,
+        continuation: Option<String>,
+    },
+    /// The session switched to this database.
+    Database(String),
+    /// The rows of a select went to the sink they were run with, as they
+    /// were produced; `continuation` as for `Page` when it was `paged`.
+    Streamed {
+        rows: u64,
+        paged: bool,
+        continuation: Option<String>,
+    },
+}
+
+/// Takes the rows of a select one at a time, as they are produced.
+type RowSink<'a> = dyn Fn(Row) -> Result<(), StorageError> + 'a;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExecutionError {
+    PermissionDenied(String),
+    InvalidStatement(String),
+    Unsupported(MessageType),
+    Storage(StorageError),
+    Catalog(CatalogError),
+}
+
+#[allow(dead_code)]
+impl ExecutionError {
+    pub fn error_code(&self) -> ErrorCode {
+        match self {
+            ExecutionError::PermissionDenied(_) => ErrorCode::PermissionDenied,
+            ExecutionError::InvalidStatement(_) => ErrorCode::InvalidStatement,
+            ExecutionError::Unsupported(_) => ErrorCode::UnsupportedMessage,
+            ExecutionError::Storage(_) | ExecutionError::Catalog(_) => ErrorCode::ExecutionFailed,
+        }
+    }
+}
+
+impl fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecutionError::PermissionDenied(what) => write!(f, "permission denied: {}", what),
+            ExecutionError::InvalidStatement(reason) => write!(f, "invalid statement: {}", reason),
+            ExecutionError::Unsupported(message_type) =>
+                write!(f, "{} is not supported by this node", message_type.to_name()),
+            ExecutionError::Storage(e) => write!(f, "{}", e),
+            ExecutionError::Catalog(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for ExecutionError {}
+
+impl From<StorageError> for ExecutionError {
+    fn from(e: StorageError) -> Self {
+        ExecutionError::Storage(e)
+    }
+}
+
+impl From<CatalogError> for ExecutionError {
+    fn from(e: CatalogError) -> Self {
+        ExecutionError::Catalog(e)
+    }
+}
+
+impl From<ExpressionError> for ExecutionError {
+    fn from(e: ExpressionError) -> Self {
+        ExecutionError::InvalidStatement(e.to_string())
+    }
+}
+
+/// `ok`, `denied` or `failed`, as reported to the audit log and metrics.
+fn outcome(error: Option<&ExecutionError>) -> &'static str {
+    return match error {
+        None => "ok",
+        Some(ExecutionError::PermissionDenied(_)) => "denied",
+        Some(_) => "failed",
+    };
+}
+
+fn decode_body<T: DeserializeOwned + Validate>(body: &[u8]) -> Result<T, ExecutionError> {
+    let stmt: T = decode::from_slice(body).map_err(|e| ExecutionError::InvalidStatement(e.to_string()))?;
+    stmt.validate().map_err(|e| ExecutionError::InvalidStatement(e.to_string()))?;
+    return Ok(stmt);
+}
+
+fn decode_statement<T: DeserializeOwned + Validate>(message: &Message) -> Result<T, ExecutionError> {
+    return decode_body(&message.body);
+}
+
+fn project(row: Row, columns: &[String]) -> Row {
+    if columns.is_empty() || columns.iter().any(|c| c == "*") {
+        return row;
+    }
+    return columns
+        .iter()
+        .map(|c| (c.clone(), lookup(&row, c)))
+        .collect();
+}
+
+/// The rows from `offset` on, at most `limit` of them.
+fn page(rows: Vec<Row>, offset: usize, limit: Option<usize>) -> Vec<Row> {
+    return rows
+        .into_iter()
+        .skip(offset)
+        .take(limit.unwrap_or(usize::MAX))
+        .collect();
+}
+
+/// The statement as written to the slow query log.
+fn statement_text(message: &Message) -> String {
+    let message_type = message.header.message_type;
+    return match deserialize_statement(message_type, &message.body) {
+        Ok(stmt) => stmt.to_redacted_string(),
+        Err(_) => message_type.to_name().to_string(),
+    };
+}
+
+/// `plan` unless it still has placeholders, which only Execute binds.
+fn unbound(plan: Plan) -> Result<Plan, ExecutionError> {
+    if plan.has_parameters() {
+        return Err(ExecutionError::InvalidStatement("placeholders are only allowed in prepared statements".to_string()));
+    }
+    return Ok(plan);
+}
+
+fn check_savepoint(transaction: &Transaction, savepoint: &str) -> Result<(), ExecutionError> {
+    if !transaction.has_savepoint(savepoint) {
+        return Err(ExecutionError::InvalidStatement(format!("no savepoint {}", savepoint)));
+    }
+    return Ok(());
+}
+
+/// Names the transaction, or the savepoint, a chunked upload runs in.
+fn upload_savepoint(stream_id: &[u8; 16]) -> String {
+    return format!("upload-{}", Uuid::from_bytes(*stream_id));
+}
+
+fn millis(duration: Duration) -> f64 {
+    return duration.as_secs_f64() * 1000.0;
+}
+
+fn name_rows(names: Vec<String>) -> Vec<Row> {
+    return names
+        .into_iter()
+        .map(|name| Row::from([("name".to_string(), Value::String(name))]))
+        .collect();
+}
+
+/// A chunked upload in progress. Its chunks all commit or none do: they run
+/// in a transaction of the upload's own, or from a savepoint of the one the
+/// connection had open when the upload started.
+struct Upload {
+    connection_id: usize,
+    /// `None` when the upload runs in the connection's transaction.
+    transaction: Option<Transaction>,
+    /// Rows inserted so far.
+    total: u64,
+}
+
+/// Runs statements against the storage engine, checking the caller's
+/// privileges in the system catalog first.
+pub struct Executor {
+    engine: Arc<StorageEngine>,
+    catalog: Catalog,
+    /// Chunked uploads in progress, by stream id.
+    uploads: Mutex<HashMap<[u8; 16], Upload>>,
+    /// Prepared statements by connection id and name.
+    prepared: Mutex<HashMap<(usize, String), PreparedPlan>>,
+    /// Open transactions by connection id.
+    transactions: Mutex<HashMap<usize, Arc<Mutex<Transaction>>>>,
+    audit: Option<Arc<AuditLog>>,
+    slow_queries: Arc<SlowQueryLog>,
+    spill: SpillConfig,
+    /// Sign and check continuation tokens.
+    token_keys: KeyRing,
+}
+
+#[allow(dead_code)]
+impl Executor {
+    pub fn new(engine: Arc<StorageEngine>) -> Self {
+        let catalog = Catalog::new(engine.clone());
+        Self {
+            engine,
+            catalog,
+            uploads: Mutex::new(HashMap::new()),
+            prepared: Mutex::new(HashMap::new()),
+            transactions: Mutex::new(HashMap::new()),
+            audit: None,
+            slow_queries: Arc::new(SlowQueryLog::default()),
+            spill: SpillConfig::default(),
+            token_keys: KeyRing::single(&Uuid::new_v4().to_string()).expect("a single key is active"),
+        }
+    }
+
+    /// Records DDL, and DML when the log asks for it, with its outcome.
+    pub fn with_audit(mut self, audit: Arc<AuditLog>) -> Self {
+        self.audit = Some(audit);
+        return self;
+    }
+
+    pub fn with_slow_query_log(mut self, slow_queries: Arc<SlowQueryLog>) -> Self {
+        self.slow_queries = slow_queries;
+        return self;
+    }
+
+    /// Memory budget of sorts and where they spill beyond it.
+    pub fn with_spill(mut self, spill: SpillConfig) -> Self {
+        self.spill = spill;
+        return self;
+    }
+
+    /// Keys continuation tokens are signed with. Without them tokens are
+    /// only valid on this executor.
+    pub fn with_token_keys(mut self, keys: KeyRing) -> Self {
+        self.token_keys = keys;
+        return self;
+    }
+
+    pub fn slow_query_log(&self) -> &Arc<SlowQueryLog> {
+        return &self.slow_queries;
+    }
+
+    pub fn engine(&self) -> &Arc<StorageEngine> {
+        return &self.engine;
+    }
+
+    pub fn catalog(&self) -> &Catalog {
+        return &self.catalog;
+    }
+
+    pub fn audit_log(&self) -> Option<&Arc<AuditLog>> {
+        return self.audit.as_ref();
+    }
+
+    fn audit(&self, connection_id: usize, ctx: &ExecutionContext, message: &Message, error: Option<&ExecutionError>) {
+        let audit = match &self.audit {
+            Some(audit) if audit.records_statement(message.header.message_type) => audit,
+            _ => {
+                return;
+            }
+        };
+        audit.record(AuditEvent::Statement {
+            connection_id,
+            principal: ctx.principal.to_string(),
+            database: ctx.database.clone(),
+            message_type: message.header.message_type.to_name().to_string(),
+            message_id: hex::encode(message.header.message_id),
+            outcome: outcome(error).to_string(),
+            error: error.map(|e| e.to_string()),
+        });
+    }
+
+    fn is_admin(&self, ctx: &ExecutionContext) -> bool {
+        match &ctx.principal {
+            Principal::Node(_) => true,
+            Principal::User(user) => self.catalog.is_superuser(user),
+        }
+    }
+
+    fn require_admin(&self, ctx: &ExecutionContext, action: &str) -> Result<(), ExecutionError> {
+        if self.is_admin(ctx) {
+            return Ok(());
+        }
+        return Err(ExecutionError::PermissionDenied(format!("{} requires a superuser", action)));
+    }
+
+    /// Checks `privilege` on `database.table`; `table` is `*` for statements
+    /// on the database itself. Nodes of the cluster are trusted.
+    pub fn authorize(
+        &self,
+        ctx: &ExecutionContext,
+        privilege: Privilege,
+        database: &str,
+        table: &str
+    ) -> Result<(), ExecutionError> {
+        // The catalog is only changed through the user management statements.
+        if database == SYSTEM_DATABASE {
+            if privilege != Privilege::Select {
+                return Err(StorageError::ReadOnly(SYSTEM_DATABASE.to_string()).into());
+            }
+            return self.require_admin(ctx, "reading the system catalog");
+        }
+
+        let user = match &ctx.principal {
+            Principal::Node(_) => {
+                return Ok(());
+            }
+            Principal::User(user) => user,
+        };
+
+        if self.catalog.check(user, privilege, database, table) {
+            return Ok(());
+        }
+        return Err(
+            ExecutionError::PermissionDenied(format!("{} on {}.{}", privilege.to_name(), database, table))
+        );
+    }
+
+    fn can_see(&self, ctx: &ExecutionContext, database: &str, table: Option<&str>) -> bool {
+        if database == SYSTEM_DATABASE {
+            return self.is_admin(ctx);
+        }
+        match &ctx.principal {
+            Principal::Node(_) => true,
+            Principal::User(user) => self.catalog.can_see(user, database, table),
+        }
+    }
+
+    pub fn execute(&self, ctx: &ExecutionContext, message: &Message) -> Result<ExecutionResult, ExecutionError> {
+        return self.execute_into(ctx, message, None);
+    }
+
+    /// `execute`, passing the rows of a select to `rows` when given.
+    #[instrument(
+        name = "executor.execute",
+        skip_all,
+        fields(message_type = message.header.message_type.to_name(), principal = %ctx.principal, database = %ctx.database)
+    )]
+    fn execute_into(
+        &self,
+        ctx: &ExecutionContext,
+        message: &Message,
+        rows: Option<&RowSink<'_>>
+    ) -> Result<ExecutionResult, ExecutionError> {
+        let db = ctx.database.as_str();
+
+        match message.header.message_type {
+            MessageType::CreateDatabase => {
+                let stmt: CreateDatabaseStatement = decode_statement(message)?;
+                self.authorize(ctx, Privilege::Ddl, &stmt.database_name, "*")?;
+                self.engine.create_database(&stmt.database_name)?;
+                Ok(ExecutionResult::Affected(1))
+            }
+            MessageType::DropDatabase => {
+                let stmt: DropDatabaseStatement = decode_statement(message)?;
+                if stmt.database_name == DEFAULT_DATABASE {
+                    return Err(StorageError::ReadOnly(DEFAULT_DATABASE.to_string()).into());
+                }
+                self.authorize(ctx, Privilege::Ddl, &stmt.database_name, "*")?;
+                self.engine.drop_database(&stmt.database_name)?;
+                Ok(ExecutionResult::Affected(1))
+            }
+            MessageType::ShowDatabases => {
+                let names = self.engine
+                    .database_names()
+                    .into_iter()
+                    .filter(|name| self.can_see(ctx, name, None))
+                    .collect();
+                Ok(ExecutionResult::Rows(name_rows(names)))
+            }
+            MessageType::UseDatabase => {
+                let stmt: UseDatabaseStatement = decode_statement(message)?;
+                if !self.engine.has_database(&stmt.database_name) {
+                    return Err(StorageError::DatabaseNotFound(stmt.database_name).into());
+                }
+                if !self.can_see(ctx, &stmt.database_name, None) {
+                    return Err(ExecutionError::PermissionDenied(format!("no access to {}", stmt.database_name)));
+                }
+                Ok(ExecutionResult::Database(stmt.database_name))
+            }
+
+            MessageType::CreateTable => {
+                let stmt: CreateTableStatement = decode_statement(message)?;
+                self.authorize(ctx, Privilege::Ddl, db, &stmt.table_name)?;
+                self.engine.create_table(db, &stmt.table_name, stmt.columns)?;
+                Ok(ExecutionResult::Affected(1))
+            }
+            MessageType::DropTable => {
+                let stmt: DropTableStatement = decode_statement(message)?;
+                self.authorize(ctx, Privilege::Ddl, db, &stmt.table_name)?;
+                self.engine.drop_table(db, &stmt.table_name)?;
+                Ok(ExecutionResult::Affected(1))
+            }
+            MessageType::RenameTable => {
+                let stmt: RenameTableStatement = decode_statement(message)?;
+                self.authorize(ctx, Privilege::Ddl, db, &stmt.old_table_name)?;
+                self.authorize(ctx, Privilege::Ddl, db, &stmt.new_table_name)?;
+                self.engine.rename_table(db, &stmt.old_table_name, &stmt.new_table_name)?;
+                Ok(ExecutionResult::Affected(1))
+            }
+            MessageType::TruncateTable => {
+                let stmt: TruncateTableStatement = decode_statement(message)?;
+                self.authorize(ctx, Privilege::Delete, db, &stmt.table_name)?;
+                Ok(ExecutionResult::Affected(self.engine.truncate(db, &stmt.table_name)?))
+            }
+            MessageType::ShowTables => {
+                let names = self.engine
+                    .table_names(db)?
+                    .into_iter()
+                    .filter(|table| self.can_see(ctx, db, Some(table)))
+                    .collect();
+                Ok(ExecutionResult::Rows(name_rows(names)))
+            }
+            MessageType::DescribeTable => {
+                let stmt: DescribeTableStatement = decode_statement(message)?;
+                self.authorize(ctx, Privilege::Select, db, &stmt.table_name)?;
+                let rows = self.engine
+                    .columns(db, &stmt.table_name)?
+                    .into_iter()
+                    .map(|column| serde_json::from_value(json!(column)).unwrap())
+                    .collect();
+                Ok(ExecutionResult::Rows(rows))
+            }
+
+            MessageType::CreateIndex => {
+                let stmt: CreateIndexStatement = decode_statement(message)?;
+                self.authorize(ctx, Privilege::Ddl, db, &stmt.table_name)?;
+                self.engine.create_index(db, &stmt.table_name, &stmt.index_name, stmt.columns)?;
+                Ok(ExecutionResult::Affected(1))
+            }
+            MessageType::DropIndex => {
+                let stmt: DropIndexStatement = decode_statement(message)?;
+                self.authorize(ctx, Privilege::Ddl, db, &stmt.table_name)?;
+                self.engine.drop_index(db, &stmt.table_name, &stmt.index_name)?;
+                Ok(ExecutionResult::Affected(1))
+            }
+            MessageType::ShowIndexes => {
+                let stmt: ShowIndexesStatement = decode_statement(message)?;
+                self.authorize(ctx, Privilege::Select, db, &stmt.table_name)?;
+                let rows = self.engine
+                    .indexes(db, &stmt.table_name)?
+                    .into_iter()
+                    .map(|index| serde_json::from_value(json!(index)).unwrap())
+                    .collect();
+                Ok(ExecutionResult::Rows(rows))
+            }
+
+            MessageType::Insert |
+            MessageType::BulkInsert |
+            MessageType::Upsert |
+            MessageType::Select |
+            MessageType::Update |
+            MessageType::Delete => {
+                let plan = unbound(self.plan(ctx, message.header.message_type, &message.body)?)?;
+                self.run_plan(db, plan, rows)
+            }
+            MessageType::Explain => {
+                let stmt: ExplainStatement = decode_statement(message)?;
+                let plan = unbound(self.plan(ctx, stmt.explained_type(), &stmt.statement)?)?;
+                let nodes = if stmt.analyze { self.analyze(db, plan)? } else { plan.describe() };
+                let rows = nodes
+                    .into_iter()
+                    .map(|node| serde_json::from_value(json!(node)).unwrap())
+                    .collect();
+                Ok(ExecutionResult::Rows(rows))
+            }
+
+            MessageType::CreateUser => {
+                self.require_admin(ctx, "CreateUser")?;
+                let stmt: CreateUserStatement = decode_statement(message)?;
+                self.catalog.create_user(&stmt.username, &stmt.password, stmt.superuser)?;
+                if !stmt.roles.is_empty() {
+                    if let Err(e) = self.catalog.grant(&stmt.username, &[], "*", "*", &stmt.roles) {
+                        let _ = self.catalog.drop_user(&stmt.username);
+                        return Err(e.into());
+                    }
+                }
+                Ok(ExecutionResult::Affected(1))
+            }
+            MessageType::DropUser => {
+                self.require_admin(ctx, "DropUser")?;
+                let stmt: DropUserStatement = decode_statement(message)?;
+                self.catalog.drop_user(&stmt.username)?;
+                Ok(ExecutionResult::Affected(1))
+            }
+            MessageType::CreateRole => {
+                self.require_admin(ctx, "CreateRole")?;
+                let stmt: CreateRoleStatement = decode_statement(message)?;
+                self.catalog.create_role(&stmt.role_name)?;
+                Ok(ExecutionResult::Affected(1))
+            }
+            MessageType::Grant => {
+                self.require_admin(ctx, "Grant")?;
+                let stmt: GrantStatement = decode_statement(message)?;
+                self.catalog.grant(&stmt.grantee, &stmt.privileges, &stmt.database, &stmt.table, &stmt.roles)?;
+                Ok(ExecutionResult::Affected((stmt.privileges.len() + stmt.roles.len()) as u64))
+            }
+            MessageType::Revoke => {
+                self.require_admin(ctx, "Revoke")?;
+                let stmt: RevokeStatement = decode_statement(message)?;
+                let revoked = self.catalog.revoke(
+                    &stmt.grantee,
+                    &stmt.privileges,
+                    &stmt.database,
+                    &stmt.table,
+                    &stmt.roles
+                )?;
+                Ok(ExecutionResult::Affected(revoked))
+            }
+
+            MessageType::ShowSlowQueries => {
+                self.require_admin(ctx, "ShowSlowQueries")?;
+                let stmt: ShowSlowQueriesStatement = decode_statement(message)?;
+                let rows = self.slow_queries
+                    .recent(stmt.limit as usize)
+                    .into_iter()
+                    .map(|entry| serde_json::from_value(json!(entry)).unwrap())
+                    .collect();
+                Ok(ExecutionResult::Rows(rows))
+            }
+
+            MessageType::Query =>
+                self.run_query(ctx, message, |ctx, message, last| {
+                    return self.execute_into(ctx, message, rows.filter(|_| last));
+                }),
+
+            MessageType::BeginTransaction |
+            MessageType::Commit |
+            MessageType::Rollback |
+            MessageType::Savepoint |
+            MessageType::ReleaseSavepoint =>
+                Err(
+                    ExecutionError::InvalidStatement(
+                        format!("{} needs a connection", message.header.message_type.to_name())
+                    )
+                ),
+
+            message_type => Err(ExecutionError::Unsupported(message_type)),
+        }
+    }
+
+    /// Compiles the SQL of a Query message and runs its statements in order
+    /// with `run`, stopping at the first failure. A USE applies to the
+    /// statements after it, and to the session only when it comes last,
+    /// since the result is that of the last statement; `run` is told which
+    /// one that is.
+    fn run_query(
+        &self,
+        ctx: &ExecutionContext,
+        message: &Message,
+        run: impl Fn(&ExecutionContext, &Message, bool) -> Result<ExecutionResult, ExecutionError>
+    ) -> Result<ExecutionResult, ExecutionError> {
+        let stmt: QueryStatement = decode_statement(message)?;
+        let statements = sql::parse(&stmt.sql).map_err(|e| ExecutionError::InvalidStatement(e.to_string()))?;
+        let mut ctx = ctx.clone();
+        let mut result = ExecutionResult::Affected(0);
+        let count = statements.len();
+        for (i, statement) in statements.into_iter().enumerate() {
+            let mut request = Message::new(statement.protocol(), statement.as_ref());
+            request.header.message_id = message.header.message_id;
+            result = run(&ctx, &request, i + 1 == count)?;
+            if let ExecutionResult::Database(database) = &result {
+                ctx.database = database.clone();
+            }
+        }
+        return Ok(result);
+    }
+
+    /// Decodes a data statement, checks the caller may run it and picks how
+    /// it reads its table.
+    fn plan(&self, ctx: &ExecutionContext, message_type: MessageType, body: &[u8]) -> Result<Plan, ExecutionError> {
+        let db = ctx.database.as_str();
+        let plan = match message_type {
+            MessageType::Insert => {
+                let stmt: InsertStatement = decode_body(body)?;
+                self.authorize(ctx, Privilege::Insert, db, &stmt.table_name)?;
+                Plan::Insert { table: stmt.table_name, rows: vec![stmt.values] }
+            }
+            MessageType::BulkInsert => {
+                let stmt: BulkInsertStatement = decode_body(body)?;
+                self.authorize(ctx, Privilege::Insert, db, &stmt.table_name)?;
+                Plan::Insert { table: stmt.table_name, rows: stmt.rows }
+            }
+            MessageType::Upsert => {
+                let stmt: UpsertStatement = decode_body(body)?;
+                self.authorize(ctx, Privilege::Insert, db, &stmt.table_name)?;
+                self.authorize(ctx, Privilege::Update, db, &stmt.table_name)?;
+                let key = stmt.values.get(&stmt.unique_key).cloned().unwrap_or(Value::Null);
+                let predicate = Expression::Binary {
+                    op: BinaryOperator::Eq,
+                    left: Box::new(Expression::Column(stmt.unique_key.clone())),
+                    right: Box::new(Expression::Literal(key)),
+                };
+                let scan = plan_scan(&self.engine, db, &stmt.table_name, Some(predicate))?;
+                Plan::Upsert { scan, row: stmt.values, unique_key: stmt.unique_key }
+            }
+            MessageType::Select => {
+                let stmt: SelectStatement = decode_body(body)?;
+                for table in stmt.tables() {
+                    self.authorize(ctx, Privilege::Select, db, table)?;
+                }
+                let filter = parse_filter(&stmt.r#where)?;
+                let (scan, join) = if stmt.joins.is_empty() {
+                    (plan_scan(&self.engine, db, &stmt.table_name, filter)?, None)
+                } else {
+                    let (scan, join) = self.plan_joins(db, &stmt, filter)?;
+                    (scan, Some(Box::new(join)))
+                };
+                let (rows, row_size) = match &join {
+                    Some(join) => (join.estimated_rows, join.estimated_row_size),
+                    None => (scan.estimated_rows, self.engine.sample_row_size(db, &stmt.table_name)?),
+                };
+                let aggregate = self.plan_aggregate(db, &stmt, rows)?.map(Box::new);
+                let (rows, row_size) = match &aggregate {
+                    Some(aggregate) => (aggregate.estimated_rows(), aggregate.estimated_row_size()),
+                    None => (rows, row_size),
+                };
+                let single = Some(&scan).filter(|_| join.is_none() && aggregate.is_none());
+                let sort = self.plan_order(db, &stmt, single, rows, row_size)?;
+                Plan::Select {
+                    scan,
+                    columns: stmt.columns,
+                    join,
+                    aggregate,
+                    sort,
+                    offset: stmt.offset as usize,
+                    limit: stmt.limit.map(|limit| limit as usize),
+                }
+            }
+            MessageType::Update => {
+                let stmt: UpdateStatement = decode_body(body)?;
+                self.authorize(ctx, Privilege::Update, db, &stmt.table_name)?;
+                let scan = plan_scan(&self.engine, db, &stmt.table_name, parse_filter(&stmt.where_clause)?)?;
+                Plan::Update { scan, updates: stmt.updates }
+            }
+            MessageType::Delete => {
+                let stmt: DeleteStatement = decode_body(body)?;
+                self.authorize(ctx, Privilege::Delete, db, &stmt.table_name)?;
+                let where_clause = stmt.r#where.unwrap_or_default();
+                let scan = plan_scan(&self.engine, db, &stmt.table_name, parse_filter(&where_clause)?)?;
+                Plan::Delete { scan }
+            }
+            message_type => {
+                return Err(
+                    ExecutionError::InvalidStatement(format!("{} cannot be planned", message_type.to_name()))
+                );
+            }
+        };
+        return Ok(plan);
+    }
+
+    /// Joins of a select, checking that every column it names is in
+    /// exactly one of its tables.
+    fn plan_joins(
+        &self,
+        db: &str,
+        stmt: &SelectStatement,
+        filter: Option<Expression>
+    ) -> Result<(ScanPlan, JoinPlan), ExecutionError> {
+        let tables: Vec<String> = stmt.tables().into_iter().map(str::to_string).collect();
+        if let Some((_, table)) = tables.iter().enumerate().find(|(i, table)| tables[..*i].contains(table)) {
+            return Err(ExecutionError::InvalidStatement(format!("table {} is joined more than once", table)));
+        }
+        let schema = JoinSchema::load(&self.engine, db, &tables)?;
+        // Aggregated selects read their own result columns.
+        let named: Vec<&String> = if stmt.is_aggregate() {
+            let aggregated = stmt.aggregates.iter().filter_map(|aggregate| aggregate.column.as_ref());
+            stmt.group_by.iter().chain(aggregated).collect()
+        } else {
+            let selected = stmt.columns.iter().filter(|column| column.as_str() != "*");
+            selected.chain(stmt.order_by.iter().map(|key| &key.column)).collect()
+        };
+        for column in named {
+            schema.resolve(column)?;
+        }
+        let mut joins = Vec::new();
+        for join in &stmt.joins {
+            joins.push((join.kind, parse_filter(&join.on)?));
+        }
+        return Ok(plan_join(&self.engine, db, &schema, joins, filter, self.spill.memory_budget)?);
+    }
+
+    /// Grouping of a select over `rows` input rows, checking that
+    /// everything read from the result rows is grouped or aggregated.
+    fn plan_aggregate(
+        &self,
+        db: &str,
+        stmt: &SelectStatement,
+        rows: u64
+    ) -> Result<Option<AggregatePlan>, ExecutionError> {
+        if !stmt.is_aggregate() {
+            if !stmt.having.trim().is_empty() {
+                return Err(ExecutionError::InvalidStatement("having needs aggregates or a group_by".to_string()));
+            }
+            return Ok(None);
+        }
+        let having = parse_filter(&stmt.having)?;
+        let estimated_groups = estimate_groups(&self.engine, db, &stmt.table_name, &stmt.group_by, rows)?;
+        let aggregate = AggregatePlan {
+            group_by: stmt.group_by.clone(),
+            aggregates: stmt.aggregates.clone(),
+            having,
+            estimated_groups,
+        };
+
+        let output = aggregate.output_columns();
+        let selected = stmt.columns.iter().filter(|column| column.as_str() != "*");
+        let sorted = stmt.order_by.iter().map(|key| &key.column);
+        let filtered = aggregate.having.iter().flat_map(|having| having.columns());
+        for column in selected.chain(sorted).cloned().chain(filtered) {
+            if !output.contains(&column) {
+                return Err(
+                    ExecutionError::InvalidStatement(format!("column {} is neither grouped nor aggregated", column))
+                );
+            }
+        }
+        return Ok(Some(aggregate));
+    }
+
+    /// Order of a select whose unsorted result is expected to be `rows`
+    /// rows of about `row_size` bytes. `single` is the scan of a select of
+    /// one table, neither joined nor grouped.
+    fn plan_order(
+        &self,
+        db: &str,
+        stmt: &SelectStatement,
+        single: Option<&ScanPlan>,
+        rows: u64,
+        row_size: usize
+    ) -> Result<Option<SortPlan>, ExecutionError> {
+        if stmt.order_by.is_empty() {
+            if stmt.after.is_some() {
+                return Err(ExecutionError::InvalidStatement("a continuation token needs an order_by".to_string()));
+            }
+            return Ok(None);
+        }
+        let query_id = Continuation::query_id(stmt);
+        let after = stmt.after
+            .as_deref()
+            .map(|token| Continuation::decode(token, &query_id, &self.token_keys))
+            .transpose()
+            .map_err(ExecutionError::InvalidStatement)?;
+        let keep = stmt.limit.map(|limit| limit.saturating_add(stmt.offset) as usize);
+        let strategy = plan_sort(rows, row_size, keep, self.spill.memory_budget);
+        // A page can read the table in order and stop once it is full.
+        let first = &stmt.order_by[0].column;
+        let index = match single {
+            Some(scan) if keep.is_some() && scan.access == AccessPath::FullScan =>
+                self.engine
+                    .indexes(db, &scan.table)?
+                    .into_iter()
+                    .find(|index| index.columns.first() == Some(first))
+                    .map(|index| index.name),
+            _ => None,
+        };
+        return Ok(Some(SortPlan { order_by: stmt.order_by.clone(), after, query_id, strategy, index }));
+    }
+
+    /// Passes the rows to `emit` in storage order, without copying those
+    /// before `offset` or after `limit`.
+    fn run_unsorted(
+        &self,
+        db: &str,
+        scan: &ScanPlan,
+        offset: usize,
+        limit: Option<usize>,
+        emit: &mut dyn FnMut(Row) -> Result<(), StorageError>
+    ) -> Result<(), ExecutionError> {
+        let limit = limit.unwrap_or(usize::MAX);
+        let mut kept = 0;
+        let mut skipped = 0;
+        self.engine.visit(db, &scan.table, &scan.access, scan.filter(), |row| {
+            if skipped < offset {
+                skipped += 1;
+            } else if kept < limit {
+                kept += 1;
+                emit(row.clone())?;
+            }
+            Ok(())
+        })?;
+        return Ok(());
+    }
+
+    /// Passes one row per group to `emit` as the aggregation gives them
+    /// out, those `having` rejects left out.
+    fn run_aggregate(
+        &self,
+        source: impl FnOnce(&mut dyn FnMut(&Row) -> Result<(), StorageError>) -> Result<(), StorageError>,
+        aggregate: &AggregatePlan,
+        emit: &mut dyn FnMut(Row) -> Result<(), StorageError>
+    ) -> Result<(), StorageError> {
+        let started = Instant::now();
+        let mut aggregator = Aggregator::new(&aggregate.group_by, &aggregate.aggregates, &self.spill);
+        source(&mut (|row: &Row| aggregator.push(row)))?;
+        let strategy = aggregator.finish(
+            &mut (|row: Row| {
+                if aggregate.having.as_ref().is_some_and(|having| !having.matches(&row)) {
+                    return Ok(());
+                }
+                return emit(row);
+            })
+        )?;
+        stats::record_aggregate(&strategy.to_string(), strategy.groups(), started.elapsed());
+        return Ok(());
+    }
+
+    /// Sorts within the spill budget and passes the page asked for to
+    /// `emit`. A page of a paged select that is full gets a token for the
+    /// next one. The rows pushed by `source` are told whether the sort has as
+    /// many rows as the page needs.
+    fn run_sorted(
+        &self,
+        source: impl FnOnce(&mut dyn FnMut(&Row) -> Result<bool, StorageError>) -> Result<(), StorageError>,
+        sort: &SortPlan,
+        offset: usize,
+        limit: Option<usize>,
+        emit: &mut dyn FnMut(Row) -> Result<(), StorageError>
+    ) -> Result<Option<String>, ExecutionError> {
+        let order = sort.order_by.as_slice();
+        let keep = limit.map(|limit| limit.saturating_add(offset));
+        let mut sorter = Sorter::new(order, keep, &self.spill);
+        let mut ties_seen = 0;
+        let mut pushed = 0;
+        source(
+            &mut (|row: &Row| {
+                if sort.after.as_ref().is_none_or(|after| after.admits(order, row, &mut ties_seen)) {
+                    sorter.push(row.clone())?;
+                    pushed += 1;
+                }
+                return Ok(keep.is_some_and(|keep| pushed >= keep));
+            })
+        )?;
+        let (sorted, strategy) = sorter.finish()?;
+        stats::record_sort(&strategy.to_string());
+
+        let equal = |left: &Row, right: &Row| compare_rows(order, left, right) == Ordering::Equal;
+        let mut skipped = 0;
+        let mut last_skipped: Option<Row> = None;
+        // Rows identical to `last_skipped` at the end of those skipped.
+        let mut skipped_ties = 0;
+        // The token is made from the last row kept, so each row is held
+        // back until the next one comes.
+        let mut last: Option<Row> = None;
+        let mut kept = 0;
+        // Rows identical to `last` at the end of those kept.
+        let mut trailing = 0;
+        for row in sorted {
+            let row = row?;
+            if skipped < offset {
+                skipped += 1;
+                skipped_ties = match &last_skipped {
+                    Some(previous) if equal(previous, &row) => skipped_ties + 1,
+                    _ => 1,
+                };
+                last_skipped = Some(row);
+                continue;
+            }
+            trailing = match &last {
+                Some(previous) if equal(previous, &row) => trailing + 1,
+                _ => 1,
+            };
+            kept += 1;
+            if let Some(previous) = last.replace(row) {
+                emit(previous)?;
+            }
+        }
+
+        let continuation = match (limit, &last) {
+            (Some(limit), Some(last)) if kept == limit => {
+                if trailing == kept && last_skipped.as_ref().is_some_and(|previous| equal(previous, last)) {
+                    trailing += skipped_ties;
+                }
+                let next = Continuation::after(sort.query_id.clone(), order, last, trailing, sort.after.as_ref());
+                Some(next.encode(&self.token_keys))
+            }
+            _ => None,
+        };
+        if let Some(last) = last {
+            emit(last)?;
+        }
+        return Ok(continuation);
+    }
+
+    /// Runs a plan; the rows of a select go to `rows` when given, and are
+    /// collected in the result otherwise.
+    fn run_plan(&self, db: &str, plan: Plan, rows: Option<&RowSink<'_>>) -> Result<ExecutionResult, ExecutionError> {
+        let result = match plan {
+            Plan::Insert { table, rows } => ExecutionResult::Affected(self.engine.insert(db, &table, rows)?),
+            Plan::Upsert { scan, row, unique_key } =>
+                ExecutionResult::Affected(self.engine.upsert(db, &scan.table, row, &unique_key)?),
+            Plan::Select { scan, columns, join, aggregate, sort, offset, limit } => {
+                let joined = match &join {
+                    Some(join) => Some(run_join(&self.engine, db, &scan, join, &self.spill)?),
+                    None => None,
+                };
+                // Feeds the joined rows, or those of the scan, to an aggregate or sort.
+                let source = |push: &mut dyn FnMut(&Row) -> Result<(), StorageError>| {
+                    match &joined {
+                        Some(rows) => rows.iter().try_for_each(push),
+                        None => self.engine.visit(db, &scan.table, &scan.access, scan.filter(), push),
+                    }
+                };
+                let mut returned = 0;
+                let mut collected = Vec::new();
+                let mut emit = |row: Row| {
+                    let row = project(row, &columns);
+                    returned += 1;
+                    match rows {
+                        Some(sink) => sink(row),
+                        None => {
+                            collected.push(row);
+                            Ok(())
+                        }
+                    }
+                };
+                let continuation = match (&aggregate, &sort) {
+                    (None, None) => {
+                        match joined {
+                            Some(rows) => page(rows, offset, limit).into_iter().try_for_each(&mut emit)?,
+                            None => self.run_unsorted(db, &scan, offset, limit, &mut emit)?,
+                        }
+                        None
+                    }
+                    (None, Some(sort)) =>
+                        match &sort.index {
+                            Some(index) => {
+                                let from = sort.after.as_ref().and_then(Continuation::first_key);
+                                let by = IndexOrder { index, order: &sort.order_by[0], from };
+                                let source = |push: &mut dyn FnMut(&Row) -> Result<bool, StorageError>| {
+                                    return self.engine.visit_ordered(db, &scan.table, by, scan.filter(), push);
+                                };
+                                self.run_sorted(source, sort, offset, limit, &mut emit)?
+                            }
+                            None => {
+                                let source = |push: &mut dyn FnMut(&Row) -> Result<bool, StorageError>| {
+                                    return source(&mut |row: &Row| push(row).map(|_| ()));
+                                };
+                                self.run_sorted(source, sort, offset, limit, &mut emit)?
+                            }
+                        }
+                    (Some(aggregate), None) => {
+                        // Groups before `offset` or past `limit` are not kept.
+                        let wanted = limit.unwrap_or(usize::MAX);
+                        let mut kept = 0;
+                        let mut skipped = 0;
+                        self.run_aggregate(source, aggregate, &mut |row| {
+                            if skipped < offset {
+                                skipped += 1;
+                            } else if kept < wanted {
+                                kept += 1;
+                                emit(row)?;
+                            }
+                            Ok(())
+                        })?;
+                        None
+                    }
+                    (Some(aggregate), Some(sort)) => {
+                        let source = |push: &mut dyn FnMut(&Row) -> Result<bool, StorageError>| {
+                            return self.run_aggregate(source, aggregate, &mut |row| push(&row).map(|_| ()));
+                        };
+                        self.run_sorted(source, sort, offset, limit, &mut emit)?
+                    }
+                };
+                let paged = sort.is_some() && limit.is_some();
+                match rows {
+                    Some(_) => ExecutionResult::Streamed { rows: returned, paged, continuation },
+                    None if paged => ExecutionResult::Page { rows: collected, continuation },
+                    None => ExecutionResult::Rows(collected),
+                }
+            }
+            Plan::Update { scan, updates } =>
+                ExecutionResult::Affected(self.engine.update(db, &scan.table, &updates, &scan.access, scan.filter())?),
+            Plan::Delete { scan } =>
+                ExecutionResult::Affected(self.engine.delete(db, &scan.table, &scan.access, scan.filter())?),
+        };
+        return Ok(result);
+    }
+
+    /// Runs `plan` and fills in what each of its operators actually did.
+    fn analyze(&self, db: &str, plan: Plan) -> Result<Vec<PlanNode>, ExecutionError> {
+        let mut nodes = plan.describe();
+        let expected_sort = match &plan {
+            Plan::Select { sort: Some(sort), .. } => Some(sort.strategy.to_string()),
+            _ => None,
+        };
+        let joined = matches!(&plan, Plan::Select { join: Some(_), .. });
+        let started = Instant::now();
+        let (result, stats) = stats::collect(|| self.run_plan(db, plan, None));
+        let elapsed = started.elapsed();
+        let rows = match result? {
+            ExecutionResult::Rows(rows) | ExecutionResult::Page { rows, .. } => rows.len() as u64,
+            ExecutionResult::Affected(affected) | ExecutionResult::Streamed { rows: affected, .. } => affected,
+            ExecutionResult::Database(_) => 0,
+        };
+        // The first table in join order is scanned by the last node.
+        let first_table = nodes
+            .last()
+            .and_then(|node| node.table.clone())
+            .unwrap_or_default();
+        let mut joins_seen = 0;
+        for node in nodes.iter_mut() {
+            if node.parent.is_none() {
+                node.actual_rows = Some(rows);
+                node.actual_ms = Some(millis(elapsed));
+                // The sort text starts with the strategy; show the one used.
+                if let (Some(expected), Some(actual), Some(sort)) = (&expected_sort, &stats.sort, &mut node.sort) {
+                    *sort = sort.replacen(expected.as_str(), actual, 1);
+                }
+            } else if node.aggregate.is_some() {
+                node.actual_rows = Some(stats.groups);
+                node.actual_ms = Some(millis(stats.aggregate_time));
+                if let (Some(text), Some(strategy)) = (&mut node.aggregate, &stats.aggregate) {
+                    text.push_str(&format!(", {}", strategy));
+                }
+            } else if node.join.is_some() {
+                // Join nodes come last step first; each step's time leaves
+                // out the steps before it and the first scan.
+                let step = stats.joins.len().saturating_sub(joins_seen + 1);
+                joins_seen += 1;
+                let first_scan = stats.tables.get(&first_table).map(|(_, took)| *took).unwrap_or_default();
+                let took: Duration = stats.joins[..(step + 1).min(stats.joins.len())]
+                    .iter()
+                    .map(|(_, took)| *took)
+                    .sum();
+                node.actual_rows = Some(stats.joins.get(step).map_or(0, |(rows, _)| *rows));
+                node.actual_ms = Some(millis(took + first_scan));
+            } else if joined {
+                let (rows, took) = node.table
+                    .as_ref()
+                    .and_then(|table| stats.tables.get(table))
+                    .copied()
+                    .unwrap_or_default();
+                node.actual_rows = Some(rows);
+                node.actual_ms = Some(millis(took));
+            } else {
+                node.actual_rows = Some(stats.rows_matched);
+                node.actual_ms = Some(millis(stats.scan_time));
+            }
+        }
+        return Ok(nodes);
+    }
+
+    /// Executes a statement on behalf of a connection and records it in the
+    /// audit log.
+    pub fn run(
+        &self,
+        connection_id: usize,
+        ctx: &ExecutionContext,
+        message: &Message
+    ) -> Result<ExecutionResult, ExecutionError> {
+        return self.run_into(connection_id, ctx, message, None);
+    }
+
+    /// `run`, passing the rows of a select to `rows` when given.
+    fn run_into(
+        &self,
+        connection_id: usize,
+        ctx: &ExecutionContext,
+        message: &Message,
+        rows: Option<&RowSink<'_>>
+    ) -> Result<ExecutionResult, ExecutionError> {
+        // Each statement of a query is metered and audited on its own. A
+        // transaction the query opened but did not get to end is rolled back.
+        if message.header.message_type == MessageType::Query {
+            let open = self.transaction(connection_id).is_some();
+            let result = self.run_query(ctx, message, |ctx, statement, last| {
+                return self.run_into(connection_id, ctx, statement, rows.filter(|_| last));
+            });
+            if result.is_err() && !open {
+                self.end_transaction(connection_id, false);
+            }
+            return result;
+        }
+        let result = self.metered(connection_id, ctx, message, rows);
+        self.audit(connection_id, ctx, message, result.as_ref().err());
+        return result;
+    }
+
+    /// Dispatches a statement, recording its metrics and, when it is slow,
+    /// an entry in the slow query log.
+    fn metered(
+        &self,
+        connection_id: usize,
+        ctx: &ExecutionContext,
+        message: &Message,
+        rows: Option<&RowSink<'_>>
+    ) -> Result<ExecutionResult, ExecutionError> {
+        let started = Instant::now();
+        let (result, stats) = stats::collect(|| self.dispatch(connection_id, ctx, message, rows));
+        let elapsed = started.elapsed();
+        let message_type = message.header.message_type.to_name();
+        metrics().request_duration.with_label_values(&[message_type]).observe(elapsed.as_secs_f64());
+        metrics().requests.with_label_values(&[message_type, outcome(result.as_ref().err())]).inc();
+        if self.slow_queries.is_slow(elapsed) {
+            self.record_slow(connection_id, ctx, message, &result, elapsed, stats);
+        }
+        return result;
+    }
+
+    /// Statements kept per connection go to their own handlers, the rest to
+    /// `execute`; inside the connection's transaction when it has one open.
+    fn dispatch(
+        &self,
+        connection_id: usize,
+        ctx: &ExecutionContext,
+        message: &Message,
+        rows: Option<&RowSink<'_>>
+    ) -> Result<ExecutionResult, ExecutionError> {
+        let message_type = message.header.message_type;
+        let run = || {
+            match message_type {
+                MessageType::Prepare => self.prepare(connection_id, ctx, decode_statement(message)?),
+                MessageType::Execute => self.execute_prepared(connection_id, ctx, decode_statement(message)?, rows),
+                MessageType::Deallocate => {
+                    let stmt: DeallocateStatement = decode_statement(message)?;
+                    Ok(ExecutionResult::Affected(self.deallocate(connection_id, stmt.name.as_deref())?))
+                }
+                _ => self.execute_into(ctx, message, rows),
+            }
+        };
+
+        match message_type {
+            MessageType::BeginTransaction => {
+                let stmt: BeginTransactionStatement = decode_statement(message)?;
+                let mut transactions = self.transactions.lock().unwrap();
+                if let Some(open) = transactions.get(&connection_id) {
+                    let open = open.lock().unwrap().name().to_string();
+                    return Err(ExecutionError::InvalidStatement(format!("transaction {} is already open", open)));
+                }
+                let transaction = self.engine.begin(&stmt.transaction_id);
+                transactions.insert(connection_id, Arc::new(Mutex::new(transaction)));
+                return Ok(ExecutionResult::Affected(0));
+            }
+            MessageType::Commit => {
+                let stmt: CommitStatement = decode_statement(message)?;
+                self.open_transaction(connection_id, &stmt.transaction_id)?;
+                self.end_transaction(connection_id, true);
+                return Ok(ExecutionResult::Affected(0));
+            }
+            MessageType::Rollback => {
+                let stmt: RollbackStatement = decode_statement(message)?;
+                let open = self.open_transaction(connection_id, &stmt.transaction_id)?;
+                match &stmt.savepoint_name {
+                    Some(savepoint) => {
+                        let mut open = open.lock().unwrap();
+                        check_savepoint(&open, savepoint)?;
+                        self.engine.rollback_to_savepoint(&mut open, savepoint);
+                    }
+                    None => self.end_transaction(connection_id, false),
+                }
+                return Ok(ExecutionResult::Affected(0));
+            }
+            MessageType::Savepoint => {
+                let stmt: SavepointStatement = decode_statement(message)?;
+                let open = self.open_transaction(connection_id, &stmt.transaction_id)?;
+                open.lock().unwrap().savepoint(&stmt.savepoint_name);
+                return Ok(ExecutionResult::Affected(0));
+            }
+            MessageType::ReleaseSavepoint => {
+                let stmt: ReleaseSavepointStatement = decode_statement(message)?;
+                let open = self.open_transaction(connection_id, &stmt.transaction_id)?;
+                let mut open = open.lock().unwrap();
+                check_savepoint(&open, &stmt.savepoint_name)?;
+                open.release(&stmt.savepoint_name);
+                return Ok(ExecutionResult::Affected(0));
+            }
+            _ => {}
+        }
+
+        let Some(open) = self.transaction(connection_id) else {
+            return run();
+        };
+        if is_ddl(message_type) {
+            return Err(
+                ExecutionError::InvalidStatement(format!("{} cannot run inside a transaction", message_type.to_name()))
+            );
+        }
+        let mut open = open.lock().unwrap();
+        return transaction::within(&mut open, run);
+    }
+
+    fn transaction(&self, connection_id: usize) -> Option<Arc<Mutex<Transaction>>> {
+        return self.transactions.lock().unwrap().get(&connection_id).cloned();
+    }
+
+    /// The connection's open transaction, which must be the one named.
+    fn open_transaction(&self, connection_id: usize, name: &str) -> Result<Arc<Mutex<Transaction>>, ExecutionError> {
+        let open = self
+            .transaction(connection_id)
+            .ok_or_else(|| ExecutionError::InvalidStatement("no transaction is open".to_string()))?;
+        if open.lock().unwrap().name() != name {
+            return Err(ExecutionError::InvalidStatement(format!("transaction {} is not open", name)));
+        }
+        return Ok(open);
+    }
+
+    /// Commits or rolls back the connection's transaction, if it has one.
+    fn end_transaction(&self, connection_id: usize, commit: bool) {
+        let Some(open) = self.transactions.lock().unwrap().remove(&connection_id) else {
+            return;
+        };
+        // Waits for a statement still running inside it.
+        let transaction = std::mem::take(&mut *open.lock().unwrap());
+        if commit {
+            self.engine.commit(transaction);
+        } else {
+            self.engine.rollback(transaction);
+        }
+    }
+
+    /// Compiles, checks and plans a data statement with placeholders once,
+    /// for the Executes of the connection. Answers with the number of
+    /// parameters it takes.
+    fn prepare(
+        &self,
+        connection_id: usize,
+        ctx: &ExecutionContext,
+        stmt: PrepareStatement
+    ) -> Result<ExecutionResult, ExecutionError> {
+        let prepared = self.compile(ctx, stmt.sql)?;
+        let parameters = prepared.parameters as u64;
+        let mut cache = self.prepared.lock().unwrap();
+        let key = (connection_id, stmt.name);
+        if cache.contains_key(&key) {
+            return Err(ExecutionError::InvalidStatement(format!("prepared statement {} already exists", key.1)));
+        }
+        cache.insert(key, prepared);
+        return Ok(ExecutionResult::Affected(parameters));
+    }
+
+    /// Plans `sql` in the database of `ctx`, against the schema as it is
+    /// now.
+    fn compile(&self, ctx: &ExecutionContext, sql: String) -> Result<PreparedPlan, ExecutionError> {
+        // Read first: a change racing with the planning makes the plan stale.
+        let schema_version = self.engine.schema_version();
+        let compiled = sql::parse_prepared(&sql).map_err(|e| ExecutionError::InvalidStatement(e.to_string()))?;
+        let message_type = compiled.statement.protocol();
+        if !EXPLAINABLE.contains(&message_type) {
+            return Err(ExecutionError::InvalidStatement(format!("{} cannot be prepared", message_type.to_name())));
+        }
+        let body = compiled.statement
+            .to_bytes()
+            .map_err(|e| ExecutionError::InvalidStatement(e.to_string()))?;
+        let plan = self.plan(ctx, message_type, &body)?;
+        self.check_columns(&ctx.database, &plan)?;
+        return Ok(PreparedPlan {
+            sql,
+            database: ctx.database.clone(),
+            message_type,
+            parameters: compiled.parameters,
+            slots: compiled.slots,
+            plan,
+            schema_version,
+        });
+    }
+
+    /// Checks the columns a single table plan writes or filters on; joins
+    /// check theirs while planning.
+    fn check_columns(&self, db: &str, plan: &Plan) -> Result<(), ExecutionError> {
+        let (table, mut columns) = match plan {
+            Plan::Insert { table, rows } => (table, rows.iter().flat_map(|row| row.keys().cloned()).collect()),
+            Plan::Upsert { scan, row, .. } => (&scan.table, row.keys().cloned().collect()),
+            Plan::Update { scan, updates } => (&scan.table, updates.keys().cloned().collect()),
+            Plan::Select { join: Some(_), .. } => {
+                return Ok(());
+            }
+            Plan::Select { scan, .. } | Plan::Delete { scan } => (&scan.table, Vec::new()),
+        };
+        if let Plan::Select { scan, .. } | Plan::Update { scan, .. } | Plan::Delete { scan } = plan {
+            columns.extend(scan.predicate.iter().flat_map(|predicate| predicate.columns()));
+        }
+        return Ok(self.engine.check_columns(db, table, &columns)?);
+    }
+
+    /// Checks the caller may still run a prepared plan in `db`.
+    fn authorize_plan(&self, ctx: &ExecutionContext, db: &str, plan: &Plan) -> Result<(), ExecutionError> {
+        match plan {
+            Plan::Select { scan, join, .. } => {
+                self.authorize(ctx, Privilege::Select, db, &scan.table)?;
+                for step in join.iter().flat_map(|join| join.steps.iter()) {
+                    self.authorize(ctx, Privilege::Select, db, &step.scan.table)?;
+                }
+            }
+            Plan::Insert { table, .. } => self.authorize(ctx, Privilege::Insert, db, table)?,
+            Plan::Upsert { scan, .. } => {
+                self.authorize(ctx, Privilege::Insert, db, &scan.table)?;
+                self.authorize(ctx, Privilege::Update, db, &scan.table)?;
+            }
+            Plan::Update { scan, .. } => self.authorize(ctx, Privilege::Update, db, &scan.table)?,
+            Plan::Delete { scan } => self.authorize(ctx, Privilege::Delete, db, &scan.table)?,
+        }
+        return Ok(());
+    }
+
+    /// Binds the parameters to a prepared statement and runs it in the
+    /// database it was prepared in. A statement prepared before a change to
+    /// the schema is planned again first.
+    fn execute_prepared(
+        &self,
+        connection_id: usize,
+        ctx: &ExecutionContext,
+        stmt: ExecuteStatement,
+        rows: Option<&RowSink<'_>>
+    ) -> Result<ExecutionResult, ExecutionError> {
+        let key = (connection_id, stmt.name.clone());
+        let mut prepared = match self.prepared.lock().unwrap().get(&key) {
+            Some(prepared) => prepared.clone(),
+            None => {
+                return Err(ExecutionError::InvalidStatement(format!("no prepared statement {}", stmt.name)));
+            }
+        };
+        if stmt.parameters.len() != prepared.parameters {
+            return Err(
+                ExecutionError::InvalidStatement(
+                    format!("{} takes {} parameters, got {}", stmt.name, prepared.parameters, stmt.parameters.len())
+                )
+            );
+        }
+        if prepared.schema_version == self.engine.schema_version() {
+            self.authorize_plan(ctx, &prepared.database, &prepared.plan)?;
+        } else {
+            let ctx = ExecutionContext { principal: ctx.principal.clone(), database: prepared.database.clone() };
+            prepared = self.compile(&ctx, prepared.sql)?;
+            // Deallocated meanwhile: run it this once, but keep it gone.
+            if let Some(entry) = self.prepared.lock().unwrap().get_mut(&key) {
+                *entry = prepared.clone();
+            }
+        }
+        let plan = prepared.bind(&stmt.parameters);
+        return self.run_plan(&prepared.database, plan, rows);
+    }
+
+    /// Drops one prepared statement of the connection, or all of them when
+    /// `name` is None; returns how many were dropped.
+    fn deallocate(&self, connection_id: usize, name: Option<&str>) -> Result<u64, ExecutionError> {
+        let mut prepared = self.prepared.lock().unwrap();
+        if let Some(name) = name {
+            if prepared.remove(&(connection_id, name.to_string())).is_none() {
+                return Err(ExecutionError::InvalidStatement(format!("no prepared statement {}", name)));
+            }
+            return Ok(1);
+        }
+        let before = prepared.len();
+        prepared.retain(|(connection, _), _| *connection != connection_id);
+        return Ok((before - prepared.len()) as u64);
+    }
+
+    fn record_slow(
+        &self,
+        connection_id: usize,
+        ctx: &ExecutionContext,
+        message: &Message,
+        result: &Result<ExecutionResult, ExecutionError>,
+        elapsed: Duration,
+        stats: StatementStats
+    ) {
+        let rows_returned = match result {
+            Ok(ExecutionResult::Rows(rows) | ExecutionResult::Page { rows, .. }) => rows.len() as u64,
+            Ok(ExecutionResult::Affected(affected) | ExecutionResult::Streamed { rows: affected, .. }) => *affected,
+            _ => 0,
+        };
+        self.slow_queries.record(SlowQuery {
+            time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            connection_id,
+            principal: ctx.principal.to_string(),
+            database: ctx.database.clone(),
+            statement: statement_text(message),
+            duration_ms: millis(elapsed),
+            rows_scanned: stats.rows_scanned,
+            rows_returned,
+            index_used: stats.index_used,
+            lock_wait_ms: millis(stats.lock_wait),
+        });
+    }
+
+    /// Runs a request on the calling thread, which storage may block, and
+    /// sends its response to `frames`: the rows of a select as it produces
+    /// them, anything else as one reply.
+    fn respond(&self, session: &Session, ctx: &ExecutionContext, message: &Message, frames: mpsc::Sender<Message>) {
+        let rows = RowFrames::new(message, frames.clone());
+        let response = match self.run_into(session.connection_id, ctx, message, Some(&|row| rows.push(row))) {
+            Ok(ExecutionResult::Streamed { paged, continuation, .. }) => rows.finish(paged, continuation),
+            Ok(ExecutionResult::Rows(collected)) => rows.finish_with(collected, false, None),
+            Ok(ExecutionResult::Page { rows: collected, continuation }) => rows.finish_with(collected, true, continuation),
+            Ok(ExecutionResult::Affected(affected)) => affected_response(message, affected),
+            Ok(ExecutionResult::Database(database)) => {
+                session.set_database(&database);
+                affected_response(message, 0)
+            }
+            Err(ExecutionError::Storage(StorageError::Cancelled)) => {
+                return;
+            }
+            Err(e) => {
+                warn!(
+                    connection_id = session.connection_id,
+                    message_id:% = message.header.message_id_string();
+                    "{} by {:?} failed: {}",
+                    message.header.message_type.to_name(),
+                    ctx.principal,
+                    e
+                );
+                error_response(message, e.error_code(), e.to_string())
+            }
+        };
+        let _ = frames.blocking_send(response);
+    }
+
+    /// The reply to one chunk of an upload, if any. The upload is audited
+    /// once, when it ends or fails.
+    fn upload_chunk(&self, connection_id: usize, ctx: &ExecutionContext, message: &Message) -> HandlerResponse {
+        return match self.execute_chunk(connection_id, ctx, message) {
+            Ok(None) => HandlerResponse::None,
+            Ok(Some(total)) => {
+                self.audit(connection_id, ctx, message, None);
+                HandlerResponse::Reply(affected_response(message, total))
+            }
+            Err(e) => {
+                self.audit(connection_id, ctx, message, Some(&e));
+                HandlerResponse::Reply(error_response(message, e.error_code(), e.to_string()))
+            }
+        };
+    }
+
+    /// Runs one chunk of an upload. The first chunk opens the upload, and
+    /// the final one commits it and reports the total for the whole stream;
+    /// a chunk that fails rolls back all of them. Each chunk is metered like
+    /// a statement of its own.
+    fn execute_chunk(
+        &self,
+        connection_id: usize,
+        ctx: &ExecutionContext,
+        message: &Message
+    ) -> Result<Option<u64>, ExecutionError> {
+        let stream_id = message.header.message_id;
+        let kind = message.header.chunk_kind();
+        let savepoint = upload_savepoint(&stream_id);
+
+        let upload = if kind == ChunkKind::First {
+            let transaction = match self.transaction(connection_id) {
+                Some(open) => {
+                    open.lock().unwrap().savepoint(&savepoint);
+                    None
+                }
+                None => Some(self.engine.begin(&savepoint)),
+            };
+            Upload { connection_id, transaction, total: 0 }
+        } else {
+            self.uploads.lock().unwrap().remove(&stream_id).ok_or_else(|| {
+                return ExecutionError::InvalidStatement("chunk of an upload that was never started".to_string());
+            })?
+        };
+        // Guards against the upload being left open when a chunk panics.
+        let mut upload = scopeguard::guard(upload, |upload| self.abort_upload(&savepoint, upload));
+
+        let result = match &mut upload.transaction {
+            Some(transaction) => transaction::within(transaction, || self.metered(connection_id, ctx, message, None)),
+            None => self.metered(connection_id, ctx, message, None),
+        };
+        if let ExecutionResult::Affected(affected) = result? {
+            upload.total += affected;
+        }
+
+        let upload = scopeguard::ScopeGuard::into_inner(upload);
+        if !kind.is_final() {
+            self.uploads.lock().unwrap().insert(stream_id, upload);
+            return Ok(None);
+        }
+        match upload.transaction {
+            Some(transaction) => self.engine.commit(transaction),
+            None => {
+                if let Some(open) = self.transaction(connection_id) {
+                    open.lock().unwrap().release(&savepoint);
+                }
+            }
+        }
+        return Ok(Some(upload.total));
+    }
+
+    /// Undoes what an unfinished upload inserted.
+    fn abort_upload(&self, savepoint: &str, upload: Upload) {
+        match upload.transaction {
+            Some(transaction) => self.engine.rollback(transaction),
+            None => {
+                // Gone with the connection's transaction if that ended first.
+                if let Some(open) = self.transaction(upload.connection_id) {
+                    let mut open = open.lock().unwrap();
+                    if open.has_savepoint(savepoint) {
+                        self.engine.rollback_to_savepoint(&mut open, savepoint);
+                        open.release(savepoint);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Rolls back the uploads a connection left unfinished.
+    fn abort_uploads(&self, connection_id: usize) {
+        let aborted: Vec<([u8; 16], Upload)> = self.uploads
+            .lock()
+            .unwrap()
+            .extract_if(|_, upload| upload.connection_id == connection_id)
+            .collect();
+        for (stream_id, upload) in aborted {
+            self.abort_upload(&upload_savepoint(&stream_id), upload);
+        }
+    }
+}
+
+/// The `ResultStatement` answering a statement that returns no rows.
+pub fn affected_response(request: &Message, affected: u64) -> Message {
+    let message_type = request.header.message_type;
+    return Message::new_response(request, message_type, &ResultStatement::new(message_type, affected));
+}
+
+/// Sends the rows of a select to the client `ROWS_PER_CHUNK` at a time, as
+/// MessagePack encoded `RowBatch`es, while the select is producing them.
+/// Sending waits while the listener has no credit left for the stream, which
+/// holds the select back, along with the read lock of a table it scans in
+/// storage order.
+struct RowFrames {
+    message_type: MessageType,
+    stream_id: [u8; 16],
+    frames: mpsc::Sender<Message>,
+    batch: RefCell<Vec<Row>>,
+    sent: Cell<usize>,
+}
+
+impl RowFrames {
+    fn new(request: &Message, frames: mpsc::Sender<Message>) -> Self {
+        return Self {
+            message_type: request.header.message_type,
+            stream_id: request.header.message_id,
+            frames,
+            batch: RefCell::new(Vec::new()),
+            sent: Cell::new(0),
+        };
+    }
+
+    fn frame(&self, kind: ChunkKind, batch: RowBatch) -> Message {
+        let body = rmp_serde::to_vec_named(&batch).unwrap();
+        return Message::chunk(self.message_type, MessageTypeFlag::ResponseMessage, self.stream_id, kind, body);
+    }
+
+    /// Adds a row, first sending the batch before it once that is full.
+    fn push(&self, row: Row) -> Result<(), StorageError> {
+        let mut batch = self.batch.borrow_mut();
+        if batch.len() == ROWS_PER_CHUNK {
+            let kind = if self.sent.get() == 0 { ChunkKind::First } else { ChunkKind::Continuation };
+            let frame = self.frame(kind, RowBatch::Rows(std::mem::take(&mut *batch)));
+            self.frames.blocking_send(frame).map_err(|_| StorageError::Cancelled)?;
+            self.sent.set(self.sent.get() + 1);
+        }
+        batch.push(row);
+        return Ok(());
+    }
+
+    /// The frame ending the stream, with the rows left and, for a paged
+    /// select, the token for the next page.
+    fn finish(&self, paged: bool, continuation: Option<String>) -> Message {
+        let rows = self.batch.take();
+        let kind = if self.sent.get() == 0 { ChunkKind::Single } else { ChunkKind::Last };
+        let batch = if paged { RowBatch::Page { rows, continuation } } else { RowBatch::Rows(rows) };
+        return self.frame(kind, batch);
+    }
+
+    /// Sends rows collected by the statement the way a select sends its own.
+    fn finish_with(&self, rows: Vec<Row>, paged: bool, continuation: Option<String>) -> Message {
+        // Once the client stopped reading, the rest goes nowhere.
+        let _ = rows.into_iter().try_for_each(|row| self.push(row));
+        return self.finish(paged, continuation);
+    }
+}
+
+/// Runs `work` on a blocking thread, since storage calls may block for a
+/// while. Its spans stay under the caller's.
+fn in_blocking_thread<T, F>(work: F) -> tokio::task::JoinHandle<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static
+{
+    let span = Span::current();
+    let dispatch = tracing::dispatcher::get_default(|dispatch| dispatch.clone());
+    return tokio::task::spawn_blocking(move || {
+        return tracing::dispatcher::with_default(&dispatch, || span.in_scope(work));
+    });
+}
+
+#[async_trait]
+impl MessageHandler for Executor {
+    async fn handle(self: Arc<Self>, session: Arc<Session>, message: Message) -> HandlerResponse {
+        let principal = match session.principal() {
+            Some(principal) => principal,
+            None => {
+                return HandlerResponse::Reply(
+                    error_response(&message, ErrorCode::AuthenticationRequired, "login required".to_string())
+                );
+            }
+        };
+        let ctx = ExecutionContext { principal, database: session.database() };
+
+        if message.header.chunk_kind() != ChunkKind::Single {
+            // Chunks are handed over one at a time, so waiting for each one
+            // keeps the upload in order.
+            let chunk = in_blocking_thread(move || self.upload_chunk(session.connection_id, &ctx, &message));
+            return chunk.await.unwrap_or(HandlerResponse::None);
+        }
+
+        // The statement runs on a blocking thread, which hands the frames
+        // over as it produces them.
+        let (frames, mut receiver) = mpsc::channel(1);
+        drop(in_blocking_thread(move || self.respond(&session, &ctx, &message, frames)));
+        let first = match receiver.recv().await {
+            Some(first) => first,
+            None => {
+                return HandlerResponse::None;
+            }
+        };
+        if first.header.chunk_kind() == ChunkKind::Single {
+            return HandlerResponse::Reply(first);
+        }
+        let rest = stream::unfold(receiver, |mut receiver| async move {
+            return receiver.recv().await.map(|frame| (frame, receiver));
+        });
+        return HandlerResponse::Stream(Box::pin(stream::iter([first]).chain(rest)));
+    }
+
+    async fn authenticate_user(&self, session: Arc<Session>, stmt: &UserLoginStatement) -> Result<(), ErrorStatement> {
+        if let Err(e) = self.catalog.authenticate(&stmt.username, &stmt.password) {
+            return Err(ErrorStatement::new(ErrorCode::AuthenticationFailed, e.to_string()));
+        }
+
+        if !stmt.database.is_empty() {
+            let ctx = ExecutionContext {
+                principal: Principal::User(stmt.username.clone()),
+                database: DEFAULT_DATABASE.to_string(),
+            };
+            if !self.engine.has_database(&stmt.database) || !self.can_see(&ctx, &stmt.database, None) {
+                return Err(
+                    ErrorStatement::new(ErrorCode::PermissionDenied, format!("no access to {}", stmt.database))
+                );
+            }
+            session.set_database(&stmt.database);
+        }
+        return Ok(());
+    }
+
+    async fn disconnected(&self, session: Arc<Session>) {
+        self.abort_uploads(session.connection_id);
+        self.end_transaction(session.connection_id, false);
+        let _ = self.deallocate(session.connection_id, None);
+    }
+}
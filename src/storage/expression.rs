@@ -0,0 +1,597 @@
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
+use serde_json::Value;
+use crate::transport::Row;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpressionError(pub String);
+
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid expression: {}", self.0)
+    }
+}
+
+impl Error for ExpressionError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Identifier(String),
+    Number(f64),
+    String(String),
+    Symbol(&'static str),
+    /// A placeholder, numbered from 0: `?` takes the number after the last
+    /// one, `$n` is number `n - 1`.
+    Parameter(usize),
+}
+
+const SYMBOLS: [&str; 14] = ["<=", ">=", "!=", "<>", "=", "<", ">", "(", ")", ",", "*", ".", "-", ";"];
+
+/// Byte range of a token in its input.
+pub type Span = (usize, usize);
+
+/// Splits an expression into tokens. Keywords come out as identifiers and
+/// are matched case-insensitively by the parser.
+pub fn tokenize(input: &str) -> Result<Vec<Token>, ExpressionError> {
+    return tokenize_with_spans(input)
+        .map(|tokens| tokens.into_iter().map(|(token, _)| token).collect())
+        .map_err(|(error, _)| error);
+}
+
+/// `tokenize`, with the span of each token; an error comes with the byte
+/// offset it was found at.
+pub fn tokenize_with_spans(input: &str) -> Result<Vec<(Token, Span)>, (ExpressionError, usize)> {
+    let chars: Vec<char> = input.chars().collect();
+    // Byte offset of each character, and of the end of the input.
+    let offsets: Vec<usize> = input
+        .char_indices()
+        .map(|(offset, _)| offset)
+        .chain(std::iter::once(input.len()))
+        .collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    let mut next_parameter = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push((Token::Identifier(chars[start..i].iter().collect()), (offsets[start], offsets[i])));
+            continue;
+        }
+
+        if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            let number = text
+                .parse::<f64>()
+                .map_err(|_| (ExpressionError(format!("bad number {}", text)), offsets[start]))?;
+            tokens.push((Token::Number(number), (offsets[start], offsets[i])));
+            continue;
+        }
+
+        if c == '?' || c == '$' {
+            let start = i;
+            i += 1;
+            let parameter = if c == '?' {
+                next_parameter
+            } else {
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+                let text: String = chars[start + 1..i].iter().collect();
+                match text.parse::<usize>() {
+                    Ok(n) if n > 0 => n - 1,
+                    _ => {
+                        return Err((ExpressionError(format!("bad parameter ${}", text)), offsets[start]));
+                    }
+                }
+            };
+            next_parameter = parameter + 1;
+            tokens.push((Token::Parameter(parameter), (offsets[start], offsets[i])));
+            continue;
+        }
+
+        if c == '\'' || c == '"' {
+            let start = i;
+            let mut text = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => {
+                        return Err((ExpressionError("unterminated string".to_string()), offsets[start]));
+                    }
+                    // A doubled quote inside a string stands for the quote itself.
+                    Some(&q) if q == c && chars.get(i + 1) == Some(&c) => {
+                        text.push(c);
+                        i += 2;
+                    }
+                    Some(&q) if q == c => {
+                        i += 1;
+                        break;
+                    }
+                    Some(&other) => {
+                        text.push(other);
+                        i += 1;
+                    }
+                }
+            }
+            tokens.push((Token::String(text), (offsets[start], offsets[i])));
+            continue;
+        }
+
+        let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+        match SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            Some(symbol) => {
+                tokens.push((Token::Symbol(symbol), (offsets[i], offsets[i + symbol.len()])));
+                i += symbol.len();
+            }
+            None => {
+                return Err((ExpressionError(format!("unexpected character {:?}", c)), offsets[i]));
+            }
+        }
+    }
+
+    return Ok(tokens);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOperator {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Literal(Value),
+    Column(String),
+    /// Placeholder of a prepared statement, bound to a literal before the
+    /// statement runs.
+    Parameter(usize),
+    Binary {
+        op: BinaryOperator,
+        left: Box<Expression>,
+        right: Box<Expression>,
+    },
+    Not(Box<Expression>),
+    IsNull {
+        expr: Box<Expression>,
+        negated: bool,
+    },
+    In {
+        expr: Box<Expression>,
+        list: Vec<Expression>,
+        negated: bool,
+    },
+}
+
+/// Recursive descent parser over a token slice.
+pub struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+#[allow(dead_code)]
+impl<'a> Parser<'a> {
+    pub fn new(tokens: &'a [Token]) -> Self {
+        Self { tokens, pos: 0 }
+    }
+
+    pub fn position(&self) -> usize {
+        return self.pos;
+    }
+
+    pub fn is_done(&self) -> bool {
+        return self.pos >= self.tokens.len();
+    }
+
+    pub fn peek(&self) -> Option<&Token> {
+        return self.tokens.get(self.pos);
+    }
+
+    pub fn next_token(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        return token;
+    }
+
+    pub fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Identifier(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    pub fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if self.peek_keyword(keyword) {
+            self.pos += 1;
+            return true;
+        }
+        return false;
+    }
+
+    pub fn expect_keyword(&mut self, keyword: &str) -> Result<(), ExpressionError> {
+        if self.eat_keyword(keyword) {
+            return Ok(());
+        }
+        return Err(self.unexpected(keyword));
+    }
+
+    pub fn eat_symbol(&mut self, symbol: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol) {
+            self.pos += 1;
+            return true;
+        }
+        return false;
+    }
+
+    pub fn expect_symbol(&mut self, symbol: &str) -> Result<(), ExpressionError> {
+        if self.eat_symbol(symbol) {
+            return Ok(());
+        }
+        return Err(self.unexpected(symbol));
+    }
+
+    pub fn expect_identifier(&mut self) -> Result<String, ExpressionError> {
+        match self.peek() {
+            Some(Token::Identifier(word)) => {
+                let word = word.clone();
+                self.pos += 1;
+                Ok(word)
+            }
+            _ => Err(self.unexpected("an identifier")),
+        }
+    }
+
+    pub fn unexpected(&self, expected: &str) -> ExpressionError {
+        match self.peek() {
+            Some(token) => ExpressionError(format!("expected {} but found {:?}", expected, token)),
+            None => ExpressionError(format!("expected {} at end of input", expected)),
+        }
+    }
+
+    pub fn parse_expression(&mut self) -> Result<Expression, ExpressionError> {
+        let mut left = self.parse_and()?;
+        while self.eat_keyword("OR") {
+            let right = self.parse_and()?;
+            left = Expression::Binary { op: BinaryOperator::Or, left: Box::new(left), right: Box::new(right) };
+        }
+        return Ok(left);
+    }
+
+    fn parse_and(&mut self) -> Result<Expression, ExpressionError> {
+        let mut left = self.parse_not()?;
+        while self.eat_keyword("AND") {
+            let right = self.parse_not()?;
+            left = Expression::Binary { op: BinaryOperator::And, left: Box::new(left), right: Box::new(right) };
+        }
+        return Ok(left);
+    }
+
+    fn parse_not(&mut self) -> Result<Expression, ExpressionError> {
+        if self.eat_keyword("NOT") {
+            return Ok(Expression::Not(Box::new(self.parse_not()?)));
+        }
+        return self.parse_comparison();
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expression, ExpressionError> {
+        let left = self.parse_primary()?;
+
+        if self.eat_keyword("IS") {
+            let negated = self.eat_keyword("NOT");
+            self.expect_keyword("NULL")?;
+            return Ok(Expression::IsNull { expr: Box::new(left), negated });
+        }
+
+        let negated = self.eat_keyword("NOT");
+        if self.eat_keyword("IN") {
+            self.expect_symbol("(")?;
+            let mut list = vec![self.parse_primary()?];
+            while self.eat_symbol(",") {
+                list.push(self.parse_primary()?);
+            }
+            self.expect_symbol(")")?;
+            return Ok(Expression::In { expr: Box::new(left), list, negated });
+        }
+        if negated {
+            return Err(self.unexpected("IN"));
+        }
+
+        let op = match self.peek() {
+            Some(Token::Symbol("=")) => BinaryOperator::Eq,
+            Some(Token::Symbol("!=")) | Some(Token::Symbol("<>")) => BinaryOperator::NotEq,
+            Some(Token::Symbol("<")) => BinaryOperator::Lt,
+            Some(Token::Symbol("<=")) => BinaryOperator::LtEq,
+            Some(Token::Symbol(">")) => BinaryOperator::Gt,
+            Some(Token::Symbol(">=")) => BinaryOperator::GtEq,
+            _ => {
+                return Ok(left);
+            }
+        };
+        self.pos += 1;
+
+        let right = self.parse_primary()?;
+        return Ok(Expression::Binary { op, left: Box::new(left), right: Box::new(right) });
+    }
+
+    fn parse_primary(&mut self) -> Result<Expression, ExpressionError> {
+        if self.eat_symbol("(") {
+            let expr = self.parse_expression()?;
+            self.expect_symbol(")")?;
+            return Ok(expr);
+        }
+
+        if self.eat_symbol("-") {
+            return match self.next_token() {
+                Some(Token::Number(n)) => Ok(Expression::Literal(number(-*n))),
+                _ => Err(ExpressionError("expected a number after '-'".to_string())),
+            };
+        }
+
+        let token = match self.next_token() {
+            Some(token) => token.clone(),
+            None => {
+                return Err(ExpressionError("unexpected end of expression".to_string()));
+            }
+        };
+
+        match token {
+            Token::Number(n) => Ok(Expression::Literal(number(n))),
+            Token::Parameter(n) => Ok(Expression::Parameter(n)),
+            Token::String(s) => Ok(Expression::Literal(Value::String(s))),
+            Token::Identifier(word) if word.eq_ignore_ascii_case("NULL") => Ok(Expression::Literal(Value::Null)),
+            Token::Identifier(word) if word.eq_ignore_ascii_case("TRUE") => Ok(Expression::Literal(Value::Bool(true))),
+            Token::Identifier(word) if word.eq_ignore_ascii_case("FALSE") =>
+                Ok(Expression::Literal(Value::Bool(false))),
+            Token::Identifier(word) => {
+                // Qualified names keep the qualifier, e.g. `users.id`.
+                if self.eat_symbol(".") {
+                    let column = self.expect_identifier()?;
+                    return Ok(Expression::Column(format!("{}.{}", word, column)));
+                }
+                Ok(Expression::Column(word))
+            }
+            Token::Symbol(symbol) => Err(ExpressionError(format!("unexpected {:?}", symbol))),
+        }
+    }
+}
+
+fn number(n: f64) -> Value {
+    if n.fract() == 0.0 && n.abs() < (i64::MAX as f64) {
+        return Value::from(n as i64);
+    }
+    return Value::from(n);
+}
+
+/// Parses a where clause. An empty clause matches every row.
+pub fn parse_filter(input: &str) -> Result<Option<Expression>, ExpressionError> {
+    if input.trim().is_empty() {
+        return Ok(None);
+    }
+
+    let tokens = tokenize(input)?;
+    let mut parser = Parser::new(&tokens);
+    let expr = parser.parse_expression()?;
+    if !parser.is_done() {
+        return Err(parser.unexpected("end of expression"));
+    }
+    return Ok(Some(expr));
+}
+
+/// Orders two values the way comparisons in a where clause do. Values of
+/// different kinds, and NULL, do not compare.
+pub fn compare_values(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+fn truth(value: &Value) -> Option<bool> {
+    match value {
+        Value::Bool(b) => Some(*b),
+        Value::Null => None,
+        _ => Some(true),
+    }
+}
+
+fn from_truth(value: Option<bool>) -> Value {
+    match value {
+        Some(b) => Value::Bool(b),
+        None => Value::Null,
+    }
+}
+
+#[allow(dead_code)]
+impl Expression {
+    /// Evaluates the expression against a row. Missing columns read as NULL,
+    /// and NULL propagates through comparisons as in SQL.
+    pub fn evaluate(&self, row: &Row) -> Value {
+        match self {
+            Expression::Literal(value) => value.clone(),
+            Expression::Column(name) => lookup(row, name),
+            // Only reached when a statement runs without binding it.
+            Expression::Parameter(_) => Value::Null,
+            Expression::Not(expr) => from_truth(truth(&expr.evaluate(row)).map(|b| !b)),
+            Expression::IsNull { expr, negated } => Value::Bool(expr.evaluate(row).is_null() != *negated),
+            Expression::In { expr, list, negated } => {
+                let value = expr.evaluate(row);
+                if value.is_null() {
+                    return Value::Null;
+                }
+                let found = list
+                    .iter()
+                    .any(|item| compare_values(&value, &item.evaluate(row)) == Some(Ordering::Equal));
+                Value::Bool(found != *negated)
+            }
+            Expression::Binary { op: BinaryOperator::And, left, right } => {
+                match (truth(&left.evaluate(row)), truth(&right.evaluate(row))) {
+                    (Some(false), _) | (_, Some(false)) => Value::Bool(false),
+                    (Some(true), Some(true)) => Value::Bool(true),
+                    _ => Value::Null,
+                }
+            }
+            Expression::Binary { op: BinaryOperator::Or, left, right } => {
+                match (truth(&left.evaluate(row)), truth(&right.evaluate(row))) {
+                    (Some(true), _) | (_, Some(true)) => Value::Bool(true),
+                    (Some(false), Some(false)) => Value::Bool(false),
+                    _ => Value::Null,
+                }
+            }
+            Expression::Binary { op, left, right } => {
+                let ordering = match compare_values(&left.evaluate(row), &right.evaluate(row)) {
+                    Some(ordering) => ordering,
+                    None => {
+                        return Value::Null;
+                    }
+                };
+                Value::Bool(match op {
+                    BinaryOperator::Eq => ordering == Ordering::Equal,
+                    BinaryOperator::NotEq => ordering != Ordering::Equal,
+                    BinaryOperator::Lt => ordering == Ordering::Less,
+                    BinaryOperator::LtEq => ordering != Ordering::Greater,
+                    BinaryOperator::Gt => ordering == Ordering::Greater,
+                    BinaryOperator::GtEq => ordering != Ordering::Less,
+                    BinaryOperator::And | BinaryOperator::Or => unreachable!(),
+                })
+            }
+        }
+    }
+
+    /// True only when the expression evaluates to TRUE for the row.
+    pub fn matches(&self, row: &Row) -> bool {
+        return truth(&self.evaluate(row)) == Some(true);
+    }
+
+    /// Column names the expression reads.
+    pub fn columns(&self) -> Vec<String> {
+        let mut columns = Vec::new();
+        self.collect_columns(&mut columns);
+        return columns;
+    }
+
+    /// Replaces every placeholder with its value in `parameters`; one past
+    /// the end binds NULL.
+    pub fn bind(&mut self, parameters: &[Value]) {
+        match self {
+            Expression::Parameter(n) => {
+                *self = Expression::Literal(parameters.get(*n).cloned().unwrap_or(Value::Null));
+            }
+            Expression::Literal(_) | Expression::Column(_) => {}
+            Expression::Not(expr) | Expression::IsNull { expr, .. } => expr.bind(parameters),
+            Expression::In { expr, list, .. } => {
+                expr.bind(parameters);
+                for item in list {
+                    item.bind(parameters);
+                }
+            }
+            Expression::Binary { left, right, .. } => {
+                left.bind(parameters);
+                right.bind(parameters);
+            }
+        }
+    }
+
+    /// Whether the expression has placeholders left to bind.
+    pub fn has_parameters(&self) -> bool {
+        match self {
+            Expression::Parameter(_) => true,
+            Expression::Literal(_) | Expression::Column(_) => false,
+            Expression::Not(expr) | Expression::IsNull { expr, .. } => expr.has_parameters(),
+            Expression::In { expr, list, .. } => expr.has_parameters() || list.iter().any(Expression::has_parameters),
+            Expression::Binary { left, right, .. } => left.has_parameters() || right.has_parameters(),
+        }
+    }
+
+    fn collect_columns(&self, columns: &mut Vec<String>) {
+        match self {
+            Expression::Literal(_) | Expression::Parameter(_) => {}
+            Expression::Column(name) => columns.push(name.clone()),
+            Expression::Not(expr) | Expression::IsNull { expr, .. } => expr.collect_columns(columns),
+            Expression::In { expr, list, .. } => {
+                expr.collect_columns(columns);
+                for item in list {
+                    item.collect_columns(columns);
+                }
+            }
+            Expression::Binary { left, right, .. } => {
+                left.collect_columns(columns);
+                right.collect_columns(columns);
+            }
+        }
+    }
+}
+
+/// Reads `name` from the row; a qualified `table.column` falls back to the
+/// bare column when the row is not qualified, and a bare column to the
+/// qualified one in joined rows, where the planner made sure it is unique.
+pub fn lookup(row: &Row, name: &str) -> Value {
+    if let Some(value) = row.get(name) {
+        return value.clone();
+    }
+    match name.split_once('.') {
+        Some((_, column)) => {
+            if let Some(value) = row.get(column) {
+                return value.clone();
+            }
+        }
+        None => {
+            let found = row
+                .iter()
+                .find(|(key, _)| key.split_once('.').is_some_and(|(_, column)| column == name));
+            if let Some((_, value)) = found {
+                return value.clone();
+            }
+        }
+    }
+    return Value::Null;
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expression::Literal(Value::String(s)) => write!(f, "'{}'", s.replace('\'', "''")),
+            Expression::Literal(value) => write!(f, "{}", value),
+            Expression::Column(name) => write!(f, "{}", name),
+            Expression::Parameter(n) => write!(f, "${}", n + 1),
+            Expression::Not(expr) => write!(f, "NOT ({})", expr),
+            Expression::IsNull { expr, negated } =>
+                write!(f, "{} IS {}NULL", expr, if *negated { "NOT " } else { "" }),
+            Expression::In { expr, list, negated } => {
+                let items: Vec<String> = list.iter().map(|item| item.to_string()).collect();
+                write!(f, "{} {}IN ({})", expr, if *negated { "NOT " } else { "" }, items.join(", "))
+            }
+            Expression::Binary { op, left, right } => {
+                let symbol = match op {
+                    BinaryOperator::Eq => "=",
+                    BinaryOperator::NotEq => "!=",
+                    BinaryOperator::Lt => "<",
+                    BinaryOperator::LtEq => "<=",
+                    BinaryOperator::Gt => ">",
+                    BinaryOperator::GtEq => ">=",
+                    BinaryOperator::And => "AND",
+                    BinaryOperator::Or => "OR",
+                };
+                write!(f, "({} {} {})", left, symbol, right)
+            }
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::{ BTreeMap, HashMap };
+use serde::Serialize;
+use serde_json::Value;
+use crate::statement::OrderBy;
+use crate::transport::Row;
+
+/// How a statement reaches the rows of a table.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccessPath {
+    FullScan,
+    /// Rows whose indexed columns equal `key`, in index column order.
+    IndexScan {
+        index: String,
+        key: Vec<Value>,
+    },
+}
+
+/// Reads an index in `order` of its first column, from the values equal to
+/// `from` on.
+#[derive(Debug, Clone, Copy)]
+pub struct IndexOrder<'a> {
+    pub index: &'a str,
+    pub order: &'a OrderBy,
+    pub from: Option<&'a Value>,
+}
+
+/// Key of one value in an index. Values equal under `compare_values` get
+/// the same key; NULL and values that never compare equal get none.
+pub fn value_key(value: &Value) -> Option<String> {
+    match value {
+        Value::Number(n) => {
+            let n = n.as_f64()?;
+            // -0 and 0 compare equal.
+            Some(format!("n:{}", if n == 0.0 { 0.0 } else { n }))
+        }
+        Value::String(s) => Some(format!("s:{}", s)),
+        Value::Bool(b) => Some(format!("b:{}", b)),
+        _ => None,
+    }
+}
+
+/// Bytes that order like `compare_for_sort` orders values: the rank of the
+/// kind first, then the value.
+pub fn sort_key(value: &Value) -> Vec<u8> {
+    let (rank, payload) = match value {
+        Value::Bool(b) => (0, vec![*b as u8]),
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or_default();
+            let bits = (if n == 0.0 { 0.0f64 } else { n }).to_bits();
+            // Flips negatives entirely and positives' sign bit, so the bytes
+            // order like the numbers.
+            let bits = if bits >> 63 == 1 { !bits } else { bits | (1 << 63) };
+            (1, bits.to_be_bytes().to_vec())
+        }
+        Value::String(s) => (2, s.as_bytes().to_vec()),
+        Value::Array(_) => (3, value.to_string().into_bytes()),
+        Value::Object(_) => (4, value.to_string().into_bytes()),
+        Value::Null => (5, Vec::new()),
+    };
+    let mut key = vec![rank];
+    key.extend(payload);
+    return key;
+}
+
+/// Equality index over one or more columns: row positions by key. It also
+/// keeps the positions in the order of its first column, for sorts.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Index {
+    pub name: String,
+    pub columns: Vec<String>,
+    #[serde(skip)]
+    entries: HashMap<Vec<String>, Vec<usize>>,
+    #[serde(skip)]
+    ordered: BTreeMap<Vec<u8>, Vec<usize>>,
+}
+
+#[allow(dead_code)]
+impl Index {
+    pub fn new(name: &str, columns: Vec<String>, rows: &[Row]) -> Self {
+        let mut index = Self { name: name.to_string(), columns, ..Self::default() };
+        index.rebuild(rows);
+        return index;
+    }
+
+    pub fn covers(&self, column: &str) -> bool {
+        return self.columns.iter().any(|c| c == column);
+    }
+
+    fn key<'a>(&self, values: impl Iterator<Item = Option<&'a Value>>) -> Option<Vec<String>> {
+        return values.map(|value| value.and_then(value_key)).collect();
+    }
+
+    pub fn add(&mut self, position: usize, row: &Row) {
+        if let Some(key) = self.key(self.columns.iter().map(|c| row.get(c))) {
+            self.entries.entry(key).or_default().push(position);
+        }
+        if let Some(first) = self.columns.first() {
+            let value = row.get(first).unwrap_or(&Value::Null);
+            self.ordered.entry(sort_key(value)).or_default().push(position);
+        }
+    }
+
+    /// Positions move when rows are deleted, so writes other than inserts
+    /// rebuild the index.
+    pub fn rebuild(&mut self, rows: &[Row]) {
+        self.entries.clear();
+        self.ordered.clear();
+        for (position, row) in rows.iter().enumerate() {
+            self.add(position, row);
+        }
+    }
+
+    /// Positions of the rows with `key`, in table order.
+    pub fn lookup(&self, key: &[Value]) -> Vec<usize> {
+        return self
+            .key(key.iter().map(Some))
+            .and_then(|key| self.entries.get(&key))
+            .cloned()
+            .unwrap_or_default();
+    }
+
+    /// Positions grouped by the value of the first column, as `sort_key`
+    /// orders them: NULL last.
+    pub fn ordered(&self) -> &BTreeMap<Vec<u8>, Vec<usize>> {
+        return &self.ordered;
+    }
+
+    /// Distinct keys in the index.
+    pub fn cardinality(&self) -> usize {
+        return self.entries.len();
+    }
+}
@@ -0,0 +1,680 @@
+use std::cmp::{ Ordering, Reverse };
+use std::collections::{ BTreeSet, HashMap };
+use std::fmt;
+use std::time::Instant;
+use serde_json::Value;
+use crate::statement::{ JoinKind, OrderBy };
+use crate::transport::Row;
+use super::engine::{ StorageEngine, StorageError };
+use super::expression::{ lookup, BinaryOperator, Expression };
+use super::index::{ value_key, AccessPath };
+use super::planner::{ conjoin, conjuncts, estimate_groups, plan_scan, scan_node, selectivity, PlanNode, ScanPlan };
+use super::sort::{ compare_for_sort, SortedRows, Sorter };
+use super::spill::SpillConfig;
+use super::stats;
+
+/// Index lookups beat building a hash table over the whole table when
+/// there are at most this many times fewer rows to look up than rows in it.
+const INDEX_LOOKUP_FACTOR: u64 = 4;
+
+/// Equalities a join is keyed on: a qualified column of the rows joined so
+/// far and a column of the table joined.
+pub type JoinKeys = Vec<(String, String)>;
+
+/// How one table is joined to the rows joined so far.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JoinStrategy {
+    /// Builds a hash table over the table's rows and probes it with each
+    /// row joined so far.
+    Hash,
+    /// Looks up each row's matches in an index of the table; `key` holds
+    /// the columns of the rows joined so far giving the index key, in index
+    /// column order.
+    IndexNestedLoop {
+        index: String,
+        key: Vec<String>,
+    },
+    /// Sorts both sides on the join keys within the spill budget and
+    /// merges them.
+    Merge,
+    /// Tries every pair; for conditions without an equality to key on.
+    NestedLoop,
+}
+
+impl JoinStrategy {
+    pub fn operator(&self) -> &'static str {
+        match self {
+            JoinStrategy::Hash => "HashJoin",
+            JoinStrategy::IndexNestedLoop { .. } => "IndexNestedLoopJoin",
+            JoinStrategy::Merge => "MergeJoin",
+            JoinStrategy::NestedLoop => "NestedLoopJoin",
+        }
+    }
+}
+
+/// One table of a join after the first, in join order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JoinStep {
+    pub kind: JoinKind,
+    pub scan: ScanPlan,
+    /// Everything pushed into the scan, for index lookups that reach the
+    /// table another way than `scan.access`.
+    pub pushed: Option<Expression>,
+    /// Declared columns of the table, NULL in the rows a left join keeps
+    /// unmatched.
+    pub columns: Vec<String>,
+    pub keys: JoinKeys,
+    /// The rest of the condition, evaluated on each joined row.
+    pub residual: Option<Expression>,
+    pub strategy: JoinStrategy,
+    pub estimated_rows: u64,
+}
+
+impl fmt::Display for JoinStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            JoinKind::Inner => "inner",
+            JoinKind::Left => "left",
+        };
+        let mut conditions: Vec<String> = self.keys
+            .iter()
+            .map(|(left, right)| format!("{} = {}.{}", left, self.scan.table, right))
+            .collect();
+        conditions.extend(self.residual.iter().map(|residual| residual.to_string()));
+        match conditions.is_empty() {
+            true => write!(f, "{} join {}", kind, self.scan.table),
+            false => write!(f, "{} join {} on {}", kind, self.scan.table, conditions.join(" AND ")),
+        }
+    }
+}
+
+/// How a select joins its tables. The first table in join order is read
+/// by the select's own scan; its rows and those joined to them carry
+/// `table.column` names.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JoinPlan {
+    pub steps: Vec<JoinStep>,
+    /// Conditions of `where` evaluated on the joined rows.
+    pub filter: Option<Expression>,
+    pub estimated_rows: u64,
+    pub estimated_row_size: usize,
+}
+
+#[allow(dead_code)]
+impl JoinPlan {
+    /// Binds the placeholders of every step and of the filter.
+    pub fn bind(&mut self, parameters: &[Value]) {
+        for step in self.steps.iter_mut() {
+            step.scan.bind(parameters);
+            for expr in step.pushed.iter_mut().chain(step.residual.iter_mut()) {
+                expr.bind(parameters);
+            }
+        }
+        if let Some(filter) = &mut self.filter {
+            filter.bind(parameters);
+        }
+    }
+
+    pub fn has_parameters(&self) -> bool {
+        let step_has_parameters = |step: &JoinStep| {
+            step.scan.has_parameters() || step.pushed.iter().chain(step.residual.iter()).any(Expression::has_parameters)
+        };
+        return self.steps.iter().any(step_has_parameters) || self.filter.as_ref().is_some_and(Expression::has_parameters);
+    }
+}
+
+/// Tables of a join with their declared columns, to resolve the columns a
+/// select names. Tables created without columns accept any.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JoinSchema {
+    pub tables: Vec<(String, Vec<String>)>,
+}
+
+#[allow(dead_code)]
+impl JoinSchema {
+    pub fn load(engine: &StorageEngine, database: &str, tables: &[String]) -> Result<Self, StorageError> {
+        let mut loaded = Vec::new();
+        for table in tables {
+            let columns = engine
+                .columns(database, table)?
+                .into_iter()
+                .map(|column| column.name)
+                .collect();
+            loaded.push((table.clone(), columns));
+        }
+        return Ok(Self { tables: loaded });
+    }
+
+    fn unknown(&self, name: &str) -> StorageError {
+        let tables: Vec<&str> = self.tables
+            .iter()
+            .map(|(table, _)| table.as_str())
+            .collect();
+        return StorageError::UnknownColumn { table: tables.join(", "), column: name.to_string() };
+    }
+
+    /// Position of the table `name` belongs to, and its bare column name.
+    pub fn resolve(&self, name: &str) -> Result<(usize, String), StorageError> {
+        let accepts = |columns: &[String], column: &str| columns.is_empty() || columns.iter().any(|c| c == column);
+        if let Some((table, column)) = name.split_once('.') {
+            return match self.tables.iter().position(|(t, _)| t == table) {
+                Some(position) if accepts(&self.tables[position].1, column) => Ok((position, column.to_string())),
+                _ => Err(self.unknown(name)),
+            };
+        }
+        // A declared column wins over tables that accept any.
+        let declared: Vec<usize> = (0..self.tables.len())
+            .filter(|&t| self.tables[t].1.iter().any(|c| c == name))
+            .collect();
+        let found = if declared.is_empty() {
+            (0..self.tables.len()).filter(|&t| self.tables[t].1.is_empty()).collect()
+        } else {
+            declared
+        };
+        return match found.as_slice() {
+            [position] => Ok((*position, name.to_string())),
+            [] => Err(self.unknown(name)),
+            _ => Err(StorageError::AmbiguousColumn(name.to_string())),
+        };
+    }
+
+    fn tables_of(&self, expr: &Expression) -> Result<BTreeSet<usize>, StorageError> {
+        return expr
+            .columns()
+            .iter()
+            .map(|column| self.resolve(column).map(|(table, _)| table))
+            .collect();
+    }
+
+    /// `a = b` between a column of the tables joined so far and one of
+    /// `table`, as the first's qualified name and the second's bare one.
+    fn join_key(
+        &self,
+        expr: &Expression,
+        joined: &BTreeSet<usize>,
+        table: usize
+    ) -> Result<Option<(String, String)>, StorageError> {
+        let (left, right) = match expr {
+            Expression::Binary { op: BinaryOperator::Eq, left, right } =>
+                match (left.as_ref(), right.as_ref()) {
+                    (Expression::Column(left), Expression::Column(right)) => (self.resolve(left)?, self.resolve(right)?),
+                    _ => {
+                        return Ok(None);
+                    }
+                }
+            _ => {
+                return Ok(None);
+            }
+        };
+        for (outer, inner) in [(&left, &right), (&right, &left)] {
+            if joined.contains(&outer.0) && inner.0 == table {
+                let qualified = format!("{}.{}", self.tables[outer.0].0, outer.1);
+                return Ok(Some((qualified, inner.1.clone())));
+            }
+        }
+        return Ok(None);
+    }
+
+    /// Splits the conditions joining `table` into keys and a residual.
+    fn split(
+        &self,
+        conditions: Vec<Expression>,
+        joined: &BTreeSet<usize>,
+        table: usize
+    ) -> Result<(JoinKeys, Option<Expression>), StorageError> {
+        let mut keys = Vec::new();
+        let mut rest = Vec::new();
+        for condition in conditions {
+            match self.join_key(&condition, joined, table)? {
+                Some(key) => keys.push(key),
+                None => rest.push(condition),
+            }
+        }
+        return Ok((keys, conjoin(rest)));
+    }
+}
+
+/// Rows out of joining `left` rows to those `scan` reads, keyed on `keys`.
+fn estimate_step(
+    engine: &StorageEngine,
+    database: &str,
+    scan: &ScanPlan,
+    left: u64,
+    keys: &[(String, String)],
+    residual: Option<&Expression>,
+    kind: JoinKind
+) -> Result<u64, StorageError> {
+    let right = scan.estimated_rows;
+    let mut rows = (left as f64) * (right as f64);
+    if !keys.is_empty() {
+        let columns: Vec<String> = keys
+            .iter()
+            .map(|(_, column)| column.clone())
+            .collect();
+        rows /= estimate_groups(engine, database, &scan.table, &columns, right)? as f64;
+    }
+    let rows = (rows * residual.map_or(1.0, selectivity)).ceil() as u64;
+    return Ok(if kind == JoinKind::Left { rows.max(left) } else { rows });
+}
+
+/// An index lookup per row joined so far when there are few of them and an
+/// index is fully keyed; else a hash table over the table if it fits in the
+/// budget, and a merge of both sides sorted on the keys if not.
+fn choose_strategy(
+    engine: &StorageEngine,
+    database: &str,
+    scan: &ScanPlan,
+    left: u64,
+    keys: &[(String, String)],
+    budget: usize
+) -> Result<JoinStrategy, StorageError> {
+    if keys.is_empty() {
+        return Ok(JoinStrategy::NestedLoop);
+    }
+    let rows = engine.row_count(database, &scan.table)? as u64;
+    if left.saturating_mul(INDEX_LOOKUP_FACTOR) <= rows {
+        for index in engine.indexes(database, &scan.table)? {
+            let key: Option<Vec<String>> = index.columns
+                .iter()
+                .map(|column| keys.iter().find(|(_, right)| right == column).map(|(left, _)| left.clone()))
+                .collect();
+            if let Some(key) = key {
+                return Ok(JoinStrategy::IndexNestedLoop { index: index.name, key });
+            }
+        }
+    }
+    let bytes = scan.estimated_rows.saturating_mul(engine.sample_row_size(database, &scan.table)? as u64);
+    if bytes > (budget as u64) {
+        return Ok(JoinStrategy::Merge);
+    }
+    return Ok(JoinStrategy::Hash);
+}
+
+/// Plans the join of `schema`'s tables, the first of which is joined to
+/// the next ones by `joins`, with `filter` from `where`.
+///
+/// Conditions on a single table are pushed into its scan, except `where`
+/// conditions on a table a left join may fill with NULLs. Inner joins are
+/// reordered greedily: the table with the fewest estimated rows first, then
+/// each time the connected table giving the fewest rows. Left joins keep
+/// the order written.
+pub fn plan_join(
+    engine: &StorageEngine,
+    database: &str,
+    schema: &JoinSchema,
+    joins: Vec<(JoinKind, Option<Expression>)>,
+    filter: Option<Expression>,
+    budget: usize
+) -> Result<(ScanPlan, JoinPlan), StorageError> {
+    let count = schema.tables.len();
+    let reorder = joins.iter().all(|(kind, _)| *kind == JoinKind::Inner);
+    let nullable: Vec<bool> = (0..count).map(|t| t > 0 && joins[t - 1].0 == JoinKind::Left).collect();
+
+    let mut pushed: Vec<Vec<Expression>> = vec![Vec::new(); count];
+    // Conditions across tables: pooled when reordering, else by the table
+    // whose join they belong to.
+    let mut pool: Vec<(Expression, BTreeSet<usize>)> = Vec::new();
+    let mut on: Vec<Vec<Expression>> = vec![Vec::new(); count];
+    let mut remaining = Vec::new();
+
+    for part in filter.map(conjuncts).unwrap_or_default() {
+        let tables = schema.tables_of(&part)?;
+        match tables.iter().next() {
+            Some(&table) if tables.len() == 1 && !nullable[table] => pushed[table].push(part),
+            _ if reorder && tables.len() > 1 => pool.push((part, tables)),
+            _ => remaining.push(part),
+        }
+    }
+    for (position, (_, condition)) in joins.into_iter().enumerate() {
+        let table = position + 1;
+        for part in condition.map(conjuncts).unwrap_or_default() {
+            let tables = schema.tables_of(&part)?;
+            if let Some(&later) = tables.iter().find(|&&t| t > table).filter(|_| !reorder) {
+                let column = part
+                    .columns()
+                    .into_iter()
+                    .find(|column| schema.resolve(column).is_ok_and(|(t, _)| t == later))
+                    .unwrap_or_default();
+                return Err(StorageError::UnknownColumn { table: schema.tables[table].0.clone(), column });
+            }
+            match tables.iter().next() {
+                Some(&only) if tables.len() == 1 && (reorder || only == table) => pushed[only].push(part),
+                _ if reorder && tables.len() > 1 => pool.push((part, tables)),
+                _ if reorder => remaining.push(part),
+                _ => on[table].push(part),
+            }
+        }
+    }
+
+    let mut scans = Vec::new();
+    let mut predicates = Vec::new();
+    for (t, parts) in pushed.into_iter().enumerate() {
+        let predicate = conjoin(parts);
+        scans.push(plan_scan(engine, database, &schema.tables[t].0, predicate.clone())?);
+        predicates.push(predicate);
+    }
+
+    let applicable = |pool: &[(Expression, BTreeSet<usize>)], joined: &BTreeSet<usize>, table: usize| -> Vec<Expression> {
+        pool.iter()
+            .filter(|(_, tables)| tables.contains(&table) && tables.iter().all(|t| *t == table || joined.contains(t)))
+            .map(|(part, _)| part.clone())
+            .collect()
+    };
+    let first = if reorder { (0..count).min_by_key(|&t| scans[t].estimated_rows).unwrap_or(0) } else { 0 };
+    let mut joined = BTreeSet::from([first]);
+    let mut rows = scans[first].estimated_rows;
+    let mut steps = Vec::new();
+    while joined.len() < count {
+        let table = if reorder {
+            let mut best: Option<((bool, Reverse<u64>), usize)> = None;
+            for t in (0..count).filter(|t| !joined.contains(t)) {
+                let conditions = applicable(&pool, &joined, t);
+                let connected = !conditions.is_empty();
+                let (keys, residual) = schema.split(conditions, &joined, t)?;
+                let estimate = estimate_step(engine, database, &scans[t], rows, &keys, residual.as_ref(), JoinKind::Inner)?;
+                let score = (connected, Reverse(estimate));
+                if best.as_ref().is_none_or(|(best, _)| score > *best) {
+                    best = Some((score, t));
+                }
+            }
+            best.map(|(_, t)| t).unwrap_or_default()
+        } else {
+            joined.len()
+        };
+        let (kind, conditions) = if reorder {
+            let conditions = applicable(&pool, &joined, table);
+            pool.retain(|(part, _)| !conditions.contains(part));
+            (JoinKind::Inner, conditions)
+        } else {
+            let kind = if nullable[table] { JoinKind::Left } else { JoinKind::Inner };
+            (kind, std::mem::take(&mut on[table]))
+        };
+        let (keys, residual) = schema.split(conditions, &joined, table)?;
+        let scan = scans[table].clone();
+        let estimated_rows = estimate_step(engine, database, &scan, rows, &keys, residual.as_ref(), kind)?;
+        let strategy = choose_strategy(engine, database, &scan, rows, &keys, budget)?;
+        steps.push(JoinStep {
+            kind,
+            scan,
+            pushed: predicates[table].clone(),
+            columns: schema.tables[table].1.clone(),
+            keys,
+            residual,
+            strategy,
+            estimated_rows,
+        });
+        joined.insert(table);
+        rows = estimated_rows;
+    }
+
+    remaining.extend(pool.into_iter().map(|(part, _)| part));
+    let filter = conjoin(remaining);
+    let mut estimated_row_size = 0;
+    for (table, _) in &schema.tables {
+        estimated_row_size += engine.sample_row_size(database, table)?;
+    }
+    let plan = JoinPlan {
+        steps,
+        estimated_rows: ((rows as f64) * filter.as_ref().map_or(1.0, selectivity)).ceil() as u64,
+        filter,
+        estimated_row_size,
+    };
+    return Ok((scans.swap_remove(first), plan));
+}
+
+/// The operators of a join, its last step first, each with the joins
+/// before it and then the scan of its table as children.
+pub fn describe_join(first: &ScanPlan, plan: &JoinPlan, nodes: &mut Vec<PlanNode>, parent: usize) {
+    let mut parent = parent;
+    for (position, step) in plan.steps.iter().enumerate().rev() {
+        let id = nodes.len();
+        let mut text = step.to_string();
+        if let (true, Some(filter)) = (position + 1 == plan.steps.len(), &plan.filter) {
+            text.push_str(&format!(", then filter {}", filter));
+        }
+        let (index, index_key) = match &step.strategy {
+            JoinStrategy::IndexNestedLoop { index, key } => (Some(index.clone()), Some(key.join(", "))),
+            _ => (None, None),
+        };
+        nodes.push(PlanNode {
+            id,
+            parent: Some(parent),
+            operator: step.strategy.operator().to_string(),
+            table: Some(step.scan.table.clone()),
+            index,
+            index_key,
+            join: Some(text),
+            estimated_rows: step.estimated_rows,
+            ..PlanNode::default()
+        });
+        let scan = scan_node(&step.scan, nodes.len(), id);
+        nodes.push(scan);
+        parent = id;
+    }
+    let scan = scan_node(first, nodes.len(), parent);
+    nodes.push(scan);
+}
+
+fn qualify(table: &str, row: &Row) -> Row {
+    return row
+        .iter()
+        .map(|(column, value)| (format!("{}.{}", table, column), value.clone()))
+        .collect();
+}
+
+/// Index keys of the values of `columns`; `None` if one never matches.
+fn join_key(row: &Row, columns: &[&str]) -> Option<Vec<String>> {
+    return columns
+        .iter()
+        .map(|column| value_key(&lookup(row, column)))
+        .collect();
+}
+
+fn compare_keys(left: &[Value], right: &[Value]) -> Ordering {
+    for (left, right) in left.iter().zip(right) {
+        let ordering = compare_for_sort(left, right);
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    return Ordering::Equal;
+}
+
+/// Adds the joins of `left` with the `candidates` the residual accepts; a
+/// left join keeps an unmatched row with the table's columns NULL.
+fn emit<'r>(out: &mut Vec<Row>, step: &JoinStep, left: Row, candidates: impl Iterator<Item = &'r Row>) {
+    let mut matched = false;
+    for right in candidates {
+        let mut row = left.clone();
+        row.extend(qualify(&step.scan.table, right));
+        if step.residual.as_ref().is_none_or(|residual| residual.matches(&row)) {
+            matched = true;
+            out.push(row);
+        }
+    }
+    if !matched && step.kind == JoinKind::Left {
+        let mut row = left;
+        row.extend(step.columns.iter().map(|column| (format!("{}.{}", step.scan.table, column), Value::Null)));
+        out.push(row);
+    }
+}
+
+/// One side of a merge join with the row it is at.
+struct MergeSide {
+    rows: SortedRows,
+    head: Option<Row>,
+}
+
+impl MergeSide {
+    fn start(rows: SortedRows) -> Result<Self, StorageError> {
+        let mut side = Self { rows, head: None };
+        side.advance()?;
+        return Ok(side);
+    }
+
+    fn advance(&mut self) -> Result<(), StorageError> {
+        self.head = self.rows.next().transpose()?;
+        return Ok(());
+    }
+}
+
+fn hash_join(
+    engine: &StorageEngine,
+    database: &str,
+    step: &JoinStep,
+    rows: Vec<Row>
+) -> Result<Vec<Row>, StorageError> {
+    let lefts: Vec<&str> = step.keys.iter().map(|(left, _)| left.as_str()).collect();
+    let rights: Vec<&str> = step.keys.iter().map(|(_, right)| right.as_str()).collect();
+    let mut table: HashMap<Vec<String>, Vec<Row>> = HashMap::new();
+    engine.visit(database, &step.scan.table, &step.scan.access, step.scan.filter(), |row| {
+        if let Some(key) = join_key(row, &rights) {
+            table.entry(key).or_default().push(row.clone());
+        }
+        Ok(())
+    })?;
+    let mut out = Vec::new();
+    for left in rows {
+        let matches = join_key(&left, &lefts).and_then(|key| table.get(&key));
+        emit(&mut out, step, left, matches.into_iter().flatten());
+    }
+    return Ok(out);
+}
+
+fn index_join(
+    engine: &StorageEngine,
+    database: &str,
+    step: &JoinStep,
+    index: &str,
+    key: &[String],
+    rows: Vec<Row>
+) -> Result<Vec<Row>, StorageError> {
+    let lefts: Vec<&str> = step.keys.iter().map(|(left, _)| left.as_str()).collect();
+    let rights: Vec<&str> = step.keys.iter().map(|(_, right)| right.as_str()).collect();
+    let filter = |row: &Row| step.pushed.as_ref().is_none_or(|pushed| pushed.matches(row));
+    let mut out = Vec::new();
+    for left in rows {
+        let values: Vec<Value> = key
+            .iter()
+            .map(|column| lookup(&left, column))
+            .collect();
+        let wanted = join_key(&left, &lefts);
+        let matches = match wanted {
+            Some(_) => {
+                let access = AccessPath::IndexScan { index: index.to_string(), key: values };
+                engine.scan(database, &step.scan.table, &access, filter)?
+            }
+            None => Vec::new(),
+        };
+        // The index may cover only some of the keys.
+        let matches = matches.iter().filter(|right| join_key(right, &rights) == wanted);
+        emit(&mut out, step, left, matches);
+    }
+    return Ok(out);
+}
+
+fn merge_join(
+    engine: &StorageEngine,
+    database: &str,
+    step: &JoinStep,
+    rows: Vec<Row>,
+    spill: &SpillConfig
+) -> Result<Vec<Row>, StorageError> {
+    let lefts: Vec<&str> = step.keys.iter().map(|(left, _)| left.as_str()).collect();
+    let rights: Vec<&str> = step.keys.iter().map(|(_, right)| right.as_str()).collect();
+    let left_order: Vec<OrderBy> = lefts.iter().map(|column| OrderBy::asc(column)).collect();
+    let right_order: Vec<OrderBy> = rights.iter().map(|column| OrderBy::asc(column)).collect();
+
+    let mut sorter = Sorter::new(&left_order, None, spill);
+    for row in rows {
+        sorter.push(row)?;
+    }
+    let (left_rows, _) = sorter.finish()?;
+    let mut sorter = Sorter::new(&right_order, None, spill);
+    engine.visit(database, &step.scan.table, &step.scan.access, step.scan.filter(), |row| sorter.push(row.clone()))?;
+    let (right_rows, _) = sorter.finish()?;
+
+    let values = |row: &Row, columns: &[&str]| -> Vec<Value> {
+        columns
+            .iter()
+            .map(|column| lookup(row, column))
+            .collect()
+    };
+    let mut right = MergeSide::start(right_rows)?;
+    let mut group: Vec<Row> = Vec::new();
+    let mut group_key: Option<Vec<Value>> = None;
+    let mut out = Vec::new();
+    for left in left_rows {
+        let left = left?;
+        if join_key(&left, &lefts).is_none() {
+            emit(&mut out, step, left, std::iter::empty());
+            continue;
+        }
+        let key = values(&left, &lefts);
+        if group_key.as_ref().is_none_or(|group_key| compare_keys(group_key, &key) != Ordering::Equal) {
+            group.clear();
+            while let Some(head) = &right.head {
+                if join_key(head, &rights).is_none() {
+                    right.advance()?;
+                    continue;
+                }
+                match compare_keys(&values(head, &rights), &key) {
+                    Ordering::Less => right.advance()?,
+                    Ordering::Equal => {
+                        group.push(right.head.take().unwrap());
+                        right.advance()?;
+                    }
+                    Ordering::Greater => {
+                        break;
+                    }
+                }
+            }
+            group_key = Some(key);
+        }
+        emit(&mut out, step, left, group.iter());
+    }
+    return Ok(out);
+}
+
+fn nested_loop_join(
+    engine: &StorageEngine,
+    database: &str,
+    step: &JoinStep,
+    rows: Vec<Row>
+) -> Result<Vec<Row>, StorageError> {
+    let rights = engine.scan(database, &step.scan.table, &step.scan.access, step.scan.filter())?;
+    let mut out = Vec::new();
+    for left in rows {
+        emit(&mut out, step, left, rights.iter());
+    }
+    return Ok(out);
+}
+
+/// The joined rows, `first` being the scan of the first table in join order.
+pub fn run_join(
+    engine: &StorageEngine,
+    database: &str,
+    first: &ScanPlan,
+    plan: &JoinPlan,
+    spill: &SpillConfig
+) -> Result<Vec<Row>, StorageError> {
+    let mut rows = Vec::new();
+    engine.visit(database, &first.table, &first.access, first.filter(), |row| {
+        rows.push(qualify(&first.table, row));
+        Ok(())
+    })?;
+    for step in &plan.steps {
+        let started = Instant::now();
+        rows = match &step.strategy {
+            JoinStrategy::Hash => hash_join(engine, database, step, rows)?,
+            JoinStrategy::IndexNestedLoop { index, key } => index_join(engine, database, step, index, key, rows)?,
+            JoinStrategy::Merge => merge_join(engine, database, step, rows, spill)?,
+            JoinStrategy::NestedLoop => nested_loop_join(engine, database, step, rows)?,
+        };
+        stats::record_join(rows.len(), started.elapsed());
+    }
+    if let Some(filter) = &plan.filter {
+        rows.retain(|row| filter.matches(row));
+    }
+    return Ok(rows);
+}
@@ -1,2 +1,46 @@
 pub mod engine;
+pub use engine::{ CompactionStats, IndexInfo, SnapshotStats, StorageEngine, StorageError };
+
+pub mod index;
+pub use index::AccessPath;
+
 pub mod kv_storage;
+
+pub mod changes;
+pub use changes::{ ChangeEvent, ChangeFeed, ChangeOperation };
+
+pub mod expression;
+pub use expression::Expression;
+
+pub mod catalog;
+pub use catalog::{ Catalog, Privilege };
+
+pub mod stats;
+pub use stats::StatementStats;
+
+pub mod slow_query;
+pub use slow_query::{ SlowQuery, SlowQueryLog };
+
+pub mod spill;
+pub use spill::SpillConfig;
+
+pub mod sort;
+pub use sort::{ Continuation, SortStrategy };
+
+pub mod aggregate;
+pub use aggregate::{ AggregateState, AggregateStrategy, Aggregator };
+
+pub mod join;
+pub use join::{ JoinPlan, JoinSchema, JoinStep, JoinStrategy };
+
+pub mod planner;
+pub use planner::{ AggregatePlan, Plan, PlanNode, ScanPlan, SortPlan };
+
+pub mod prepared;
+pub use prepared::PreparedPlan;
+
+pub mod transaction;
+pub use transaction::Transaction;
+
+pub mod executor;
+pub use executor::{ ExecutionContext, ExecutionError, ExecutionResult, Executor };
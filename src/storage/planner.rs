@@ -0,0 +1,509 @@
+use std::collections::HashMap;
+use serde::Serialize;
+use serde_json::Value;
+use crate::statement::{ Aggregate, OrderBy };
+use crate::transport::Row;
+use super::engine::{ StorageEngine, StorageError };
+use super::expression::{ BinaryOperator, Expression };
+use super::index::AccessPath;
+use super::join::{ describe_join, JoinPlan };
+use super::sort::{ Continuation, SortStrategy };
+use super::spill::row_size;
+
+/// Share of rows assumed to pass a predicate, by kind, when nothing better
+/// is known.
+const EQUALITY_SELECTIVITY: f64 = 0.1;
+const RANGE_SELECTIVITY: f64 = 0.33;
+const DEFAULT_SELECTIVITY: f64 = 0.5;
+
+/// How one table is read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanPlan {
+    pub table: String,
+    pub access: AccessPath,
+    /// Pushed down into the scan: evaluated on each row the access path
+    /// reaches, before anything is copied out of storage.
+    pub predicate: Option<Expression>,
+    pub estimated_rows: u64,
+    /// Positions of the index key of `access` that placeholders fill in, with
+    /// their parameter; NULL until bound.
+    pub key_parameters: Vec<(usize, usize)>,
+}
+
+#[allow(dead_code)]
+impl ScanPlan {
+    pub fn filter(&self) -> impl Fn(&Row) -> bool + '_ {
+        return move |row: &Row| self.predicate.as_ref().is_none_or(|predicate| predicate.matches(row));
+    }
+
+    pub fn bind(&mut self, parameters: &[Value]) {
+        if let AccessPath::IndexScan { key, .. } = &mut self.access {
+            for (position, parameter) in self.key_parameters.drain(..) {
+                key[position] = parameters.get(parameter).cloned().unwrap_or(Value::Null);
+            }
+        }
+        if let Some(predicate) = &mut self.predicate {
+            predicate.bind(parameters);
+        }
+    }
+
+    pub fn has_parameters(&self) -> bool {
+        return !self.key_parameters.is_empty() || self.predicate.as_ref().is_some_and(Expression::has_parameters);
+    }
+}
+
+/// How a select orders its rows, and where a keyset page starts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortPlan {
+    pub order_by: Vec<OrderBy>,
+    pub after: Option<Continuation>,
+    /// Identifies the query in the continuation tokens it issues.
+    pub query_id: String,
+    /// Expected from the estimated rows; the sort picks its own as it runs.
+    pub strategy: SortStrategy,
+    /// Index on the first sort key a limited select reads the table through,
+    /// in order, stopping once the page is full.
+    pub index: Option<String>,
+}
+
+/// Grouping of a select.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggregatePlan {
+    pub group_by: Vec<String>,
+    pub aggregates: Vec<Aggregate>,
+    /// Evaluated on each group's result row.
+    pub having: Option<Expression>,
+    pub estimated_groups: u64,
+}
+
+#[allow(dead_code)]
+impl AggregatePlan {
+    /// Columns of the result rows.
+    pub fn output_columns(&self) -> Vec<String> {
+        let mut columns = self.group_by.clone();
+        columns.extend(self.aggregates.iter().map(Aggregate::output_name));
+        return columns;
+    }
+
+    /// Size of a result row, for memory budgets.
+    pub fn estimated_row_size(&self) -> usize {
+        let row: Row = self
+            .output_columns()
+            .into_iter()
+            .map(|column| (column, Value::Null))
+            .collect();
+        return row_size(&row);
+    }
+
+    /// Groups left once `having` is applied.
+    pub fn estimated_rows(&self) -> u64 {
+        return estimate(self.estimated_groups as usize, self.having.as_ref());
+    }
+}
+
+/// What the executor will do for a data statement.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Plan {
+    Select {
+        /// With joins, the scan of the first table in join order.
+        scan: ScanPlan,
+        columns: Vec<String>,
+        join: Option<Box<JoinPlan>>,
+        aggregate: Option<Box<AggregatePlan>>,
+        sort: Option<SortPlan>,
+        offset: usize,
+        limit: Option<usize>,
+    },
+    Update {
+        scan: ScanPlan,
+        updates: HashMap<String, Value>,
+    },
+    Delete {
+        scan: ScanPlan,
+    },
+    Insert {
+        table: String,
+        rows: Vec<Row>,
+    },
+    Upsert {
+        scan: ScanPlan,
+        row: Row,
+        unique_key: String,
+    },
+}
+
+/// One operator of a plan as returned by `Explain`; the root has no
+/// parent. Actual values are only set by `EXPLAIN ANALYZE` and include the
+/// operator's children.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PlanNode {
+    pub id: usize,
+    pub parent: Option<usize>,
+    pub operator: String,
+    pub table: Option<String>,
+    pub index: Option<String>,
+    pub index_key: Option<String>,
+    pub pushed_predicate: Option<String>,
+    pub sort: Option<String>,
+    pub aggregate: Option<String>,
+    pub join: Option<String>,
+    pub estimated_rows: u64,
+    pub actual_rows: Option<u64>,
+    pub actual_ms: Option<f64>,
+}
+
+/// Splits a predicate into the parts joined by AND.
+pub fn conjuncts(expr: Expression) -> Vec<Expression> {
+    match expr {
+        Expression::Binary { op: BinaryOperator::And, left, right } => {
+            let mut parts = conjuncts(*left);
+            parts.extend(conjuncts(*right));
+            parts
+        }
+        expr => vec![expr],
+    }
+}
+
+pub fn conjoin(parts: Vec<Expression>) -> Option<Expression> {
+    return parts.into_iter().reduce(|left, right| Expression::Binary {
+        op: BinaryOperator::And,
+        left: Box::new(left),
+        right: Box::new(right),
+    });
+}
+
+/// `column = literal`, either way round, with a literal that is not NULL
+/// or a placeholder.
+fn equality(expr: &Expression) -> Option<(&str, &Expression)> {
+    let (left, right) = match expr {
+        Expression::Binary { op: BinaryOperator::Eq, left, right } => (left.as_ref(), right.as_ref()),
+        _ => {
+            return None;
+        }
+    };
+    let (column, value) = match (left, right) {
+        (Expression::Column(column), value @ (Expression::Literal(_) | Expression::Parameter(_))) |
+        (value @ (Expression::Literal(_) | Expression::Parameter(_)), Expression::Column(column)) => (column, value),
+        _ => {
+            return None;
+        }
+    };
+    if value == &Expression::Literal(Value::Null) {
+        return None;
+    }
+    // A qualified `table.column` names the same column here.
+    let column = column.rsplit_once('.').map_or(column.as_str(), |(_, column)| column);
+    return Some((column, value));
+}
+
+/// Estimated share of rows for which `expr` holds.
+pub fn selectivity(expr: &Expression) -> f64 {
+    match expr {
+        Expression::Literal(value) => if value == &Value::Bool(true) { 1.0 } else { 0.0 },
+        Expression::Column(_) | Expression::Parameter(_) => DEFAULT_SELECTIVITY,
+        Expression::Not(expr) => 1.0 - selectivity(expr),
+        Expression::IsNull { negated, .. } =>
+            if *negated { 1.0 - EQUALITY_SELECTIVITY } else { EQUALITY_SELECTIVITY },
+        Expression::In { list, negated, .. } => {
+            let share = (EQUALITY_SELECTIVITY * (list.len() as f64)).min(1.0);
+            if *negated { 1.0 - share } else { share }
+        }
+        Expression::Binary { op, left, right } =>
+            match op {
+                BinaryOperator::And => selectivity(left) * selectivity(right),
+                BinaryOperator::Or => {
+                    let (left, right) = (selectivity(left), selectivity(right));
+                    left + right - left * right
+                }
+                BinaryOperator::Eq => EQUALITY_SELECTIVITY,
+                BinaryOperator::NotEq => 1.0 - EQUALITY_SELECTIVITY,
+                _ => RANGE_SELECTIVITY,
+            }
+    }
+}
+
+fn estimate(rows: usize, predicate: Option<&Expression>) -> u64 {
+    let share = predicate.map_or(1.0, selectivity);
+    return ((rows as f64) * share).ceil() as u64;
+}
+
+/// An index `plan_scan` can read, with the parts of the predicate its key
+/// answers.
+struct IndexChoice {
+    index: String,
+    key: Vec<Value>,
+    key_parameters: Vec<(usize, usize)>,
+    used: Vec<usize>,
+    matches: usize,
+}
+
+/// Picks how to read `table` for `predicate`: the index whose columns are
+/// all fixed by equalities in the predicate and that returns the fewest
+/// rows, or a full scan. Equalities an index lookup answers are dropped
+/// from the pushed predicate. A key with placeholders is expected to
+/// return an average share of the index.
+pub fn plan_scan(
+    engine: &StorageEngine,
+    database: &str,
+    table: &str,
+    predicate: Option<Expression>
+) -> Result<ScanPlan, StorageError> {
+    let rows = engine.row_count(database, table)?;
+    let parts = predicate.map(conjuncts).unwrap_or_default();
+
+    let mut best: Option<IndexChoice> = None;
+    for index in engine.indexes(database, table)? {
+        let mut key = Vec::new();
+        let mut key_parameters = Vec::new();
+        let mut used = Vec::new();
+        for column in &index.columns {
+            let found = parts
+                .iter()
+                .enumerate()
+                .find_map(|(i, part)| equality(part).filter(|(c, _)| c == column).map(|(_, value)| (i, value)));
+            match found {
+                Some((i, Expression::Parameter(parameter))) => {
+                    key_parameters.push((key.len(), *parameter));
+                    key.push(Value::Null);
+                    used.push(i);
+                }
+                Some((i, value)) => {
+                    key.push(value.evaluate(&Row::new()));
+                    used.push(i);
+                }
+                None => {
+                    break;
+                }
+            }
+        }
+        if key.len() < index.columns.len() {
+            continue;
+        }
+        let matches = match key_parameters.is_empty() {
+            true => engine.index_matches(database, table, &index.name, &key)?,
+            false => rows.div_ceil(index.cardinality.max(1)),
+        };
+        if best.as_ref().is_none_or(|best| matches < best.matches) {
+            best = Some(IndexChoice { index: index.name, key, key_parameters, used, matches });
+        }
+    }
+
+    let plan = match best {
+        Some(IndexChoice { index, key, key_parameters, used, matches }) => {
+            let residual = conjoin(
+                parts
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(i, _)| !used.contains(i))
+                    .map(|(_, part)| part)
+                    .collect()
+            );
+            ScanPlan {
+                table: table.to_string(),
+                access: AccessPath::IndexScan { index, key },
+                estimated_rows: estimate(matches, residual.as_ref()),
+                predicate: residual,
+                key_parameters,
+            }
+        }
+        None => {
+            let predicate = conjoin(parts);
+            ScanPlan {
+                table: table.to_string(),
+                access: AccessPath::FullScan,
+                estimated_rows: estimate(rows, predicate.as_ref()),
+                predicate,
+                key_parameters: Vec::new(),
+            }
+        }
+    };
+    return Ok(plan);
+}
+
+/// Distinct `group_by` keys among `rows` rows: one without `group_by`, the
+/// cardinality of an index on exactly those columns, else a share of the
+/// rows.
+pub fn estimate_groups(
+    engine: &StorageEngine,
+    database: &str,
+    table: &str,
+    group_by: &[String],
+    rows: u64
+) -> Result<u64, StorageError> {
+    if group_by.is_empty() {
+        return Ok(1);
+    }
+    let indexed = engine
+        .indexes(database, table)?
+        .into_iter()
+        .find(|index| index.columns.len() == group_by.len() && group_by.iter().all(|c| index.columns.contains(c)));
+    let groups = match indexed {
+        Some(index) => index.cardinality as u64,
+        None => ((rows as f64) * EQUALITY_SELECTIVITY).ceil() as u64,
+    };
+    return Ok(groups.clamp(1, rows.max(1)));
+}
+
+/// How a sort of `rows` rows of about `row_size` bytes each is expected to
+/// run within `budget` bytes, keeping the `keep` first when set.
+pub fn plan_sort(rows: u64, row_size: usize, keep: Option<usize>, budget: usize) -> SortStrategy {
+    let held = keep.map_or(rows, |keep| rows.min(keep as u64));
+    let bytes = held.saturating_mul(row_size as u64);
+    if bytes > (budget as u64) {
+        let total = rows.saturating_mul(row_size as u64);
+        return SortStrategy::External { runs: total.div_ceil(budget.max(1) as u64) as usize };
+    }
+    return match keep {
+        Some(keep) => SortStrategy::TopN(keep),
+        None => SortStrategy::InMemory,
+    };
+}
+
+fn sort_text(sort: Option<&SortPlan>, offset: usize, limit: Option<usize>) -> String {
+    let mut text = match sort {
+        None => "none".to_string(),
+        Some(sort) => {
+            let keys: Vec<String> = sort.order_by
+                .iter()
+                .map(|key| key.to_string())
+                .collect();
+            format!("{} by {}", sort.strategy, keys.join(", "))
+        }
+    };
+    if let Some(index) = sort.and_then(|sort| sort.index.as_ref()) {
+        text.push_str(&format!(", reading index {} in order", index));
+    }
+    if sort.is_some_and(|sort| sort.after.is_some()) {
+        text.push_str(", after continuation");
+    }
+    if offset > 0 {
+        text.push_str(&format!(", offset {}", offset));
+    }
+    if let Some(limit) = limit {
+        text.push_str(&format!(", limit {}", limit));
+    }
+    return text;
+}
+
+pub fn scan_node(scan: &ScanPlan, id: usize, parent: usize) -> PlanNode {
+    let (operator, index, index_key) = match &scan.access {
+        AccessPath::FullScan => ("FullScan", None, None),
+        AccessPath::IndexScan { index, key } => {
+            let mut key: Vec<String> = key
+                .iter()
+                .map(|value| value.to_string())
+                .collect();
+            for (position, parameter) in &scan.key_parameters {
+                key[*position] = Expression::Parameter(*parameter).to_string();
+            }
+            ("IndexScan", Some(index.clone()), Some(key.join(", ")))
+        }
+    };
+    return PlanNode {
+        id,
+        parent: Some(parent),
+        operator: operator.to_string(),
+        table: Some(scan.table.clone()),
+        index,
+        index_key,
+        pushed_predicate: scan.predicate.as_ref().map(|predicate| predicate.to_string()),
+        estimated_rows: scan.estimated_rows,
+        ..PlanNode::default()
+    };
+}
+
+#[allow(dead_code)]
+impl Plan {
+    pub fn scan(&self) -> Option<&ScanPlan> {
+        match self {
+            Plan::Select { scan, .. } | Plan::Update { scan, .. } | Plan::Delete { scan } | Plan::Upsert { scan, .. } =>
+                Some(scan),
+            Plan::Insert { .. } => None,
+        }
+    }
+
+    /// Binds the placeholders of a prepared plan to `parameters`.
+    pub fn bind(&mut self, parameters: &[Value]) {
+        match self {
+            Plan::Select { scan, join, aggregate, .. } => {
+                scan.bind(parameters);
+                if let Some(join) = join {
+                    join.bind(parameters);
+                }
+                if let Some(having) = aggregate.as_mut().and_then(|aggregate| aggregate.having.as_mut()) {
+                    having.bind(parameters);
+                }
+            }
+            Plan::Update { scan, .. } | Plan::Delete { scan } | Plan::Upsert { scan, .. } => scan.bind(parameters),
+            Plan::Insert { .. } => {}
+        }
+    }
+
+    /// Whether the plan has placeholders left to bind.
+    pub fn has_parameters(&self) -> bool {
+        match self {
+            Plan::Select { scan, join, aggregate, .. } => {
+                let having = aggregate.as_ref().and_then(|aggregate| aggregate.having.as_ref());
+                scan.has_parameters() ||
+                    join.as_ref().is_some_and(|join| join.has_parameters()) ||
+                    having.is_some_and(Expression::has_parameters)
+            }
+            Plan::Update { scan, .. } | Plan::Delete { scan } | Plan::Upsert { scan, .. } => scan.has_parameters(),
+            Plan::Insert { .. } => false,
+        }
+    }
+
+    /// The operators of the plan, root first.
+    pub fn describe(&self) -> Vec<PlanNode> {
+        let (operator, table, estimated_rows, sort) = match self {
+            Plan::Select { scan, join, aggregate, sort, offset, limit, .. } => {
+                let rows = join.as_ref().map_or(scan.estimated_rows, |join| join.estimated_rows);
+                let rows = aggregate.as_ref().map_or(rows, |aggregate| aggregate.estimated_rows());
+                let rows = rows.saturating_sub(*offset as u64);
+                let rows = limit.map_or(rows, |limit| rows.min(limit as u64));
+                ("Select", &scan.table, rows, Some(sort_text(sort.as_ref(), *offset, *limit)))
+            }
+            Plan::Update { scan, .. } => ("Update", &scan.table, scan.estimated_rows, None),
+            Plan::Delete { scan } => ("Delete", &scan.table, scan.estimated_rows, None),
+            Plan::Insert { table, rows } => ("Insert", table, rows.len() as u64, None),
+            Plan::Upsert { scan, .. } => ("Upsert", &scan.table, scan.estimated_rows.max(1), None),
+        };
+        let mut nodes = vec![PlanNode {
+            id: 0,
+            operator: operator.to_string(),
+            table: Some(table.clone()),
+            sort,
+            estimated_rows,
+            ..PlanNode::default()
+        }];
+        if let Plan::Select { aggregate: Some(aggregate), .. } = self {
+            let aggregates: Vec<String> = aggregate.aggregates
+                .iter()
+                .map(|aggregate| aggregate.to_string())
+                .collect();
+            let mut text = aggregates.join(", ");
+            if !aggregate.group_by.is_empty() {
+                text.push_str(&format!(" group by {}", aggregate.group_by.join(", ")));
+            }
+            if let Some(having) = &aggregate.having {
+                text.push_str(&format!(" having {}", having));
+            }
+            nodes.push(PlanNode {
+                id: 1,
+                parent: Some(0),
+                operator: "HashAggregate".to_string(),
+                table: Some(table.clone()),
+                aggregate: Some(text.trim().to_string()),
+                estimated_rows: aggregate.estimated_rows(),
+                ..PlanNode::default()
+            });
+        }
+        let parent = nodes.len() - 1;
+        match (self, self.scan()) {
+            (Plan::Select { scan, join: Some(join), .. }, _) => describe_join(scan, join, &mut nodes, parent),
+            (_, Some(scan)) => nodes.push(scan_node(scan, nodes.len(), parent)),
+            (_, None) => {}
+        }
+        return nodes;
+    }
+}
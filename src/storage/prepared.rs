@@ -0,0 +1,43 @@
+use serde_json::Value;
+use crate::protocol::MessageType;
+use crate::sql::ValueSlot;
+use super::planner::Plan;
+
+/// A statement compiled and planned by Prepare, run by Execute with values
+/// bound to its placeholders.
+#[derive(Debug, Clone)]
+pub struct PreparedPlan {
+    pub sql: String,
+    /// Database it was prepared in, where it keeps running.
+    pub database: String,
+    pub message_type: MessageType,
+    /// Values an Execute must give.
+    pub parameters: usize,
+    /// Values written by the statement that placeholders give.
+    pub slots: Vec<ValueSlot>,
+    pub plan: Plan,
+    /// `StorageEngine::schema_version()` the plan was made against.
+    pub schema_version: u64,
+}
+
+#[allow(dead_code)]
+impl PreparedPlan {
+    /// The plan with `parameters` in place of its placeholders.
+    pub fn bind(&self, parameters: &[Value]) -> Plan {
+        let mut plan = self.plan.clone();
+        for slot in &self.slots {
+            let value = parameters.get(slot.parameter).cloned().unwrap_or(Value::Null);
+            let row = match &mut plan {
+                Plan::Insert { rows, .. } => rows.get_mut(slot.row),
+                Plan::Update { updates, .. } => Some(updates),
+                Plan::Upsert { row, .. } => Some(row),
+                Plan::Select { .. } | Plan::Delete { .. } => None,
+            };
+            if let Some(row) = row {
+                row.insert(slot.column.clone(), value);
+            }
+        }
+        plan.bind(parameters);
+        return plan;
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::VecDeque;
+use std::sync::{ Mutex, RwLock };
+use std::time::Duration;
+use log::warn;
+use serde::{ Deserialize, Serialize };
+use crate::utils::config::SlowQueryConfig;
+
+/// One statement that ran longer than the threshold.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SlowQuery {
+    pub time: String,
+    pub connection_id: usize,
+    pub principal: String,
+    pub database: String,
+    /// `Statement::to_redacted_string()` of the statement.
+    pub statement: String,
+    pub duration_ms: f64,
+    pub rows_scanned: u64,
+    /// Rows sent back, or rows changed by a write.
+    pub rows_returned: u64,
+    pub index_used: Option<String>,
+    pub lock_wait_ms: f64,
+}
+
+/// Logs slow statements and keeps the most recent ones in memory.
+#[derive(Debug)]
+pub struct SlowQueryLog {
+    config: RwLock<SlowQueryConfig>,
+    entries: Mutex<VecDeque<SlowQuery>>,
+}
+
+impl Default for SlowQueryLog {
+    fn default() -> Self {
+        return Self::new(SlowQueryConfig::default());
+    }
+}
+
+#[allow(dead_code)]
+impl SlowQueryLog {
+    pub fn new(config: SlowQueryConfig) -> Self {
+        return Self {
+            config: RwLock::new(config),
+            entries: Mutex::new(VecDeque::new()),
+        };
+    }
+
+    /// Applies a reloaded threshold and capacity; entries beyond the new
+    /// capacity are dropped, oldest first.
+    pub fn configure(&self, config: &SlowQueryConfig) {
+        *self.config.write().unwrap() = config.clone();
+        let mut entries = self.entries.lock().unwrap();
+        while entries.len() > config.capacity {
+            entries.pop_front();
+        }
+    }
+
+    pub fn is_slow(&self, elapsed: Duration) -> bool {
+        let config = self.config.read().unwrap();
+        return config.enabled && elapsed >= Duration::from_millis(config.threshold_ms);
+    }
+
+    pub fn record(&self, entry: SlowQuery) {
+        warn!(
+            connection_id = entry.connection_id,
+            duration_ms = entry.duration_ms,
+            rows_scanned = entry.rows_scanned,
+            rows_returned = entry.rows_returned,
+            index_used = entry.index_used.as_deref().unwrap_or("none"),
+            lock_wait_ms = entry.lock_wait_ms;
+            "Slow statement by {} on {}: {}",
+            entry.principal,
+            entry.database,
+            entry.statement
+        );
+        let capacity = self.config.read().unwrap().capacity;
+        let mut entries = self.entries.lock().unwrap();
+        entries.push_back(entry);
+        while entries.len() > capacity {
+            entries.pop_front();
+        }
+    }
+
+    /// Newest first; every entry when `limit` is 0.
+    pub fn recent(&self, limit: usize) -> Vec<SlowQuery> {
+        let entries = self.entries.lock().unwrap();
+        let limit = if limit == 0 { entries.len() } else { limit };
+        return entries.iter().rev().take(limit).cloned().collect();
+    }
+}
+
@@ -0,0 +1,407 @@
+use std::cmp::Ordering;
+use std::fmt;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{ Hmac, Mac };
+use serde::{ Deserialize, Serialize };
+use serde_json::{ json, Value };
+use sha2::{ Digest, Sha256 };
+use crate::statement::{ OrderBy, SelectStatement };
+use crate::transport::Row;
+use crate::utils::KeyRing;
+use super::engine::StorageError;
+use super::expression::lookup;
+use super::spill::{ row_size, SpillConfig, SpillReader, SpillWriter };
+
+/// Length of the MAC at the end of a continuation token.
+const TOKEN_MAC_LEN: usize = 32;
+/// Separates the key continuation tokens are signed with from the cluster
+/// token it is derived from.
+const TOKEN_KEY_CONTEXT: &[u8] = b"zenith continuation token";
+
+/// Place of each kind of value in a sort.
+fn kind_rank(value: &Value) -> u8 {
+    match value {
+        Value::Bool(_) => 0,
+        Value::Number(_) => 1,
+        Value::String(_) => 2,
+        Value::Array(_) => 3,
+        Value::Object(_) => 4,
+        Value::Null => 5,
+    }
+}
+
+/// Total order over values for sorting. Unlike `compare_values` every pair
+/// compares: values of different kinds are ordered by kind.
+pub fn compare_for_sort(left: &Value, right: &Value) -> Ordering {
+    match (left, right) {
+        (Value::Number(a), Value::Number(b)) => {
+            let (a, b) = (a.as_f64().unwrap_or_default(), b.as_f64().unwrap_or_default());
+            a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+        }
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (Value::Array(_), Value::Array(_)) | (Value::Object(_), Value::Object(_)) =>
+            left.to_string().cmp(&right.to_string()),
+        _ => kind_rank(left).cmp(&kind_rank(right)),
+    }
+}
+
+pub fn compare_key(order: &OrderBy, left: &Value, right: &Value) -> Ordering {
+    let nulls = if order.places_nulls_first() { Ordering::Less } else { Ordering::Greater };
+    match (left.is_null(), right.is_null()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => nulls,
+        (false, true) => nulls.reverse(),
+        (false, false) if order.descending => compare_for_sort(left, right).reverse(),
+        (false, false) => compare_for_sort(left, right),
+    }
+}
+
+/// Orders rows tied on every sort key. Identical rows get the same digest,
+/// so the order is total; it is all a continuation keeps of such a row.
+pub fn row_digest(row: &Row) -> String {
+    let mut columns: Vec<(&String, &Value)> = row.iter().collect();
+    columns.sort_by(|a, b| a.0.cmp(b.0));
+    let mut hasher = Sha256::new();
+    for (column, value) in columns {
+        let value = value.to_string();
+        hasher.update((column.len() as u64).to_be_bytes());
+        hasher.update(column.as_bytes());
+        hasher.update((value.len() as u64).to_be_bytes());
+        hasher.update(value.as_bytes());
+    }
+    return hex::encode(&hasher.finalize()[..16]);
+}
+
+/// Compares the sort keys of `row` with `keys`, the values of another row.
+fn compare_keys(order: &[OrderBy], row: &Row, keys: &[Value]) -> Ordering {
+    for (key, value) in order.iter().zip(keys) {
+        let ordering = compare_key(key, &lookup(row, &key.column), value);
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    return Ordering::Equal;
+}
+
+/// Compares the sort keys of two rows; rows tied on all of them are equal.
+fn compare_sort_keys(order: &[OrderBy], left: &Row, right: &Row) -> Ordering {
+    for key in order {
+        let ordering = compare_key(key, &lookup(left, &key.column), &lookup(right, &key.column));
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    return Ordering::Equal;
+}
+
+pub fn compare_rows(order: &[OrderBy], left: &Row, right: &Row) -> Ordering {
+    return compare_sort_keys(order, left, right).then_with(|| row_digest(left).cmp(&row_digest(right)));
+}
+
+/// How a sort ran, or is expected to run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SortStrategy {
+    InMemory,
+    /// Only the first `n` rows are kept while sorting.
+    TopN(usize),
+    /// Sorted runs were written to disk and merged.
+    External {
+        runs: usize,
+    },
+}
+
+impl fmt::Display for SortStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SortStrategy::InMemory => write!(f, "in-memory"),
+            SortStrategy::TopN(n) => write!(f, "top-n ({})", n),
+            SortStrategy::External { runs } => write!(f, "external merge ({} runs)", runs),
+        }
+    }
+}
+
+/// Sorts rows within a memory budget. When the budget is exceeded the rows
+/// held are cut down to the `keep` first ones if that frees enough, and
+/// otherwise written to disk as a sorted run; runs are merged at the end.
+pub struct Sorter<'a> {
+    order: &'a [OrderBy],
+    keep: Option<usize>,
+    config: &'a SpillConfig,
+    buffer: Vec<Row>,
+    buffered_bytes: usize,
+    runs: Vec<SpillReader>,
+}
+
+#[allow(dead_code)]
+impl<'a> Sorter<'a> {
+    pub fn new(order: &'a [OrderBy], keep: Option<usize>, config: &'a SpillConfig) -> Self {
+        return Self { order, keep, config, buffer: Vec::new(), buffered_bytes: 0, runs: Vec::new() };
+    }
+
+    pub fn push(&mut self, row: Row) -> Result<(), StorageError> {
+        self.buffered_bytes += row_size(&row);
+        self.buffer.push(row);
+        if self.buffered_bytes <= self.config.memory_budget {
+            return Ok(());
+        }
+        // Pruning only pays off when it drops a good share of the buffer.
+        if let Some(keep) = self.keep.filter(|keep| self.buffer.len() >= 2 * keep.max(&1)) {
+            self.sort_buffer(keep);
+            if self.buffered_bytes <= self.config.memory_budget {
+                return Ok(());
+            }
+        }
+        return self.spill();
+    }
+
+    fn sort_buffer(&mut self, keep: usize) {
+        let order = self.order;
+        // Each row's digest is worked out once rather than on every tie.
+        let mut digested: Vec<(String, Row)> = self.buffer
+            .drain(..)
+            .map(|row| (row_digest(&row), row))
+            .collect();
+        digested.sort_by(|a, b| compare_sort_keys(order, &a.1, &b.1).then_with(|| a.0.cmp(&b.0)));
+        self.buffer = digested
+            .into_iter()
+            .map(|(_, row)| row)
+            .collect();
+        if self.buffer.len() > keep {
+            self.buffer.truncate(keep);
+            self.buffered_bytes = self.buffer.iter().map(row_size).sum();
+        }
+    }
+
+    fn spill(&mut self) -> Result<(), StorageError> {
+        self.sort_buffer(self.keep.unwrap_or(usize::MAX));
+        let mut writer = SpillWriter::create(&self.config.directory)?;
+        for row in self.buffer.drain(..) {
+            writer.write(&row)?;
+        }
+        self.buffered_bytes = 0;
+        self.runs.push(writer.finish()?);
+        return Ok(());
+    }
+
+    /// The sorted rows, the `keep` first ones when set.
+    pub fn finish(mut self) -> Result<(SortedRows, SortStrategy), StorageError> {
+        if self.runs.is_empty() {
+            self.sort_buffer(self.keep.unwrap_or(usize::MAX));
+            let strategy = match self.keep {
+                Some(keep) => SortStrategy::TopN(keep),
+                None => SortStrategy::InMemory,
+            };
+            return Ok((SortedRows::Memory(self.buffer.into_iter()), strategy));
+        }
+
+        if !self.buffer.is_empty() {
+            self.spill()?;
+        }
+        let strategy = SortStrategy::External { runs: self.runs.len() };
+        let merge = Merge {
+            order: self.order.to_vec(),
+            heads: self.runs
+                .iter()
+                .map(|_| None)
+                .collect(),
+            runs: self.runs,
+            started: false,
+            remaining: self.keep.unwrap_or(usize::MAX),
+        };
+        return Ok((SortedRows::Merged(merge), strategy));
+    }
+}
+
+/// K-way merge of sorted runs.
+pub struct Merge {
+    order: Vec<OrderBy>,
+    heads: Vec<Option<Row>>,
+    runs: Vec<SpillReader>,
+    started: bool,
+    remaining: usize,
+}
+
+impl Merge {
+    fn fill(&mut self, run: usize) -> Result<(), StorageError> {
+        self.heads[run] = self.runs[run].next().transpose()?;
+        return Ok(());
+    }
+
+    fn next_row(&mut self) -> Result<Option<Row>, StorageError> {
+        if !self.started {
+            self.started = true;
+            for run in 0..self.runs.len() {
+                self.fill(run)?;
+            }
+        }
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        let mut smallest: Option<usize> = None;
+        for (run, head) in self.heads.iter().enumerate() {
+            let head = match head {
+                Some(head) => head,
+                None => {
+                    continue;
+                }
+            };
+            let is_smaller = match smallest {
+                None => true,
+                Some(current) =>
+                    compare_rows(&self.order, head, self.heads[current].as_ref().unwrap()) == Ordering::Less,
+            };
+            if is_smaller {
+                smallest = Some(run);
+            }
+        }
+        let run = match smallest {
+            Some(run) => run,
+            None => {
+                return Ok(None);
+            }
+        };
+        let row = self.heads[run].take();
+        self.fill(run)?;
+        self.remaining -= 1;
+        return Ok(row);
+    }
+}
+
+pub enum SortedRows {
+    Memory(std::vec::IntoIter<Row>),
+    Merged(Merge),
+}
+
+impl Iterator for SortedRows {
+    type Item = Result<Row, StorageError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            SortedRows::Memory(rows) => rows.next().map(Ok),
+            SortedRows::Merged(merge) => merge.next_row().transpose(),
+        }
+    }
+}
+
+/// Where the next page of a keyset paginated select starts: after the row
+/// with sort keys `keys` and digest `digest`, skipping the `ties` rows
+/// identical to it that were already returned. Tokens carry a MAC under a
+/// key of the cluster key ring, and the id of that key, so clients cannot
+/// forge a boundary and any node holding the key accepts the token.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Continuation {
+    /// Identifies the tables, filter and order the token was issued for.
+    query: String,
+    keys: Vec<Value>,
+    digest: String,
+    ties: usize,
+}
+
+/// MAC keyed by a key derived from the cluster token `token`.
+fn token_mac(token: &str) -> Hmac<Sha256> {
+    let mut derive = Hmac::<Sha256>::new_from_slice(token.as_bytes()).expect("HMAC can take key of any size");
+    derive.update(TOKEN_KEY_CONTEXT);
+    let key = derive.finalize().into_bytes();
+    return Hmac::<Sha256>::new_from_slice(&key).expect("HMAC can take key of any size");
+}
+
+/// Id of the key a token was signed with, next to the continuation itself.
+#[derive(Deserialize)]
+struct SignedBy {
+    key_id: String,
+}
+
+#[allow(dead_code)]
+impl Continuation {
+    pub fn query_id(stmt: &SelectStatement) -> String {
+        let query = json!([
+            stmt.table_name,
+            stmt.joins,
+            stmt.r#where,
+            stmt.order_by,
+            stmt.aggregates,
+            stmt.group_by,
+            stmt.having,
+        ]);
+        let digest = Sha256::digest(query.to_string().as_bytes());
+        return hex::encode(&digest[..8]);
+    }
+
+    pub fn decode(token: &str, query_id: &str, keys: &KeyRing) -> Result<Self, String> {
+        let malformed = || "malformed continuation token".to_string();
+        let bytes = URL_SAFE_NO_PAD.decode(token).map_err(|_| malformed())?;
+        if bytes.len() < TOKEN_MAC_LEN {
+            return Err(malformed());
+        }
+        let (payload, tag) = bytes.split_at(bytes.len() - TOKEN_MAC_LEN);
+        let signed_by: SignedBy = serde_json::from_slice(payload).map_err(|_| malformed())?;
+        let key = keys
+            .get(&signed_by.key_id)
+            .ok_or_else(|| format!("continuation token was signed with unknown key {}", signed_by.key_id))?;
+        let mut mac = token_mac(&key);
+        mac.update(payload);
+        mac.verify_slice(tag).map_err(|_| malformed())?;
+        let continuation: Continuation = serde_json::from_slice(payload).map_err(|_| malformed())?;
+        if continuation.query != query_id {
+            return Err("continuation token was issued for another query".to_string());
+        }
+        return Ok(continuation);
+    }
+
+    /// Signs the token with the active key of `keys`.
+    pub fn encode(&self, keys: &KeyRing) -> String {
+        let (key_id, key) = keys.active();
+        let mut payload = json!(self);
+        payload["key_id"] = json!(key_id);
+        let mut bytes = serde_json::to_vec(&payload).unwrap();
+        let mut mac = token_mac(&key);
+        mac.update(&bytes);
+        bytes.extend_from_slice(&mac.finalize().into_bytes());
+        return URL_SAFE_NO_PAD.encode(bytes);
+    }
+
+    /// Value of the first sort key at the boundary.
+    pub fn first_key(&self) -> Option<&Value> {
+        return self.keys.first();
+    }
+
+    fn compare(&self, order: &[OrderBy], row: &Row) -> Ordering {
+        return compare_keys(order, row, &self.keys).then_with(|| row_digest(row).cmp(&self.digest));
+    }
+
+    /// Whether a row comes after the boundary. Called on rows in any
+    /// order; it lets through all but `ties` of the rows identical to the
+    /// last one returned.
+    pub fn admits(&self, order: &[OrderBy], row: &Row, ties_seen: &mut usize) -> bool {
+        match self.compare(order, row) {
+            Ordering::Less => false,
+            Ordering::Greater => true,
+            Ordering::Equal if *ties_seen < self.ties => {
+                *ties_seen += 1;
+                false
+            }
+            Ordering::Equal => true,
+        }
+    }
+
+    /// The boundary after a page ending with `last`, preceded in sort order
+    /// by `trailing - 1` identical rows since the previous boundary.
+    pub fn after(
+        query_id: String,
+        order: &[OrderBy],
+        last: &Row,
+        trailing: usize,
+        previous: Option<&Continuation>
+    ) -> Self {
+        let carried = previous
+            .filter(|previous| previous.compare(order, last) == Ordering::Equal)
+            .map_or(0, |previous| previous.ties);
+        let keys = order
+            .iter()
+            .map(|key| lookup(last, &key.column))
+            .collect();
+        return Self { query: query_id, keys, digest: row_digest(last), ties: carried + trailing };
+    }
+}
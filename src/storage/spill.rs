@@ -0,0 +1,133 @@
+use std::fs::{ self, File };
+use std::io::{ BufReader, BufWriter, Write };
+use std::marker::PhantomData;
+use std::path::{ Path, PathBuf };
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use uuid::Uuid;
+use crate::transport::Row;
+use crate::utils::config::{ QueryConfig, StorageConfig };
+use super::engine::StorageError;
+
+const BYTES_PER_MB: u64 = 1024 * 1024;
+const SPILL_DIRECTORY: &str = "spill";
+
+/// How much memory an operator may hold before it writes rows to disk, and
+/// where they go.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpillConfig {
+    pub memory_budget: usize,
+    pub directory: PathBuf,
+}
+
+impl Default for SpillConfig {
+    fn default() -> Self {
+        return Self {
+            memory_budget: (QueryConfig::default().work_memory_mb * BYTES_PER_MB) as usize,
+            directory: std::env::temp_dir().join("zenith-spill"),
+        };
+    }
+}
+
+#[allow(dead_code)]
+impl SpillConfig {
+    pub fn from_config(query: &QueryConfig, storage: &StorageConfig) -> Self {
+        let directory = match &query.spill_directory {
+            Some(directory) if !directory.is_empty() => PathBuf::from(directory),
+            _ => Path::new(&storage.path).join(SPILL_DIRECTORY),
+        };
+        return Self { memory_budget: (query.work_memory_mb * BYTES_PER_MB) as usize, directory };
+    }
+}
+
+/// Rough in-memory size of a value, for budgeting.
+pub fn value_size(value: &Value) -> usize {
+    match value {
+        Value::String(s) => 24 + s.len(),
+        Value::Array(items) => 24 + items.iter().map(value_size).sum::<usize>(),
+        Value::Object(fields) =>
+            32 + fields
+                .iter()
+                .map(|(key, value)| 24 + key.len() + value_size(value))
+                .sum::<usize>(),
+        _ => 16,
+    }
+}
+
+/// Rough in-memory size of one column of a row, for budgeting.
+pub fn column_size(column: &str, value: &Value) -> usize {
+    return 24 + column.len() + value_size(value);
+}
+
+/// Rough in-memory size of a row, for budgeting.
+pub fn row_size(row: &Row) -> usize {
+    return 48 + row
+        .iter()
+        .map(|(column, value)| column_size(column, value))
+        .sum::<usize>();
+}
+
+/// A temporary file that is removed when dropped.
+#[derive(Debug)]
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Rows, or other records, written to a temporary file and read back in the
+/// same order.
+pub struct SpillWriter {
+    file: TempFile,
+    writer: BufWriter<File>,
+    rows: usize,
+}
+
+#[allow(dead_code)]
+impl SpillWriter {
+    pub fn create(directory: &Path) -> Result<Self, StorageError> {
+        fs::create_dir_all(directory)?;
+        let path = directory.join(format!("{}.run", Uuid::new_v4().simple()));
+        let writer = BufWriter::new(File::create(&path)?);
+        return Ok(Self { file: TempFile(path), writer, rows: 0 });
+    }
+
+    pub fn write<T: Serialize>(&mut self, record: &T) -> Result<(), StorageError> {
+        rmp_serde::encode::write_named(&mut self.writer, record).map_err(|e| StorageError::Spill(e.to_string()))?;
+        self.rows += 1;
+        return Ok(());
+    }
+
+    pub fn rows(&self) -> usize {
+        return self.rows;
+    }
+
+    /// Reads the records back; `T` must be the type they were written as.
+    pub fn finish<T: DeserializeOwned>(mut self) -> Result<SpillReader<T>, StorageError> {
+        self.writer.flush()?;
+        let reader = BufReader::new(File::open(&self.file.0)?);
+        return Ok(SpillReader { _file: self.file, reader, remaining: self.rows, records: PhantomData });
+    }
+}
+
+pub struct SpillReader<T = Row> {
+    _file: TempFile,
+    reader: BufReader<File>,
+    remaining: usize,
+    records: PhantomData<T>,
+}
+
+impl<T: DeserializeOwned> Iterator for SpillReader<T> {
+    type Item = Result<T, StorageError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        return Some(rmp_serde::decode::from_read(&mut self.reader).map_err(|e| StorageError::Spill(e.to_string())));
+    }
+}
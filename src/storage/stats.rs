@@ -0,0 +1,133 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use serde::Serialize;
+
+/// What storage did for one statement. Statements run synchronously on one
+/// thread, so the engine records into a thread-local collector set up by
+/// `collect`; outside of it recording does nothing.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct StatementStats {
+    /// Rows read by scans, before their filter.
+    pub rows_scanned: u64,
+    /// Rows that passed the filter of a scan.
+    pub rows_matched: u64,
+    /// Time spent in scans, lock waits excluded.
+    pub scan_time: Duration,
+    /// Index that served the lookup; `None` for a full table scan.
+    pub index_used: Option<String>,
+    /// Time spent waiting for the storage lock.
+    pub lock_wait: Duration,
+    /// How rows were sorted, when they were.
+    pub sort: Option<String>,
+    /// How rows were grouped, when they were, into how many groups and
+    /// how long it took, scans included.
+    pub aggregate: Option<String>,
+    pub groups: u64,
+    pub aggregate_time: Duration,
+    /// Rows matched and time spent by the scans and lookups of each table.
+    pub tables: BTreeMap<String, (u64, Duration)>,
+    /// Rows out of each join and the time it took, in join order.
+    pub joins: Vec<(u64, Duration)>,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<StatementStats>> = const { RefCell::new(None) };
+}
+
+impl StatementStats {
+    fn add(&mut self, other: &StatementStats) {
+        self.rows_scanned += other.rows_scanned;
+        self.rows_matched += other.rows_matched;
+        self.scan_time += other.scan_time;
+        if other.index_used.is_some() {
+            self.index_used = other.index_used.clone();
+        }
+        self.lock_wait += other.lock_wait;
+        if other.sort.is_some() {
+            self.sort = other.sort.clone();
+        }
+        if other.aggregate.is_some() {
+            self.aggregate = other.aggregate.clone();
+        }
+        self.groups += other.groups;
+        self.aggregate_time += other.aggregate_time;
+        for (table, (rows, took)) in &other.tables {
+            let entry = self.tables.entry(table.clone()).or_default();
+            entry.0 += rows;
+            entry.1 += *took;
+        }
+        self.joins.extend(other.joins.iter().copied());
+    }
+}
+
+/// Runs `f` and returns what storage recorded while it ran. A collector
+/// already in place also gets the records.
+pub fn collect<T>(f: impl FnOnce() -> T) -> (T, StatementStats) {
+    let outer = CURRENT.with(|current| current.replace(Some(StatementStats::default())));
+    let result = f();
+    let stats = CURRENT.with(|current| current.replace(outer)).unwrap_or_default();
+    record(|outer| outer.add(&stats));
+    return (result, stats);
+}
+
+fn record(f: impl FnOnce(&mut StatementStats)) {
+    CURRENT.with(|current| {
+        if let Some(stats) = current.borrow_mut().as_mut() {
+            f(stats);
+        }
+    });
+}
+
+pub fn record_scan(rows: usize) {
+    record(|stats| {
+        stats.rows_scanned += rows as u64;
+    });
+}
+
+pub fn record_matched(rows: usize, took: Duration) {
+    record(|stats| {
+        stats.rows_matched += rows as u64;
+        stats.scan_time += took;
+    });
+}
+
+pub fn record_index(name: &str) {
+    record(|stats| {
+        stats.index_used = Some(name.to_string());
+    });
+}
+
+pub fn record_lock_wait(waited: Duration) {
+    record(|stats| {
+        stats.lock_wait += waited;
+    });
+}
+
+pub fn record_sort(strategy: &str) {
+    record(|stats| {
+        stats.sort = Some(strategy.to_string());
+    });
+}
+
+pub fn record_aggregate(strategy: &str, groups: usize, took: Duration) {
+    record(|stats| {
+        stats.aggregate = Some(strategy.to_string());
+        stats.groups += groups as u64;
+        stats.aggregate_time += took;
+    });
+}
+
+pub fn record_table(table: &str, rows: usize, took: Duration) {
+    record(|stats| {
+        let entry = stats.tables.entry(table.to_string()).or_default();
+        entry.0 += rows as u64;
+        entry.1 += took;
+    });
+}
+
+pub fn record_join(rows: usize, took: Duration) {
+    record(|stats| {
+        stats.joins.push((rows as u64, took));
+    });
+}
@@ -0,0 +1,157 @@
+use std::cell::RefCell;
+use std::collections::{ BTreeMap, BTreeSet };
+use std::sync::Arc;
+use super::engine::{ Changes, Table };
+
+/// Marks a table written by an open transaction. Other sessions read the
+/// table as it was before, and cannot write it until the owner ends.
+#[derive(Debug, Clone)]
+pub struct TableLock {
+    pub owner: u64,
+    pub committed: Arc<Table>,
+}
+
+/// Writes made since a savepoint, or since BEGIN for the first level.
+#[derive(Debug, Default)]
+struct Level {
+    savepoint: Option<String>,
+    /// Tables as they were before this level first wrote them. The first
+    /// level keeps none: the lock of the table has them.
+    before: BTreeMap<(String, String), Table>,
+    /// Row changes held back from the change feed until commit.
+    changes: Vec<(String, String, Changes)>,
+}
+
+/// An open transaction. Statements run inside it with `within`, which has
+/// the storage engine lock the tables they write.
+#[derive(Debug, Default)]
+pub struct Transaction {
+    id: u64,
+    name: String,
+    tables: BTreeSet<(String, String)>,
+    levels: Vec<Level>,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Transaction>> = const { RefCell::new(None) };
+}
+
+/// Puts the transaction back when `within` returns or unwinds.
+struct Restore<'a>(&'a mut Transaction);
+
+impl Drop for Restore<'_> {
+    fn drop(&mut self) {
+        if let Some(transaction) = CURRENT.with(|current| current.borrow_mut().take()) {
+            *self.0 = transaction;
+        }
+    }
+}
+
+/// Runs `f` inside `transaction`. Statements run synchronously on one
+/// thread, so the engine finds the transaction in a thread-local.
+pub fn within<T>(transaction: &mut Transaction, f: impl FnOnce() -> T) -> T {
+    let open = std::mem::take(transaction);
+    CURRENT.with(|current| *current.borrow_mut() = Some(open));
+    let _restore = Restore(transaction);
+    return f();
+}
+
+/// Id of the transaction running on this thread, if any.
+pub fn current_id() -> Option<u64> {
+    return CURRENT.with(|current| current.borrow().as_ref().map(|transaction| transaction.id));
+}
+
+/// Calls `f` with the transaction running on this thread; `None` outside
+/// of `within`.
+pub fn with_current<T>(f: impl FnOnce(&mut Transaction) -> T) -> Option<T> {
+    return CURRENT.with(|current| current.borrow_mut().as_mut().map(f));
+}
+
+#[allow(dead_code)]
+impl Transaction {
+    pub fn new(id: u64, name: &str) -> Self {
+        return Self { id, name: name.to_string(), tables: BTreeSet::new(), levels: vec![Level::default()] };
+    }
+
+    pub fn id(&self) -> u64 {
+        return self.id;
+    }
+
+    pub fn name(&self) -> &str {
+        return &self.name;
+    }
+
+    /// Tables the transaction locked, by database and name.
+    pub fn tables(&self) -> &BTreeSet<(String, String)> {
+        return &self.tables;
+    }
+
+    pub fn has_savepoint(&self, name: &str) -> bool {
+        return self.level_of(name).is_some();
+    }
+
+    fn level_of(&self, name: &str) -> Option<usize> {
+        return self.levels.iter().rposition(|level| level.savepoint.as_deref() == Some(name));
+    }
+
+    /// Locks `t` on its first write and keeps a copy of it for the savepoint
+    /// it is written under.
+    pub fn record(&mut self, database: &str, table: &str, t: &mut Table) {
+        let key = (database.to_string(), table.to_string());
+        if t.lock.is_none() {
+            t.lock = Some(TableLock { owner: self.id, committed: Arc::new(t.clone()) });
+            self.tables.insert(key.clone());
+        }
+        if self.levels.len() > 1 {
+            self.levels.last_mut().unwrap().before.entry(key).or_insert_with(|| t.clone());
+        }
+    }
+
+    /// Holds back the row changes of a statement until commit.
+    pub fn hold(&mut self, database: &str, table: &str, changes: Changes) {
+        if !changes.is_empty() {
+            self.levels.last_mut().unwrap().changes.push((database.to_string(), table.to_string(), changes));
+        }
+    }
+
+    pub fn savepoint(&mut self, name: &str) {
+        self.levels.push(Level { savepoint: Some(name.to_string()), ..Level::default() });
+    }
+
+    /// Folds the levels from savepoint `name` on into the one before it.
+    pub fn release(&mut self, name: &str) {
+        let Some(index) = self.level_of(name) else {
+            return;
+        };
+        for level in self.levels.split_off(index) {
+            let parent = self.levels.last_mut().unwrap();
+            if index > 1 {
+                for (key, before) in level.before {
+                    parent.before.entry(key).or_insert(before);
+                }
+            }
+            parent.changes.extend(level.changes);
+        }
+    }
+
+    /// Takes the tables to put back for a rollback to savepoint `name`,
+    /// newest first, and leaves the savepoint open again.
+    pub fn unwind(&mut self, name: &str) -> Vec<((String, String), Table)> {
+        let Some(index) = self.level_of(name) else {
+            return Vec::new();
+        };
+        let unwound = self.levels
+            .split_off(index)
+            .into_iter()
+            .rev()
+            .flat_map(|level| level.before)
+            .collect();
+        self.savepoint(name);
+        return unwound;
+    }
+
+    /// Row changes to publish on commit, in the order they were made.
+    pub fn into_changes(self) -> impl Iterator<Item = (String, String, Changes)> {
+        return self.levels.into_iter().flat_map(|level| level.changes);
+    }
+}
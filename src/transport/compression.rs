@@ -0,0 +1,198 @@
+use std::sync::atomic::{ AtomicU64, Ordering };
+use std::time::Instant;
+use super::{ FrameError, Message };
+
+/// Bodies smaller than this are sent as is, compressing them costs more than
+/// it saves.
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 1024;
+const ZSTD_LEVEL: i32 = 3;
+
+/// Codecs in order of preference when offering them to a peer.
+pub const SUPPORTED_CODECS: [&str; 2] = ["zstd", "lz4"];
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    None = 0,
+    Lz4 = 1,
+    Zstd = 2,
+}
+
+#[allow(dead_code)]
+impl Codec {
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Codec::None),
+            1 => Some(Codec::Lz4),
+            2 => Some(Codec::Zstd),
+            _ => None,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(Codec::None),
+            "lz4" => Some(Codec::Lz4),
+            "zstd" => Some(Codec::Zstd),
+            _ => None,
+        }
+    }
+
+    pub fn to_name(self) -> &'static str {
+        match self {
+            Codec::None => "none",
+            Codec::Lz4 => "lz4",
+            Codec::Zstd => "zstd",
+        }
+    }
+
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>, FrameError> {
+        match self {
+            Codec::None => Ok(data.to_vec()),
+            Codec::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+            Codec::Zstd =>
+                zstd::bulk::compress(data, ZSTD_LEVEL).map_err(|e| FrameError::Compression(e.to_string())),
+        }
+    }
+
+    /// Decompresses a body, refusing to produce more than `max_size` bytes so
+    /// that a small frame cannot expand into an arbitrary allocation.
+    pub fn decompress(self, data: &[u8], max_size: usize) -> Result<Vec<u8>, FrameError> {
+        match self {
+            Codec::None => Ok(data.to_vec()),
+            Codec::Lz4 => {
+                if data.len() < 4 {
+                    return Err(FrameError::Compression("lz4 body too short".to_string()));
+                }
+                let size = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+                if size > max_size {
+                    return Err(FrameError::Compression(format!("lz4 body expands to {} bytes", size)));
+                }
+                lz4_flex::decompress_size_prepended(data).map_err(|e| FrameError::Compression(e.to_string()))
+            }
+            Codec::Zstd =>
+                zstd::bulk::decompress(data, max_size).map_err(|e| FrameError::Compression(e.to_string())),
+        }
+    }
+}
+
+/// Process wide compression counters, exported as metrics.
+pub struct CompressionStats {
+    frames_compressed: AtomicU64,
+    frames_skipped: AtomicU64,
+    bytes_before: AtomicU64,
+    bytes_after: AtomicU64,
+    compress_nanos: AtomicU64,
+    frames_decompressed: AtomicU64,
+    decompress_nanos: AtomicU64,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompressionSnapshot {
+    pub frames_compressed: u64,
+    pub frames_skipped: u64,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+    pub compress_nanos: u64,
+    pub frames_decompressed: u64,
+    pub decompress_nanos: u64,
+}
+
+pub static COMPRESSION_STATS: CompressionStats = CompressionStats {
+    frames_compressed: AtomicU64::new(0),
+    frames_skipped: AtomicU64::new(0),
+    bytes_before: AtomicU64::new(0),
+    bytes_after: AtomicU64::new(0),
+    compress_nanos: AtomicU64::new(0),
+    frames_decompressed: AtomicU64::new(0),
+    decompress_nanos: AtomicU64::new(0),
+};
+
+#[allow(dead_code)]
+impl CompressionStats {
+    pub fn snapshot(&self) -> CompressionSnapshot {
+        CompressionSnapshot {
+            frames_compressed: self.frames_compressed.load(Ordering::Relaxed),
+            frames_skipped: self.frames_skipped.load(Ordering::Relaxed),
+            bytes_before: self.bytes_before.load(Ordering::Relaxed),
+            bytes_after: self.bytes_after.load(Ordering::Relaxed),
+            compress_nanos: self.compress_nanos.load(Ordering::Relaxed),
+            frames_decompressed: self.frames_decompressed.load(Ordering::Relaxed),
+            decompress_nanos: self.decompress_nanos.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl CompressionSnapshot {
+    /// Compressed size over original size; below 1.0 means bytes were saved.
+    pub fn ratio(&self) -> f64 {
+        if self.bytes_before == 0 {
+            return 1.0;
+        }
+        return (self.bytes_after as f64) / (self.bytes_before as f64);
+    }
+}
+
+#[allow(dead_code)]
+impl Message {
+    pub fn codec(&self) -> Result<Codec, FrameError> {
+        let id = self.header.compression_id();
+        return Codec::from_id(id).ok_or(FrameError::UnsupportedCompression(id));
+    }
+
+    /// Compresses the body with `codec` when it is at least `threshold` bytes
+    /// long and compression actually makes it smaller.
+    pub fn compress(&mut self, codec: Codec, threshold: usize) -> Result<(), FrameError> {
+        if codec == Codec::None || self.header.compression_id() != (Codec::None as u8) {
+            return Ok(());
+        }
+        if self.body.len() < threshold {
+            COMPRESSION_STATS.frames_skipped.fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
+
+        let started = Instant::now();
+        let compressed = codec.compress(&self.body)?;
+        COMPRESSION_STATS.compress_nanos.fetch_add(
+            started.elapsed().as_nanos() as u64,
+            Ordering::Relaxed
+        );
+
+        if compressed.len() >= self.body.len() {
+            COMPRESSION_STATS.frames_skipped.fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
+
+        COMPRESSION_STATS.frames_compressed.fetch_add(1, Ordering::Relaxed);
+        COMPRESSION_STATS.bytes_before.fetch_add(self.body.len() as u64, Ordering::Relaxed);
+        COMPRESSION_STATS.bytes_after.fetch_add(compressed.len() as u64, Ordering::Relaxed);
+
+        self.body = compressed;
+        self.header.set_compression_id(codec as u8);
+        self.header.body_size = self.body.len() as u32;
+        self.header.checksum = self.header.compute_checksum(&self.body);
+        return Ok(());
+    }
+
+    pub fn decompress(&mut self, max_size: usize) -> Result<(), FrameError> {
+        let codec = self.codec()?;
+        if codec == Codec::None {
+            return Ok(());
+        }
+
+        let started = Instant::now();
+        self.body = codec.decompress(&self.body, max_size)?;
+        COMPRESSION_STATS.decompress_nanos.fetch_add(
+            started.elapsed().as_nanos() as u64,
+            Ordering::Relaxed
+        );
+        COMPRESSION_STATS.frames_decompressed.fetch_add(1, Ordering::Relaxed);
+
+        self.header.set_compression_id(Codec::None as u8);
+        self.header.body_size = self.body.len() as u32;
+        self.header.checksum = self.header.compute_checksum(&self.body);
+        return Ok(());
+    }
+}
@@ -0,0 +1,85 @@
+use std::error::Error;
+use std::fmt;
+use tokio::io;
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum FrameError {
+    Io(io::Error),
+    InvalidStartMarker(u32),
+    InvalidEndMarker(u32),
+    UnsupportedVersion(u8),
+    InvalidMessageFlag(u8),
+    InvalidHeaderSize(usize),
+    BodyTooLarge {
+        body_size: u32,
+        max_body_size: u32,
+    },
+    BodySizeMismatch {
+        expected: u32,
+        actual: usize,
+    },
+    ChecksumMismatch {
+        message_id: [u8; 16],
+        expected: u32,
+        actual: u32,
+    },
+    UnsupportedCompression(u8),
+    Compression(String),
+    InvalidExtension(String),
+    /// The frame needs a header feature the legacy layout does not have.
+    UnsupportedByLegacyFraming(&'static str),
+}
+
+#[allow(dead_code)]
+impl FrameError {
+    /// Corrupt frames leave the stream usable: the next read scans forward to
+    /// the following start marker. I/O errors mean the connection is gone. An
+    /// oversized body, or a header of another version, is left unread, and
+    /// scanning through it could pick up a frame forged inside it.
+    pub fn is_recoverable(&self) -> bool {
+        !matches!(self, FrameError::Io(_) | FrameError::BodyTooLarge { .. } | FrameError::UnsupportedVersion(_))
+    }
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Io(e) => write!(f, "frame i/o error: {}", e),
+            FrameError::InvalidStartMarker(marker) =>
+                write!(f, "invalid start marker: {:#010x}", marker),
+            FrameError::InvalidEndMarker(marker) => write!(f, "invalid end marker: {:#010x}", marker),
+            FrameError::UnsupportedVersion(version) =>
+                write!(f, "unsupported header version: {}", version),
+            FrameError::InvalidMessageFlag(flag) => write!(f, "invalid message flag: {}", flag),
+            FrameError::InvalidHeaderSize(size) => write!(f, "invalid header size: {}", size),
+            FrameError::BodyTooLarge { body_size, max_body_size } =>
+                write!(f, "body size {} exceeds the maximum of {}", body_size, max_body_size),
+            FrameError::BodySizeMismatch { expected, actual } =>
+                write!(f, "body size mismatch: header says {}, got {}", expected, actual),
+            FrameError::ChecksumMismatch { expected, actual, .. } =>
+                write!(f, "checksum mismatch: expected {:#010x}, got {:#010x}", expected, actual),
+            FrameError::UnsupportedCompression(codec) =>
+                write!(f, "unsupported compression codec: {}", codec),
+            FrameError::Compression(reason) => write!(f, "compression error: {}", reason),
+            FrameError::InvalidExtension(reason) => write!(f, "invalid header extension: {}", reason),
+            FrameError::UnsupportedByLegacyFraming(feature) =>
+                write!(f, "{} cannot be sent with the legacy framing", feature),
+        }
+    }
+}
+
+impl Error for FrameError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FrameError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for FrameError {
+    fn from(e: io::Error) -> Self {
+        FrameError::Io(e)
+    }
+}
@@ -1,15 +1,63 @@
-use tokio::io;
-use tokio::net::TcpStream;
 use std::time::{ SystemTime, UNIX_EPOCH };
 use uuid::Uuid;
 use byteorder::{ BigEndian, ReadBytesExt, WriteBytesExt };
-use std::io::{ Read, Write };
+use std::io::Read;
 use crate::protocol::MessageType;
-use crate::statement::Statement;
+use super::FrameError;
 
-const START_MARKER: u32 = 0xdeadbeef;
+pub const START_MARKER: u32 = 0xdeadbeef;
 const END_MARKER: u32 = 0xbeefdead;
-pub const MESSAGE_HEADER_SIZE: usize = 37;
+/// Bumped whenever the header layout changes: 1 was the 42-byte header with
+/// a checksum, 2 adds the `flags` byte.
+pub const HEADER_VERSION: u8 = 2;
+pub const MESSAGE_HEADER_SIZE: usize = 43;
+/// Header of peers that predate the handshake: no version byte, flags or
+/// checksum. See `Framing::Legacy`.
+pub const LEGACY_HEADER_SIZE: usize = 37;
+/// `version` of a header read in the legacy layout, which has no version byte.
+pub const LEGACY_HEADER_VERSION: u8 = 0;
+/// Offset of the end marker in a legacy header.
+const LEGACY_END_MARKER_OFFSET: usize = 33;
+/// Offset of the checksum field; the CRC covers every header byte before it.
+const CHECKSUM_OFFSET: usize = 35;
+
+/// Bits 0-1 of `flags` carry the position of the frame within a chunked stream.
+const CHUNK_KIND_MASK: u8 = 0b0000_0011;
+/// Bits 2-3 of `flags` carry the codec the body is compressed with.
+const COMPRESSION_MASK: u8 = 0b0000_1100;
+const COMPRESSION_SHIFT: u8 = 2;
+/// Bit 4 of `flags` marks a trace context extension between the header and
+/// the body; see `Message::trace_context`.
+const TRACE_CONTEXT_FLAG: u8 = 0b0001_0000;
+
+/// Header layout spoken on a connection. `Legacy` is only used once the
+/// handshake fell back to the legacy protocol, and it cannot carry chunks,
+/// compression or a trace context.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    Legacy,
+    Current,
+}
+
+#[allow(dead_code)]
+impl Framing {
+    /// Guesses the layout of a header from its first `LEGACY_HEADER_SIZE`
+    /// bytes: only a legacy header has its end marker at that offset.
+    pub fn detect(prefix: &[u8]) -> Self {
+        let end = &prefix[LEGACY_END_MARKER_OFFSET..LEGACY_HEADER_SIZE];
+        if u32::from_be_bytes([end[0], end[1], end[2], end[3]]) == END_MARKER {
+            return Framing::Legacy;
+        }
+        return Framing::Current;
+    }
+
+    pub fn header_size(self) -> usize {
+        match self {
+            Framing::Legacy => LEGACY_HEADER_SIZE,
+            Framing::Current => MESSAGE_HEADER_SIZE,
+        }
+    }
+}
 
 #[repr(u8)]
 #[derive(Debug, Clone, Copy)]
@@ -18,14 +66,44 @@ pub enum MessageTypeFlag {
     ResponseMessage = 2,
 }
 
+/// Position of a frame within a chunked message. Every chunk of a stream
+/// shares the message id of the request; a plain message is `Single`.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkKind {
+    Single = 0,
+    First = 1,
+    Continuation = 2,
+    Last = 3,
+}
+
+#[allow(dead_code)]
+impl ChunkKind {
+    pub fn from_flags(flags: u8) -> Self {
+        match flags & CHUNK_KIND_MASK {
+            1 => ChunkKind::First,
+            2 => ChunkKind::Continuation,
+            3 => ChunkKind::Last,
+            _ => ChunkKind::Single,
+        }
+    }
+
+    pub fn is_final(self) -> bool {
+        matches!(self, ChunkKind::Single | ChunkKind::Last)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct MessageHeader {
     pub start_marker: u32,
+    pub version: u8,
     pub message_id: [u8; 16],
     pub message_type: MessageType,
     pub message_flag: MessageTypeFlag,
+    pub flags: u8,
     pub timestamp: u32,
     pub body_size: u32,
+    pub checksum: u32,
     pub end_marker: u32,
 }
 
@@ -34,54 +112,122 @@ impl MessageHeader {
     pub fn new(message_type: MessageType, message_flag: MessageTypeFlag, body_size: u32) -> Self {
         Self {
             start_marker: START_MARKER,
-            message_id: Uuid::new_v4().as_bytes().clone(),
+            version: HEADER_VERSION,
+            message_id: *Uuid::new_v4().as_bytes(),
             message_type,
             message_flag,
+            flags: 0,
             timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u32,
             body_size,
+            checksum: 0,
             end_marker: END_MARKER,
         }
     }
 
     pub fn serialize(&self) -> Vec<u8> {
         let mut buffer: Vec<u8> = Vec::with_capacity(MESSAGE_HEADER_SIZE);
-        
+
         buffer.write_u32::<BigEndian>(self.start_marker).unwrap();
+        buffer.write_u8(self.version).unwrap();
         buffer.extend_from_slice(&self.message_id);
         buffer.write_u32::<BigEndian>(self.message_type as u32).unwrap();
         buffer.write_u8(self.message_flag as u8).unwrap();
+        buffer.write_u8(self.flags).unwrap();
         buffer.write_u32::<BigEndian>(self.timestamp).unwrap();
         buffer.write_u32::<BigEndian>(self.body_size).unwrap();
+        buffer.write_u32::<BigEndian>(self.checksum).unwrap();
         buffer.write_u32::<BigEndian>(self.end_marker).unwrap();
 
         return buffer;
     }
 
-    pub fn deserialize(mut buffer: &[u8]) -> io::Result<Self> {
+    pub fn deserialize(mut buffer: &[u8]) -> Result<Self, FrameError> {
         if buffer.len() != MESSAGE_HEADER_SIZE {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid header size"));
+            return Err(FrameError::InvalidHeaderSize(buffer.len()));
         }
 
         let start_marker = buffer.read_u32::<BigEndian>()?;
         if start_marker != START_MARKER {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid start marker"));
+            return Err(FrameError::InvalidStartMarker(start_marker));
+        }
+
+        let version = buffer.read_u8()?;
+        if version != HEADER_VERSION {
+            return Err(FrameError::UnsupportedVersion(version));
         }
 
         let mut message_id = [0u8; 16];
         buffer.read_exact(&mut message_id)?;
 
-        let message_type = match buffer.read_u32::<BigEndian>() {
-            Ok(value) => MessageType::from_id(value),
-            Err(_) => {
-                return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid message type"));
+        let message_type = MessageType::from_id(buffer.read_u32::<BigEndian>()?);
+
+        let message_flag = match buffer.read_u8()? {
+            1 => MessageTypeFlag::RequestMessage,
+            2 => MessageTypeFlag::ResponseMessage,
+            flag => {
+                return Err(FrameError::InvalidMessageFlag(flag));
             }
         };
 
+        let flags = buffer.read_u8()?;
+        let timestamp = buffer.read_u32::<BigEndian>()?;
+        let body_size = buffer.read_u32::<BigEndian>()?;
+        let checksum = buffer.read_u32::<BigEndian>()?;
+        let end_marker = buffer.read_u32::<BigEndian>()?;
+
+        if end_marker != END_MARKER {
+            return Err(FrameError::InvalidEndMarker(end_marker));
+        }
+
+        Ok(Self {
+            start_marker,
+            version,
+            message_id,
+            message_type,
+            message_flag,
+            flags,
+            timestamp,
+            body_size,
+            checksum,
+            end_marker,
+        })
+    }
+
+    /// The header in the legacy layout. Flags and the checksum are left out.
+    pub fn serialize_legacy(&self) -> Vec<u8> {
+        let mut buffer: Vec<u8> = Vec::with_capacity(LEGACY_HEADER_SIZE);
+
+        buffer.write_u32::<BigEndian>(self.start_marker).unwrap();
+        buffer.extend_from_slice(&self.message_id);
+        buffer.write_u32::<BigEndian>(self.message_type as u32).unwrap();
+        buffer.write_u8(self.message_flag as u8).unwrap();
+        buffer.write_u32::<BigEndian>(self.timestamp).unwrap();
+        buffer.write_u32::<BigEndian>(self.body_size).unwrap();
+        buffer.write_u32::<BigEndian>(self.end_marker).unwrap();
+
+        return buffer;
+    }
+
+    pub fn deserialize_legacy(mut buffer: &[u8]) -> Result<Self, FrameError> {
+        if buffer.len() != LEGACY_HEADER_SIZE {
+            return Err(FrameError::InvalidHeaderSize(buffer.len()));
+        }
+
+        let start_marker = buffer.read_u32::<BigEndian>()?;
+        if start_marker != START_MARKER {
+            return Err(FrameError::InvalidStartMarker(start_marker));
+        }
+
+        let mut message_id = [0u8; 16];
+        buffer.read_exact(&mut message_id)?;
+
+        let message_type = MessageType::from_id(buffer.read_u32::<BigEndian>()?);
+
         let message_flag = match buffer.read_u8()? {
             1 => MessageTypeFlag::RequestMessage,
             2 => MessageTypeFlag::ResponseMessage,
-            _ => {
-                return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid message flag"));
+            flag => {
+                return Err(FrameError::InvalidMessageFlag(flag));
             }
         };
 
@@ -90,20 +236,80 @@ impl MessageHeader {
         let end_marker = buffer.read_u32::<BigEndian>()?;
 
         if end_marker != END_MARKER {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid end marker"));
+            return Err(FrameError::InvalidEndMarker(end_marker));
         }
 
         Ok(Self {
             start_marker,
+            version: LEGACY_HEADER_VERSION,
             message_id,
             message_type,
             message_flag,
+            flags: 0,
             timestamp,
             body_size,
+            checksum: 0,
             end_marker,
         })
     }
 
+    pub fn chunk_kind(&self) -> ChunkKind {
+        ChunkKind::from_flags(self.flags)
+    }
+
+    pub fn set_chunk_kind(&mut self, kind: ChunkKind) {
+        self.flags = (self.flags & !CHUNK_KIND_MASK) | (kind as u8);
+    }
+
+    pub fn compression_id(&self) -> u8 {
+        (self.flags & COMPRESSION_MASK) >> COMPRESSION_SHIFT
+    }
+
+    pub fn set_compression_id(&mut self, codec: u8) {
+        self.flags = (self.flags & !COMPRESSION_MASK) | ((codec << COMPRESSION_SHIFT) & COMPRESSION_MASK);
+    }
+
+    pub fn has_trace_context(&self) -> bool {
+        (self.flags & TRACE_CONTEXT_FLAG) != 0
+    }
+
+    pub fn set_trace_context(&mut self, present: bool) {
+        if present {
+            self.flags |= TRACE_CONTEXT_FLAG;
+        } else {
+            self.flags &= !TRACE_CONTEXT_FLAG;
+        }
+    }
+
+    /// CRC32C over the header fields preceding the checksum and the body.
+    pub fn compute_checksum(&self, body: &[u8]) -> u32 {
+        return self.compute_checksum_with(&[], body);
+    }
+
+    /// Like `compute_checksum`, also covering the header extension.
+    pub fn compute_checksum_with(&self, extension: &[u8], body: &[u8]) -> u32 {
+        let serialized = self.serialize();
+        let crc = crc32c::crc32c(&serialized[..CHECKSUM_OFFSET]);
+        let crc = crc32c::crc32c_append(crc, extension);
+        return crc32c::crc32c_append(crc, body);
+    }
+
+    pub fn verify_checksum(&self, body: &[u8]) -> Result<(), FrameError> {
+        return self.verify_checksum_with(&[], body);
+    }
+
+    pub fn verify_checksum_with(&self, extension: &[u8], body: &[u8]) -> Result<(), FrameError> {
+        let actual = self.compute_checksum_with(extension, body);
+        if actual != self.checksum {
+            return Err(FrameError::ChecksumMismatch {
+                message_id: self.message_id,
+                expected: self.checksum,
+                actual,
+            });
+        }
+        return Ok(());
+    }
+
     pub fn message_id_string(&self) -> String {
         Uuid::from_slice(&self.message_id).unwrap().to_string()
     }
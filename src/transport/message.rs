@@ -1,65 +1,231 @@
-use tokio::net::TcpStream;
-use std::time::{ SystemTime, UNIX_EPOCH };
-use tokio::io::{ self, AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf };
-use uuid::Uuid;
-use byteorder::{ BigEndian, ReadBytesExt, WriteBytesExt };
-use std::io::Write;
+use tokio::io::{ AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt };
+use byteorder::{ BigEndian, ByteOrder };
+use log::warn;
 use crate::protocol::MessageType;
 use crate::statement::Statement;
-use super::{ MessageHeader, MessageTypeFlag, MESSAGE_HEADER_SIZE };
+use crate::utils::metrics;
+use super::{ ChunkKind, Codec, FrameError, Framing, MessageHeader, MessageTypeFlag, MESSAGE_HEADER_SIZE };
+use super::header::{ LEGACY_HEADER_SIZE, START_MARKER };
+
+/// Longest trace context a frame can carry; its length is sent as one byte.
+pub const MAX_TRACE_CONTEXT_SIZE: usize = 255;
 
 #[derive(Debug, Clone)]
 pub struct Message {
     pub header: MessageHeader,
     pub body: Vec<u8>,
+    /// W3C `traceparent` of the span that sent the request. It travels in a
+    /// header extension, a length byte and the value, that sits between the
+    /// header and the body and is covered by the checksum but not by
+    /// `body_size` or compression. Only sent to peers on protocol version 3
+    /// or later.
+    pub trace_context: Option<String>,
 }
 
 #[allow(dead_code)]
 impl Message {
-    pub fn new(message_type: MessageType, stmt: &impl Statement) -> Self {
+    pub fn new(message_type: MessageType, stmt: &(impl Statement + ?Sized)) -> Self {
         let body = stmt.to_bytes().unwrap();
         let body_size = body.len() as u32;
-        Self {
-            header: MessageHeader::new(message_type, MessageTypeFlag::RequestMessage, body_size),
-            body,
-        }
+        let header = MessageHeader::new(message_type, MessageTypeFlag::RequestMessage, body_size);
+        return Self::sealed(header, body);
+    }
+
+    pub fn new_response(request: &Message, message_type: MessageType, stmt: &impl Statement) -> Self {
+        let body = stmt.to_bytes().unwrap();
+        let mut header = MessageHeader::new(
+            message_type,
+            MessageTypeFlag::ResponseMessage,
+            body.len() as u32
+        );
+        header.message_id = request.header.message_id;
+        return Self::sealed(header, body);
+    }
+
+    fn sealed(mut header: MessageHeader, body: Vec<u8>) -> Self {
+        header.checksum = header.compute_checksum(&body);
+        Self { header, body, trace_context: None }
+    }
+
+    /// The header extension for `trace_context`, empty without one. A value
+    /// too long to encode is left out rather than failing the request.
+    fn extension(&self) -> Vec<u8> {
+        let trace_context = match &self.trace_context {
+            Some(trace_context) if trace_context.len() <= MAX_TRACE_CONTEXT_SIZE => trace_context,
+            Some(trace_context) => {
+                warn!("Dropping trace context of {} bytes", trace_context.len());
+                return Vec::new();
+            }
+            None => {
+                return Vec::new();
+            }
+        };
+        let mut extension = Vec::with_capacity(1 + trace_context.len());
+        extension.push(trace_context.len() as u8);
+        extension.extend_from_slice(trace_context.as_bytes());
+        return extension;
     }
 
+    fn parse_trace_context(value: &[u8]) -> Result<String, FrameError> {
+        return String::from_utf8(value.to_vec()).map_err(|e| FrameError::InvalidExtension(e.to_string()));
+    }
+
+    /// Serializes the frame, recomputing the checksum so that changes made to
+    /// the header or body after construction are always covered.
     pub fn serialize(&self) -> Vec<u8> {
-        let mut buffer = self.header.serialize();
+        let extension = self.extension();
+        let mut header = self.header.clone();
+        header.set_trace_context(!extension.is_empty());
+        header.body_size = self.body.len() as u32;
+        header.checksum = header.compute_checksum_with(&extension, &self.body);
+
+        let mut buffer = header.serialize();
+        buffer.extend_from_slice(&extension);
         buffer.extend_from_slice(&self.body);
         return buffer;
     }
 
-    pub fn deserialize(buffer: &[u8]) -> io::Result<Self> {
+    /// Serializes the frame in the given layout. The legacy layout has no
+    /// flags, so chunked and compressed frames are refused and the trace
+    /// context is left out.
+    pub fn serialize_for(&self, framing: Framing) -> Result<Vec<u8>, FrameError> {
+        if framing == Framing::Current {
+            return Ok(self.serialize());
+        }
+        if self.header.chunk_kind() != ChunkKind::Single {
+            return Err(FrameError::UnsupportedByLegacyFraming("a chunked message"));
+        }
+        if self.header.compression_id() != (Codec::None as u8) {
+            return Err(FrameError::UnsupportedByLegacyFraming("a compressed body"));
+        }
+
+        let mut header = self.header.clone();
+        header.body_size = self.body.len() as u32;
+        let mut buffer = header.serialize_legacy();
+        buffer.extend_from_slice(&self.body);
+        return Ok(buffer);
+    }
+
+    pub fn deserialize(buffer: &[u8]) -> Result<Self, FrameError> {
         if buffer.len() < MESSAGE_HEADER_SIZE {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Message size too small"));
+            return Err(FrameError::InvalidHeaderSize(buffer.len()));
         }
 
         let header = MessageHeader::deserialize(&buffer[..MESSAGE_HEADER_SIZE])?;
-        let body = buffer[MESSAGE_HEADER_SIZE..].to_vec();
+        let mut rest = &buffer[MESSAGE_HEADER_SIZE..];
+        let mut extension: &[u8] = &[];
+        if header.has_trace_context() {
+            let length = 1 + (*rest.first().ok_or_else(|| FrameError::InvalidExtension("missing length".to_string()))? as usize);
+            if rest.len() < length {
+                return Err(FrameError::InvalidExtension("truncated trace context".to_string()));
+            }
+            (extension, rest) = rest.split_at(length);
+        }
+        let body = rest.to_vec();
 
         if (body.len() as u32) != header.body_size {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Body size mismatch"));
+            return Err(FrameError::BodySizeMismatch {
+                expected: header.body_size,
+                actual: body.len(),
+            });
         }
 
-        Ok(Self { header, body })
+        header.verify_checksum_with(extension, &body)?;
+
+        let trace_context = match extension.split_first() {
+            Some((_, value)) => Some(Self::parse_trace_context(value)?),
+            None => None,
+        };
+        Ok(Self { header, body, trace_context })
     }
 
-    pub async fn read_from(reader: &mut ReadHalf<TcpStream>) -> io::Result<Self> {
+    /// Reads the next frame in the given layout. Any bytes preceding a start
+    /// marker are skipped, so after a corrupt frame the stream resynchronizes
+    /// on the next one. The body size is checked against `max_body_size`
+    /// before allocating, and a compressed body is inflated to at most that
+    /// size.
+    pub async fn read_from<R: AsyncRead + Unpin>(
+        reader: &mut R,
+        max_body_size: u32,
+        framing: Framing
+    ) -> Result<Self, FrameError> {
+        let (message, _) = Self::read_with(reader, max_body_size, |_| framing).await?;
+        return Ok(message);
+    }
+
+    /// Like `read_from`, with the layout picked by `choose` once the first
+    /// `LEGACY_HEADER_SIZE` bytes of the header are in, e.g. `Framing::detect`
+    /// for the first frame of a connection.
+    pub async fn read_with<R: AsyncRead + Unpin, F: FnOnce(&[u8]) -> Framing>(
+        reader: &mut R,
+        max_body_size: u32,
+        choose: F
+    ) -> Result<(Self, Framing), FrameError> {
         let mut header_bytes = vec![0; MESSAGE_HEADER_SIZE];
-        reader.read_exact(&mut header_bytes).await?;
+        reader.read_exact(&mut header_bytes[..4]).await?;
+
+        let mut skipped: usize = 0;
+        while BigEndian::read_u32(&header_bytes[..4]) != START_MARKER {
+            header_bytes.copy_within(1..4, 0);
+            reader.read_exact(&mut header_bytes[3..4]).await?;
+            skipped += 1;
+        }
+        if skipped > 0 {
+            warn!("Skipped {} bytes to resynchronize on the next frame", skipped);
+        }
+
+        // Both layouts are at least as long as the legacy header.
+        reader.read_exact(&mut header_bytes[4..LEGACY_HEADER_SIZE]).await?;
+        let framing = choose(&header_bytes[..LEGACY_HEADER_SIZE]);
+        header_bytes.truncate(framing.header_size());
+        reader.read_exact(&mut header_bytes[LEGACY_HEADER_SIZE..]).await?;
+        let mut header = match framing {
+            Framing::Legacy => MessageHeader::deserialize_legacy(&header_bytes)?,
+            Framing::Current => MessageHeader::deserialize(&header_bytes)?,
+        };
+
+        if header.body_size > max_body_size {
+            return Err(FrameError::BodyTooLarge {
+                body_size: header.body_size,
+                max_body_size,
+            });
+        }
+
+        let mut extension = Vec::new();
+        if header.has_trace_context() {
+            let length = reader.read_u8().await? as usize;
+            extension = vec![0; 1 + length];
+            extension[0] = length as u8;
+            reader.read_exact(&mut extension[1..]).await?;
+        }
 
-        let header = MessageHeader::deserialize(&header_bytes)?;
         let mut body = vec![0; header.body_size as usize];
         reader.read_exact(&mut body).await?;
+        metrics().bytes_in.inc_by((skipped + header_bytes.len() + extension.len() + body.len()) as u64);
+
+        match framing {
+            Framing::Current => header.verify_checksum_with(&extension, &body)?,
+            // Legacy frames carry no checksum; fill one in so the frame can be
+            // handled like any other.
+            Framing::Legacy => {
+                header.checksum = header.compute_checksum(&body);
+            }
+        }
 
-        return Ok(Self { header, body });
+        let trace_context = match extension.split_first() {
+            Some((_, value)) => Some(Self::parse_trace_context(value)?),
+            None => None,
+        };
+        let mut message = Self { header, body, trace_context };
+        message.decompress(max_body_size as usize)?;
+        return Ok((message, framing));
     }
 
-    pub async fn write_to(&self, writer: &mut WriteHalf<TcpStream>) -> io::Result<()> {
-        let serialized = self.serialize();
+    pub async fn write_to<W: AsyncWrite + Unpin>(&self, writer: &mut W, framing: Framing) -> Result<(), FrameError> {
+        let serialized = self.serialize_for(framing)?;
         writer.write_all(&serialized).await?;
-        return writer.flush().await;
+        writer.flush().await?;
+        metrics().bytes_out.inc_by(serialized.len() as u64);
+        return Ok(());
     }
 }
@@ -1,7 +1,16 @@
 pub mod response;
 
+pub mod error;
+pub use error::FrameError;
+
 pub mod header;
-pub use header::{ MessageHeader, MessageTypeFlag, MESSAGE_HEADER_SIZE };
+pub use header::{ ChunkKind, Framing, MessageHeader, MessageTypeFlag, MESSAGE_HEADER_SIZE };
 
 pub mod message;
-pub use message::Message;
+pub use message::{ Message, MAX_TRACE_CONTEXT_SIZE };
+
+pub mod stream;
+pub use stream::{ AssemblyError, ChunkAssembler, FlowControl, Row, RowBatch };
+
+pub mod compression;
+pub use compression::Codec;
@@ -29,6 +29,7 @@ impl<T> ExecutionResult<T> {
     }
 }
 
+#[allow(dead_code)]
 #[derive(Debug)]
 pub struct TimeoutError;
 
@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use serde::{ Deserialize, Serialize };
+use tokio::sync::Semaphore;
+use crate::protocol::MessageType;
+use crate::protocol::handshake::DEFAULT_MAX_FRAME_SIZE;
+use super::{ ChunkKind, Message, MessageHeader, MessageTypeFlag };
+
+/// A row as it travels in streamed `Select` results and `BulkInsert` uploads.
+pub type Row = HashMap<String, serde_json::Value>;
+
+/// Body of one chunk of a `Select` result. Paged selects send `Page`, with
+/// the continuation token on the final chunk only; others send bare rows.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RowBatch {
+    Rows(Vec<Row>),
+    Page {
+        rows: Vec<Row>,
+        continuation: Option<String>,
+    },
+}
+
+#[allow(dead_code)]
+impl RowBatch {
+    pub fn into_parts(self) -> (Vec<Row>, Option<String>) {
+        match self {
+            RowBatch::Rows(rows) => (rows, None),
+            RowBatch::Page { rows, continuation } => (rows, continuation),
+        }
+    }
+}
+
+/// Chunks a receiver accepts on a stream before it has granted any credit.
+pub const INITIAL_STREAM_WINDOW: u32 = 16;
+
+/// Sender side of per-stream flow control: one permit per chunk in flight.
+#[derive(Debug, Clone)]
+pub struct FlowControl {
+    permits: Arc<Semaphore>,
+}
+
+#[allow(dead_code)]
+impl FlowControl {
+    pub fn new(window: u32) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(window as usize)),
+        }
+    }
+
+    /// Waits until the peer allows one more chunk. Fails once the stream was
+    /// cancelled by the receiver.
+    pub async fn acquire(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        match self.permits.acquire().await {
+            Ok(permit) => {
+                permit.forget();
+                return Ok(());
+            }
+            Err(_) => {
+                return Err("stream cancelled by the peer".into());
+            }
+        }
+    }
+
+    pub fn grant(&self, credits: u32) {
+        self.permits.add_permits(credits as usize);
+    }
+
+    pub fn cancel(&self) {
+        self.permits.close();
+    }
+}
+
+/// Chunked messages a receiver assembles at once by default.
+pub const MAX_OPEN_STREAMS: usize = 64;
+
+/// Why a `ChunkAssembler` refused a chunk. The partial message it belonged
+/// to, if any, is dropped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssemblyError {
+    /// A `Continuation` or `Last` chunk with no `First` before it.
+    Orphan,
+    /// The joined body would outgrow the largest body accepted.
+    TooLarge {
+        size: usize,
+        max_body_size: usize,
+    },
+    /// A `First` chunk while the most streams allowed are already open.
+    TooManyStreams(usize),
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssemblyError::Orphan => write!(f, "chunk of a message that was never started"),
+            AssemblyError::TooLarge { size, max_body_size } =>
+                write!(f, "assembled body of {} bytes exceeds the maximum of {}", size, max_body_size),
+            AssemblyError::TooManyStreams(max_streams) =>
+                write!(f, "more than {} chunked messages open at once", max_streams),
+        }
+    }
+}
+
+impl std::error::Error for AssemblyError {}
+
+/// Receiver side of a chunked request: collects the chunks of each message id
+/// until the last one arrives. The joined body is held to the largest body
+/// accepted in one frame, and only so many messages may be open at once.
+#[derive(Debug)]
+pub struct ChunkAssembler {
+    pending: HashMap<[u8; 16], (Vec<Message>, usize)>,
+    max_body_size: usize,
+    max_streams: usize,
+}
+
+impl Default for ChunkAssembler {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_FRAME_SIZE, MAX_OPEN_STREAMS)
+    }
+}
+
+#[allow(dead_code)]
+impl ChunkAssembler {
+    pub fn new(max_body_size: u32, max_streams: usize) -> Self {
+        Self {
+            pending: HashMap::new(),
+            max_body_size: max_body_size as usize,
+            max_streams,
+        }
+    }
+
+    /// Returns every chunk of the message once it is complete. A `Single`
+    /// frame is complete on its own.
+    pub fn push(&mut self, message: Message) -> Result<Option<Vec<Message>>, AssemblyError> {
+        let message_id = message.header.message_id;
+        let kind = message.header.chunk_kind();
+        if kind == ChunkKind::Single {
+            return Ok(Some(vec![message]));
+        }
+
+        if kind == ChunkKind::First {
+            // A restarted message replaces the one it had open.
+            if self.pending.remove(&message_id).is_none() && self.pending.len() >= self.max_streams {
+                return Err(AssemblyError::TooManyStreams(self.max_streams));
+            }
+            self.pending.insert(message_id, (Vec::new(), 0));
+        }
+
+        let (chunks, size) = match self.pending.get_mut(&message_id) {
+            Some(entry) => entry,
+            None => {
+                return Err(AssemblyError::Orphan);
+            }
+        };
+        let total = *size + message.body.len();
+        if total > self.max_body_size {
+            self.pending.remove(&message_id);
+            return Err(AssemblyError::TooLarge { size: total, max_body_size: self.max_body_size });
+        }
+        *size = total;
+        chunks.push(message);
+
+        if kind != ChunkKind::Last {
+            return Ok(None);
+        }
+        return Ok(self.pending.remove(&message_id).map(|(chunks, _)| chunks));
+    }
+
+    /// Forgets the chunks gathered so far for a message.
+    pub fn discard(&mut self, message_id: &[u8; 16]) {
+        self.pending.remove(message_id);
+    }
+
+    pub fn pending_streams(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+#[allow(dead_code)]
+impl Message {
+    /// Builds one frame of a chunked message. The bodies of all chunks of a
+    /// message concatenate into its full body.
+    pub fn chunk(
+        message_type: MessageType,
+        message_flag: MessageTypeFlag,
+        message_id: [u8; 16],
+        kind: ChunkKind,
+        body: Vec<u8>
+    ) -> Self {
+        let mut header = MessageHeader::new(message_type, message_flag, body.len() as u32);
+        header.message_id = message_id;
+        header.set_chunk_kind(kind);
+        header.checksum = header.compute_checksum(&body);
+        return Self { header, body, trace_context: None };
+    }
+
+    /// Joins the chunks of a message into a single frame.
+    pub fn assemble(chunks: Vec<Message>) -> Option<Message> {
+        let mut chunks = chunks.into_iter();
+        let mut message = chunks.next()?;
+        for chunk in chunks {
+            message.body.extend_from_slice(&chunk.body);
+        }
+        message.header.set_chunk_kind(ChunkKind::Single);
+        message.header.body_size = message.body.len() as u32;
+        message.header.checksum = message.header.compute_checksum(&message.body);
+        return Some(message);
+    }
+}
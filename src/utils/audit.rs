@@ -0,0 +1,380 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::{ self, File, OpenOptions };
+use std::io::{ self, BufRead, BufReader, Write };
+use std::path::{ Path, PathBuf };
+use std::sync::Mutex;
+use chrono::{ SecondsFormat, Utc };
+use log::error;
+use serde::{ Deserialize, Serialize };
+use sha2::{ Digest, Sha256 };
+use crate::protocol::MessageType;
+use super::config::{ AuditConfig, StorageConfig };
+use super::metrics;
+
+/// Directory under the storage path the audit files go to by default.
+pub const AUDIT_DIRECTORY: &str = "audit";
+/// `prev_hash` of the very first record.
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+pub const DEFAULT_MAX_FILE_SIZE_MB: u64 = 64;
+const FILE_PREFIX: &str = "audit-";
+const FILE_SUFFIX: &str = ".log";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    NodeLogin {
+        connection_id: usize,
+        peer_address: String,
+        node_id: String,
+        node_name: String,
+        address: String,
+        tags: Vec<String>,
+    },
+    UserLogin {
+        connection_id: usize,
+        peer_address: String,
+        username: String,
+        database: String,
+    },
+    AuthenticationFailed {
+        connection_id: usize,
+        peer_address: String,
+        /// Node id or username the peer tried to log in as.
+        identity: String,
+        reason: String,
+    },
+    Statement {
+        connection_id: usize,
+        principal: String,
+        database: String,
+        message_type: String,
+        message_id: String,
+        /// `ok`, `denied` or `failed`.
+        outcome: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+/// One line of an audit file. `hash` is the SHA-256 of the previous
+/// record's hash followed by this record without its `hash`, so editing,
+/// dropping or reordering a record breaks every link after it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub sequence: u64,
+    pub timestamp: String,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+#[derive(Serialize)]
+struct UnsignedRecord<'a> {
+    sequence: u64,
+    timestamp: &'a str,
+    #[serde(flatten)]
+    event: &'a AuditEvent,
+    prev_hash: &'a str,
+}
+
+#[allow(dead_code)]
+impl AuditRecord {
+    pub fn compute_hash(&self) -> String {
+        let unsigned = UnsignedRecord {
+            sequence: self.sequence,
+            timestamp: &self.timestamp,
+            event: &self.event,
+            prev_hash: &self.prev_hash,
+        };
+        let mut hasher = Sha256::new();
+        hasher.update(self.prev_hash.as_bytes());
+        hasher.update(serde_json::to_vec(&unsigned).unwrap());
+        return hex::encode(hasher.finalize());
+    }
+}
+
+/// Statements that change the schema or the catalog; always audited.
+pub fn is_ddl(message_type: MessageType) -> bool {
+    matches!(
+        message_type,
+        MessageType::CreateDatabase |
+            MessageType::DropDatabase |
+            MessageType::CreateTable |
+            MessageType::DropTable |
+            MessageType::AlterTable |
+            MessageType::RenameTable |
+            MessageType::TruncateTable |
+            MessageType::CreateIndex |
+            MessageType::DropIndex |
+            MessageType::CreateUser |
+            MessageType::DropUser |
+            MessageType::CreateRole |
+            MessageType::Grant |
+            MessageType::Revoke
+    )
+}
+
+/// Statements that change rows; audited when `include_dml` is set.
+pub fn is_dml(message_type: MessageType) -> bool {
+    matches!(
+        message_type,
+        MessageType::Insert |
+            MessageType::Update |
+            MessageType::Delete |
+            MessageType::BulkInsert |
+            MessageType::Upsert |
+            // A prepared statement may write.
+            MessageType::Execute
+    )
+}
+
+fn file_name(index: u64) -> String {
+    return format!("{}{:06}{}", FILE_PREFIX, index, FILE_SUFFIX);
+}
+
+fn file_index(path: &Path) -> Option<u64> {
+    let name = path.file_name()?.to_str()?;
+    return name.strip_prefix(FILE_PREFIX)?.strip_suffix(FILE_SUFFIX)?.parse().ok();
+}
+
+/// Audit files in `directory`, oldest first.
+pub fn audit_files(directory: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut files: Vec<(u64, PathBuf)> = fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| file_index(&entry.path()).map(|index| (index, entry.path())))
+        .collect();
+    files.sort();
+    return Ok(files);
+}
+
+#[derive(Debug)]
+struct AuditWriter {
+    file: File,
+    file_index: u64,
+    file_size: u64,
+    sequence: u64,
+    last_hash: String,
+}
+
+/// Append-only audit trail, written as JSON lines to numbered files that
+/// are rotated once they reach `max_file_bytes`. The hash chain carries on
+/// across files and across restarts.
+#[derive(Debug)]
+pub struct AuditLog {
+    directory: PathBuf,
+    max_file_bytes: u64,
+    include_dml: bool,
+    writer: Mutex<AuditWriter>,
+}
+
+#[allow(dead_code)]
+impl AuditLog {
+    pub fn open(directory: impl Into<PathBuf>, max_file_bytes: u64, include_dml: bool) -> io::Result<Self> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+
+        let (file_index, sequence, last_hash) = match audit_files(&directory)?.pop() {
+            Some((index, path)) => {
+                match last_record(&path)? {
+                    Some(record) => (index, record.sequence, record.hash),
+                    None => (index, 0, GENESIS_HASH.to_string()),
+                }
+            }
+            None => (1, 0, GENESIS_HASH.to_string()),
+        };
+        let (file, file_size) = open_file(&directory, file_index)?;
+
+        return Ok(Self {
+            directory,
+            max_file_bytes,
+            include_dml,
+            writer: Mutex::new(AuditWriter { file, file_index, file_size, sequence, last_hash }),
+        });
+    }
+
+    /// Opens the log configured under `[audit]`, or `None` when disabled.
+    pub fn from_config(audit: &AuditConfig, storage: &StorageConfig) -> io::Result<Option<Self>> {
+        if !audit.enabled {
+            return Ok(None);
+        }
+        let directory = match &audit.directory {
+            Some(directory) if !directory.is_empty() => PathBuf::from(directory),
+            _ => Path::new(&storage.path).join(AUDIT_DIRECTORY),
+        };
+        let log = Self::open(directory, audit.max_file_size_mb * 1024 * 1024, audit.include_dml)?;
+        return Ok(Some(log));
+    }
+
+    pub fn directory(&self) -> &Path {
+        return &self.directory;
+    }
+
+    /// Whether statements of this type go to the trail.
+    pub fn records_statement(&self, message_type: MessageType) -> bool {
+        return is_ddl(message_type) || (self.include_dml && is_dml(message_type));
+    }
+
+    /// Appends `event`. A failed write is logged rather than returned, so
+    /// that an audit problem does not take the node down.
+    pub fn record(&self, event: AuditEvent) {
+        if let Err(e) = self.append(event) {
+            error!("Failed to write audit record to {}: {}", self.directory.display(), e);
+        }
+    }
+
+    pub fn append(&self, event: AuditEvent) -> io::Result<AuditRecord> {
+        let mut writer = self.writer.lock().unwrap();
+
+        let mut record = AuditRecord {
+            sequence: writer.sequence + 1,
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Nanos, true),
+            event,
+            prev_hash: writer.last_hash.clone(),
+            hash: String::new(),
+        };
+        record.hash = record.compute_hash();
+
+        let mut line = serde_json::to_vec(&record).map_err(io::Error::other)?;
+        line.push(b'\n');
+
+        if writer.file_size > 0 && writer.file_size + (line.len() as u64) > self.max_file_bytes {
+            let (file, file_size) = open_file(&self.directory, writer.file_index + 1)?;
+            writer.file = file;
+            writer.file_index += 1;
+            writer.file_size = file_size;
+        }
+
+        writer.file.write_all(&line)?;
+        // The record must survive a crash once `append` returns.
+        metrics().time_fsync("audit", || writer.file.sync_data())?;
+        writer.file_size += line.len() as u64;
+        writer.sequence = record.sequence;
+        writer.last_hash = record.hash.clone();
+        return Ok(record);
+    }
+}
+
+fn open_file(directory: &Path, index: u64) -> io::Result<(File, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(directory.join(file_name(index)))?;
+    let size = file.metadata()?.len();
+    return Ok((file, size));
+}
+
+fn last_record(path: &Path) -> io::Result<Option<AuditRecord>> {
+    let mut last = None;
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            last = Some(line);
+        }
+    }
+    return match last {
+        Some(line) => serde_json::from_str(&line).map(Some).map_err(io::Error::other),
+        None => Ok(None),
+    };
+}
+
+#[derive(Debug)]
+pub enum AuditError {
+    Io(io::Error),
+    Malformed {
+        file: PathBuf,
+        line: usize,
+        reason: String,
+    },
+    /// The sequence number is not the one after the previous record.
+    Gap {
+        file: PathBuf,
+        line: usize,
+        expected: u64,
+        found: u64,
+    },
+    /// `prev_hash` does not match the previous record's hash.
+    BrokenLink {
+        file: PathBuf,
+        line: usize,
+        sequence: u64,
+    },
+    /// The record no longer hashes to its `hash`.
+    Tampered {
+        file: PathBuf,
+        line: usize,
+        sequence: u64,
+    },
+}
+
+impl fmt::Display for AuditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditError::Io(e) => write!(f, "{}", e),
+            AuditError::Malformed { file, line, reason } =>
+                write!(f, "{}:{}: malformed record: {}", file.display(), line, reason),
+            AuditError::Gap { file, line, expected, found } =>
+                write!(f, "{}:{}: expected record {} but found {}", file.display(), line, expected, found),
+            AuditError::BrokenLink { file, line, sequence } =>
+                write!(f, "{}:{}: record {} does not link to the previous record", file.display(), line, sequence),
+            AuditError::Tampered { file, line, sequence } =>
+                write!(f, "{}:{}: record {} does not match its hash", file.display(), line, sequence),
+        }
+    }
+}
+
+impl Error for AuditError {}
+
+impl From<io::Error> for AuditError {
+    fn from(e: io::Error) -> Self {
+        AuditError::Io(e)
+    }
+}
+
+/// What a successful verification covered. `last_hash` can be compared
+/// with a copy kept elsewhere to also detect a truncated tail.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainSummary {
+    pub files: usize,
+    pub records: u64,
+    pub last_hash: String,
+}
+
+/// Walks every audit file in `directory` and checks the hash chain.
+pub fn verify_chain(directory: &Path) -> Result<ChainSummary, AuditError> {
+    let files = audit_files(directory)?;
+    let mut summary = ChainSummary { files: files.len(), records: 0, last_hash: GENESIS_HASH.to_string() };
+
+    for (_, file) in files {
+        for (number, line) in BufReader::new(File::open(&file)?).lines().enumerate() {
+            let line = line?;
+            let line_number = number + 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let record: AuditRecord = serde_json::from_str(&line).map_err(|e| AuditError::Malformed {
+                file: file.clone(),
+                line: line_number,
+                reason: e.to_string(),
+            })?;
+            if record.sequence != summary.records + 1 {
+                return Err(AuditError::Gap {
+                    file,
+                    line: line_number,
+                    expected: summary.records + 1,
+                    found: record.sequence,
+                });
+            }
+            if record.prev_hash != summary.last_hash {
+                return Err(AuditError::BrokenLink { file, line: line_number, sequence: record.sequence });
+            }
+            if record.compute_hash() != record.hash {
+                return Err(AuditError::Tampered { file, line: line_number, sequence: record.sequence });
+            }
+
+            summary.records = record.sequence;
+            summary.last_hash = record.hash;
+        }
+    }
+
+    return Ok(summary);
+}
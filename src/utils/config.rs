@@ -1,36 +1,635 @@
-use serde::Deserialize;
-use std::fs;
+use serde::{ Deserialize, Serialize };
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::net::SocketAddr;
 use std::path::Path;
-use toml;
+use std::str::FromStr;
+use log::LevelFilter;
+use serde_json::Value;
+use ::config::{ Environment, File, FileFormat };
+use url::Url;
+use super::audit::DEFAULT_MAX_FILE_SIZE_MB;
+use super::keyring::ClusterKey;
+use super::telemetry::{ DEFAULT_OTLP_ENDPOINT, DEFAULT_SERVICE_NAME };
+use super::keyring::KeyRing;
+use crate::network::auth::DEFAULT_MAX_CLOCK_SKEW;
+use crate::protocol::handshake::DEFAULT_MAX_FRAME_SIZE;
+use crate::storage::changes::DEFAULT_RETAINED_CHANGES;
+use crate::transport::compression::{ DEFAULT_COMPRESSION_THRESHOLD, SUPPORTED_CODECS };
+
+/// Prefix of the environment variables overriding the config file, e.g.
+/// `ZENITH_STORAGE__PATH` for `storage.path`.
+pub const ENV_PREFIX: &str = "ZENITH";
+/// Separates nested keys in environment variable names.
+pub const ENV_SEPARATOR: &str = "__";
+pub const DEFAULT_LISTEN_ADDRESS: &str = "127.0.0.1:7400";
+/// Keys applied by a reload without a restart; a key covers everything
+/// under it.
+pub const RELOADABLE_KEYS: [&str; 6] = [
+    "logging",
+    "slow_query_log",
+    "management.pool",
+    "management.cluster_token",
+    "management.cluster_keys",
+    "management.active_key_id",
+];
+/// Shown instead of cluster tokens when the config is served.
+pub const REDACTED: &str = "<redacted>";
 
 #[allow(dead_code)]
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Management {
     pub url: String,
     pub addr: String,
     pub node_id: String,
+    /// Single shared token, kept for configurations without `cluster_keys`.
+    #[serde(default)]
     pub cluster_token: String,
+    /// Keys accepted during a rotation; see `KeyRing`.
+    #[serde(default)]
+    pub cluster_keys: Vec<ClusterKey>,
+    /// Key new logins are signed with, `default` when unset.
+    pub active_key_id: Option<String>,
+    /// Management endpoint serving the current key set.
+    pub keys_url: Option<String>,
+    /// How often `keys_url` is polled; 0 disables it. Keys edited in the
+    /// config file are applied by the config reloader.
+    #[serde(default)]
+    pub key_refresh_interval_secs: u64,
+    /// `host:port` of the management server's binary protocol endpoint.
+    #[serde(default)]
+    pub server_addr: String,
+    /// Tags the node registers with, e.g. `replica`.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub pool: PoolConfig,
+}
+
+/// Connections kept to the management server; see `MessageClient`.
+#[allow(dead_code)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct PoolConfig {
+    pub min_connections: usize,
+    pub max_connections: usize,
+    pub connect_timeout_secs: u64,
 }
 
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            min_connections: 1,
+            max_connections: 8,
+            connect_timeout_secs: 3,
+        }
+    }
+}
+
+/// The binary protocol listener other nodes and clients connect to.
 #[allow(dead_code)]
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ListenerSettings {
+    pub address: String,
+    /// How far a login timestamp may drift from the local clock.
+    pub max_clock_skew_secs: u64,
+    /// Accept logins from peers on the legacy protocol, which carry no nonce.
+    pub allow_legacy_login: bool,
+    pub max_frame_size: u32,
+    /// Codecs accepted from peers, in order of preference.
+    pub compression: Vec<String>,
+    pub compression_threshold: usize,
+}
+
+impl Default for ListenerSettings {
+    fn default() -> Self {
+        Self {
+            address: DEFAULT_LISTEN_ADDRESS.to_string(),
+            max_clock_skew_secs: DEFAULT_MAX_CLOCK_SKEW.as_secs(),
+            allow_legacy_login: false,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            compression: SUPPORTED_CODECS.iter()
+                .map(|codec| codec.to_string())
+                .collect(),
+            compression_threshold: DEFAULT_COMPRESSION_THRESHOLD,
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
     pub storage: StorageConfig,
     pub management: Management,
+    #[serde(default)]
+    pub listener: ListenerSettings,
+    #[serde(default)]
+    pub tls: TlsConfig,
+    #[serde(default)]
+    pub audit: AuditConfig,
+    #[serde(default)]
+    pub api: ApiConfig,
+    #[serde(default)]
+    pub admin: AdminConfig,
+    #[serde(default)]
+    pub grpc: GrpcConfig,
+    #[serde(default)]
+    pub cdc: CdcConfig,
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub reload: ReloadConfig,
+    #[serde(default)]
+    pub slow_query_log: SlowQueryConfig,
+    #[serde(default)]
+    pub query: QueryConfig,
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct StorageConfig {
     pub path: String,
+    /// Most the rows of all tables may take up in memory, as estimated.
     pub max_size_mb: u64,
+    /// How often tables are compacted in the background; 0 disables it.
+    #[serde(default)]
+    pub compaction_interval_secs: u64,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct TlsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// CA bundle used to verify the peer: the server for the dialer, client
+    /// certificates for the listener.
+    pub ca_path: Option<String>,
+    pub cert_path: Option<String>,
+    pub key_path: Option<String>,
+    /// Listener side: reject clients that do not present a certificate
+    /// signed by the CA (mutual TLS).
+    #[serde(default)]
+    pub require_client_cert: bool,
+    /// Dialer side: name expected in the server certificate.
+    pub server_name: Option<String>,
+    /// How often certificate files are checked for changes; 0 disables reload.
+    #[serde(default)]
+    pub reload_interval_secs: u64,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct AuditConfig {
+    pub enabled: bool,
+    /// Where audit files are written, `<storage.path>/audit` when unset.
+    pub directory: Option<String>,
+    /// Size at which the current file is closed and a new one started.
+    pub max_file_size_mb: u64,
+    /// Also record inserts, updates and deletes, not only DDL.
+    pub include_dml: bool,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            directory: None,
+            max_file_size_mb: DEFAULT_MAX_FILE_SIZE_MB,
+            include_dml: false,
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ApiConfig {
+    /// Serve the REST API; requests authenticate with HTTP basic auth.
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub address: String,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AdminConfig {
+    /// Serve `/healthz`, `/readyz`, `/metrics` and the `/admin` endpoints.
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub address: String,
+    /// Where `POST /admin/snapshot` writes to; `<storage.path>/snapshots` when unset.
+    pub snapshot_directory: Option<String>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct GrpcConfig {
+    /// Serve the `zenith.v1.ZenithStore` gRPC service from `proto/zenith.proto`.
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub address: String,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct CdcConfig {
+    /// Serve the change feed to WebSocket subscribers.
+    pub enabled: bool,
+    pub address: String,
+    /// Changes kept for subscribers resuming from an older position.
+    pub retained_changes: usize,
+}
+
+impl Default for CdcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: String::new(),
+            retained_changes: DEFAULT_RETAINED_CHANGES,
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    /// Export spans over OTLP; the trace context is propagated either way.
+    pub enabled: bool,
+    /// gRPC endpoint of the OTLP collector.
+    pub otlp_endpoint: String,
+    pub service_name: String,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            otlp_endpoint: DEFAULT_OTLP_ENDPOINT.to_string(),
+            service_name: DEFAULT_SERVICE_NAME.to_string(),
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// `off`, `error`, `warn`, `info`, `debug` or `trace`, for every module
+    /// without its own entry in `modules`.
+    pub level: String,
+    /// Levels by module path, e.g. `"zenith_store::network" = "debug"`.
+    pub modules: BTreeMap<String, String>,
+    /// `text`, or `json` for one object per line with the node id and the
+    /// connection and message ids of the record.
+    pub format: String,
+    /// Written besides stdout and rotated once it reaches `max_file_size_mb`.
+    pub file: Option<String>,
+    pub max_file_size_mb: u64,
+    /// Rotated files kept, as `<file>.0` to `<file>.<max_files - 1>`.
+    pub max_files: u32,
+    /// A log4rs file to use instead of the settings above.
+    pub config_file: Option<String>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            modules: BTreeMap::new(),
+            format: "text".to_string(),
+            file: None,
+            max_file_size_mb: 100,
+            max_files: 5,
+            config_file: None,
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl LoggingConfig {
+    pub fn level_filter(&self) -> Result<LevelFilter, String> {
+        return LevelFilter::from_str(&self.level).map_err(|_| format!("unknown log level {:?}", self.level));
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Err(e) = self.level_filter() {
+            problems.push(format!("logging.level: {}", e));
+        }
+        for (module, level) in &self.modules {
+            if LevelFilter::from_str(level).is_err() {
+                problems.push(format!("logging.modules.{}: unknown log level {:?}", module, level));
+            }
+        }
+        if self.format != "text" && self.format != "json" {
+            problems.push(format!("logging.format: {:?} is neither \"text\" nor \"json\"", self.format));
+        }
+        if self.file.as_ref().is_some_and(|file| !file.is_empty()) {
+            if self.max_file_size_mb == 0 {
+                problems.push("logging.max_file_size_mb: must be at least 1".to_string());
+            }
+            if self.max_files == 0 {
+                problems.push("logging.max_files: must be at least 1".to_string());
+            }
+        }
+        if let Some(path) = self.config_file.as_ref().filter(|path| !path.is_empty()) {
+            if !Path::new(path).is_file() {
+                problems.push(format!("logging.config_file: {} does not exist", path));
+            }
+        }
+        return problems;
+    }
+}
+
+/// How the config file is picked up again after it changes. SIGHUP always
+/// triggers a reload.
+#[allow(dead_code)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ReloadConfig {
+    /// How often the file is checked for changes; 0 only reloads on SIGHUP.
+    pub watch_interval_secs: u64,
+}
+
+impl Default for ReloadConfig {
+    fn default() -> Self {
+        Self { watch_interval_secs: 5 }
+    }
+}
+
+/// Statements that run longer than `threshold_ms` are logged and kept in a
+/// ring buffer of the last `capacity` entries.
+#[allow(dead_code)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SlowQueryConfig {
+    pub enabled: bool,
+    pub threshold_ms: u64,
+    pub capacity: usize,
+}
+
+impl Default for SlowQueryConfig {
+    fn default() -> Self {
+        Self { enabled: true, threshold_ms: 1000, capacity: 128 }
+    }
+}
+
+/// Memory a sort may hold before it writes rows to disk.
+#[allow(dead_code)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct QueryConfig {
+    pub work_memory_mb: u64,
+    /// Defaults to `spill` under `storage.path`.
+    pub spill_directory: Option<String>,
+}
+
+impl Default for QueryConfig {
+    fn default() -> Self {
+        Self { work_memory_mb: 64, spill_directory: None }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file could not be read or does not deserialize into `Config`.
+    Load(String),
+    /// Every value that failed validation, as `key: problem`.
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Load(reason) => write!(f, "cannot load configuration: {}", reason),
+            ConfigError::Invalid(problems) => {
+                write!(f, "invalid configuration:")?;
+                for problem in problems {
+                    write!(f, "\n  - {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+/// Values given on the command line; they win over the environment and
+/// the config file.
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    pub node_id: Option<String>,
+    pub data_dir: Option<String>,
+    pub listen_address: Option<String>,
+}
+
+fn check_address(problems: &mut Vec<String>, key: &str, address: &str) {
+    // Host names are allowed, so only the shape is checked here.
+    let valid = address.parse::<SocketAddr>().is_ok() ||
+        address.rsplit_once(':').is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+    if !valid {
+        problems.push(format!("{}: {:?} is not a host:port address", key, address));
+    }
+}
+
+fn check_url(problems: &mut Vec<String>, key: &str, url: &str) {
+    if let Err(e) = Url::parse(url) {
+        problems.push(format!("{}: {:?} is not a valid URL ({})", key, url, e));
+    }
+}
+
+fn check_codecs(problems: &mut Vec<String>, key: &str, codecs: &[String]) {
+    for codec in codecs.iter().filter(|codec| !SUPPORTED_CODECS.contains(&codec.as_str())) {
+        problems.push(format!("{}: unknown codec {:?}, expected one of {:?}", key, codec, SUPPORTED_CODECS));
+    }
 }
 
 #[allow(dead_code)]
 impl Config {
+    /// Reads `path` with `ZENITH_*` environment overrides applied, without
+    /// validating it.
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
-        let config_content = fs::read_to_string(path)?;
-        let config: Config = toml::from_str(&config_content)?;
+        return Ok(Self::layered(path.as_ref(), &Overrides::default())?);
+    }
+
+    /// Reads `path`, applies the environment and then `overrides`, and
+    /// checks the result.
+    pub fn load_with(path: &Path, overrides: &Overrides) -> Result<Self, ConfigError> {
+        let config = Self::layered(path, overrides)?;
+        config.validate()?;
         return Ok(config);
     }
+
+    fn layered(path: &Path, overrides: &Overrides) -> Result<Self, ConfigError> {
+        let load_error = |e: ::config::ConfigError| ConfigError::Load(format!("{}: {}", path.display(), e));
+        let environment = Environment::with_prefix(ENV_PREFIX)
+            .prefix_separator("_")
+            .separator(ENV_SEPARATOR)
+            .try_parsing(true)
+            .list_separator(",")
+            .with_list_parse_key("listener.compression");
+
+        return ::config::Config::builder()
+            .add_source(File::from(path).format(FileFormat::Toml))
+            .add_source(environment)
+            .set_override_option("management.node_id", overrides.node_id.clone())
+            .and_then(|builder| builder.set_override_option("storage.path", overrides.data_dir.clone()))
+            .and_then(|builder| builder.set_override_option("listener.address", overrides.listen_address.clone()))
+            .and_then(|builder| builder.build())
+            .and_then(|layers| layers.try_deserialize())
+            .map_err(load_error);
+    }
+
+    /// Collects every problem instead of stopping at the first one.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+
+        let management = &self.management;
+        if management.node_id.trim().is_empty() {
+            problems.push("management.node_id: must not be empty".to_string());
+        }
+        check_address(&mut problems, "management.server_addr", &management.server_addr);
+        if management.tags.is_empty() {
+            problems.push("management.tags: at least one tag is required".to_string());
+        }
+        if let Err(e) = KeyRing::from_management(management) {
+            problems.push(format!("management.cluster_keys: {}", e));
+        }
+        if let Some(url) = management.keys_url.as_ref().filter(|url| !url.is_empty()) {
+            check_url(&mut problems, "management.keys_url", url);
+        }
+        let pool = &management.pool;
+        if pool.max_connections == 0 {
+            problems.push("management.pool.max_connections: must be at least 1".to_string());
+        }
+        if pool.min_connections > pool.max_connections {
+            problems.push(
+                format!(
+                    "management.pool.min_connections: {} is more than max_connections ({})",
+                    pool.min_connections,
+                    pool.max_connections
+                )
+            );
+        }
+        if pool.connect_timeout_secs == 0 {
+            problems.push("management.pool.connect_timeout_secs: must be at least 1".to_string());
+        }
+
+        check_address(&mut problems, "listener.address", &self.listener.address);
+        if self.listener.max_frame_size == 0 {
+            problems.push("listener.max_frame_size: must be at least 1".to_string());
+        }
+        check_codecs(&mut problems, "listener.compression", &self.listener.compression);
+
+        if self.tls.enabled {
+            for (key, value) in [("tls.cert_path", &self.tls.cert_path), ("tls.key_path", &self.tls.key_path)] {
+                if value.as_ref().is_none_or(|path| path.is_empty()) {
+                    problems.push(format!("{}: required when tls.enabled is set", key));
+                }
+            }
+        }
+
+        if self.storage.path.trim().is_empty() {
+            problems.push("storage.path: must not be empty".to_string());
+        }
+        if self.storage.max_size_mb == 0 {
+            problems.push("storage.max_size_mb: must be at least 1".to_string());
+        }
+        if self.audit.enabled && self.audit.max_file_size_mb == 0 {
+            problems.push("audit.max_file_size_mb: must be at least 1".to_string());
+        }
+
+        let servers = [
+            ("api.address", self.api.enabled, &self.api.address),
+            ("admin.address", self.admin.enabled, &self.admin.address),
+            ("grpc.address", self.grpc.enabled, &self.grpc.address),
+            ("cdc.address", self.cdc.enabled, &self.cdc.address),
+        ];
+        for (key, enabled, address) in servers {
+            if enabled {
+                check_address(&mut problems, key, address);
+            }
+        }
+        problems.extend(self.logging.problems());
+        if self.query.work_memory_mb == 0 {
+            problems.push("query.work_memory_mb: must be at least 1".to_string());
+        }
+        if self.slow_query_log.enabled && self.slow_query_log.capacity == 0 {
+            problems.push("slow_query_log.capacity: must be at least 1".to_string());
+        }
+        if self.telemetry.enabled {
+            check_url(&mut problems, "telemetry.otlp_endpoint", &self.telemetry.otlp_endpoint);
+        }
+
+        if problems.is_empty() {
+            return Ok(());
+        }
+        return Err(ConfigError::Invalid(problems));
+    }
+
+    /// A copy safe to show to operators, with cluster tokens replaced.
+    pub fn redacted(&self) -> Config {
+        let mut config = self.clone();
+        if !config.management.cluster_token.is_empty() {
+            config.management.cluster_token = REDACTED.to_string();
+        }
+        for key in config.management.cluster_keys.iter_mut() {
+            key.token = REDACTED.to_string();
+        }
+        return config;
+    }
+
+    /// Dotted keys whose value differs between `self` and `other`.
+    pub fn changed_keys(&self, other: &Config) -> Vec<String> {
+        let mut keys = Vec::new();
+        let old = serde_json::to_value(self).unwrap_or(Value::Null);
+        let new = serde_json::to_value(other).unwrap_or(Value::Null);
+        diff_values("", &old, &new, &mut keys);
+        return keys;
+    }
+
+    /// `self` with the reloadable values of `other` applied; everything
+    /// else keeps its current value until the node restarts.
+    pub fn with_reloadable(&self, other: &Config) -> Config {
+        let mut config = self.clone();
+        config.logging = other.logging.clone();
+        config.slow_query_log = other.slow_query_log.clone();
+        config.management.pool = other.management.pool.clone();
+        config.management.cluster_token = other.management.cluster_token.clone();
+        config.management.cluster_keys = other.management.cluster_keys.clone();
+        config.management.active_key_id = other.management.active_key_id.clone();
+        return config;
+    }
+}
+
+pub fn is_reloadable(key: &str) -> bool {
+    return RELOADABLE_KEYS.iter().any(|reloadable| {
+        key == *reloadable || key.strip_prefix(reloadable).is_some_and(|rest| rest.starts_with('.'))
+    });
+}
+
+fn diff_values(path: &str, old: &Value, new: &Value, keys: &mut Vec<String>) {
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            let mut fields: Vec<&String> = old.keys().chain(new.keys()).collect();
+            fields.sort();
+            fields.dedup();
+            for field in fields {
+                let child = if path.is_empty() { field.clone() } else { format!("{}.{}", path, field) };
+                diff_values(&child, old.get(field).unwrap_or(&Value::Null), new.get(field).unwrap_or(&Value::Null), keys);
+            }
+        }
+        _ if old != new => keys.push(path.to_string()),
+        _ => {}
+    }
 }
@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{ Arc, RwLock };
+use std::time::Duration;
+use log::{ info, warn };
+use serde::{ Deserialize, Serialize };
+use tokio::sync::watch;
+use super::config::Management;
+
+/// Key id assumed for logins that do not carry one and for the single
+/// `cluster_token` of older configurations.
+pub const DEFAULT_KEY_ID: &str = "default";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ClusterKey {
+    pub id: String,
+    pub token: String,
+}
+
+/// Body returned by the management server's key endpoint.
+#[derive(Debug, Deserialize)]
+pub struct KeySetResponse {
+    pub active_key_id: String,
+    pub keys: Vec<ClusterKey>,
+}
+
+#[derive(PartialEq, Eq)]
+struct KeySet {
+    active: String,
+    keys: HashMap<String, String>,
+}
+
+/// Cluster tokens by key id. New logins are signed with the active key, and
+/// every key in the ring is accepted, so a token can be rotated by adding
+/// the new key everywhere, switching the active id, then dropping the old one.
+#[derive(Clone)]
+pub struct KeyRing {
+    keys: Arc<RwLock<KeySet>>,
+    changes: Arc<watch::Sender<u64>>,
+}
+
+impl fmt::Debug for KeyRing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let keys = self.keys.read().unwrap();
+        f.debug_struct("KeyRing")
+            .field("active", &keys.active)
+            .field("key_ids", &keys.keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+fn build_key_set(active: &str, keys: Vec<ClusterKey>) -> Result<KeySet, String> {
+    let keys: HashMap<String, String> = keys
+        .into_iter()
+        .filter(|key| !key.token.is_empty())
+        .map(|key| (key.id, key.token))
+        .collect();
+
+    if !keys.contains_key(active) {
+        return Err(format!("active key {} is not in the key ring", active));
+    }
+
+    return Ok(KeySet { active: active.to_string(), keys });
+}
+
+#[allow(dead_code)]
+impl KeyRing {
+    pub fn new(active: &str, keys: Vec<ClusterKey>) -> Result<Self, String> {
+        let (changes, _) = watch::channel(0);
+        return Ok(Self {
+            keys: Arc::new(RwLock::new(build_key_set(active, keys)?)),
+            changes: Arc::new(changes),
+        });
+    }
+
+    /// A ring holding one token under `DEFAULT_KEY_ID`.
+    pub fn single(token: &str) -> Result<Self, String> {
+        return Self::new(
+            DEFAULT_KEY_ID,
+            vec![ClusterKey { id: DEFAULT_KEY_ID.to_string(), token: token.to_string() }]
+        );
+    }
+
+    pub fn from_management(management: &Management) -> Result<Self, String> {
+        let (active, keys) = management_keys(management);
+        return Self::new(&active, keys);
+    }
+
+    /// Replaces the ring with the keys configured under `[management]`.
+    pub fn update_from_management(&self, management: &Management) -> Result<bool, String> {
+        let (active, keys) = management_keys(management);
+        return self.update(&active, keys);
+    }
+
+    /// Id and token new logins are signed with.
+    pub fn active(&self) -> (String, String) {
+        let keys = self.keys.read().unwrap();
+        return (keys.active.clone(), keys.keys[&keys.active].clone());
+    }
+
+    pub fn get(&self, key_id: &str) -> Option<String> {
+        let key_id = if key_id.is_empty() { DEFAULT_KEY_ID } else { key_id };
+        return self.keys.read().unwrap().keys.get(key_id).cloned();
+    }
+
+    /// Replaces the keys. Subscribers are only notified when something
+    /// actually changed.
+    pub fn update(&self, active: &str, keys: Vec<ClusterKey>) -> Result<bool, String> {
+        let key_set = build_key_set(active, keys)?;
+        let mut current = self.keys.write().unwrap();
+        if *current == key_set {
+            return Ok(false);
+        }
+        *current = key_set;
+        drop(current);
+
+        info!("Cluster key ring updated, active key is now {}", active);
+        self.changes.send_modify(|version| {
+            *version += 1;
+        });
+        return Ok(true);
+    }
+
+    /// Fires after every change to the ring.
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        return self.changes.subscribe();
+    }
+
+    /// Polls the management server for the current key set.
+    pub fn watch_management(&self, url: String, interval: Duration) {
+        let ring = self.clone();
+        tokio::spawn(async move {
+            let client = reqwest::Client::new();
+            loop {
+                tokio::time::sleep(interval).await;
+                if let Err(e) = ring.fetch(&client, &url).await {
+                    warn!("Failed to fetch cluster keys from {}: {}", url, e);
+                }
+            }
+        });
+    }
+
+    async fn fetch(&self, client: &reqwest::Client, url: &str) -> Result<bool, String> {
+        let (_, token) = self.active();
+        let response = client
+            .get(url)
+            .bearer_auth(token)
+            .send().await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| e.to_string())?;
+        let key_set: KeySetResponse = response.json().await.map_err(|e| e.to_string())?;
+        return self.update(&key_set.active_key_id, key_set.keys);
+    }
+}
+
+/// Keys configured under `[management]`; a bare `cluster_token` becomes the
+/// `DEFAULT_KEY_ID` key.
+fn management_keys(management: &Management) -> (String, Vec<ClusterKey>) {
+    let mut keys = management.cluster_keys.clone();
+    if !management.cluster_token.is_empty() && !keys.iter().any(|key| key.id == DEFAULT_KEY_ID) {
+        keys.push(ClusterKey {
+            id: DEFAULT_KEY_ID.to_string(),
+            token: management.cluster_token.clone(),
+        });
+    }
+
+    let active = match &management.active_key_id {
+        Some(active) if !active.is_empty() => active.clone(),
+        _ => DEFAULT_KEY_ID.to_string(),
+    };
+
+    return (active, keys);
+}
@@ -1,10 +1,172 @@
+use std::path::Path;
+use std::sync::OnceLock;
+use chrono::{ SecondsFormat, Utc };
+use log::kv::{ Error as KvError, Key, Value, VisitSource };
+use log::{ LevelFilter, Record };
+use log4rs::append::console::ConsoleAppender;
+use log4rs::append::rolling_file::policy::compound::roll::fixed_window::FixedWindowRoller;
+use log4rs::append::rolling_file::policy::compound::trigger::size::SizeTrigger;
+use log4rs::append::rolling_file::policy::compound::CompoundPolicy;
+use log4rs::append::rolling_file::RollingFileAppender;
+use log4rs::config::{ Appender, Deserialize, Deserializers, Logger, Root };
+use log4rs::encode::{ Encode, Write };
+use log4rs::Handle;
+use serde_json::{ Map, Value as JsonValue };
+use super::config::LoggingConfig;
 
-use log::LevelFilter;
-use env_logger::Builder;
+/// Encoder kinds to use in `log4rs.yaml` for lines with node fields.
+pub const JSON_ENCODER_KIND: &str = "zenith_json";
+pub const TEXT_ENCODER_KIND: &str = "zenith_text";
+const CONSOLE_APPENDER: &str = "stdout";
+const FILE_APPENDER: &str = "file";
+const BYTES_PER_MB: u64 = 1024 * 1024;
 
-#[allow(dead_code)]
-pub fn init_logger() {
-    Builder::new()
-        .filter(None, LevelFilter::Info)
-        .init();
+static NODE_ID: OnceLock<String> = OnceLock::new();
+static HANDLE: OnceLock<Handle> = OnceLock::new();
+
+/// Node id written on every line. Set once at startup.
+pub fn set_node_id(node_id: &str) {
+    let _ = NODE_ID.set(node_id.to_string());
+}
+
+/// Writes each record on one line with its time, level, target, message,
+/// the node id and the record's key-values, e.g. `connection_id` and
+/// `message_id` given as `info!(connection_id = id; "...")`. Lines are JSON
+/// objects, or `key=value` pairs after the message in text form.
+#[derive(Debug, Default)]
+pub struct LineEncoder {
+    json: bool,
+}
+
+struct Fields<'a>(&'a mut Map<String, JsonValue>);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), KvError> {
+        let value = if let Some(number) = value.to_u64() {
+            JsonValue::from(number)
+        } else if let Some(number) = value.to_i64() {
+            JsonValue::from(number)
+        } else if let Some(number) = value.to_f64() {
+            JsonValue::from(number)
+        } else if let Some(flag) = value.to_bool() {
+            JsonValue::from(flag)
+        } else {
+            JsonValue::from(value.to_string())
+        };
+        self.0.insert(key.to_string(), value);
+        return Ok(());
+    }
+}
+
+impl Encode for LineEncoder {
+    fn encode(&self, w: &mut dyn Write, record: &Record) -> anyhow::Result<()> {
+        let time = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+        let mut fields = Map::new();
+        if let Some(node_id) = NODE_ID.get() {
+            fields.insert("node_id".to_string(), JsonValue::from(node_id.as_str()));
+        }
+        record.key_values().visit(&mut Fields(&mut fields))?;
+
+        if self.json {
+            let mut line = Map::new();
+            line.insert("time".to_string(), JsonValue::from(time));
+            line.insert("level".to_string(), JsonValue::from(record.level().as_str()));
+            line.insert("target".to_string(), JsonValue::from(record.target()));
+            line.insert("message".to_string(), JsonValue::from(record.args().to_string()));
+            line.extend(fields);
+            serde_json::to_writer(&mut *w, &line)?;
+        } else {
+            write!(w, "{} {:<5} {} - {}", time, record.level(), record.target(), record.args())?;
+            for (key, value) in fields {
+                match value {
+                    // Unquoted, like the rest of the line.
+                    JsonValue::String(value) => write!(w, " {}={}", key, value)?,
+                    value => write!(w, " {}={}", key, value)?,
+                }
+            }
+        }
+        w.write_all(b"\n")?;
+        return Ok(());
+    }
+}
+
+struct LineEncoderDeserializer {
+    json: bool,
+}
+
+impl Deserialize for LineEncoderDeserializer {
+    type Trait = dyn Encode;
+    type Config = serde_json::Value;
+
+    fn deserialize(&self, _config: Self::Config, _: &Deserializers) -> anyhow::Result<Box<dyn Encode>> {
+        return Ok(Box::new(LineEncoder { json: self.json }));
+    }
+}
+
+/// The stock log4rs components plus `JSON_ENCODER_KIND` and
+/// `TEXT_ENCODER_KIND`.
+pub fn deserializers() -> Deserializers {
+    let mut deserializers = Deserializers::default();
+    deserializers.insert(JSON_ENCODER_KIND, LineEncoderDeserializer { json: true });
+    deserializers.insert(TEXT_ENCODER_KIND, LineEncoderDeserializer { json: false });
+    return deserializers;
+}
+
+fn encoder(config: &LoggingConfig) -> Box<dyn Encode> {
+    return Box::new(LineEncoder { json: config.format == "json" });
+}
+
+fn level(value: &str) -> Result<LevelFilter, String> {
+    return value.parse().map_err(|_| format!("unknown log level {:?}", value));
+}
+
+/// The log4rs config described by `[logging]`, or read from its
+/// `config_file` when one is set.
+pub fn build(config: &LoggingConfig) -> Result<log4rs::Config, String> {
+    if let Some(path) = config.config_file.as_ref().filter(|path| !path.is_empty()) {
+        return log4rs::config
+            ::load_config_file(path, deserializers())
+            .map_err(|e| format!("cannot load {}: {}", path, e));
+    }
+
+    let mut builder = log4rs::Config::builder().appender(
+        Appender::builder().build(CONSOLE_APPENDER, Box::new(ConsoleAppender::builder().encoder(encoder(config)).build()))
+    );
+    let mut root = Root::builder().appender(CONSOLE_APPENDER);
+
+    if let Some(file) = config.file.as_ref().filter(|file| !file.is_empty()) {
+        let rolled = format!("{}.{{}}", file);
+        let roller = FixedWindowRoller::builder()
+            .build(&rolled, config.max_files)
+            .map_err(|e| format!("invalid rotation for {}: {}", file, e))?;
+        let policy = CompoundPolicy::new(
+            Box::new(SizeTrigger::new(config.max_file_size_mb * BYTES_PER_MB)),
+            Box::new(roller)
+        );
+        let appender = RollingFileAppender::builder()
+            .encoder(encoder(config))
+            .build(Path::new(file), Box::new(policy))
+            .map_err(|e| format!("cannot open {}: {}", file, e))?;
+        builder = builder.appender(Appender::builder().build(FILE_APPENDER, Box::new(appender)));
+        root = root.appender(FILE_APPENDER);
+    }
+
+    for (module, module_level) in &config.modules {
+        builder = builder.logger(Logger::builder().build(module, level(module_level)?));
+    }
+
+    return builder.build(root.build(level(&config.level)?)).map_err(|e| e.to_string());
+}
+
+/// Installs the process-wide logger, or swaps its config when it is already
+/// installed.
+pub fn init(config: &LoggingConfig) -> Result<(), String> {
+    let built = build(config)?;
+    if let Some(handle) = HANDLE.get() {
+        handle.set_config(built);
+        return Ok(());
+    }
+    let handle = log4rs::init_config(built).map_err(|e| e.to_string())?;
+    let _ = HANDLE.set(handle);
+    return Ok(());
 }
@@ -0,0 +1,150 @@
+use lazy_static::lazy_static;
+use prometheus::{
+    Encoder,
+    HistogramOpts,
+    HistogramVec,
+    IntCounter,
+    IntCounterVec,
+    IntGauge,
+    IntGaugeVec,
+    Opts,
+    Registry,
+    TextEncoder,
+};
+use std::io;
+use std::time::Instant;
+use crate::storage::StorageEngine;
+
+/// Content type of the text exposition format served on `/metrics`.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+lazy_static! {
+    static ref METRICS: Metrics = Metrics::new();
+}
+
+/// The node-wide registry; every component records into it.
+pub fn metrics() -> &'static Metrics {
+    return &METRICS;
+}
+
+/// Prometheus metrics of the node. Transport and pool metrics are recorded
+/// where frames and connections are handled, statement metrics by the
+/// executor; per-table row counts are read from storage on each scrape.
+pub struct Metrics {
+    registry: Registry,
+    /// Statements run, by `MessageType` name and `ok`/`denied`/`failed`.
+    pub requests: IntCounterVec,
+    pub request_duration: HistogramVec,
+    pub bytes_in: IntCounter,
+    pub bytes_out: IntCounter,
+    /// Requests waiting for a response in a connection's `response_map`.
+    pub in_flight: IntGauge,
+    pub pool_connections: IntGauge,
+    pub pool_loans: IntGauge,
+    pub reconnects: IntCounter,
+    /// Failed logins, by `node`, `user` or `http`.
+    pub auth_failures: IntCounterVec,
+    pub compactions: IntCounter,
+    pub compaction_slots_released: IntCounter,
+    /// Time spent flushing files to disk, by `audit` or `snapshot`. There is
+    /// no write-ahead log; these are the only files the node syncs.
+    pub fsync_duration: HistogramVec,
+    /// Cache lookups, by cache name and `hit`/`miss`. `credentials` counts
+    /// passwords found in the catalog's verified password cache.
+    pub cache_lookups: IntCounterVec,
+    pub table_rows: IntGaugeVec,
+}
+
+#[allow(dead_code)]
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("zenith".to_string()), None).unwrap();
+        let metrics = Self {
+            requests: IntCounterVec::new(
+                Opts::new("requests_total", "Statements executed"),
+                &["message_type", "outcome"]
+            ).unwrap(),
+            request_duration: HistogramVec::new(
+                HistogramOpts::new("request_duration_seconds", "Statement execution time"),
+                &["message_type"]
+            ).unwrap(),
+            bytes_in: IntCounter::new("bytes_in_total", "Frame bytes read").unwrap(),
+            bytes_out: IntCounter::new("bytes_out_total", "Frame bytes written").unwrap(),
+            in_flight: IntGauge::new("in_flight_requests", "Requests awaiting a response").unwrap(),
+            pool_connections: IntGauge::new("pool_connections", "Pooled management connections").unwrap(),
+            pool_loans: IntGauge::new("pool_loans", "Pooled connections currently lent out").unwrap(),
+            reconnects: IntCounter::new("reconnects_total", "Management connections replaced after a failure").unwrap(),
+            auth_failures: IntCounterVec::new(
+                Opts::new("auth_failures_total", "Failed logins"),
+                &["kind"]
+            ).unwrap(),
+            compactions: IntCounter::new("compactions_total", "Compactions run").unwrap(),
+            compaction_slots_released: IntCounter::new(
+                "compaction_slots_released_total",
+                "Row slots released by compaction"
+            ).unwrap(),
+            fsync_duration: HistogramVec::new(
+                HistogramOpts::new("fsync_duration_seconds", "File sync time").buckets(
+                    prometheus::exponential_buckets(0.0001, 2.0, 14).unwrap()
+                ),
+                &["file"]
+            ).unwrap(),
+            cache_lookups: IntCounterVec::new(
+                Opts::new("cache_lookups_total", "Cache lookups"),
+                &["cache", "result"]
+            ).unwrap(),
+            table_rows: IntGaugeVec::new(Opts::new("table_rows", "Rows per table"), &["database", "table"]).unwrap(),
+            registry,
+        };
+
+        let collectors: Vec<Box<dyn prometheus::core::Collector>> = vec![
+            Box::new(metrics.requests.clone()),
+            Box::new(metrics.request_duration.clone()),
+            Box::new(metrics.bytes_in.clone()),
+            Box::new(metrics.bytes_out.clone()),
+            Box::new(metrics.in_flight.clone()),
+            Box::new(metrics.pool_connections.clone()),
+            Box::new(metrics.pool_loans.clone()),
+            Box::new(metrics.reconnects.clone()),
+            Box::new(metrics.auth_failures.clone()),
+            Box::new(metrics.compactions.clone()),
+            Box::new(metrics.compaction_slots_released.clone()),
+            Box::new(metrics.fsync_duration.clone()),
+            Box::new(metrics.cache_lookups.clone()),
+            Box::new(metrics.table_rows.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).unwrap();
+        }
+        return metrics;
+    }
+
+    pub fn record_cache(&self, cache: &str, hit: bool) {
+        self.cache_lookups.with_label_values(&[cache, if hit { "hit" } else { "miss" }]).inc();
+    }
+
+    /// Runs `sync`, a flush of one of the node's files, and records how long
+    /// it took under `file`.
+    pub fn time_fsync(&self, file: &str, sync: impl FnOnce() -> io::Result<()>) -> io::Result<()> {
+        let started = Instant::now();
+        let result = sync();
+        self.fsync_duration.with_label_values(&[file]).observe(started.elapsed().as_secs_f64());
+        return result;
+    }
+
+    /// Every metric in the text exposition format, with the row counts of
+    /// `engine` refreshed first.
+    pub fn render(&self, engine: Option<&StorageEngine>) -> String {
+        if let Some(engine) = engine {
+            // Reset so dropped tables disappear from the output.
+            self.table_rows.reset();
+            for (database, table, rows) in engine.row_counts() {
+                self.table_rows.with_label_values(&[&database, &table]).set(rows as i64);
+            }
+        }
+
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer).unwrap();
+        return String::from_utf8(buffer).unwrap();
+    }
+}
@@ -1,4 +1,13 @@
+pub mod audit;
+pub use audit::{ AuditEvent, AuditLog };
 pub mod config;
+pub mod keyring;
+pub use keyring::KeyRing;
 pub mod logger;
+pub mod metrics;
+pub use metrics::metrics;
+pub mod reload;
+pub use reload::ConfigReloader;
 pub mod secure;
-pub use secure::generate_hash;
\ No newline at end of file
+pub mod telemetry;
+pub use secure::{ generate_hash, generate_legacy_hash };
\ No newline at end of file
@@ -0,0 +1,145 @@
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::sync::{ Arc, Mutex, RwLock };
+use std::time::{ Duration, SystemTime };
+use log::{ info, warn };
+use serde::Serialize;
+use super::config::{ is_reloadable, Config, ConfigError, Overrides };
+use super::logger;
+
+/// Called with the new active config after each reload that changed a
+/// reloadable value.
+pub type ReloadHook = Box<dyn Fn(&Config) + Send + Sync>;
+
+/// What a reload did.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReloadReport {
+    /// Reloadable keys that changed and are now live.
+    pub applied: Vec<String>,
+    /// Keys that differ from the running config but need a restart.
+    pub pending_restart: Vec<String>,
+}
+
+/// Owns the active config of the node and applies the reloadable parts of
+/// `config.toml` when it changes or on SIGHUP. A file that does not load or
+/// validate is rejected as a whole and the active config is kept.
+pub struct ConfigReloader {
+    path: PathBuf,
+    overrides: Overrides,
+    active: RwLock<Arc<Config>>,
+    pending_restart: RwLock<Vec<String>>,
+    hooks: RwLock<Vec<ReloadHook>>,
+    /// Serializes reloads from the watcher, signals and the admin endpoint.
+    reloading: Mutex<()>,
+}
+
+#[allow(dead_code)]
+impl ConfigReloader {
+    /// `config` must have been loaded from `path` with `overrides`, which
+    /// are applied again on every reload.
+    pub fn new(path: impl Into<PathBuf>, overrides: Overrides, config: Config) -> Self {
+        return Self {
+            path: path.into(),
+            overrides,
+            active: RwLock::new(Arc::new(config)),
+            pending_restart: RwLock::new(Vec::new()),
+            hooks: RwLock::new(Vec::new()),
+            reloading: Mutex::new(()),
+        };
+    }
+
+    pub fn path(&self) -> &Path {
+        return &self.path;
+    }
+
+    pub fn active(&self) -> Arc<Config> {
+        return self.active.read().unwrap().clone();
+    }
+
+    /// Changes found by the last reload that wait for a restart.
+    pub fn pending_restart(&self) -> Vec<String> {
+        return self.pending_restart.read().unwrap().clone();
+    }
+
+    pub fn on_reload(&self, hook: ReloadHook) {
+        self.hooks.write().unwrap().push(hook);
+    }
+
+    /// Reads the file again and applies what can be applied live.
+    pub fn reload(&self) -> Result<ReloadReport, ConfigError> {
+        let _guard = self.reloading.lock().unwrap();
+        let loaded = Config::load_with(&self.path, &self.overrides)?;
+        let active = self.active();
+
+        let (applied, pending_restart): (Vec<String>, Vec<String>) = active
+            .changed_keys(&loaded)
+            .into_iter()
+            .partition(|key| is_reloadable(key));
+        if !pending_restart.is_empty() {
+            warn!("{} changed in {} and will apply after a restart", pending_restart.join(", "), self.path.display());
+        }
+        *self.pending_restart.write().unwrap() = pending_restart.clone();
+
+        if !applied.is_empty() {
+            let next = Arc::new(active.with_reloadable(&loaded));
+            if let Err(e) = logger::init(&next.logging) {
+                warn!("Keeping the current logging setup: {}", e);
+            }
+            for hook in self.hooks.read().unwrap().iter() {
+                hook(&next);
+            }
+            *self.active.write().unwrap() = next;
+            info!("Reloaded {} from {}", applied.join(", "), self.path.display());
+        }
+
+        return Ok(ReloadReport { applied, pending_restart });
+    }
+
+    fn reload_or_warn(&self) {
+        if let Err(e) = self.reload() {
+            warn!("Keeping the current configuration, reload failed: {}", e);
+        }
+    }
+
+    /// Reloads whenever the modification time of the file changes.
+    pub fn watch(self: &Arc<Self>, interval: Duration) {
+        let modified = |path: &Path| -> Option<SystemTime> {
+            fs::metadata(path).and_then(|m| m.modified()).ok()
+        };
+        // Taken before the task starts so an edit made right away is seen.
+        let mut seen = modified(&self.path);
+        let reloader = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let current = modified(&reloader.path);
+                if current == seen {
+                    continue;
+                }
+                seen = current;
+                reloader.reload_or_warn();
+            }
+        });
+    }
+
+    /// Reloads on SIGHUP.
+    #[cfg(unix)]
+    pub fn watch_signals(self: &Arc<Self>) -> std::io::Result<()> {
+        use tokio::signal::unix::{ signal, SignalKind };
+
+        let mut hangups = signal(SignalKind::hangup())?;
+        let reloader = self.clone();
+        tokio::spawn(async move {
+            while hangups.recv().await.is_some() {
+                info!("SIGHUP received, reloading {}", reloader.path.display());
+                reloader.reload_or_warn();
+            }
+        });
+        return Ok(());
+    }
+
+    #[cfg(not(unix))]
+    pub fn watch_signals(self: &Arc<Self>) -> std::io::Result<()> {
+        return Ok(());
+    }
+}
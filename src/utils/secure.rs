@@ -2,7 +2,25 @@ use hmac::{ Hmac, Mac };
 use sha2::Sha256;
 use hex;
 
-pub fn generate_hash(
+/// HMAC-SHA256 over `fields`. Every field is prefixed with its length so that
+/// no two different field lists produce the same signed bytes.
+pub fn generate_hash(token: &str, fields: &[&str]) -> String {
+    let mut mac = Hmac::<Sha256>
+        ::new_from_slice(token.as_bytes())
+        .expect("HMAC can take key of any size");
+
+    for field in fields {
+        mac.update(&(field.len() as u64).to_be_bytes());
+        mac.update(field.as_bytes());
+    }
+
+    return hex::encode(mac.finalize().into_bytes());
+}
+
+/// The signature logins carried before the handshake: one HMAC-SHA256 over
+/// the timestamp, node id, replica flag and tags joined with `|`. Only used
+/// with peers on the legacy protocol.
+pub fn generate_legacy_hash(
     token: &str,
     timestamp: u64,
     node_id: &str,
@@ -12,9 +30,8 @@ pub fn generate_hash(
     let mut mac = Hmac::<Sha256>
         ::new_from_slice(token.as_bytes())
         .expect("HMAC can take key of any size");
- 
-    let data = format!("{}|{}|{}|{}", timestamp, node_id, is_replica, tags.join(","));
 
+    let data = format!("{}|{}|{}|{}", timestamp, node_id, is_replica, tags.join(","));
     mac.update(data.as_bytes());
 
     return hex::encode(mac.finalize().into_bytes());
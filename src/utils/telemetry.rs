@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::{ TraceContextExt, TracerProvider as _ };
+use opentelemetry::{ global, KeyValue };
+use opentelemetry_otlp::{ SpanExporter, WithExportConfig };
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{ Tracer, TracerProvider };
+use opentelemetry_sdk::{ runtime, Resource };
+use tracing::{ Span, Subscriber };
+use tracing_opentelemetry::{ OpenTelemetryLayer, OpenTelemetrySpanExt };
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Registry;
+use super::config::TelemetryConfig;
+
+pub const DEFAULT_OTLP_ENDPOINT: &str = "http://127.0.0.1:4317";
+pub const DEFAULT_SERVICE_NAME: &str = "zenith-store";
+/// Name the W3C trace context header goes by.
+const TRACEPARENT: &str = "traceparent";
+
+/// The W3C `traceparent` of `span`, or `None` when it is not being traced.
+pub fn traceparent(span: &Span) -> Option<String> {
+    let context = span.context();
+    if !context.span().span_context().is_valid() {
+        return None;
+    }
+    let mut carrier = HashMap::new();
+    TraceContextPropagator::new().inject_context(&context, &mut carrier);
+    return carrier.remove(TRACEPARENT);
+}
+
+/// Makes `span` a child of the remote span `traceparent` was taken from.
+/// A malformed value is ignored and `span` stays a root.
+pub fn set_remote_parent(span: &Span, traceparent: &str) {
+    let carrier = HashMap::from([(TRACEPARENT.to_string(), traceparent.to_string())]);
+    let context = TraceContextPropagator::new().extract(&carrier);
+    if context.span().span_context().is_valid() {
+        span.set_parent(context);
+    }
+}
+
+/// Layer sending `tracing` spans to `provider`.
+pub fn layer<S>(provider: &TracerProvider) -> OpenTelemetryLayer<S, Tracer>
+    where S: Subscriber + for<'a> LookupSpan<'a>
+{
+    return tracing_opentelemetry::layer().with_tracer(provider.tracer(DEFAULT_SERVICE_NAME));
+}
+
+/// Provider batching spans to the OTLP collector at `config.otlp_endpoint`.
+/// Must be called from within the tokio runtime.
+pub fn otlp_provider(config: &TelemetryConfig) -> Result<TracerProvider, Box<dyn std::error::Error>> {
+    let exporter = SpanExporter::builder().with_tonic().with_endpoint(&config.otlp_endpoint).build()?;
+    let resource = Resource::new(vec![KeyValue::new("service.name", config.service_name.clone())]);
+    return Ok(
+        TracerProvider::builder().with_batch_exporter(exporter, runtime::Tokio).with_resource(resource).build()
+    );
+}
+
+/// Sets up OTLP export as configured under `[telemetry]` and makes it the
+/// process-wide subscriber. Returns the provider so it can be shut down,
+/// flushing pending spans, or `None` when disabled.
+pub fn init(config: &TelemetryConfig) -> Result<Option<TracerProvider>, Box<dyn std::error::Error>> {
+    if !config.enabled {
+        // Without a subscriber every span would be mirrored into the log.
+        let _ = tracing::subscriber::set_global_default(Registry::default());
+        return Ok(None);
+    }
+    let provider = otlp_provider(config)?;
+    tracing::subscriber::set_global_default(Registry::default().with(layer(&provider)))?;
+    global::set_tracer_provider(provider.clone());
+    return Ok(Some(provider));
+}
@@ -0,0 +1,204 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use reqwest::StatusCode;
+use serde_json::Value;
+use tokio::net::{ TcpListener, TcpStream };
+use zenith_store::api::admin::{ self, AdminState, NodeStatus };
+use zenith_store::api::ServerListener;
+use zenith_store::managment::{ MessageClient, MessageConfig };
+use zenith_store::network::auth::Principal;
+use zenith_store::network::{ ListenerConfig, NodeListener };
+use zenith_store::protocol::MessageType;
+use zenith_store::statement::{ QueryStatement, SelectStatement };
+use zenith_store::storage::{ ExecutionContext, Executor, StorageEngine };
+use zenith_store::transport::Message;
+use zenith_store::utils::KeyRing;
+
+const TOKEN: &str = "test-cluster-token";
+
+fn query(executor: &Executor, sql: &str) {
+    let stmt = QueryStatement::new(sql.to_string()).unwrap();
+    let ctx = ExecutionContext { principal: Principal::Node("node-1".to_string()), database: "default".to_string() };
+    executor.execute(&ctx, &Message::new(MessageType::Query, &stmt)).unwrap();
+}
+
+/// A node with its admin endpoints, a binary listener and a management
+/// client logged in to it. Storage starts out not open.
+struct Node {
+    url: String,
+    client: MessageClient,
+    listener_address: String,
+    status: Arc<NodeStatus>,
+    snapshots: PathBuf,
+    http: reqwest::Client,
+}
+
+impl Node {
+    async fn start() -> Self {
+        let executor = Arc::new(Executor::new(Arc::new(StorageEngine::new())));
+        query(
+            &executor,
+            "CREATE TABLE notes (id int PRIMARY KEY, note text);
+             INSERT INTO notes (id, note) VALUES (1, 'a'), (2, 'b'), (3, 'c');
+             CREATE USER root WITH PASSWORD 'secret123' SUPERUSER;
+             CREATE USER bob WITH PASSWORD 'secret123'"
+        );
+
+        let config = ListenerConfig::new("127.0.0.1:0".to_string(), "node-0".to_string(), KeyRing::single(TOKEN).unwrap());
+        let listener = NodeListener::bind(config, executor.clone()).await.unwrap();
+        let listener_address = listener.local_addr().unwrap().to_string();
+        let (active_connections, draining) = (listener.active_connections(), listener.draining());
+        tokio::spawn(listener.serve());
+        let client = MessageClient::new(MessageConfig {
+            server_addr: listener_address.clone(),
+            keys: KeyRing::single(TOKEN).unwrap(),
+            node_id: "node_1".to_string(),
+            address: "".to_string(),
+            tags: vec!["replica".to_string()],
+            min_conn: 1,
+            max_conn: 1,
+            timeout: Duration::from_secs(1),
+            max_frame_size: 1024 * 1024,
+            compression: Vec::new(),
+            compression_threshold: 1024,
+            tls: None,
+        }).await.unwrap();
+
+        let status = Arc::new(NodeStatus::new());
+        let snapshots = std::env::temp_dir().join(format!("zenith-snapshots-{}", uuid::Uuid::new_v4()));
+        let state = AdminState::new(executor, status.clone(), snapshots.clone())
+            .with_listener(active_connections, draining)
+            .with_client(client.clone());
+        let http = ServerListener::plain(TcpListener::bind("127.0.0.1:0").await.unwrap()).unwrap();
+        let url = format!("http://{}", http.local_addr());
+        tokio::spawn(admin::serve(http, state));
+
+        Self { url, client, listener_address, status, snapshots, http: reqwest::Client::new() }
+    }
+
+    async fn get(&self, path: &str) -> (StatusCode, Value) {
+        let response = self.http.get(format!("{}{}", self.url, path)).send().await.unwrap();
+        (response.status(), response.json().await.unwrap())
+    }
+
+    async fn post(&self, path: &str, user: Option<&str>) -> (StatusCode, Value) {
+        let mut request = self.http.post(format!("{}{}", self.url, path));
+        if let Some(user) = user {
+            request = request.basic_auth(user, Some("secret123"));
+        }
+        let response = request.send().await.unwrap();
+        (response.status(), response.json().await.unwrap())
+    }
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.snapshots);
+    }
+}
+
+#[tokio::test]
+async fn healthz_answers_before_the_node_is_ready() {
+    let node = Node::start().await;
+
+    let (status, body) = node.get("/healthz").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "ok");
+
+    let (status, body) = node.get("/readyz").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["ready"], false);
+    assert_eq!(body["storage_open"], false);
+    assert_eq!(body["management_authenticated"], true);
+
+    node.status.set_storage_open(true);
+    let (status, body) = node.get("/readyz").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["ready"], true);
+
+    node.status.set_storage_open(false);
+    assert_eq!(node.get("/readyz").await.0, StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn drain_fails_readiness_and_refuses_new_connections() {
+    let node = Node::start().await;
+    node.status.set_storage_open(true);
+
+    assert_eq!(node.post("/admin/drain", None).await.0, StatusCode::UNAUTHORIZED);
+    assert_eq!(node.post("/admin/drain", Some("bob")).await.0, StatusCode::FORBIDDEN);
+    assert_eq!(node.get("/readyz").await.0, StatusCode::OK);
+
+    let (status, body) = node.post("/admin/drain", Some("root")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["draining"], true);
+    let (status, body) = node.get("/readyz").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["draining"], true);
+
+    // New connections are closed right away.
+    let mut stream = TcpStream::connect(&node.listener_address).await.unwrap();
+    let mut buffer = [0u8; 1];
+    let read = tokio::time::timeout(Duration::from_secs(2), tokio::io::AsyncReadExt::read(&mut stream, &mut buffer)).await;
+    assert!(matches!(read, Ok(Ok(0)) | Ok(Err(_))), "{:?}", read);
+
+    let (_, body) = node.post("/admin/resume", Some("root")).await;
+    assert_eq!(body["draining"], false);
+    assert_eq!(node.get("/readyz").await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn compaction_and_snapshot_report_what_they_did() {
+    let node = Node::start().await;
+
+    assert_eq!(node.post("/admin/compact", Some("bob")).await.0, StatusCode::FORBIDDEN);
+    let (status, body) = node.post("/admin/compact", Some("root")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["tables"].as_u64().unwrap() >= 1, "{}", body);
+    assert!(body["rows"].as_u64().unwrap() >= 3, "{}", body);
+
+    assert_eq!(node.post("/admin/snapshot", None).await.0, StatusCode::UNAUTHORIZED);
+    let (status, body) = node.post("/admin/snapshot", Some("root")).await;
+    assert_eq!(status, StatusCode::OK);
+    let path = PathBuf::from(body["path"].as_str().unwrap());
+    assert!(path.starts_with(&node.snapshots), "{}", path.display());
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), body["bytes"].as_u64().unwrap());
+    let snapshot: Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    assert_eq!(snapshot["default"]["tables"]["notes"]["rows"].as_array().map(|rows| rows.len()), Some(3), "{}", snapshot);
+}
+
+/// Value of the sample `series` in a scrape, labels included.
+fn sample(scrape: &str, series: &str) -> f64 {
+    scrape
+        .lines()
+        .find_map(|line| line.strip_prefix(series).and_then(|rest| rest.strip_prefix(' ')))
+        .unwrap_or_else(|| panic!("no {} in:\n{}", series, scrape))
+        .parse()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn metrics_scrape_covers_statements_transport_pool_and_fsync() {
+    let node = Node::start().await;
+    let conn = node.client.allocate_connection().await.unwrap();
+    let select = SelectStatement::new("notes".to_string(), vec!["*".to_string()], String::new()).unwrap();
+    let response = conn.send(&Message::new(MessageType::Select, &select)).await.unwrap();
+    assert_eq!(response.header.message_type, MessageType::Select);
+    drop(conn);
+    node.post("/admin/snapshot", Some("root")).await;
+
+    let response = node.http.get(format!("{}/metrics", node.url)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers()["content-type"].to_str().unwrap().starts_with("text/plain"));
+    let scrape = response.text().await.unwrap();
+
+    assert!(sample(&scrape, "zenith_requests_total{message_type=\"Select\",outcome=\"ok\"}") >= 1.0);
+    assert!(sample(&scrape, "zenith_request_duration_seconds_count{message_type=\"Select\"}") >= 1.0);
+    assert!(sample(&scrape, "zenith_bytes_in_total") > 0.0);
+    assert!(sample(&scrape, "zenith_bytes_out_total") > 0.0);
+    assert!(sample(&scrape, "zenith_pool_connections") >= 1.0);
+    assert!(scrape.contains("zenith_pool_loans "), "{}", scrape);
+    assert_eq!(sample(&scrape, "zenith_table_rows{database=\"default\",table=\"notes\"}"), 3.0);
+    assert!(sample(&scrape, "zenith_fsync_duration_seconds_count{file=\"snapshot\"}") >= 1.0);
+}
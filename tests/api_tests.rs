@@ -0,0 +1,219 @@
+use std::sync::Arc;
+use std::time::Duration;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use futures::StreamExt;
+use prost_types::value::Kind;
+use serde_json::json;
+use tokio::net::{ TcpListener, TcpStream };
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::{ MaybeTlsStream, WebSocketStream };
+use tonic::{ Code, Request };
+use zenith_store::api::{ cdc, grpc, ServerListener };
+use zenith_store::api::grpc::proto;
+use zenith_store::api::grpc::proto::zenith_store_client::ZenithStoreClient;
+use zenith_store::network::auth::Principal;
+use zenith_store::protocol::MessageType;
+use zenith_store::statement::QueryStatement;
+use zenith_store::storage::{ ChangeEvent, ChangeFeed, ChangeOperation, ExecutionContext, Executor, StorageEngine };
+use zenith_store::storage::executor::ROWS_PER_CHUNK;
+use zenith_store::transport::Message;
+
+fn node() -> ExecutionContext {
+    ExecutionContext { principal: Principal::Node("node-1".to_string()), database: "default".to_string() }
+}
+
+fn query(executor: &Executor, sql: &str) {
+    let stmt = QueryStatement::new(sql.to_string()).unwrap();
+    executor.execute(&node(), &Message::new(MessageType::Query, &stmt)).unwrap();
+}
+
+/// An executor on `engine` with empty `notes` and `tags` tables and the
+/// superuser `root`.
+fn executor_on(engine: StorageEngine) -> Arc<Executor> {
+    let executor = Arc::new(Executor::new(Arc::new(engine)));
+    query(
+        &executor,
+        "CREATE TABLE notes (id int PRIMARY KEY, note text);
+         CREATE TABLE tags (id int PRIMARY KEY, tag text);
+         CREATE USER root WITH PASSWORD 'secret123' SUPERUSER"
+    );
+    executor
+}
+
+fn executor() -> Arc<Executor> {
+    executor_on(StorageEngine::new())
+}
+
+fn basic_credentials() -> String {
+    format!("Basic {}", STANDARD.encode("root:secret123"))
+}
+
+async fn start_grpc(executor: Arc<Executor>) -> ZenithStoreClient<tonic::transport::Channel> {
+    let listener = ServerListener::plain(TcpListener::bind("127.0.0.1:0").await.unwrap()).unwrap();
+    let address = listener.local_addr();
+    tokio::spawn(grpc::serve(listener, executor));
+    ZenithStoreClient::connect(format!("http://{}", address)).await.unwrap()
+}
+
+fn authorized<T>(body: T) -> Request<T> {
+    let mut request = Request::new(body);
+    request.metadata_mut().insert("authorization", basic_credentials().parse().unwrap());
+    request
+}
+
+fn note_values(id: usize) -> prost_types::Struct {
+    let values = [
+        ("id".to_string(), prost_types::Value { kind: Some(Kind::NumberValue(id as f64)) }),
+        ("note".to_string(), prost_types::Value { kind: Some(Kind::StringValue(format!("note {}", id))) }),
+    ];
+    prost_types::Struct { fields: values.into_iter().collect() }
+}
+
+fn note(id: usize) -> proto::InsertStatement {
+    proto::InsertStatement { table_name: "notes".to_string(), values: Some(note_values(id)) }
+}
+
+fn select_notes(r#where: &str) -> proto::SelectStatement {
+    proto::SelectStatement {
+        table_name: "notes".to_string(),
+        columns: vec!["*".to_string()],
+        r#where: r#where.to_string(),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn grpc_client_inserts_and_streams_a_select() {
+    let executor = executor();
+    let mut client = start_grpc(executor.clone()).await;
+    let rows = ROWS_PER_CHUNK * 2 + 10;
+
+    let response = client.insert(authorized(note(0))).await.unwrap();
+    assert_eq!(response.into_inner().affected_rows, 1);
+    let bulk = proto::BulkInsertStatement { table_name: "notes".to_string(), rows: (1..rows).map(note_values).collect() };
+    let response = client.bulk_insert(authorized(bulk)).await.unwrap();
+    assert_eq!(response.into_inner().affected_rows, (rows - 1) as u64);
+    assert_eq!(executor.engine().row_count("default", "notes").unwrap(), rows);
+
+    let mut stream = client.select(authorized(select_notes(""))).await.unwrap().into_inner();
+    let mut batches = Vec::new();
+    while let Some(batch) = stream.message().await.unwrap() {
+        batches.push(batch.rows.len());
+    }
+    assert_eq!(batches, vec![ROWS_PER_CHUNK, ROWS_PER_CHUNK, 10]);
+
+    let mut stream = client.select(authorized(select_notes("id = 7"))).await.unwrap().into_inner();
+    let batch = stream.message().await.unwrap().unwrap();
+    assert_eq!(batch.rows.len(), 1);
+    assert_eq!(batch.rows[0].fields["note"].kind, Some(Kind::StringValue("note 7".to_string())));
+    assert!(stream.message().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn grpc_calls_without_credentials_are_refused() {
+    let mut client = start_grpc(executor()).await;
+
+    let status = client.insert(Request::new(note(1))).await.unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+    let status = client.select(Request::new(select_notes(""))).await.unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+}
+
+#[tokio::test]
+async fn grpc_errors_map_to_status_codes() {
+    let mut client = start_grpc(executor()).await;
+
+    let status = client
+        .insert(authorized(proto::InsertStatement { table_name: "missing".to_string(), ..note(1) })).await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+
+    let status = client
+        .select(authorized(proto::SelectStatement { table_name: "missing".to_string(), ..select_notes("") })).await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+}
+
+type Subscriber = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Starts the CDC feed and returns the executor writing to it, its
+/// address and the current position of the feed.
+async fn start_cdc() -> (Arc<Executor>, String, String) {
+    let feed = Arc::new(ChangeFeed::new(64));
+    let executor = executor_on(StorageEngine::new().with_change_feed(feed.clone()));
+    let listener = ServerListener::plain(TcpListener::bind("127.0.0.1:0").await.unwrap()).unwrap();
+    let address = listener.local_addr().to_string();
+    tokio::spawn(cdc::serve(listener, executor.clone()));
+    (executor, address, feed.position())
+}
+
+/// Subscribes from `from`, so changes made before the subscriber is set up
+/// are not missed.
+async fn subscribe(address: &str, from: &str, table: Option<&str>) -> Subscriber {
+    let from: String = url::form_urlencoded::byte_serialize(from.as_bytes()).collect();
+    let mut url = format!("ws://{}/?from={}", address, from);
+    if let Some(table) = table {
+        url.push_str(&format!("&table={}", table));
+    }
+    let mut request = url.into_client_request().unwrap();
+    request.headers_mut().insert("authorization", basic_credentials().parse().unwrap());
+    let (socket, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+    socket
+}
+
+async fn next_event(socket: &mut Subscriber) -> ChangeEvent {
+    let message = tokio::time::timeout(Duration::from_secs(5), socket.next()).await
+        .expect("no change event")
+        .unwrap()
+        .unwrap();
+    serde_json::from_str(message.to_text().unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn cdc_events_carry_before_and_after_images() {
+    let (executor, address, from) = start_cdc().await;
+    query(
+        &executor,
+        "INSERT INTO notes (id, note) VALUES (1, 'draft');
+         UPDATE notes SET note = 'final' WHERE id = 1;
+         DELETE FROM notes WHERE id = 1"
+    );
+    let mut socket = subscribe(&address, &from, None).await;
+
+    let insert = next_event(&mut socket).await;
+    assert_eq!(insert.operation, ChangeOperation::Insert);
+    assert_eq!(insert.before, None);
+    assert_eq!(insert.after.unwrap()["note"], json!("draft"));
+
+    let update = next_event(&mut socket).await;
+    assert_eq!((update.database.as_str(), update.table.as_str()), ("default", "notes"));
+    assert_eq!(update.operation, ChangeOperation::Update);
+    assert_eq!(update.before.unwrap()["note"], json!("draft"));
+    assert_eq!(update.after.unwrap()["note"], json!("final"));
+
+    let delete = next_event(&mut socket).await;
+    assert_eq!(delete.operation, ChangeOperation::Delete);
+    assert_eq!(delete.before.unwrap()["note"], json!("final"));
+    assert_eq!(delete.after, None);
+}
+
+#[tokio::test]
+async fn cdc_subscription_to_one_table_skips_the_others() {
+    let (executor, address, from) = start_cdc().await;
+    let mut socket = subscribe(&address, &from, Some("notes")).await;
+
+    query(
+        &executor,
+        "INSERT INTO tags (id, tag) VALUES (1, 'red');
+         INSERT INTO notes (id, note) VALUES (1, 'draft');
+         UPDATE tags SET tag = 'blue' WHERE id = 1;
+         DELETE FROM tags WHERE id = 1"
+    );
+
+    // Events arrive in order, so the tags insert was filtered out.
+    let event = next_event(&mut socket).await;
+    assert_eq!(event.table, "notes");
+    assert_eq!(event.operation, ChangeOperation::Insert);
+    assert!(tokio::time::timeout(Duration::from_millis(300), socket.next()).await.is_err());
+}
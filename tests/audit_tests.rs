@@ -0,0 +1,180 @@
+use std::fs;
+use std::path::{ Path, PathBuf };
+use zenith_store::utils::audit::{ audit_files, verify_chain, AuditError, AuditEvent, AuditLog, AuditRecord, GENESIS_HASH };
+
+fn directory() -> PathBuf {
+    std::env::temp_dir().join(format!("zenith-audit-{}", uuid::Uuid::new_v4()))
+}
+
+fn event(n: usize) -> AuditEvent {
+    AuditEvent::Statement {
+        connection_id: n,
+        principal: "bob".to_string(),
+        database: "shop".to_string(),
+        message_type: "CreateTable".to_string(),
+        message_id: format!("message-{}", n),
+        outcome: "ok".to_string(),
+        error: None,
+    }
+}
+
+/// Writes `count` records to a fresh log and returns its directory.
+fn write_log(count: usize, max_file_bytes: u64) -> PathBuf {
+    let directory = directory();
+    let log = AuditLog::open(&directory, max_file_bytes, false).unwrap();
+    for n in 0..count {
+        log.append(event(n)).unwrap();
+    }
+    directory
+}
+
+fn only_file(directory: &Path) -> PathBuf {
+    let mut files = audit_files(directory).unwrap();
+    assert_eq!(files.len(), 1);
+    files.pop().unwrap().1
+}
+
+fn read_lines(file: &Path) -> Vec<String> {
+    fs::read_to_string(file).unwrap().lines().map(str::to_string).collect()
+}
+
+fn write_lines(file: &Path, lines: &[String]) {
+    fs::write(file, lines.join("\n") + "\n").unwrap();
+}
+
+#[test]
+fn intact_chain_verifies() {
+    let directory = write_log(5, 1024 * 1024);
+
+    let summary = verify_chain(&directory).unwrap();
+    assert_eq!(summary.files, 1);
+    assert_eq!(summary.records, 5);
+
+    let lines = read_lines(&only_file(&directory));
+    let first: AuditRecord = serde_json::from_str(&lines[0]).unwrap();
+    let last: AuditRecord = serde_json::from_str(&lines[4]).unwrap();
+    assert_eq!(first.prev_hash, GENESIS_HASH);
+    assert_eq!(summary.last_hash, last.hash);
+}
+
+#[test]
+fn edited_record_is_tampered() {
+    let directory = write_log(3, 1024 * 1024);
+    let file = only_file(&directory);
+    let mut lines = read_lines(&file);
+    lines[1] = lines[1].replace("\"outcome\":\"ok\"", "\"outcome\":\"denied\"");
+    write_lines(&file, &lines);
+
+    assert!(matches!(verify_chain(&directory), Err(AuditError::Tampered { line: 2, sequence: 2, .. })));
+}
+
+#[test]
+fn rehashed_record_breaks_the_next_link() {
+    let directory = write_log(3, 1024 * 1024);
+    let file = only_file(&directory);
+    let mut lines = read_lines(&file);
+    let mut record: AuditRecord = serde_json::from_str(&lines[1]).unwrap();
+    record.event = event(42);
+    record.hash = record.compute_hash();
+    lines[1] = serde_json::to_string(&record).unwrap();
+    write_lines(&file, &lines);
+
+    assert!(matches!(verify_chain(&directory), Err(AuditError::BrokenLink { line: 3, sequence: 3, .. })));
+}
+
+#[test]
+fn dropped_record_is_a_gap() {
+    let directory = write_log(4, 1024 * 1024);
+    let file = only_file(&directory);
+    let mut lines = read_lines(&file);
+    lines.remove(1);
+    write_lines(&file, &lines);
+
+    assert!(
+        matches!(verify_chain(&directory), Err(AuditError::Gap { line: 2, expected: 2, found: 3, .. }))
+    );
+}
+
+#[test]
+fn reordered_records_are_a_gap() {
+    let directory = write_log(4, 1024 * 1024);
+    let file = only_file(&directory);
+    let mut lines = read_lines(&file);
+    lines.swap(1, 2);
+    write_lines(&file, &lines);
+
+    assert!(
+        matches!(verify_chain(&directory), Err(AuditError::Gap { line: 2, expected: 2, found: 3, .. }))
+    );
+}
+
+#[test]
+fn garbage_line_is_malformed() {
+    let directory = write_log(2, 1024 * 1024);
+    let file = only_file(&directory);
+    let mut lines = read_lines(&file);
+    lines.insert(1, "not json".to_string());
+    write_lines(&file, &lines);
+
+    assert!(matches!(verify_chain(&directory), Err(AuditError::Malformed { line: 2, .. })));
+}
+
+#[test]
+fn chain_carries_on_across_rotated_files() {
+    // Small enough that every record gets a file of its own.
+    let directory = write_log(4, 64);
+
+    let files = audit_files(&directory).unwrap();
+    assert_eq!(files.len(), 4);
+    assert_eq!(files.iter().map(|(index, _)| *index).collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+    let summary = verify_chain(&directory).unwrap();
+    assert_eq!((summary.files, summary.records), (4, 4));
+}
+
+#[test]
+fn deleted_rotated_file_is_a_gap() {
+    let directory = write_log(4, 64);
+    fs::remove_file(&audit_files(&directory).unwrap()[1].1).unwrap();
+
+    assert!(matches!(verify_chain(&directory), Err(AuditError::Gap { expected: 2, found: 3, .. })));
+}
+
+#[test]
+fn chain_carries_on_after_a_restart() {
+    let directory = write_log(3, 1024 * 1024);
+    let before = verify_chain(&directory).unwrap();
+
+    let log = AuditLog::open(&directory, 1024 * 1024, false).unwrap();
+    let record = log.append(event(3)).unwrap();
+    assert_eq!(record.sequence, 4);
+    assert_eq!(record.prev_hash, before.last_hash);
+
+    let summary = verify_chain(&directory).unwrap();
+    assert_eq!((summary.files, summary.records), (1, 4));
+}
+
+#[test]
+fn restart_after_rotation_appends_to_the_newest_file() {
+    let directory = write_log(3, 64);
+
+    let log = AuditLog::open(&directory, 1024 * 1024, false).unwrap();
+    log.append(event(3)).unwrap();
+
+    let files = audit_files(&directory).unwrap();
+    assert_eq!(files.len(), 3);
+    assert_eq!(read_lines(&files[2].1).len(), 2);
+    assert_eq!(verify_chain(&directory).unwrap().records, 4);
+}
+
+#[test]
+fn only_ddl_is_recorded_unless_dml_is_included() {
+    use zenith_store::protocol::MessageType;
+
+    let ddl_only = AuditLog::open(directory(), 1024, false).unwrap();
+    let with_dml = AuditLog::open(directory(), 1024, true).unwrap();
+
+    assert!(ddl_only.records_statement(MessageType::Grant));
+    assert!(!ddl_only.records_statement(MessageType::Insert));
+    assert!(with_dml.records_statement(MessageType::Insert));
+    assert!(!with_dml.records_statement(MessageType::Select));
+}
@@ -0,0 +1,182 @@
+use std::time::Duration;
+use zenith_store::network::auth::{ LoginError, LoginVerifier };
+use zenith_store::statement::{ LegacyLoginStatement, LoginStatement };
+use zenith_store::utils::KeyRing;
+use zenith_store::utils::keyring::ClusterKey;
+
+const TOKEN: &str = "test-cluster-token";
+const SKEW: Duration = Duration::from_secs(30);
+const NOW: u64 = 1_700_000_000_000_000_000;
+
+fn keys() -> KeyRing {
+    KeyRing::single(TOKEN).unwrap()
+}
+
+/// A login signed with `TOKEN` at `timestamp`.
+fn login_at(timestamp: u64) -> LoginStatement {
+    let mut stmt = LoginStatement::new(
+        TOKEN.to_string(),
+        "default".to_string(),
+        "node-1".to_string(),
+        "node_1".to_string(),
+        false,
+        "127.0.0.1:9000".to_string(),
+        vec!["replica".to_string()]
+    ).unwrap();
+    stmt.timestamp = timestamp;
+    stmt.hash = stmt.sign(TOKEN);
+    stmt
+}
+
+#[test]
+fn login_inside_the_skew_window_is_accepted() {
+    let verifier = LoginVerifier::new(SKEW, 16);
+    let window = SKEW.as_nanos() as u64;
+
+    assert_eq!(verifier.verify_at(&login_at(NOW - window), &keys(), NOW), Ok(()));
+    assert_eq!(verifier.verify_at(&login_at(NOW + window), &keys(), NOW), Ok(()));
+}
+
+#[test]
+fn login_outside_the_skew_window_is_expired() {
+    let verifier = LoginVerifier::new(SKEW, 16);
+    let window = SKEW.as_nanos() as u64;
+
+    for timestamp in [NOW - window - 1, NOW + window + 1] {
+        match verifier.verify_at(&login_at(timestamp), &keys(), NOW) {
+            Err(LoginError::Expired { skew }) => assert_eq!(skew, SKEW + Duration::from_nanos(1)),
+            other => panic!("expected an expired login, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn replayed_login_is_rejected() {
+    let verifier = LoginVerifier::new(SKEW, 16);
+    let stmt = login_at(NOW);
+
+    assert_eq!(verifier.verify_at(&stmt, &keys(), NOW), Ok(()));
+    assert_eq!(verifier.verify_at(&stmt, &keys(), NOW + 1), Err(LoginError::Replayed));
+
+    // A new nonce at the same instant is a different login.
+    assert_eq!(verifier.verify_at(&login_at(NOW), &keys(), NOW), Ok(()));
+}
+
+#[test]
+fn tampered_login_fails_the_signature() {
+    let verifier = LoginVerifier::new(SKEW, 16);
+
+    let mut stmt = login_at(NOW);
+    stmt.tags.push("admin".to_string());
+    assert_eq!(verifier.verify_at(&stmt, &keys(), NOW), Err(LoginError::BadSignature));
+
+    let mut stmt = login_at(NOW);
+    stmt.nonce = "0".repeat(32);
+    assert_eq!(verifier.verify_at(&stmt, &keys(), NOW), Err(LoginError::BadSignature));
+
+    let mut stmt = login_at(NOW);
+    stmt.address = "10.0.0.1:9000".to_string();
+    assert_eq!(verifier.verify_at(&stmt, &keys(), NOW), Err(LoginError::BadSignature));
+}
+
+#[test]
+fn regrouped_tags_sign_differently() {
+    let stmt = login_at(NOW);
+    let signed = |tags: &[&str]| {
+        let mut stmt = stmt.clone();
+        stmt.tags = tags.iter().map(|tag| tag.to_string()).collect();
+        stmt.sign(TOKEN)
+    };
+
+    assert_ne!(signed(&["a", "b"]), signed(&["a,b"]));
+    assert_ne!(signed(&["ab"]), signed(&["a", "b"]));
+    assert_ne!(signed(&[]), signed(&[""]));
+    assert_eq!(signed(&["a", "b"]), signed(&["a", "b"]));
+}
+
+#[test]
+fn tampered_login_does_not_burn_its_nonce() {
+    let verifier = LoginVerifier::new(SKEW, 16);
+    let stmt = login_at(NOW);
+
+    let mut tampered = stmt.clone();
+    tampered.is_replica = true;
+    assert_eq!(verifier.verify_at(&tampered, &keys(), NOW), Err(LoginError::BadSignature));
+    assert_eq!(verifier.verify_at(&stmt, &keys(), NOW), Ok(()));
+}
+
+#[test]
+fn login_with_an_unknown_key_is_rejected() {
+    let verifier = LoginVerifier::new(SKEW, 16);
+    let mut stmt = login_at(NOW);
+    stmt.key_id = "retired".to_string();
+
+    assert_eq!(verifier.verify_at(&stmt, &keys(), NOW), Err(LoginError::UnknownKey("retired".to_string())));
+}
+
+#[test]
+fn login_signed_with_a_rotated_key_is_accepted() {
+    let verifier = LoginVerifier::new(SKEW, 16);
+    let keys = KeyRing::new(
+        "next",
+        vec![
+            ClusterKey { id: "default".to_string(), token: TOKEN.to_string() },
+            ClusterKey { id: "next".to_string(), token: "next-token".to_string() }
+        ]
+    ).unwrap();
+
+    assert_eq!(verifier.verify_at(&login_at(NOW), &keys, NOW), Ok(()));
+}
+
+#[test]
+fn nonce_cache_forgets_the_oldest_login_when_full() {
+    let verifier = LoginVerifier::new(SKEW, 2);
+    let first = login_at(NOW);
+
+    assert_eq!(verifier.verify_at(&first, &keys(), NOW), Ok(()));
+    assert_eq!(verifier.verify_at(&login_at(NOW), &keys(), NOW), Ok(()));
+    assert_eq!(verifier.verify_at(&first, &keys(), NOW), Err(LoginError::Replayed));
+
+    // A third login pushes the first one out of the cache.
+    assert_eq!(verifier.verify_at(&login_at(NOW), &keys(), NOW), Ok(()));
+    assert_eq!(verifier.verify_at(&first, &keys(), NOW), Ok(()));
+}
+
+#[test]
+fn expired_nonces_are_evicted_and_replays_stay_rejected() {
+    let verifier = LoginVerifier::new(SKEW, 2);
+    let window = SKEW.as_nanos() as u64;
+    let first = login_at(NOW);
+    assert_eq!(verifier.verify_at(&first, &keys(), NOW), Ok(()));
+
+    // Once out of the window the nonce may be dropped, and the login is then
+    // refused by its timestamp instead.
+    let later = NOW + window + 1;
+    assert_eq!(verifier.verify_at(&login_at(later), &keys(), later), Ok(()));
+    assert!(matches!(verifier.verify_at(&first, &keys(), later), Err(LoginError::Expired { .. })));
+
+    // Evicting the expired entry must not forget the fresh ones.
+    let second = login_at(later);
+    assert_eq!(verifier.verify_at(&second, &keys(), later), Ok(()));
+    assert_eq!(verifier.verify_at(&second, &keys(), later), Err(LoginError::Replayed));
+}
+
+#[test]
+fn legacy_login_is_verified_with_the_default_key() {
+    let verifier = LoginVerifier::new(SKEW, 16);
+    let mut stmt = LegacyLoginStatement::new(
+        TOKEN.to_string(),
+        "node-1".to_string(),
+        "node_1".to_string(),
+        false,
+        "127.0.0.1:9000".to_string(),
+        vec!["replica".to_string()]
+    ).unwrap();
+    let now = stmt.timestamp;
+
+    assert_eq!(verifier.verify_legacy_at(&stmt, &keys(), now), Ok(()));
+    assert_eq!(verifier.verify_legacy_at(&stmt, &keys(), now), Err(LoginError::Replayed));
+
+    stmt.tags = vec!["primary".to_string()];
+    assert_eq!(verifier.verify_legacy_at(&stmt, &keys(), now), Err(LoginError::BadSignature));
+}
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use futures::{ stream, StreamExt };
+use serde_json::json;
+use zenith_store::managment::{ MessageClient, MessageConfig };
+use zenith_store::network::{ ListenerConfig, NodeListener };
+use zenith_store::network::auth::Principal;
+use zenith_store::protocol::{ Capabilities, HandshakeError, MessageType };
+use zenith_store::protocol::handshake::{ self, DEFAULT_MAX_FRAME_SIZE, PROTOCOL_VERSION };
+use zenith_store::statement::{
+    ColumnDefinition,
+    CreateTableStatement,
+    EmptyStatement,
+    GreetingStatement,
+    SelectStatement,
+    WelcomeStatement,
+};
+use zenith_store::storage::{ ExecutionContext, Executor, StorageEngine };
+use zenith_store::transport::compression::COMPRESSION_STATS;
+use zenith_store::transport::{ Codec, FrameError, Framing, Message };
+use zenith_store::utils::KeyRing;
+
+const TOKEN: &str = "test-cluster-token";
+const THRESHOLD: usize = 1024;
+
+fn codecs(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+/// A body well over the threshold that compresses well.
+fn pong(size: usize) -> Message {
+    let mut message = Message::new(MessageType::Ping, &EmptyStatement::new(MessageType::Ping));
+    message.header.message_type = MessageType::Pong;
+    message.body = b"zenith ".iter().cycle().take(size).cloned().collect();
+    message
+}
+
+async fn round_trip(codec: Codec) {
+    let original = pong(8 * THRESHOLD);
+    let mut message = original.clone();
+    message.compress(codec, THRESHOLD).unwrap();
+    assert_eq!(message.codec().unwrap(), codec);
+    assert!(message.body.len() < original.body.len());
+
+    let bytes = message.serialize();
+    let decoded = Message::read_from(&mut bytes.as_slice(), DEFAULT_MAX_FRAME_SIZE, Framing::Current).await.unwrap();
+    assert_eq!(decoded.codec().unwrap(), Codec::None);
+    assert_eq!(decoded.body, original.body);
+    assert_eq!(decoded.header.message_id, original.header.message_id);
+}
+
+#[tokio::test]
+async fn lz4_round_trips() {
+    round_trip(Codec::Lz4).await
+}
+
+#[tokio::test]
+async fn zstd_round_trips() {
+    round_trip(Codec::Zstd).await
+}
+
+#[test]
+fn bodies_under_the_threshold_are_sent_as_is() {
+    let mut message = pong(THRESHOLD - 1);
+    let body = message.body.clone();
+    message.compress(Codec::Zstd, THRESHOLD).unwrap();
+    assert_eq!(message.codec().unwrap(), Codec::None);
+    assert_eq!(message.body, body);
+
+    let mut message = pong(THRESHOLD);
+    message.compress(Codec::Zstd, THRESHOLD).unwrap();
+    assert_eq!(message.codec().unwrap(), Codec::Zstd);
+}
+
+#[test]
+fn inflating_past_the_maximum_is_refused() {
+    let mut message = pong(8 * THRESHOLD);
+    message.compress(Codec::Lz4, THRESHOLD).unwrap();
+    assert!(message.decompress(THRESHOLD).is_err());
+}
+
+#[test]
+fn unknown_codec_id_is_refused() {
+    let mut message = pong(16);
+    message.header.set_compression_id(3);
+    match message.decompress(DEFAULT_MAX_FRAME_SIZE as usize) {
+        Err(FrameError::UnsupportedCompression(3)) => {}
+        other => panic!("expected an unsupported codec, got {:?}", other),
+    }
+}
+
+#[test]
+fn only_codecs_both_sides_know_are_negotiated() {
+    let server = Capabilities::local(DEFAULT_MAX_FRAME_SIZE).with_compression(&codecs(&["lz4"]));
+    let client = Capabilities::local(DEFAULT_MAX_FRAME_SIZE).with_compression(&codecs(&["zstd", "lz4"]));
+    let greeting = Message::new(MessageType::Greeting, &client.to_greeting("node_1"));
+
+    let (response, negotiated) = handshake::handle_greeting(&server, &greeting);
+    let welcome = WelcomeStatement::decode(&response.body).unwrap();
+    assert_eq!(welcome.compression, codecs(&["lz4"]));
+    assert_eq!(negotiated.unwrap().codec(), Codec::Lz4);
+    assert_eq!(handshake::accept_welcome(&client, &welcome).unwrap().codec(), Codec::Lz4);
+
+    let disabled = Capabilities::local(DEFAULT_MAX_FRAME_SIZE).with_compression(&[]);
+    let greeting: GreetingStatement = disabled.to_greeting("node_2");
+    let welcome = handshake::negotiate(&server, &greeting).unwrap();
+    assert!(welcome.compression.is_empty());
+}
+
+#[test]
+fn codec_the_client_did_not_offer_is_refused() {
+    let client = Capabilities::local(DEFAULT_MAX_FRAME_SIZE).with_compression(&codecs(&["lz4"]));
+    let welcome = WelcomeStatement::accept(PROTOCOL_VERSION, Vec::new(), codecs(&["zstd"]), DEFAULT_MAX_FRAME_SIZE);
+    match handshake::accept_welcome(&client, &welcome) {
+        Err(HandshakeError::InvalidWelcome(reason)) => assert!(reason.contains("zstd"), "{}", reason),
+        other => panic!("expected an invalid welcome, got {:?}", other.map(|p| p.compression)),
+    }
+}
+
+#[test]
+fn stats_count_compressed_and_skipped_frames() {
+    let before = COMPRESSION_STATS.snapshot();
+
+    let mut message = pong(8 * THRESHOLD);
+    message.compress(Codec::Zstd, THRESHOLD).unwrap();
+    let compressed = message.body.len() as u64;
+    message.decompress(DEFAULT_MAX_FRAME_SIZE as usize).unwrap();
+    pong(16).compress(Codec::Zstd, THRESHOLD).unwrap();
+
+    // Other tests compress concurrently, so only look for growth.
+    let after = COMPRESSION_STATS.snapshot();
+    assert!(after.frames_compressed > before.frames_compressed);
+    assert!(after.frames_skipped > before.frames_skipped);
+    assert!(after.frames_decompressed > before.frames_decompressed);
+    assert!(after.bytes_before >= before.bytes_before + 8 * (THRESHOLD as u64));
+    assert!(after.bytes_after >= before.bytes_after + compressed);
+    assert!(after.ratio() < 1.0);
+}
+
+fn column(name: &str, col_type: &str) -> ColumnDefinition {
+    ColumnDefinition {
+        name: name.to_string(),
+        col_type: col_type.to_string(),
+        length: 0,
+        primary_key: false,
+        index: false,
+        default_value: String::new(),
+    }
+}
+
+fn context() -> ExecutionContext {
+    ExecutionContext { principal: Principal::Node("test".to_string()), database: "default".to_string() }
+}
+
+#[tokio::test]
+async fn compressed_connection_carries_requests_and_responses() {
+    let executor = Arc::new(Executor::new(Arc::new(StorageEngine::new())));
+    let create = CreateTableStatement::new("notes".to_string(), vec![column("id", "int"), column("note", "text")], None)
+        .unwrap();
+    executor.run(0, &context(), &Message::new(MessageType::CreateTable, &create)).unwrap();
+
+    let mut config = ListenerConfig::new("127.0.0.1:0".to_string(), "node-0".to_string(), KeyRing::single(TOKEN).unwrap());
+    config.compression = codecs(&["lz4"]);
+    config.compression_threshold = THRESHOLD;
+    let listener = NodeListener::bind(config, executor.clone()).await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(listener.serve());
+
+    let client = tokio::time
+        ::timeout(
+            Duration::from_secs(5),
+            MessageClient::new(MessageConfig {
+                server_addr: address,
+                keys: KeyRing::single(TOKEN).unwrap(),
+                node_id: "node_1".to_string(),
+                address: "".to_string(),
+                tags: vec!["replica".to_string()],
+                min_conn: 1,
+                max_conn: 1,
+                timeout: Duration::from_secs(1),
+                max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+                compression: codecs(&["zstd", "lz4"]),
+                compression_threshold: THRESHOLD,
+                tls: None,
+            })
+        ).await
+        .expect("client did not connect")
+        .unwrap();
+    let conn = client.allocate_connection().await.unwrap();
+    assert_eq!(conn.protocol().codec(), Codec::Lz4);
+
+    let before = COMPRESSION_STATS.snapshot();
+    let rows = stream::iter(
+        (0..500).map(|id| {
+            HashMap::from([
+                ("id".to_string(), json!(id)),
+                ("note".to_string(), json!("compressible ".repeat(8))),
+            ])
+        })
+    );
+    conn.bulk_insert_stream("notes", rows, 500).await.unwrap();
+
+    let select = SelectStatement::new("notes".to_string(), vec!["*".to_string()], String::new()).unwrap();
+    let selected = conn.select_rows(&select).await.unwrap().count().await;
+    assert_eq!(selected, 500);
+
+    let after = COMPRESSION_STATS.snapshot();
+    assert!(after.frames_compressed >= before.frames_compressed + 2);
+    assert!(after.frames_decompressed >= before.frames_decompressed + 2);
+}
@@ -0,0 +1,311 @@
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::sync::{ Arc, OnceLock };
+use std::time::Duration;
+use log::LevelFilter;
+use tokio::sync::{ Mutex, MutexGuard };
+use zenith_store::managment::{ MessageClient, MessageConfig };
+use zenith_store::network::{ ListenerConfig, NodeListener };
+use zenith_store::node;
+use zenith_store::storage::{ Executor, SlowQueryLog, StorageEngine };
+use zenith_store::utils::config::{ Config, ConfigError, Overrides };
+use zenith_store::utils::{ logger, ConfigReloader, KeyRing };
+
+/// `ZENITH_*` variables are read by every load, and the logger and SIGHUP
+/// are process-wide, so tests that load a file take turns.
+static ENVIRONMENT: Mutex<()> = Mutex::const_new(());
+
+fn environment() -> MutexGuard<'static, ()> {
+    ENVIRONMENT.blocking_lock()
+}
+
+/// The config.toml shipped with the repository.
+fn shipped() -> String {
+    fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("config.toml")).unwrap()
+}
+
+/// A copy of the shipped config in a directory of its own.
+struct ConfigFile {
+    directory: PathBuf,
+}
+
+impl ConfigFile {
+    fn new(contents: &str) -> Self {
+        let directory = std::env::temp_dir().join(format!("zenith-config-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&directory).unwrap();
+        let file = Self { directory };
+        file.write(contents);
+        file
+    }
+
+    fn path(&self) -> PathBuf {
+        self.directory.join("config.toml")
+    }
+
+    fn write(&self, contents: &str) {
+        fs::write(self.path(), contents).unwrap();
+    }
+
+    fn load(&self, overrides: &Overrides) -> Result<Config, ConfigError> {
+        Config::load_with(&self.path(), overrides)
+    }
+}
+
+impl Drop for ConfigFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.directory);
+    }
+}
+
+/// The shipped config with each `(line, replacement)` applied.
+fn edited(edits: &[(&str, &str)]) -> String {
+    let mut contents = shipped();
+    for (line, replacement) in edits {
+        assert!(contents.contains(line), "config.toml has no {:?}", line);
+        contents = contents.replacen(line, replacement, 1);
+    }
+    contents
+}
+
+fn problems(result: Result<Config, ConfigError>) -> Vec<String> {
+    match result {
+        Err(ConfigError::Invalid(problems)) => problems,
+        other => panic!("expected invalid values, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn shipped_config_is_valid() {
+    let _environment = environment();
+    let config = ConfigFile::new(&shipped()).load(&Overrides::default()).unwrap();
+    assert_eq!(config.listener.address, "127.0.0.1:7400");
+    assert!(!config.listener.allow_legacy_login);
+}
+
+#[test]
+fn every_invalid_value_is_reported() {
+    let _environment = environment();
+    let file = ConfigFile::new(
+        &edited(
+            &[
+                ("level = \"info\"", "level = \"loud\""),
+                ("max_connections = 8", "max_connections = 0"),
+                ("compression = [\"zstd\", \"lz4\"]", "compression = [\"brotli\"]"),
+                ("address = \"127.0.0.1:7400\"", "address = \"nowhere\""),
+                ("tags = [\"replica\"]", "tags = []"),
+            ]
+        )
+    );
+
+    let problems = problems(file.load(&Overrides::default()));
+    for key in [
+        "logging.level",
+        "management.pool.max_connections",
+        "management.pool.min_connections",
+        "listener.compression",
+        "listener.address",
+        "management.tags",
+    ] {
+        assert!(problems.iter().any(|problem| problem.starts_with(key)), "{} not in {:?}", key, problems);
+    }
+}
+
+#[test]
+fn values_of_the_wrong_type_are_rejected() {
+    let _environment = environment();
+    let file = ConfigFile::new(&edited(&[("max_frame_size = 16777216", "max_frame_size = \"large\"")]));
+    assert!(matches!(file.load(&Overrides::default()), Err(ConfigError::Load(_))));
+
+    file.write("[management\nnode_id = ");
+    assert!(matches!(file.load(&Overrides::default()), Err(ConfigError::Load(_))));
+}
+
+#[test]
+fn environment_wins_over_the_file_and_the_command_line_over_both() {
+    let _environment = environment();
+    let file = ConfigFile::new(&shipped());
+    let variables = [
+        ("ZENITH_LISTENER__ADDRESS", "127.0.0.1:7500"),
+        ("ZENITH_MANAGEMENT__NODE_ID", "env-node"),
+        ("ZENITH_STORAGE__PATH", "/env/data"),
+        ("ZENITH_LISTENER__COMPRESSION", "lz4"),
+    ];
+    for (name, value) in variables {
+        std::env::set_var(name, value);
+    }
+
+    let environment = file.load(&Overrides::default());
+    let overrides = Overrides {
+        node_id: None,
+        data_dir: Some("/cli/data".to_string()),
+        listen_address: Some("127.0.0.1:7600".to_string()),
+    };
+    let command_line = file.load(&overrides);
+    std::env::set_var("ZENITH_MANAGEMENT__POOL__MAX_CONNECTIONS", "0");
+    let invalid = file.load(&Overrides::default());
+    for (name, _) in variables {
+        std::env::remove_var(name);
+    }
+    std::env::remove_var("ZENITH_MANAGEMENT__POOL__MAX_CONNECTIONS");
+
+    let environment = environment.unwrap();
+    assert_eq!(environment.listener.address, "127.0.0.1:7500");
+    assert_eq!(environment.management.node_id, "env-node");
+    assert_eq!(environment.storage.path, "/env/data");
+    assert_eq!(environment.listener.compression, vec!["lz4".to_string()]);
+    // Untouched keys keep the value from the file.
+    assert_eq!(environment.listener.max_frame_size, 16777216);
+    assert_eq!(environment.management.tags, vec!["replica".to_string()]);
+
+    let command_line = command_line.unwrap();
+    assert_eq!(command_line.listener.address, "127.0.0.1:7600");
+    assert_eq!(command_line.storage.path, "/cli/data");
+    assert_eq!(command_line.management.node_id, "env-node");
+
+    // Values from the environment are validated like the file's.
+    assert!(problems(invalid).iter().any(|problem| problem.starts_with("management.pool.max_connections")));
+
+    let file_only = file.load(&Overrides::default()).unwrap();
+    assert_eq!(file_only.listener.address, "127.0.0.1:7400");
+    assert_eq!(file_only.management.node_id, "slave-node-1");
+}
+
+/// What `watch_reloads` acts on in a running node.
+struct ReloadingNode {
+    file: ConfigFile,
+    /// Edits every version of the file carries.
+    base: Vec<(String, String)>,
+    reloader: Arc<ConfigReloader>,
+    keys: KeyRing,
+    client: MessageClient,
+}
+
+impl ReloadingNode {
+    /// Loads the shipped config with `edits`, pointing the management
+    /// client at a listener of its own, and watches it for reloads.
+    async fn start(edits: &[(&str, &str)]) -> Self {
+        let executor = Arc::new(Executor::new(Arc::new(StorageEngine::new())));
+        let listener_keys = KeyRing::single("1234567890").unwrap();
+        let listener = NodeListener::bind(
+            ListenerConfig::new("127.0.0.1:0".to_string(), "node-0".to_string(), listener_keys),
+            executor
+        ).await.unwrap();
+        let server_addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(listener.serve());
+
+        let mut base: Vec<(String, String)> = edits
+            .iter()
+            .map(|(line, replacement)| (line.to_string(), replacement.to_string()))
+            .collect();
+        base.push(("server_addr = \"127.0.0.1:7000\"".to_string(), format!("server_addr = \"{}\"", server_addr)));
+        // Login node names take no hyphens.
+        base.push(("node_id = \"slave-node-1\"".to_string(), "node_id = \"slave_node_1\"".to_string()));
+        let file = ConfigFile::new(&Self::contents(&base, &[]));
+        let config = file.load(&Overrides::default()).unwrap();
+        logger::init(&config.logging).unwrap();
+
+        let keys = KeyRing::from_management(&config.management).unwrap();
+        let pool = &config.management.pool;
+        let client = MessageClient::new(MessageConfig {
+            server_addr,
+            keys: keys.clone(),
+            node_id: config.management.node_id.clone(),
+            address: "".to_string(),
+            tags: config.management.tags.clone(),
+            min_conn: pool.min_connections,
+            max_conn: pool.max_connections,
+            timeout: Duration::from_secs(pool.connect_timeout_secs),
+            max_frame_size: config.listener.max_frame_size,
+            compression: Vec::new(),
+            compression_threshold: config.listener.compression_threshold,
+            tls: None,
+        }).await.unwrap();
+        let slow_queries = Arc::new(SlowQueryLog::new(config.slow_query_log.clone()));
+
+        let reloader = Arc::new(ConfigReloader::new(file.path(), Overrides::default(), config));
+        let installed = Arc::new(OnceLock::new());
+        let _ = installed.set(client.clone());
+        node::watch_reloads(&reloader, keys.clone(), installed, slow_queries).unwrap();
+        Self { file, base, reloader, keys, client }
+    }
+
+    fn contents(base: &[(String, String)], edits: &[(&str, &str)]) -> String {
+        let mut all: Vec<(&str, &str)> = base
+            .iter()
+            .map(|(line, replacement)| (line.as_str(), replacement.as_str()))
+            .collect();
+        all.extend_from_slice(edits);
+        edited(&all)
+    }
+
+    /// Rewrites the file with `edits` on top of the base ones.
+    fn rewrite(&self, edits: &[(&str, &str)]) {
+        self.file.write(&Self::contents(&self.base, edits));
+    }
+
+    async fn wait_for_level(&self, level: &str) {
+        for _ in 0..100 {
+            if self.reloader.active().logging.level == level {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("logging.level never became {}", level);
+    }
+}
+
+/// Edits that change one value of each reloadable kind, and one that
+/// needs a restart.
+const RELOADED: [(&str, &str); 4] = [
+    ("level = \"info\"", "level = \"debug\""),
+    ("max_connections = 8", "max_connections = 4"),
+    ("cluster_token = \"1234567890\"", "cluster_token = \"rotated-token\""),
+    ("max_frame_size = 16777216", "max_frame_size = 1048576"),
+];
+
+#[tokio::test]
+async fn rewritten_config_file_is_applied_live() {
+    let _environment = ENVIRONMENT.lock().await;
+    let node = ReloadingNode::start(&[("watch_interval_secs = 5", "watch_interval_secs = 1")]).await;
+    assert_eq!(log::max_level(), LevelFilter::Info);
+    assert_eq!(node.client.limits().max_conn, 8);
+
+    node.rewrite(&RELOADED);
+    node.wait_for_level("debug").await;
+
+    assert_eq!(log::max_level(), LevelFilter::Debug);
+    assert_eq!(node.client.limits().max_conn, 4);
+    assert_eq!(node.keys.active().1, "rotated-token");
+    let active = node.reloader.active();
+    assert_eq!(active.management.pool.max_connections, 4);
+    assert_eq!(active.listener.max_frame_size, 16777216);
+    assert_eq!(node.reloader.pending_restart(), vec!["listener.max_frame_size".to_string()]);
+
+    // A file that does not validate is dropped as a whole.
+    node.rewrite(&[("level = \"info\"", "level = \"loud\""), ("max_connections = 8", "max_connections = 2")]);
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    assert!(matches!(node.reloader.reload(), Err(ConfigError::Invalid(_))));
+    assert_eq!(node.reloader.active().logging.level, "debug");
+    assert_eq!(log::max_level(), LevelFilter::Debug);
+    assert_eq!(node.client.limits().max_conn, 4);
+    assert_eq!(node.keys.active().1, "rotated-token");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn sighup_reloads_the_config_file() {
+    let _environment = ENVIRONMENT.lock().await;
+    let node = ReloadingNode::start(&[("watch_interval_secs = 5", "watch_interval_secs = 0")]).await;
+
+    node.rewrite(&RELOADED);
+    // Nothing watches the file; only the signal picks it up.
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(node.reloader.active().logging.level, "info");
+
+    let status = std::process::Command::new("kill").args(["-HUP", &std::process::id().to_string()]).status().unwrap();
+    assert!(status.success());
+    node.wait_for_level("debug").await;
+    assert_eq!(log::max_level(), LevelFilter::Debug);
+    assert_eq!(node.client.limits().max_conn, 4);
+    assert_eq!(node.keys.active().1, "rotated-token");
+}
@@ -0,0 +1,256 @@
+use std::collections::{ BTreeMap, HashMap };
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use futures::{ stream, StreamExt };
+use serde_json::{ json, Value };
+use tokio::sync::Mutex;
+use zenith_store::managment::{ MessageClient, MessageConfig };
+use zenith_store::network::{ ListenerConfig, NodeListener, ZenithConnection };
+use zenith_store::network::auth::Principal;
+use zenith_store::protocol::MessageType;
+use zenith_store::statement::{ ColumnDefinition, CreateTableStatement, SelectStatement };
+use zenith_store::storage::{ ExecutionContext, Executor, StorageEngine };
+use zenith_store::transport::Message;
+use zenith_store::utils::config::LoggingConfig;
+use zenith_store::utils::{ logger, KeyRing };
+
+const TOKEN: &str = "test-cluster-token";
+const NODE_ID: &str = "node_7";
+
+/// The logger is process-wide, so tests take turns installing theirs.
+static LOGGER: Mutex<()> = Mutex::const_new(());
+
+/// A directory of its own for the log files of a test.
+struct LogDirectory {
+    path: PathBuf,
+}
+
+impl LogDirectory {
+    fn new() -> Self {
+        let path = std::env::temp_dir().join(format!("zenith-logs-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+
+    fn file(&self, name: &str) -> String {
+        self.path.join(name).to_string_lossy().into_owned()
+    }
+}
+
+impl Drop for LogDirectory {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+fn install(config: &LoggingConfig) {
+    logger::set_node_id(NODE_ID);
+    logger::init(config).unwrap();
+}
+
+fn json_lines(file: &str) -> Vec<Value> {
+    fs::read_to_string(file)
+        .unwrap_or_default()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap_or_else(|e| panic!("{}: {:?}", e, line)))
+        .collect()
+}
+
+fn column(name: &str, col_type: &str) -> ColumnDefinition {
+    ColumnDefinition {
+        name: name.to_string(),
+        col_type: col_type.to_string(),
+        length: 0,
+        primary_key: false,
+        index: false,
+        default_value: String::new(),
+    }
+}
+
+/// A connection logged in to a listener whose `notes` table holds `rows`.
+async fn connect(rows: usize) -> ZenithConnection {
+    let executor = Arc::new(Executor::new(Arc::new(StorageEngine::new())));
+    let context = ExecutionContext { principal: Principal::Node("test".to_string()), database: "default".to_string() };
+    let create = CreateTableStatement::new("notes".to_string(), vec![column("id", "int"), column("note", "text")], None)
+        .unwrap();
+    executor.run(0, &context, &Message::new(MessageType::CreateTable, &create)).unwrap();
+
+    let config = ListenerConfig::new("127.0.0.1:0".to_string(), "node-0".to_string(), KeyRing::single(TOKEN).unwrap());
+    let listener = NodeListener::bind(config, executor).await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(listener.serve());
+
+    let client = tokio::time
+        ::timeout(
+            Duration::from_secs(5),
+            MessageClient::new(MessageConfig {
+                server_addr: address,
+                keys: KeyRing::single(TOKEN).unwrap(),
+                node_id: "node_1".to_string(),
+                address: "".to_string(),
+                tags: vec!["replica".to_string()],
+                min_conn: 1,
+                max_conn: 1,
+                timeout: Duration::from_secs(1),
+                max_frame_size: 16 * 1024 * 1024,
+                compression: Vec::new(),
+                compression_threshold: 1024,
+                tls: None,
+            })
+        ).await
+        .expect("client did not connect")
+        .unwrap();
+    let conn = client.allocate_connection().await.unwrap();
+    let notes = stream::iter(
+        (0..rows).map(|id| HashMap::from([("id".to_string(), json!(id)), ("note".to_string(), json!("note"))]))
+    );
+    conn.bulk_insert_stream("notes", notes, 1000).await.unwrap();
+    conn
+}
+
+/// Lines of `file` that match `found`, waiting up to five seconds for one.
+async fn wait_for(file: &str, found: impl Fn(&Value) -> bool) -> Vec<Value> {
+    for _ in 0..100 {
+        let lines: Vec<Value> = json_lines(file).into_iter().filter(|line| found(line)).collect();
+        if !lines.is_empty() {
+            return lines;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("no matching line in {}:\n{}", file, fs::read_to_string(file).unwrap_or_default());
+}
+
+#[tokio::test]
+async fn json_lines_carry_node_connection_and_message_ids() {
+    let _logger = LOGGER.lock().await;
+    let directory = LogDirectory::new();
+    let file = directory.file("zenith.log");
+    install(&LoggingConfig {
+        level: "warn".to_string(),
+        modules: BTreeMap::from([("zenith_store::network::listener".to_string(), "info".to_string())]),
+        format: "json".to_string(),
+        file: Some(file.clone()),
+        ..LoggingConfig::default()
+    });
+
+    // Enough rows that the listener is still waiting for credit when the
+    // client drops the stream.
+    let conn = connect(8000).await;
+    let select = SelectStatement::new("notes".to_string(), vec!["*".to_string()], String::new()).unwrap();
+    let mut rows = Box::pin(conn.select_rows(&select).await.unwrap());
+    rows.next().await.unwrap().unwrap();
+    drop(rows);
+
+    let cancelled = wait_for(&file, |line| line["message"].as_str().unwrap().starts_with("Stream cancelled")).await;
+    let line = &cancelled[0];
+    assert_eq!(line["level"], "INFO");
+    assert_eq!(line["target"], "zenith_store::network::listener");
+    assert_eq!(line["node_id"], NODE_ID);
+    assert!(line["connection_id"].is_u64(), "{}", line);
+    assert!(uuid::Uuid::parse_str(line["message_id"].as_str().unwrap()).is_ok(), "{}", line);
+    assert!(line["time"].is_string());
+
+    let logins = wait_for(&file, |line| line["message"].as_str().unwrap().starts_with("Node node_1 authenticated")).await;
+    assert_eq!(logins[0]["connection_id"], line["connection_id"]);
+
+    // Below the root level everywhere else.
+    log::info!("hidden");
+    log::warn!(connection_id = 42, message_id = "abc"; "shown");
+    let shown = wait_for(&file, |line| line["message"] == "shown").await;
+    assert_eq!(shown[0]["connection_id"], 42);
+    assert_eq!(shown[0]["message_id"], "abc");
+    assert_eq!(shown[0]["target"], "logging_tests");
+    assert!(json_lines(&file).iter().all(|line| line["message"] != "hidden"));
+}
+
+#[tokio::test]
+async fn changed_settings_take_effect_when_reinstalled() {
+    let _logger = LOGGER.lock().await;
+    let directory = LogDirectory::new();
+    let file = directory.file("zenith.log");
+    let mut config = LoggingConfig {
+        level: "info".to_string(),
+        format: "text".to_string(),
+        file: Some(file.clone()),
+        ..LoggingConfig::default()
+    };
+    install(&config);
+
+    log::info!(connection_id = 5; "as text");
+    log::debug!("too detailed");
+    let text = fs::read_to_string(&file).unwrap();
+    let line = text.lines().find(|line| line.contains("as text")).unwrap();
+    assert!(line.contains("INFO  logging_tests - as text"), "{}", line);
+    assert!(line.contains(&format!(" node_id={}", NODE_ID)), "{}", line);
+    assert!(line.contains(" connection_id=5"), "{}", line);
+    assert!(!text.contains("too detailed"));
+
+    config.level = "debug".to_string();
+    config.format = "json".to_string();
+    config.modules.insert("logging_tests".to_string(), "error".to_string());
+    config.file = Some(directory.file("reloaded.log"));
+    install(&config);
+    log::warn!("muted by its module");
+    log::error!("kept");
+    let lines = json_lines(&directory.file("reloaded.log"))
+        .into_iter()
+        .filter(|line| line["target"] == "logging_tests")
+        .collect::<Vec<_>>();
+    assert_eq!(lines.len(), 1, "{:?}", lines);
+    assert_eq!(lines[0]["message"], "kept");
+    assert_eq!(lines[0]["level"], "ERROR");
+}
+
+#[tokio::test]
+async fn log4rs_file_with_the_json_encoder_is_used_and_rotated() {
+    let _logger = LOGGER.lock().await;
+    let directory = LogDirectory::new();
+    let file = directory.file("zenith.log");
+    let yaml = directory.file("log4rs.yaml");
+    fs::write(
+        &yaml,
+        format!(
+            "appenders:
+  file:
+    kind: rolling_file
+    path: \"{file}\"
+    encoder:
+      kind: {encoder}
+    policy:
+      kind: compound
+      trigger:
+        kind: size
+        limit: 4 kb
+      roller:
+        kind: fixed_window
+        pattern: \"{file}.{{}}\"
+        count: 2
+root:
+  level: warn
+  appenders:
+    - file
+",
+            file = file,
+            encoder = logger::JSON_ENCODER_KIND
+        )
+    ).unwrap();
+    install(&LoggingConfig { config_file: Some(yaml), ..LoggingConfig::default() });
+
+    log::info!("below warn");
+    log::warn!(message_id = "m-1"; "from log4rs.yaml");
+    let lines = json_lines(&file);
+    assert_eq!(lines.len(), 1, "{:?}", lines);
+    assert_eq!(lines[0]["message"], "from log4rs.yaml");
+    assert_eq!(lines[0]["node_id"], NODE_ID);
+    assert_eq!(lines[0]["message_id"], "m-1");
+
+    // Past the size limit the file is rolled over to `<file>.0`.
+    let filler = "x".repeat(1024);
+    for _ in 0..8 {
+        log::warn!("{}", filler);
+    }
+    assert!(PathBuf::from(format!("{}.0", file)).exists());
+    assert!(json_lines(&format!("{}.0", file)).iter().all(|line| line["node_id"] == NODE_ID));
+}
@@ -0,0 +1,528 @@
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use rcgen::{
+    BasicConstraints,
+    Certificate,
+    CertificateParams,
+    ExtendedKeyUsagePurpose,
+    IsCa,
+    KeyPair,
+};
+use tokio::io::{ AsyncReadExt, AsyncWriteExt };
+use tokio::net::{ TcpListener, TcpStream };
+use zenith_store::api::{ rest, ServerListener };
+use zenith_store::managment::{ MessageClient, MessageConfig };
+use zenith_store::network::{
+    ClientTls,
+    DialOptions,
+    HandlerResponse,
+    ListenerConfig,
+    MessageHandler,
+    NodeListener,
+    ServerTls,
+    Session,
+    dial_timeout,
+};
+use zenith_store::protocol::MessageType;
+use zenith_store::storage::{ Executor, StorageEngine };
+use zenith_store::protocol::handshake::{ DEFAULT_MAX_FRAME_SIZE, LEGACY_PROTOCOL_VERSION };
+use zenith_store::statement::{ EmptyStatement, ErrorStatement, LegacyLoginStatement };
+use zenith_store::transport::{ Framing, Message };
+use serde_json::json;
+use zenith_store::utils::audit::{ audit_files, AuditRecord };
+use zenith_store::utils::keyring::{ ClusterKey, DEFAULT_KEY_ID };
+use zenith_store::utils::{ AuditEvent, AuditLog, KeyRing };
+use zenith_store::utils::config::TlsConfig;
+
+const TOKEN: &str = "test-cluster-token";
+
+struct Ca {
+    cert: Certificate,
+    key: KeyPair,
+}
+
+impl Ca {
+    fn new(name: &str) -> Self {
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.distinguished_name.push(rcgen::DnType::CommonName, name);
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let key = KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key).unwrap();
+        Self { cert, key }
+    }
+
+    fn issue(&self, name: &str, purpose: ExtendedKeyUsagePurpose) -> (String, String) {
+        let mut params = CertificateParams::new(vec![name.to_string()]).unwrap();
+        params.extended_key_usages = vec![purpose];
+        let key = KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key, &self.cert, &self.key).unwrap();
+        (cert.pem(), key.serialize_pem())
+    }
+}
+
+struct Pki {
+    dir: PathBuf,
+}
+
+impl Pki {
+    fn new() -> Self {
+        let dir = std::env::temp_dir().join(format!("zenith-tls-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        Self { dir }
+    }
+
+    fn write(&self, name: &str, contents: &str) -> String {
+        let path = self.dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path_string(&path)
+    }
+
+    /// Writes the CA plus a server and a client certificate signed by it.
+    fn issue_all(&self, ca: &Ca) {
+        self.write("ca.pem", &ca.cert.pem());
+        let (cert, key) = ca.issue("localhost", ExtendedKeyUsagePurpose::ServerAuth);
+        self.write("server.pem", &cert);
+        self.write("server.key", &key);
+        let (cert, key) = ca.issue("node_1", ExtendedKeyUsagePurpose::ClientAuth);
+        self.write("client.pem", &cert);
+        self.write("client.key", &key);
+    }
+
+    fn path(&self, name: &str) -> Option<String> {
+        Some(path_string(&self.dir.join(name)))
+    }
+
+    fn server_config(&self) -> TlsConfig {
+        TlsConfig {
+            enabled: true,
+            ca_path: self.path("ca.pem"),
+            cert_path: self.path("server.pem"),
+            key_path: self.path("server.key"),
+            require_client_cert: true,
+            ..Default::default()
+        }
+    }
+
+    fn client_config(&self, with_cert: bool) -> TlsConfig {
+        TlsConfig {
+            enabled: true,
+            ca_path: self.path("ca.pem"),
+            cert_path: if with_cert { self.path("client.pem") } else { None },
+            key_path: if with_cert { self.path("client.key") } else { None },
+            server_name: Some("localhost".to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+impl Drop for Pki {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn path_string(path: &Path) -> String {
+    path.to_string_lossy().to_string()
+}
+
+struct PingHandler;
+
+#[async_trait]
+impl MessageHandler for PingHandler {
+    async fn handle(self: Arc<Self>, session: Arc<Session>, message: Message) -> HandlerResponse {
+        assert!(session.peer_fingerprint().is_some());
+        HandlerResponse::Reply(
+            Message::new_response(&message, MessageType::Pong, &EmptyStatement::new(MessageType::Pong))
+        )
+    }
+}
+
+async fn start_listener(tls: ServerTls) -> String {
+    let mut config = ListenerConfig::new(
+        "127.0.0.1:0".to_string(),
+        "node-0".to_string(),
+        KeyRing::single(TOKEN).unwrap()
+    );
+    config.tls = Some(tls);
+    let listener = NodeListener::bind(config, Arc::new(PingHandler)).await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(listener.serve());
+    address
+}
+
+fn client_config(server_addr: &str, tls: Option<ClientTls>) -> MessageConfig {
+    MessageConfig {
+        server_addr: server_addr.to_string(),
+        keys: KeyRing::single(TOKEN).unwrap(),
+        node_id: "node_1".to_string(),
+        address: "".to_string(),
+        tags: vec!["replica".to_string()],
+        min_conn: 1,
+        max_conn: 1,
+        timeout: Duration::from_secs(1),
+        max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        compression: Vec::new(),
+        compression_threshold: 1024,
+        tls,
+    }
+}
+
+async fn ping(tls: ClientTls, address: &str) -> Result<Message, Box<dyn std::error::Error + Send + Sync>> {
+    let options = DialOptions { tls: Some(tls), ..Default::default() };
+    let conn = dial_timeout(address, options).await?;
+    let ping = Message::new(MessageType::Ping, &EmptyStatement::new(MessageType::Ping));
+    tokio::time::timeout(Duration::from_secs(2), conn.send(&ping)).await?
+}
+
+#[tokio::test]
+async fn mutual_tls_client_logs_in_and_pings() {
+    let pki = Pki::new();
+    pki.issue_all(&Ca::new("zenith-test-ca"));
+
+    let address = start_listener(ServerTls::server(&pki.server_config()).unwrap()).await;
+    let tls = ClientTls::client(&pki.client_config(true)).unwrap();
+
+    let client = tokio::time
+        ::timeout(Duration::from_secs(5), MessageClient::new(client_config(&address, Some(tls)))).await
+        .expect("client did not connect")
+        .unwrap();
+    let conn = client.allocate_connection().await.unwrap();
+
+    let ping = Message::new(MessageType::Ping, &EmptyStatement::new(MessageType::Ping));
+    let response = conn.send(&ping).await.unwrap();
+    assert_eq!(response.header.message_type, MessageType::Pong);
+}
+
+#[tokio::test]
+async fn unauthenticated_requests_are_refused() {
+    let pki = Pki::new();
+    pki.issue_all(&Ca::new("zenith-test-ca"));
+
+    let address = start_listener(ServerTls::server(&pki.server_config()).unwrap()).await;
+    let tls = ClientTls::client(&pki.client_config(true)).unwrap();
+
+    let response = ping(tls, &address).await.unwrap();
+    assert_eq!(response.header.message_type, MessageType::Error);
+}
+
+#[tokio::test]
+async fn client_without_certificate_is_rejected() {
+    let pki = Pki::new();
+    pki.issue_all(&Ca::new("zenith-test-ca"));
+
+    let address = start_listener(ServerTls::server(&pki.server_config()).unwrap()).await;
+    let tls = ClientTls::client(&pki.client_config(false)).unwrap();
+
+    // With TLS 1.3 the client finishes its side of the handshake before the
+    // server checks for a certificate, so the refusal shows up on read.
+    let tcp = TcpStream::connect(&address).await.unwrap();
+    let server_name = tls.server_name("localhost").unwrap();
+    let result = match tls.connector().connect(server_name, tcp).await {
+        Ok(mut stream) => {
+            let _ = stream.write_all(b"hello").await;
+            let mut buf = Vec::new();
+            tokio::time::timeout(Duration::from_secs(2), stream.read_to_end(&mut buf)).await.unwrap()
+        }
+        Err(e) => Err(e),
+    };
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn reload_picks_up_rotated_certificates() {
+    let pki = Pki::new();
+    pki.issue_all(&Ca::new("zenith-test-ca"));
+
+    let server_tls = ServerTls::server(&pki.server_config()).unwrap();
+    let address = start_listener(server_tls.clone()).await;
+
+    // Rotate everything to a new CA: clients of the old one must stop being
+    // accepted once the listener reloads.
+    let old_client = ClientTls::client(&pki.client_config(true)).unwrap();
+    pki.issue_all(&Ca::new("zenith-rotated-ca"));
+    server_tls.reload().unwrap();
+    let new_client = ClientTls::client(&pki.client_config(true)).unwrap();
+
+    assert!(ping(old_client, &address).await.is_err());
+    let response = ping(new_client, &address).await.unwrap();
+    assert_eq!(response.header.message_type, MessageType::Error);
+}
+
+/// A node that predates the handshake: it never answers a Greeting and only
+/// speaks the legacy framing.
+async fn start_legacy_node() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                loop {
+                    let (message, framing) = match
+                        Message::read_with(&mut stream, DEFAULT_MAX_FRAME_SIZE, Framing::detect).await
+                    {
+                        Ok(read) => read,
+                        Err(e) if e.is_recoverable() => {
+                            continue;
+                        }
+                        Err(_) => {
+                            return;
+                        }
+                    };
+                    if framing != Framing::Legacy {
+                        continue;
+                    }
+                    let reply_type = match message.header.message_type {
+                        MessageType::Ping => MessageType::Pong,
+                        message_type => message_type,
+                    };
+                    let reply = Message::new_response(&message, reply_type, &EmptyStatement::new(reply_type));
+                    reply.write_to(&mut stream, Framing::Legacy).await.unwrap();
+                }
+            });
+        }
+    });
+    address
+}
+
+#[tokio::test]
+async fn client_falls_back_to_legacy_framing() {
+    let address = start_legacy_node().await;
+    let client = tokio::time
+        ::timeout(Duration::from_secs(5), MessageClient::new(client_config(&address, None))).await
+        .expect("client did not connect")
+        .unwrap();
+    let conn = client.allocate_connection().await.unwrap();
+    assert_eq!(conn.protocol().protocol_version, LEGACY_PROTOCOL_VERSION);
+    assert_eq!(conn.framing(), Framing::Legacy);
+
+    let ping = Message::new(MessageType::Ping, &EmptyStatement::new(MessageType::Ping));
+    let response = tokio::time::timeout(Duration::from_secs(2), conn.send(&ping)).await.unwrap().unwrap();
+    assert_eq!(response.header.message_type, MessageType::Pong);
+}
+
+#[tokio::test]
+async fn listener_answers_legacy_clients_in_legacy_framing() {
+    let config = ListenerConfig::new(
+        "127.0.0.1:0".to_string(),
+        "node-0".to_string(),
+        KeyRing::single(TOKEN).unwrap()
+    );
+    let listener = NodeListener::bind(config, Arc::new(PingHandler)).await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(listener.serve());
+
+    let mut stream = TcpStream::connect(&address).await.unwrap();
+    let ping = Message::new(MessageType::Ping, &EmptyStatement::new(MessageType::Ping));
+    ping.write_to(&mut stream, Framing::Legacy).await.unwrap();
+
+    let response = tokio::time
+        ::timeout(Duration::from_secs(2), Message::read_from(&mut stream, DEFAULT_MAX_FRAME_SIZE, Framing::Legacy)).await
+        .unwrap()
+        .unwrap();
+    assert_eq!(response.header.message_id, ping.header.message_id);
+    assert_eq!(response.header.message_type, MessageType::Error);
+}
+
+#[tokio::test]
+async fn corrupted_response_fails_the_waiting_request() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let request = Message::read_from(&mut stream, DEFAULT_MAX_FRAME_SIZE, Framing::Current).await.unwrap();
+        let reply = Message::new_response(&request, MessageType::Pong, &EmptyStatement::new(MessageType::Pong));
+        let mut bytes = reply.serialize();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        stream.write_all(&bytes).await.unwrap();
+        // Keep the connection open so only the checksum can fail the request.
+        let mut rest = Vec::new();
+        let _ = stream.read_to_end(&mut rest).await;
+    });
+
+    let conn = dial_timeout(&address, DialOptions::default()).await.unwrap();
+    let ping = Message::new(MessageType::Ping, &EmptyStatement::new(MessageType::Ping));
+    let result = tokio::time::timeout(Duration::from_secs(2), conn.send(&ping)).await.expect("request hung");
+    assert!(result.is_err());
+}
+
+/// Starts a listener and opens a connection to it on the legacy protocol.
+async fn legacy_connection(allow_legacy_login: bool) -> TcpStream {
+    let mut config = ListenerConfig::new(
+        "127.0.0.1:0".to_string(),
+        "node-0".to_string(),
+        KeyRing::single(TOKEN).unwrap()
+    );
+    config.allow_legacy_login = allow_legacy_login;
+    let listener = NodeListener::bind(config, Arc::new(PingHandler)).await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(listener.serve());
+    TcpStream::connect(&address).await.unwrap()
+}
+
+fn legacy_login() -> Message {
+    let stmt = LegacyLoginStatement::new(
+        TOKEN.to_string(),
+        "node-1".to_string(),
+        "node_1".to_string(),
+        false,
+        "".to_string(),
+        vec!["replica".to_string()]
+    ).unwrap();
+    Message::new(MessageType::Login, &stmt)
+}
+
+#[tokio::test]
+async fn legacy_client_logs_in_without_a_nonce() {
+    let mut stream = legacy_connection(true).await;
+    let login = legacy_login();
+    login.write_to(&mut stream, Framing::Legacy).await.unwrap();
+    let response = tokio::time
+        ::timeout(Duration::from_secs(2), Message::read_from(&mut stream, DEFAULT_MAX_FRAME_SIZE, Framing::Legacy)).await
+        .unwrap()
+        .unwrap();
+    assert_eq!(response.header.message_type, MessageType::Login);
+
+    // The same frame again is a replay.
+    login.write_to(&mut stream, Framing::Legacy).await.unwrap();
+    let response = Message::read_from(&mut stream, DEFAULT_MAX_FRAME_SIZE, Framing::Legacy).await.unwrap();
+    assert_eq!(response.header.message_type, MessageType::Error);
+}
+
+#[tokio::test]
+async fn legacy_logins_are_refused_by_default() {
+    assert!(!ListenerConfig::new("127.0.0.1:0".to_string(), "node-0".to_string(), KeyRing::single(TOKEN).unwrap())
+        .allow_legacy_login);
+
+    let mut stream = legacy_connection(false).await;
+    legacy_login().write_to(&mut stream, Framing::Legacy).await.unwrap();
+    let response = tokio::time
+        ::timeout(Duration::from_secs(2), Message::read_from(&mut stream, DEFAULT_MAX_FRAME_SIZE, Framing::Legacy)).await
+        .unwrap()
+        .unwrap();
+    assert_eq!(response.header.message_type, MessageType::Error);
+    let error = ErrorStatement::decode(&response.body).unwrap();
+    assert!(error.message.contains("nonce"), "{}", error.message);
+
+    // Still not logged in.
+    let ping = Message::new(MessageType::Ping, &EmptyStatement::new(MessageType::Ping));
+    ping.write_to(&mut stream, Framing::Legacy).await.unwrap();
+    let response = Message::read_from(&mut stream, DEFAULT_MAX_FRAME_SIZE, Framing::Legacy).await.unwrap();
+    assert_eq!(response.header.message_type, MessageType::Error);
+}
+
+#[tokio::test]
+async fn certificate_watcher_stops_with_its_configuration() {
+    let pki = Pki::new();
+    pki.issue_all(&Ca::new("zenith-test-ca"));
+    let config = TlsConfig { reload_interval_secs: 1, ..pki.server_config() };
+
+    let metrics = tokio::runtime::Handle::current().metrics();
+    let before = metrics.num_alive_tasks();
+    let server_tls = ServerTls::server(&config).unwrap();
+    assert_eq!(metrics.num_alive_tasks(), before + 1);
+
+    drop(server_tls);
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert_eq!(metrics.num_alive_tasks(), before);
+}
+
+/// Serves `keys` the way the management server's key endpoint does.
+async fn start_key_server(keys: Arc<std::sync::Mutex<serde_json::Value>>) -> String {
+    let app = axum::Router::new().route(
+        "/keys",
+        axum::routing::get(move || {
+            let keys = keys.lock().unwrap().clone();
+            async move { axum::Json(keys) }
+        })
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}/keys", address)
+}
+
+fn node_logins(directory: &Path) -> Vec<AuditEvent> {
+    let mut events = Vec::new();
+    for (_, file) in audit_files(directory).unwrap() {
+        for line in fs::read_to_string(file).unwrap().lines() {
+            let record: AuditRecord = serde_json::from_str(line).unwrap();
+            events.push(record.event);
+        }
+    }
+    events
+}
+
+#[tokio::test]
+async fn pooled_connections_log_in_again_after_a_key_rotation() {
+    let directory = std::env::temp_dir().join(format!("zenith-rotation-{}", uuid::Uuid::new_v4()));
+    let server_keys = KeyRing::single(TOKEN).unwrap();
+    let mut config = ListenerConfig::new("127.0.0.1:0".to_string(), "node-0".to_string(), server_keys.clone());
+    config.audit = Some(Arc::new(AuditLog::open(&directory, 1024 * 1024, false).unwrap()));
+    let listener = NodeListener::bind(config, Arc::new(PingHandler)).await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(listener.serve());
+
+    let client_keys = KeyRing::single(TOKEN).unwrap();
+    let client = MessageClient::new(MessageConfig { keys: client_keys.clone(), ..client_config(&address, None) }).await.unwrap();
+    assert!(client.is_authenticated());
+    assert_eq!(node_logins(&directory).len(), 1);
+
+    // The server only accepts the new key from now on; the client picks it
+    // up from the management server and must log in again with it.
+    let next = ClusterKey { id: "next".to_string(), token: "next-token".to_string() };
+    server_keys.update("next", vec![next.clone()]).unwrap();
+    let rotated = vec![ClusterKey { id: DEFAULT_KEY_ID.to_string(), token: TOKEN.to_string() }, next];
+    let key_set = json!({ "active_key_id": "next", "keys": rotated });
+    let url = start_key_server(Arc::new(std::sync::Mutex::new(key_set))).await;
+    client_keys.watch_management(url, Duration::from_millis(50));
+
+    let mut events = node_logins(&directory);
+    for _ in 0..100 {
+        if events.len() > 1 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        events = node_logins(&directory);
+    }
+    assert_eq!(events.len(), 2, "no second login after the rotation: {:?}", events);
+    assert!(matches!(events[1], AuditEvent::NodeLogin { .. }), "{:?}", events[1]);
+    let _ = fs::remove_dir_all(&directory);
+}
+
+async fn start_rest_api(tls: ServerTls) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let listener = ServerListener::new(listener, Some(tls)).unwrap();
+    let address = listener.local_addr().to_string();
+    let executor = Arc::new(Executor::new(Arc::new(StorageEngine::new())));
+    tokio::spawn(rest::serve(listener, executor));
+    address
+}
+
+#[tokio::test]
+async fn rest_api_is_served_over_tls() {
+    let pki = Pki::new();
+    pki.issue_all(&Ca::new("zenith-test-ca"));
+    let address = start_rest_api(ServerTls::server(&pki.server_config()).unwrap()).await;
+
+    let tls = ClientTls::client(&pki.client_config(true)).unwrap();
+    let tcp = TcpStream::connect(&address).await.unwrap();
+    let mut stream = tls.connector().connect(tls.server_name("localhost").unwrap(), tcp).await.unwrap();
+    stream.write_all(b"GET /v1/openapi.json HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.contains("/v1/databases"));
+    // Transactions need a connection that outlives one request.
+    assert!(!response.contains("/v1/transactions"));
+
+    // Plain HTTP gets no answer from a TLS port.
+    let mut plain = TcpStream::connect(&address).await.unwrap();
+    plain.write_all(b"GET /v1/openapi.json HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+    let mut answer = Vec::new();
+    let _ = tokio::time::timeout(Duration::from_secs(5), plain.read_to_end(&mut answer)).await;
+    assert!(!answer.starts_with(b"HTTP/"));
+}
@@ -0,0 +1,228 @@
+use std::sync::Arc;
+use zenith_store::network::auth::Principal;
+use zenith_store::protocol::MessageType;
+use zenith_store::statement::QueryStatement;
+use zenith_store::storage::catalog::{ CatalogError, Privilege, SYSTEM_DATABASE };
+use zenith_store::storage::{ ExecutionContext, ExecutionError, ExecutionResult, Executor, StorageEngine, StorageError };
+use zenith_store::transport::Message;
+use zenith_store::utils::metrics;
+
+fn node() -> ExecutionContext {
+    ExecutionContext { principal: Principal::Node("node-1".to_string()), database: "shop".to_string() }
+}
+
+fn user(name: &str) -> ExecutionContext {
+    ExecutionContext { principal: Principal::User(name.to_string()), database: "shop".to_string() }
+}
+
+fn query(executor: &Executor, ctx: &ExecutionContext, sql: &str) -> Result<ExecutionResult, ExecutionError> {
+    let stmt = QueryStatement::new(sql.to_string()).unwrap();
+    executor.execute(ctx, &Message::new(MessageType::Query, &stmt))
+}
+
+fn denied(result: Result<ExecutionResult, ExecutionError>) -> bool {
+    matches!(result, Err(ExecutionError::PermissionDenied(_)))
+}
+
+/// A `shop` database with an `items` and an `orders` table, a `books`
+/// database, and the users `bob` and `root`, the latter a superuser.
+fn executor() -> Executor {
+    let executor = Executor::new(Arc::new(StorageEngine::new()));
+    query(
+        &executor,
+        &node(),
+        "CREATE DATABASE shop; USE shop;
+         CREATE TABLE items (id int PRIMARY KEY, name text);
+         CREATE TABLE orders (id int PRIMARY KEY, item int);
+         INSERT INTO items (id, name) VALUES (1, 'pen');
+         CREATE DATABASE books; USE books; CREATE TABLE titles (id int PRIMARY KEY);
+         CREATE USER bob WITH PASSWORD 'secret123';
+         CREATE USER root WITH PASSWORD 'secret123' SUPERUSER"
+    ).unwrap();
+    executor
+}
+
+#[test]
+fn nodes_of_the_cluster_are_trusted() {
+    let executor = executor();
+
+    for privilege in [Privilege::Select, Privilege::Insert, Privilege::Update, Privilege::Delete, Privilege::Ddl] {
+        assert_eq!(executor.authorize(&node(), privilege, "shop", "items"), Ok(()));
+    }
+    assert!(query(&executor, &node(), "DELETE FROM items WHERE id = 1; DROP TABLE orders").is_ok());
+}
+
+#[test]
+fn users_start_without_privileges() {
+    let executor = executor();
+    let bob = user("bob");
+
+    assert!(denied(query(&executor, &bob, "SELECT * FROM items")));
+    assert!(denied(query(&executor, &bob, "INSERT INTO items (id, name) VALUES (2, 'ink')")));
+    assert!(denied(query(&executor, &bob, "DROP TABLE items")));
+    assert!(denied(query(&executor, &bob, "CREATE DATABASE mine")));
+}
+
+#[test]
+fn grant_and_revoke_on_one_table() {
+    let executor = executor();
+    let bob = user("bob");
+
+    query(&executor, &node(), "GRANT SELECT ON shop.items TO bob").unwrap();
+    assert!(query(&executor, &bob, "SELECT * FROM items").is_ok());
+    assert!(denied(query(&executor, &bob, "SELECT * FROM orders")));
+    assert!(denied(query(&executor, &bob, "INSERT INTO items (id, name) VALUES (2, 'ink')")));
+
+    query(&executor, &node(), "REVOKE SELECT ON shop.items FROM bob").unwrap();
+    assert!(denied(query(&executor, &bob, "SELECT * FROM items")));
+}
+
+#[test]
+fn revoke_only_removes_the_matching_grant() {
+    let executor = executor();
+    let bob = user("bob");
+
+    query(&executor, &node(), "GRANT SELECT, INSERT ON shop.items TO bob; GRANT SELECT ON shop.* TO bob").unwrap();
+    query(&executor, &node(), "REVOKE SELECT ON shop.items FROM bob").unwrap();
+
+    // The database wide grant still covers the table.
+    assert!(query(&executor, &bob, "SELECT * FROM items").is_ok());
+    assert!(query(&executor, &bob, "INSERT INTO items (id, name) VALUES (2, 'ink')").is_ok());
+}
+
+#[test]
+fn table_wildcard_covers_every_table_of_the_database() {
+    let executor = executor();
+    let bob = user("bob");
+
+    query(&executor, &node(), "GRANT SELECT ON shop.* TO bob").unwrap();
+    assert!(query(&executor, &bob, "SELECT * FROM items").is_ok());
+    assert!(query(&executor, &bob, "SELECT * FROM orders").is_ok());
+    assert!(executor.authorize(&bob, Privilege::Select, "books", "titles").is_err());
+    assert!(denied(query(&executor, &bob, "DELETE FROM items WHERE id = 1")));
+}
+
+#[test]
+fn database_wildcard_covers_every_database() {
+    let executor = executor();
+    let bob = user("bob");
+
+    query(&executor, &node(), "GRANT ALL ON *.* TO bob").unwrap();
+    assert_eq!(executor.authorize(&bob, Privilege::Ddl, "books", "*"), Ok(()));
+    assert_eq!(executor.authorize(&bob, Privilege::Delete, "shop", "orders"), Ok(()));
+    assert!(query(&executor, &bob, "CREATE DATABASE mine").is_ok());
+}
+
+#[test]
+fn grants_reach_users_through_roles() {
+    let executor = executor();
+    let bob = user("bob");
+
+    query(
+        &executor,
+        &node(),
+        "CREATE ROLE reader; CREATE ROLE clerk; GRANT SELECT ON shop.* TO reader;
+         GRANT reader TO clerk; GRANT clerk TO bob"
+    ).unwrap();
+    assert!(query(&executor, &bob, "SELECT * FROM items").is_ok());
+
+    query(&executor, &node(), "REVOKE clerk FROM bob").unwrap();
+    assert!(denied(query(&executor, &bob, "SELECT * FROM items")));
+}
+
+#[test]
+fn listings_only_show_what_the_user_can_use() {
+    let executor = executor();
+    let bob = user("bob");
+
+    query(&executor, &node(), "GRANT SELECT ON shop.items TO bob").unwrap();
+    let databases = match query(&executor, &bob, "SHOW DATABASES").unwrap() {
+        ExecutionResult::Rows(rows) => rows.iter().map(|row| row["name"].clone()).collect::<Vec<_>>(),
+        other => panic!("unexpected result {:?}", other),
+    };
+    assert_eq!(databases, vec!["shop"]);
+    assert!(denied(query(&executor, &bob, "USE books")));
+}
+
+#[test]
+fn system_database_is_read_only_and_for_superusers() {
+    let executor = executor();
+    query(&executor, &node(), "GRANT ALL ON *.* TO bob").unwrap();
+
+    // Not even a grant on every database reaches the catalog.
+    assert!(matches!(
+        executor.authorize(&user("bob"), Privilege::Select, SYSTEM_DATABASE, "users"),
+        Err(ExecutionError::PermissionDenied(_))
+    ));
+
+    for ctx in [user("root"), node()] {
+        assert_eq!(executor.authorize(&ctx, Privilege::Select, SYSTEM_DATABASE, "users"), Ok(()));
+        for privilege in [Privilege::Insert, Privilege::Update, Privilege::Delete, Privilege::Ddl] {
+            assert_eq!(
+                executor.authorize(&ctx, privilege, SYSTEM_DATABASE, "grants"),
+                Err(ExecutionError::Storage(StorageError::ReadOnly(SYSTEM_DATABASE.to_string())))
+            );
+        }
+    }
+}
+
+#[test]
+fn user_management_needs_a_superuser() {
+    let executor = executor();
+    query(&executor, &node(), "GRANT ALL ON *.* TO bob").unwrap();
+    let bob = user("bob");
+
+    assert!(denied(query(&executor, &bob, "CREATE ROLE mine")));
+    assert!(denied(query(&executor, &bob, "GRANT SELECT ON books.* TO bob")));
+    assert!(denied(query(&executor, &bob, "DROP USER root")));
+
+    assert!(query(&executor, &user("root"), "CREATE ROLE mine; GRANT SELECT ON books.* TO mine").is_ok());
+}
+
+#[test]
+fn dropped_user_loses_its_grants() {
+    let executor = executor();
+    query(&executor, &node(), "GRANT SELECT ON shop.* TO bob; DROP USER bob").unwrap();
+    assert!(!executor.catalog().check("bob", Privilege::Select, "shop", "items"));
+}
+
+#[test]
+fn passwords_are_checked() {
+    let executor = executor();
+    let catalog = executor.catalog();
+
+    assert_eq!(catalog.authenticate("bob", "secret123"), Ok(()));
+    assert_eq!(catalog.authenticate("bob", "secret124"), Err(CatalogError::InvalidCredentials));
+    assert_eq!(catalog.authenticate("nobody", "secret123"), Err(CatalogError::InvalidCredentials));
+}
+
+#[test]
+fn verified_passwords_are_cached_until_the_user_is_dropped() {
+    let executor = executor();
+    let catalog = executor.catalog();
+    let hits = || metrics().cache_lookups.with_label_values(&["credentials", "hit"]).get();
+
+    assert_eq!(catalog.authenticate("bob", "secret123"), Ok(()));
+    let before = hits();
+    assert_eq!(catalog.authenticate("bob", "secret123"), Ok(()));
+    assert!(hits() > before);
+    assert_eq!(catalog.authenticate("bob", "secret124"), Err(CatalogError::InvalidCredentials));
+
+    query(&executor, &node(), "DROP USER bob").unwrap();
+    assert_eq!(catalog.authenticate("bob", "secret123"), Err(CatalogError::InvalidCredentials));
+    query(&executor, &node(), "CREATE USER bob WITH PASSWORD 'other456'").unwrap();
+    assert_eq!(catalog.authenticate("bob", "secret123"), Err(CatalogError::InvalidCredentials));
+    assert_eq!(catalog.authenticate("bob", "other456"), Ok(()));
+}
+
+#[test]
+fn credential_cache_lookups_are_exported() {
+    let executor = executor();
+    executor.catalog().authenticate("bob", "secret123").unwrap();
+    executor.catalog().authenticate("bob", "secret123").unwrap();
+
+    let rendered = metrics().render(None);
+    assert!(rendered.contains("zenith_cache_lookups_total{cache=\"credentials\",result=\"hit\"}"), "{}", rendered);
+    assert!(rendered.contains("zenith_cache_lookups_total{cache=\"credentials\",result=\"miss\"}"));
+    assert!(!rendered.contains("wal_fsync"));
+}
@@ -0,0 +1,418 @@
+use std::sync::Arc;
+use serde_json::{ json, Value };
+use zenith_store::network::auth::Principal;
+use zenith_store::protocol::MessageType;
+use zenith_store::sql::{ parse, SqlError };
+use zenith_store::statement::{ DeallocateStatement, ExecuteStatement, PrepareStatement, QueryStatement };
+use zenith_store::storage::{
+    ChangeFeed,
+    ExecutionContext,
+    ExecutionError,
+    ExecutionResult,
+    Executor,
+    StorageEngine,
+    StorageError,
+};
+use zenith_store::transport::Message;
+use zenith_store::utils::config::SlowQueryConfig;
+
+fn context() -> ExecutionContext {
+    ExecutionContext { principal: Principal::Node("test".to_string()), database: "default".to_string() }
+}
+
+fn query(executor: &Executor, ctx: &ExecutionContext, sql: &str) -> Result<ExecutionResult, ExecutionError> {
+    let stmt = QueryStatement::new(sql.to_string()).unwrap();
+    executor.execute(ctx, &Message::new(MessageType::Query, &stmt))
+}
+
+fn prepare(executor: &Executor, connection_id: usize, name: &str, sql: &str) -> Result<ExecutionResult, ExecutionError> {
+    let stmt = PrepareStatement::new(name.to_string(), sql.to_string()).unwrap();
+    executor.run(connection_id, &context(), &Message::new(MessageType::Prepare, &stmt))
+}
+
+fn execute(
+    executor: &Executor,
+    connection_id: usize,
+    name: &str,
+    parameters: Vec<Value>
+) -> Result<ExecutionResult, ExecutionError> {
+    let stmt = ExecuteStatement::new(name.to_string(), parameters).unwrap();
+    executor.run(connection_id, &context(), &Message::new(MessageType::Execute, &stmt))
+}
+
+/// Runs `sql` on a connection, where transactions stay open between queries.
+fn run_sql(
+    executor: &Executor,
+    connection_id: usize,
+    sql: &str
+) -> Result<ExecutionResult, ExecutionError> {
+    let stmt = QueryStatement::new(sql.to_string()).unwrap();
+    executor.run(connection_id, &context(), &Message::new(MessageType::Query, &stmt))
+}
+
+/// Ids of the rows in `items`, as a session outside any transaction sees them.
+fn item_ids(executor: &Executor) -> Vec<Value> {
+    match query(executor, &context(), "SELECT id FROM items ORDER BY id").unwrap() {
+        ExecutionResult::Rows(rows) => rows.iter().map(|row| row["id"].clone()).collect(),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+fn items_executor() -> Executor {
+    let executor = Executor::new(Arc::new(StorageEngine::new()));
+    query(&executor, &context(), "CREATE TABLE items (id int PRIMARY KEY, label text); INSERT INTO items (id) VALUES (1)")
+        .unwrap();
+    executor
+}
+
+fn names(result: ExecutionResult) -> Vec<Value> {
+    match result {
+        ExecutionResult::Rows(rows) => rows.iter().map(|row| row["name"].clone()).collect(),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+/// Index used by the statement run last; every statement is logged as slow.
+fn last_index_used(executor: &Executor) -> Option<String> {
+    executor.slow_query_log().recent(1)[0].index_used.clone()
+}
+
+fn error(sql: &str) -> SqlError {
+    match parse(sql) {
+        Ok(_) => panic!("{:?} should not parse", sql),
+        Err(e) => e,
+    }
+}
+
+#[test]
+fn parses_every_kind_of_statement() {
+    let script = "
+        CREATE DATABASE shop; USE shop;
+        CREATE TABLE users (id int PRIMARY KEY, name varchar(40) INDEX, age int DEFAULT 18);
+        CREATE INDEX users_age ON users (age); SHOW INDEXES FROM users; DESCRIBE users;
+        ALTER TABLE users RENAME TO people; ALTER TABLE people ADD COLUMN email text;
+        INSERT INTO people (id, name) VALUES (1, 'ann'); INSERT INTO people (id, name) VALUES (2, 'bo'), (3, 'cy');
+        INSERT INTO people (id, name) VALUES (1, 'al') ON CONFLICT (id) DO UPDATE;
+        UPDATE people SET age = 30, name = 'al' WHERE id = 1; DELETE FROM people WHERE age IS NULL;
+        SELECT name, count(*) AS n FROM people LEFT JOIN orders ON people.id = orders.user_id
+            WHERE age > 20 GROUP BY name HAVING n > 1 ORDER BY n DESC NULLS LAST LIMIT 10 OFFSET 5;
+        EXPLAIN ANALYZE SELECT * FROM people;
+        BEGIN; SAVEPOINT before_delete; RELEASE SAVEPOINT before_delete; COMMIT;
+        START TRANSACTION t1; ROLLBACK t1;
+        CREATE ROLE analyst; CREATE USER bob WITH PASSWORD 'secret123' IN ROLE analyst;
+        GRANT SELECT, INSERT ON shop.* TO analyst; REVOKE analyst FROM bob; DROP USER bob;
+        SHOW DATABASES; SHOW TABLES; SHOW SLOW QUERIES LIMIT 5;
+        TRUNCATE TABLE people; DROP INDEX users_age ON people; DROP TABLE people; DROP DATABASE shop
+    ";
+    let types: Vec<MessageType> = parse(script).unwrap().iter().map(|stmt| stmt.protocol()).collect();
+    assert_eq!(
+        types,
+        vec![
+            MessageType::CreateDatabase,
+            MessageType::UseDatabase,
+            MessageType::CreateTable,
+            MessageType::CreateIndex,
+            MessageType::ShowIndexes,
+            MessageType::DescribeTable,
+            MessageType::RenameTable,
+            MessageType::AlterTable,
+            MessageType::Insert,
+            MessageType::BulkInsert,
+            MessageType::Upsert,
+            MessageType::Update,
+            MessageType::Delete,
+            MessageType::Select,
+            MessageType::Explain,
+            MessageType::BeginTransaction,
+            MessageType::Savepoint,
+            MessageType::ReleaseSavepoint,
+            MessageType::Commit,
+            MessageType::BeginTransaction,
+            MessageType::Rollback,
+            MessageType::CreateRole,
+            MessageType::CreateUser,
+            MessageType::Grant,
+            MessageType::Revoke,
+            MessageType::DropUser,
+            MessageType::ShowDatabases,
+            MessageType::ShowTables,
+            MessageType::ShowSlowQueries,
+            MessageType::TruncateTable,
+            MessageType::DropIndex,
+            MessageType::DropTable,
+            MessageType::DropDatabase,
+        ]
+    );
+}
+
+#[test]
+fn errors_carry_line_and_column() {
+    let e = error("SELECT name\nFROM users\nWHERE age >");
+    assert_eq!((e.line, e.column), (3, 12));
+
+    let e = error("SELECT * FROM users;\n  INSERT INTO users (id) VALUES (1, 2)");
+    assert_eq!((e.line, e.column), (2, 33));
+    assert!(e.to_string().ends_with("at line 2, column 33"), "{}", e);
+
+    let e = error("SELECT * FROM users WHERE name = 'ann");
+    assert_eq!((e.line, e.column), (1, 34));
+
+    let e = error("COMMIT");
+    assert_eq!((e.line, e.column), (1, 1));
+    let e = error("UPDATE users SET age = other WHERE id = 1");
+    assert_eq!((e.line, e.column), (1, 24));
+}
+
+#[test]
+fn query_runs_its_statements_in_order() {
+    let executor = Executor::new(Arc::new(StorageEngine::new()));
+    let ctx = context();
+    let script = "
+        CREATE TABLE users (id int PRIMARY KEY, name text, age int);
+        INSERT INTO users (id, name, age) VALUES (1, 'ann', 31), (2, 'bo', 17), (3, 'cy', 45);
+        UPDATE users SET age = 18 WHERE name = 'bo';
+    ";
+    assert_eq!(query(&executor, &ctx, script).unwrap(), ExecutionResult::Affected(1));
+
+    let rows = match query(&executor, &ctx, "SELECT name FROM users WHERE age >= 18 ORDER BY age DESC").unwrap() {
+        ExecutionResult::Rows(rows) => rows,
+        other => panic!("unexpected result {:?}", other),
+    };
+    let names: Vec<_> = rows.iter().map(|row| row["name"].clone()).collect();
+    assert_eq!(names, vec![json!("cy"), json!("ann"), json!("bo")]);
+
+    // The failing statement stops the script; the ones before it stay.
+    let e = query(&executor, &ctx, "DELETE FROM users WHERE id = 3; SELECT * FROM missing").unwrap_err();
+    assert!(matches!(e, ExecutionError::Storage(_)), "{:?}", e);
+    let e = query(&executor, &ctx, "SELECT * FROM users WHERE").unwrap_err();
+    assert!(e.to_string().contains("line 1, column 26"), "{}", e);
+
+    let script = "CREATE DATABASE shop; USE shop; CREATE TABLE items (id int PRIMARY KEY); SHOW TABLES";
+    let rows = match query(&executor, &ctx, script).unwrap() {
+        ExecutionResult::Rows(rows) => rows,
+        other => panic!("unexpected result {:?}", other),
+    };
+    assert_eq!(rows.len(), 1);
+    assert!(rows[0].values().any(|value| value == "items"), "{:?}", rows);
+}
+
+#[test]
+fn prepared_statements_bind_their_placeholders() {
+    let executor = Executor::new(Arc::new(StorageEngine::new()));
+    executor.slow_query_log().configure(&SlowQueryConfig { enabled: true, threshold_ms: 0, capacity: 16 });
+    let ctx = context();
+    query(&executor, &ctx, "CREATE TABLE users (id int, name text, age int); CREATE INDEX users_name ON users (name)")
+        .unwrap();
+
+    let insert = "INSERT INTO users (id, name, age) VALUES (?, ?, 30), (?, ?, ?)";
+    assert_eq!(prepare(&executor, 1, "add", insert).unwrap(), ExecutionResult::Affected(5));
+    let rows = vec![json!(1), json!("ann"), json!(2), json!("bo"), json!(17)];
+    assert_eq!(execute(&executor, 1, "add", rows).unwrap(), ExecutionResult::Affected(2));
+    let rows = vec![json!(3), json!("cy"), json!(4), json!("di"), json!(45)];
+    assert_eq!(execute(&executor, 1, "add", rows).unwrap(), ExecutionResult::Affected(2));
+
+    // `$n` placeholders may repeat; the index is keyed on one.
+    let by_name = "SELECT name FROM users WHERE name = $2 OR (name = $1 AND age >= $2)";
+    assert_eq!(prepare(&executor, 1, "by_name", by_name).unwrap(), ExecutionResult::Affected(2));
+    prepare(&executor, 1, "named", "SELECT name FROM users WHERE name = ?").unwrap();
+    assert_eq!(names(execute(&executor, 1, "named", vec![json!("cy")]).unwrap()), vec![json!("cy")]);
+    assert_eq!(last_index_used(&executor).as_deref(), Some("users_name"));
+    assert!(names(execute(&executor, 1, "named", vec![json!("zed")]).unwrap()).is_empty());
+
+    prepare(&executor, 1, "birthday", "UPDATE users SET age = ? WHERE id = ?").unwrap();
+    assert_eq!(execute(&executor, 1, "birthday", vec![json!(18), json!(2)]).unwrap(), ExecutionResult::Affected(1));
+    let adults = "SELECT name FROM users WHERE age >= $1 AND age < $2 ORDER BY age, name";
+    prepare(&executor, 1, "adults", adults).unwrap();
+    let adults = names(execute(&executor, 1, "adults", vec![json!(18), json!(40)]).unwrap());
+    assert_eq!(adults, vec![json!("bo"), json!("ann"), json!("cy")]);
+
+    // Statements belong to the connection that prepared them.
+    let e = execute(&executor, 2, "named", vec![json!("cy")]).unwrap_err();
+    assert!(matches!(e, ExecutionError::InvalidStatement(_)), "{:?}", e);
+
+    let e = execute(&executor, 1, "named", vec![]).unwrap_err();
+    assert!(e.to_string().contains("takes 1 parameters, got 0"), "{}", e);
+    let e = prepare(&executor, 1, "named", "DELETE FROM users WHERE id = ?").unwrap_err();
+    assert!(e.to_string().contains("already exists"), "{}", e);
+    let e = prepare(&executor, 1, "ghost", "SELECT * FROM users WHERE shoe_size = ?").unwrap_err();
+    assert!(matches!(e, ExecutionError::Storage(StorageError::UnknownColumn { .. })), "{:?}", e);
+    let e = prepare(&executor, 1, "ddl", "DROP TABLE users").unwrap_err();
+    assert!(e.to_string().contains("DropTable cannot be prepared"), "{}", e);
+    let e = query(&executor, &ctx, "SELECT * FROM users\nWHERE id = ?").unwrap_err();
+    assert!(e.to_string().contains("only allowed in prepared statements at line 2, column 12"), "{}", e);
+}
+
+#[test]
+fn prepared_statements_are_planned_again_after_schema_changes() {
+    let executor = Executor::new(Arc::new(StorageEngine::new()));
+    executor.slow_query_log().configure(&SlowQueryConfig { enabled: true, threshold_ms: 0, capacity: 16 });
+    let ctx = context();
+    let script = "CREATE TABLE users (id int, name text); INSERT INTO users (id, name) VALUES (1, 'ann'), (2, 'bo')";
+    query(&executor, &ctx, script).unwrap();
+
+    prepare(&executor, 7, "named", "SELECT name FROM users WHERE name = ?").unwrap();
+    assert_eq!(names(execute(&executor, 7, "named", vec![json!("bo")]).unwrap()), vec![json!("bo")]);
+    assert_eq!(last_index_used(&executor), None);
+
+    query(&executor, &ctx, "CREATE INDEX users_name ON users (name)").unwrap();
+    assert_eq!(names(execute(&executor, 7, "named", vec![json!("bo")]).unwrap()), vec![json!("bo")]);
+    assert_eq!(last_index_used(&executor).as_deref(), Some("users_name"));
+
+    query(&executor, &ctx, "DROP TABLE users").unwrap();
+    let e = execute(&executor, 7, "named", vec![json!("bo")]).unwrap_err();
+    assert_eq!(e, ExecutionError::Storage(StorageError::TableNotFound("users".to_string())));
+    query(&executor, &ctx, "CREATE TABLE users (id int, name text); INSERT INTO users (id, name) VALUES (3, 'cy')")
+        .unwrap();
+    assert_eq!(names(execute(&executor, 7, "named", vec![json!("cy")]).unwrap()), vec![json!("cy")]);
+
+    let deallocate = DeallocateStatement::new(Some("named".to_string())).unwrap();
+    let result = executor.run(7, &ctx, &Message::new(MessageType::Deallocate, &deallocate));
+    assert_eq!(result.unwrap(), ExecutionResult::Affected(1));
+    let e = execute(&executor, 7, "named", vec![json!("cy")]).unwrap_err();
+    assert!(e.to_string().contains("no prepared statement named"), "{}", e);
+}
+
+#[test]
+fn prepared_statements_can_be_managed_in_sql() {
+    let executor = Executor::new(Arc::new(StorageEngine::new()));
+    let ctx = context();
+    let run = |sql: &str| {
+        let stmt = QueryStatement::new(sql.to_string()).unwrap();
+        executor.run(3, &ctx, &Message::new(MessageType::Query, &stmt))
+    };
+    run("CREATE TABLE items (id int, label text)").unwrap();
+    let script = "
+        PREPARE add AS INSERT INTO items (id, label) VALUES ($1, $2);
+        EXECUTE add (1, 'pen'); EXECUTE add (2, 'ink');
+        PREPARE pick AS SELECT label FROM items WHERE id = ?;
+        EXECUTE pick (2)
+    ";
+    let rows = match run(script).unwrap() {
+        ExecutionResult::Rows(rows) => rows,
+        other => panic!("unexpected result {:?}", other),
+    };
+    assert_eq!(rows.len(), 1);
+    assert!(rows[0].values().any(|value| value == "ink"), "{:?}", rows);
+
+    assert_eq!(run("DEALLOCATE PREPARE ALL").unwrap(), ExecutionResult::Affected(2));
+    assert!(run("EXECUTE pick (1)").is_err());
+}
+
+#[test]
+fn transaction_commits_its_statements_together() {
+    let executor = items_executor();
+
+    run_sql(&executor, 1, "BEGIN; INSERT INTO items (id) VALUES (2); UPDATE items SET label = 'pen' WHERE id = 1; COMMIT")
+        .unwrap();
+    assert_eq!(item_ids(&executor), vec![json!(1), json!(2)]);
+
+    run_sql(&executor, 1, "BEGIN; INSERT INTO items (id) VALUES (3); DELETE FROM items WHERE id = 1; ROLLBACK").unwrap();
+    assert_eq!(item_ids(&executor), vec![json!(1), json!(2)]);
+}
+
+#[test]
+fn failed_statement_rolls_back_the_transaction_of_its_query() {
+    let executor = items_executor();
+
+    let result = run_sql(&executor, 1, "BEGIN; INSERT INTO items (id) VALUES (2); INSERT INTO missing (id) VALUES (3); COMMIT");
+    assert!(matches!(result, Err(ExecutionError::Storage(StorageError::TableNotFound(_)))));
+    assert_eq!(item_ids(&executor), vec![json!(1)]);
+    // Nothing is left open, or locked.
+    assert!(run_sql(&executor, 1, "INSERT INTO items (id) VALUES (4)").is_ok());
+    assert!(run_sql(&executor, 2, "INSERT INTO items (id) VALUES (5)").is_ok());
+}
+
+#[test]
+fn rollback_to_savepoint_keeps_what_came_before_it() {
+    let executor = items_executor();
+
+    let script = "
+        BEGIN; INSERT INTO items (id) VALUES (2); SAVEPOINT two;
+        INSERT INTO items (id) VALUES (3); SAVEPOINT three; DELETE FROM items WHERE id = 1;
+        ROLLBACK TO SAVEPOINT two; INSERT INTO items (id) VALUES (4);
+        SAVEPOINT four; INSERT INTO items (id) VALUES (5); RELEASE SAVEPOINT four;
+        COMMIT
+    ";
+    run_sql(&executor, 1, script).unwrap();
+    assert_eq!(item_ids(&executor), vec![json!(1), json!(2), json!(4), json!(5)]);
+
+    let e = run_sql(&executor, 1, "BEGIN; SAVEPOINT a; RELEASE SAVEPOINT a; ROLLBACK TO SAVEPOINT a").unwrap_err();
+    assert_eq!(e, ExecutionError::InvalidStatement("no savepoint a".to_string()));
+}
+
+#[test]
+fn open_transaction_is_isolated_from_other_connections() {
+    let executor = items_executor();
+
+    run_sql(&executor, 1, "BEGIN t1; INSERT INTO items (id) VALUES (2)").unwrap();
+    // Connection 1 reads its own writes, the others what was committed.
+    match run_sql(&executor, 1, "SELECT id FROM items").unwrap() {
+        ExecutionResult::Rows(rows) => assert_eq!(rows.len(), 2),
+        other => panic!("unexpected result {:?}", other),
+    }
+    assert_eq!(item_ids(&executor), vec![json!(1)]);
+    assert_eq!(
+        run_sql(&executor, 2, "DELETE FROM items WHERE id = 1").unwrap_err(),
+        ExecutionError::Storage(StorageError::Locked("items".to_string()))
+    );
+    assert!(run_sql(&executor, 2, "DROP TABLE items").is_err());
+
+    run_sql(&executor, 1, "COMMIT t1").unwrap();
+    assert_eq!(item_ids(&executor), vec![json!(1), json!(2)]);
+    assert!(run_sql(&executor, 2, "DELETE FROM items WHERE id = 1").is_ok());
+}
+
+#[test]
+fn transactions_are_checked_against_the_connection() {
+    let executor = items_executor();
+
+    assert!(run_sql(&executor, 1, "BEGIN t1; CREATE TABLE other (id int)").is_err());
+    // The query opened t1, so its failure rolled t1 back.
+    assert!(run_sql(&executor, 1, "BEGIN t1").is_ok());
+    assert!(run_sql(&executor, 1, "BEGIN t2").is_err());
+    // t1 was open before this query, so it stays open.
+    assert!(run_sql(&executor, 1, "CREATE TABLE other (id int)").is_err());
+    assert!(run_sql(&executor, 2, "COMMIT t1").is_err());
+    assert!(run_sql(&executor, 1, "ROLLBACK t1").is_ok());
+    assert!(run_sql(&executor, 1, "COMMIT t1").is_err());
+    // Without a connection there is nothing to keep a transaction open on.
+    assert!(query(&executor, &context(), "BEGIN; COMMIT").is_err());
+}
+
+#[test]
+fn changes_reach_the_feed_on_commit_only() {
+    let feed = Arc::new(ChangeFeed::new(100));
+    let executor = Executor::new(Arc::new(StorageEngine::new().with_change_feed(feed.clone())));
+    query(&executor, &context(), "CREATE TABLE items (id int PRIMARY KEY)").unwrap();
+
+    run_sql(&executor, 1, "BEGIN t1; INSERT INTO items (id) VALUES (1), (2)").unwrap();
+    assert_eq!(feed.sequence(), 0);
+    run_sql(&executor, 1, "COMMIT t1").unwrap();
+    assert_eq!(feed.sequence(), 2);
+
+    run_sql(&executor, 1, "BEGIN; DELETE FROM items WHERE id = 1; ROLLBACK").unwrap();
+    assert_eq!(feed.sequence(), 2);
+}
+
+#[test]
+fn slow_query_log_redacts_filter_literals() {
+    let executor = items_executor();
+    executor.slow_query_log().configure(&SlowQueryConfig { enabled: true, threshold_ms: 0, capacity: 16 });
+    query(&executor, &context(), "CREATE TABLE tags (item int, label text)").unwrap();
+    let script = "UPDATE items SET label = 'pen' WHERE label = 'hunter2';
+         DELETE FROM items WHERE id = 42 AND label != 'hunter2';
+         SELECT items.id FROM items JOIN tags ON items.id = tags.item AND tags.label = 'hunter2'
+           WHERE items.label = 'hunter2' OR items.id > 42";
+    run_sql(&executor, 1, script).unwrap();
+
+    let logged: Vec<String> = executor.slow_query_log().recent(16).into_iter().map(|slow| slow.statement).collect();
+    let update = logged.iter().find(|statement| statement.starts_with("UpdateStatement")).unwrap();
+    assert!(update.contains("Where: label = <redacted>"), "{}", update);
+    let delete = logged.iter().find(|statement| statement.starts_with("DeleteStatement")).unwrap();
+    assert!(delete.contains("id = <redacted> AND label != <redacted>"), "{}", delete);
+    let select = logged.iter().find(|statement| statement.starts_with("SelectStatement")).unwrap();
+    assert!(select.contains("tags.label = <redacted>"), "{}", select);
+    for statement in &logged {
+        assert!(!statement.contains("hunter2") && !statement.contains("42"), "{}", statement);
+    }
+}
@@ -0,0 +1,681 @@
+use std::collections::{ HashMap, HashSet };
+use std::sync::Arc;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde_json::{ json, Value };
+use zenith_store::network::auth::Principal;
+use zenith_store::protocol::MessageType;
+use zenith_store::statement::{
+    Aggregate,
+    ColumnDefinition,
+    DeleteStatement,
+    ExplainStatement,
+    Join,
+    JoinKind,
+    OrderBy,
+    SelectStatement,
+    Statement,
+    UpdateStatement,
+};
+use zenith_store::storage::{
+    AccessPath,
+    ExecutionContext,
+    ExecutionError,
+    ExecutionResult,
+    Executor,
+    SpillConfig,
+    StorageEngine,
+    StorageError,
+};
+use zenith_store::transport::{ Message, Row };
+use zenith_store::utils::KeyRing;
+use zenith_store::utils::keyring::ClusterKey;
+
+const DATABASE: &str = "default";
+const USERS: usize = 40;
+const ORDERS: usize = 400;
+
+fn column(name: &str, primary_key: bool, index: bool) -> ColumnDefinition {
+    ColumnDefinition {
+        name: name.to_string(),
+        col_type: "int".to_string(),
+        length: 0,
+        primary_key,
+        index,
+        default_value: String::new(),
+    }
+}
+
+fn row(values: &[(&str, Value)]) -> Row {
+    values
+        .iter()
+        .map(|(column, value)| (column.to_string(), value.clone()))
+        .collect()
+}
+
+fn users() -> Vec<Row> {
+    (0..USERS)
+        .map(|i| {
+            let age = if i % 5 == 0 { Value::Null } else { json!(18 + ((i * 7) % 40)) };
+            row(&[("id", json!(i)), ("name", json!(format!("user{}", i % 7))), ("age", age)])
+        })
+        .collect()
+}
+
+fn orders() -> Vec<Row> {
+    (0..ORDERS)
+        .map(|j| {
+            let user_id = if j % 17 == 0 { Value::Null } else { json!((j * 13) % 50) };
+            row(&[("id", json!(j)), ("user_id", user_id), ("amount", json!((j * 31) % 100))])
+        })
+        .collect()
+}
+
+/// An executor over `users` and `orders`, with an index on
+/// `orders.user_id` when `indexed`, sorting and hashing within `budget`.
+fn executor(indexed: bool, budget: usize) -> Executor {
+    let engine = StorageEngine::new();
+    let user_columns = vec![column("id", true, false), column("name", false, false), column("age", false, false)];
+    let order_columns = vec![column("id", true, false), column("user_id", false, indexed), column("amount", false, false)];
+    engine.create_table(DATABASE, "users", user_columns).unwrap();
+    engine.create_table(DATABASE, "orders", order_columns).unwrap();
+    engine.insert(DATABASE, "users", users()).unwrap();
+    engine.insert(DATABASE, "orders", orders()).unwrap();
+    let spill = SpillConfig { memory_budget: budget, directory: std::env::temp_dir().join("zenith-join-tests") };
+    Executor::new(Arc::new(engine)).with_spill(spill)
+}
+
+fn context() -> ExecutionContext {
+    ExecutionContext { principal: Principal::Node("test".to_string()), database: DATABASE.to_string() }
+}
+
+fn select(executor: &Executor, stmt: &SelectStatement) -> Result<Vec<Row>, ExecutionError> {
+    match executor.execute(&context(), &Message::new(MessageType::Select, stmt))? {
+        ExecutionResult::Rows(rows) => Ok(rows),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+/// Rows of one page of a select with a limit, and the token for the next.
+fn page(executor: &Executor, stmt: &SelectStatement) -> Result<(Vec<Row>, Option<String>), ExecutionError> {
+    match executor.execute(&context(), &Message::new(MessageType::Select, stmt))? {
+        ExecutionResult::Page { rows, continuation } => Ok((rows, continuation)),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+/// Ids of all the rows of `stmt`, read `size` rows a page.
+fn paged_ids(executor: &Executor, stmt: &SelectStatement, size: u64) -> Vec<Value> {
+    let mut ids = Vec::new();
+    let mut next = stmt.clone().with_limit(size);
+    loop {
+        let (rows, continuation) = page(executor, &next).unwrap();
+        ids.extend(rows.iter().map(|row| row["id"].clone()));
+        match continuation {
+            Some(token) => next = stmt.clone().with_limit(size).with_after(token),
+            None => break ids,
+        }
+    }
+}
+
+fn ids(rows: Vec<Row>) -> Vec<Value> {
+    rows.iter().map(|row| row["id"].clone()).collect()
+}
+
+/// Nodes of the statement's plan, run when `analyze`.
+fn explained(executor: &Executor, stmt: &dyn Statement, analyze: bool) -> Vec<Row> {
+    let explain = ExplainStatement::new(stmt, analyze).unwrap();
+    match executor.execute(&context(), &Message::new(MessageType::Explain, &explain)).unwrap() {
+        ExecutionResult::Rows(nodes) => nodes,
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+/// Nodes of the select's plan, as run by EXPLAIN ANALYZE.
+fn analyzed(executor: &Executor, stmt: &SelectStatement) -> Vec<Row> {
+    explained(executor, stmt, true)
+}
+
+/// How the select was sorted, as EXPLAIN ANALYZE reports it.
+fn sort_used(executor: &Executor, stmt: &SelectStatement) -> String {
+    analyzed(executor, stmt)[0]["sort"].as_str().unwrap().to_string()
+}
+
+fn sorted_orders(order_by: Vec<OrderBy>) -> SelectStatement {
+    SelectStatement::new("orders".to_string(), Vec::new(), String::new())
+        .and_then(|stmt| stmt.with_order_by(order_by))
+        .unwrap()
+}
+
+/// Operators of the join nodes of the select's plan.
+fn join_operators(executor: &Executor, stmt: &SelectStatement) -> Vec<String> {
+    analyzed(executor, stmt)
+        .iter()
+        .filter(|node| !node["join"].is_null())
+        .map(|node| node["operator"].as_str().unwrap().to_string())
+        .collect()
+}
+
+fn qualify(table: &str, row: &Row) -> Row {
+    row.iter()
+        .map(|(column, value)| (format!("{}.{}", table, column), value.clone()))
+        .collect()
+}
+
+/// Every pair of a user and an order `on` accepts, users without one kept
+/// by a left join, then `filter`: the join computed the slow way.
+fn brute_force(
+    kind: JoinKind,
+    on: impl Fn(&Row, &Row) -> bool,
+    filter: impl Fn(&Row) -> bool
+) -> Vec<Row> {
+    let orders = orders();
+    let mut rows = Vec::new();
+    for user in users() {
+        let mut matched = false;
+        for order in orders.iter().filter(|order| on(&user, order)) {
+            matched = true;
+            let mut joined = qualify("users", &user);
+            joined.extend(qualify("orders", order));
+            rows.push(joined);
+        }
+        if !matched && kind == JoinKind::Left {
+            let mut joined = qualify("users", &user);
+            joined.extend(["id", "user_id", "amount"].map(|column| (format!("orders.{}", column), Value::Null)));
+            rows.push(joined);
+        }
+    }
+    rows.retain(|row| filter(row));
+    rows
+}
+
+/// Rows as sorted JSON text, to compare results regardless of order.
+fn normalized(rows: Vec<Row>) -> Vec<String> {
+    let mut rows: Vec<String> = rows
+        .into_iter()
+        .map(|row| {
+            let row: serde_json::Map<String, Value> = row.into_iter().collect();
+            Value::Object(row).to_string()
+        })
+        .collect();
+    rows.sort();
+    rows
+}
+
+fn same_user(user: &Row, order: &Row) -> bool {
+    !user["id"].is_null() && user["id"] == order["user_id"]
+}
+
+fn user_join(kind: JoinKind, on: &str, filter: &str) -> SelectStatement {
+    let join = Join { kind, table_name: "orders".to_string(), on: on.to_string() };
+    SelectStatement::new("users".to_string(), Vec::new(), filter.to_string())
+        .and_then(|stmt| stmt.with_joins(vec![join]))
+        .unwrap()
+}
+
+#[test]
+fn hash_join_matches_brute_force() {
+    let executor = executor(false, 64 << 20);
+    let stmt = user_join(JoinKind::Inner, "users.id = orders.user_id", "age > 30");
+    assert_eq!(join_operators(&executor, &stmt), vec!["HashJoin"]);
+    let expected = brute_force(JoinKind::Inner, same_user, |row| row["users.age"].as_u64().is_some_and(|age| age > 30));
+    assert!(!expected.is_empty());
+    assert_eq!(normalized(select(&executor, &stmt).unwrap()), normalized(expected));
+
+    let stmt = user_join(JoinKind::Left, "users.id = orders.user_id AND orders.amount < 20", "");
+    assert_eq!(join_operators(&executor, &stmt), vec!["HashJoin"]);
+    let on = |user: &Row, order: &Row| same_user(user, order) && order["amount"].as_u64().unwrap() < 20;
+    assert_eq!(normalized(select(&executor, &stmt).unwrap()), normalized(brute_force(JoinKind::Left, on, |_| true)));
+}
+
+#[test]
+fn index_nested_loop_join_matches_brute_force() {
+    let executor = executor(true, 64 << 20);
+    // Written with the larger table first; the planner starts from users.
+    let join = Join::inner("users", "orders.user_id = users.id");
+    let stmt = SelectStatement::new("orders".to_string(), Vec::new(), "amount >= 50".to_string())
+        .and_then(|stmt| stmt.with_joins(vec![join]))
+        .unwrap();
+    assert_eq!(join_operators(&executor, &stmt), vec!["IndexNestedLoopJoin"]);
+    let filter = |row: &Row| row["orders.amount"].as_u64().unwrap() >= 50;
+    let expected = brute_force(JoinKind::Inner, same_user, filter);
+    assert_eq!(normalized(select(&executor, &stmt).unwrap()), normalized(expected));
+
+    let stmt = user_join(JoinKind::Left, "users.id = orders.user_id AND orders.amount > 90", "orders.id IS NULL");
+    assert_eq!(join_operators(&executor, &stmt), vec!["IndexNestedLoopJoin"]);
+    let on = |user: &Row, order: &Row| same_user(user, order) && order["amount"].as_u64().unwrap() > 90;
+    let expected = brute_force(JoinKind::Left, on, |row| row["orders.id"].is_null());
+    assert!(!expected.is_empty());
+    assert_eq!(normalized(select(&executor, &stmt).unwrap()), normalized(expected));
+}
+
+#[test]
+fn merge_join_matches_brute_force() {
+    // Too small a budget for a hash table over orders: both sides are
+    // sorted, spilling to disk, and merged.
+    let executor = executor(false, 2048);
+    let stmt = user_join(JoinKind::Inner, "users.id = orders.user_id", "");
+    assert_eq!(join_operators(&executor, &stmt), vec!["MergeJoin"]);
+    assert_eq!(normalized(select(&executor, &stmt).unwrap()), normalized(brute_force(JoinKind::Inner, same_user, |_| true)));
+
+    let stmt = user_join(JoinKind::Left, "users.id = orders.user_id AND users.age <> orders.amount", "");
+    assert_eq!(join_operators(&executor, &stmt), vec!["MergeJoin"]);
+    let on = |user: &Row, order: &Row| same_user(user, order) && user["age"].is_number() && user["age"] != order["amount"];
+    assert_eq!(normalized(select(&executor, &stmt).unwrap()), normalized(brute_force(JoinKind::Left, on, |_| true)));
+}
+
+#[test]
+fn nested_loop_join_matches_brute_force() {
+    let executor = executor(true, 64 << 20);
+    let on = |user: &Row, order: &Row| {
+        let user_id = user["id"].as_u64().unwrap();
+        order["user_id"].as_u64().is_some_and(|order_user| user_id < order_user) && user_id > 35
+    };
+    for kind in [JoinKind::Inner, JoinKind::Left] {
+        let stmt = user_join(kind, "users.id < orders.user_id AND users.id > 35", "");
+        assert_eq!(join_operators(&executor, &stmt), vec!["NestedLoopJoin"]);
+        let expected = brute_force(kind, on, |_| true);
+        assert_eq!(normalized(select(&executor, &stmt).unwrap()), normalized(expected));
+    }
+}
+
+#[test]
+fn join_rejects_ambiguous_and_unknown_columns() {
+    let executor = executor(false, 64 << 20);
+    let stmt = user_join(JoinKind::Inner, "id = user_id", "");
+    assert!(matches!(select(&executor, &stmt), Err(ExecutionError::Storage(_))));
+    let stmt = user_join(JoinKind::Inner, "users.id = orders.missing", "");
+    assert!(matches!(select(&executor, &stmt), Err(ExecutionError::Storage(_))));
+}
+
+#[test]
+fn writes_past_the_size_limit_are_refused() {
+    let users = users();
+    let size: usize = {
+        let engine = StorageEngine::new();
+        engine.create_table(DATABASE, "users", Vec::new()).unwrap();
+        engine.insert(DATABASE, "users", users.clone()).unwrap();
+        engine.size()
+    };
+    let engine = StorageEngine::new().with_max_size(size + 100);
+    engine.create_table(DATABASE, "users", Vec::new()).unwrap();
+    engine.insert(DATABASE, "users", users.clone()).unwrap();
+    assert_eq!(engine.size(), size);
+
+    let big = row(&[("id", json!(100)), ("name", json!("x".repeat(200)))]);
+    assert_eq!(engine.insert(DATABASE, "users", vec![big.clone()]), Err(StorageError::Full((size + 100) as u64)));
+    let updates = [("name".to_string(), json!("x".repeat(200)))].into_iter().collect();
+    let update = engine.update(DATABASE, "users", &updates, &AccessPath::FullScan, |_| true);
+    assert_eq!(update, Err(StorageError::Full((size + 100) as u64)));
+    assert_eq!(engine.size(), size);
+
+    // Deleting rows makes room again.
+    let deleted = engine.delete(DATABASE, "users", &AccessPath::FullScan, |row| row["id"].as_u64() < Some(4));
+    assert_eq!(deleted, Ok(4));
+    assert!(engine.size() < size);
+    assert_eq!(engine.insert(DATABASE, "users", vec![big]), Ok(1));
+    engine.truncate(DATABASE, "users").unwrap();
+    assert_eq!(engine.size(), 0);
+}
+
+#[test]
+fn external_sort_matches_in_memory_sort() {
+    let stmt = sorted_orders(vec![OrderBy::desc("amount"), OrderBy::asc("user_id")]);
+    let in_memory = executor(false, 64 << 20);
+    let external = executor(false, 2048);
+    assert!(sort_used(&in_memory, &stmt).starts_with("in-memory"));
+    assert!(sort_used(&external, &stmt).starts_with("external merge"));
+
+    let rows = select(&in_memory, &stmt).unwrap();
+    assert_eq!(rows.len(), ORDERS);
+    let amounts: Vec<u64> = rows.iter().map(|row| row["amount"].as_u64().unwrap()).collect();
+    assert!(amounts.windows(2).all(|pair| pair[0] >= pair[1]));
+    assert_eq!(ids(select(&external, &stmt).unwrap()), ids(rows));
+}
+
+#[test]
+fn top_n_sort_keeps_the_page_of_the_full_sort() {
+    let stmt = sorted_orders(vec![OrderBy::asc("amount")]);
+    let full = ids(select(&executor(false, 64 << 20), &stmt).unwrap());
+    for budget in [64 << 20, 8192] {
+        let executor = executor(false, budget);
+        let stmt = stmt.clone().with_offset(5).with_limit(10);
+        assert!(sort_used(&executor, &stmt).starts_with("top-n (15)"), "{}", sort_used(&executor, &stmt));
+        assert_eq!(ids(page(&executor, &stmt).unwrap().0), full[5..15]);
+    }
+}
+
+#[test]
+fn nulls_sort_where_asked() {
+    let executor = executor(false, 64 << 20);
+    let nulls = ORDERS.div_ceil(17);
+    let user_ids = |order: OrderBy| -> Vec<Value> {
+        select(&executor, &sorted_orders(vec![order]))
+            .unwrap()
+            .into_iter()
+            .map(|row| row["user_id"].clone())
+            .collect()
+    };
+    let leading = |values: &[Value]| values.iter().take_while(|value| value.is_null()).count();
+    let trailing = |values: &[Value]| values.iter().rev().take_while(|value| value.is_null()).count();
+
+    let ascending = user_ids(OrderBy::asc("user_id"));
+    assert_eq!(trailing(&ascending), nulls);
+    assert_eq!(ascending[0], json!(0));
+    assert_eq!(leading(&user_ids(OrderBy::asc("user_id").nulls_first(true))), nulls);
+    let descending = user_ids(OrderBy::desc("user_id"));
+    assert_eq!(leading(&descending), nulls);
+    assert_eq!(descending[nulls], json!(49));
+    assert_eq!(trailing(&user_ids(OrderBy::desc("user_id").nulls_first(false))), nulls);
+}
+
+#[test]
+fn keyset_pages_return_every_row_once_across_ties() {
+    let plain = executor(false, 64 << 20);
+    let indexed = executor(true, 64 << 20);
+    let orders = [
+        vec![OrderBy::asc("amount")],
+        vec![OrderBy::asc("user_id")],
+        vec![OrderBy::desc("user_id"), OrderBy::asc("amount")],
+        vec![OrderBy::asc("user_id").nulls_first(true)],
+        vec![OrderBy::desc("user_id").nulls_first(false)],
+    ];
+    for order_by in orders {
+        let stmt = sorted_orders(order_by);
+        let full = ids(select(&plain, &stmt).unwrap());
+        // Pages of 5 and 11 end inside runs of rows tied on the sort keys.
+        assert_eq!(paged_ids(&plain, &stmt, 5), full, "{:?}", stmt.order_by);
+        assert_eq!(paged_ids(&indexed, &stmt, 5), full, "{:?}", stmt.order_by);
+        assert_eq!(paged_ids(&indexed, &stmt, 11), full, "{:?}", stmt.order_by);
+    }
+}
+
+#[test]
+fn keyset_pages_read_the_index_in_order() {
+    let executor = executor(true, 64 << 20);
+    let stmt = sorted_orders(vec![OrderBy::desc("user_id")]).with_limit(5);
+    let (_, token) = page(&executor, &stmt).unwrap();
+    let stmt = stmt.with_after(token.unwrap());
+    let nodes = analyzed(&executor, &stmt);
+    assert!(nodes[0]["sort"].as_str().unwrap().contains("reading index user_id_idx in order"), "{:?}", nodes);
+    // The rows with the highest user ids, not the whole table.
+    let scanned = nodes[1]["actual_rows"].as_u64().unwrap();
+    assert!(scanned < (ORDERS / 10) as u64, "{} rows scanned", scanned);
+}
+
+#[test]
+fn continuation_tokens_carry_only_sort_keys_and_are_signed() {
+    let executor = executor(false, 64 << 20);
+    let stmt = sorted_orders(vec![OrderBy::asc("amount")]).with_limit(3);
+    let (rows, token) = page(&executor, &stmt).unwrap();
+    let token = token.unwrap();
+    let bytes = URL_SAFE_NO_PAD.decode(&token).unwrap();
+    let (payload, mac) = bytes.split_at(bytes.len() - 32);
+    let payload: Value = serde_json::from_slice(payload).unwrap();
+    assert_eq!(payload["keys"], json!([rows[2]["amount"]]));
+    assert!(payload.get("last").is_none() && !payload.to_string().contains("user_id"), "{}", payload);
+
+    // A boundary moved by the client no longer matches its MAC.
+    let mut forged = payload.clone();
+    forged["keys"] = json!([90]);
+    let mut bytes = serde_json::to_vec(&forged).unwrap();
+    bytes.extend_from_slice(mac);
+    let forged = stmt.clone().with_after(URL_SAFE_NO_PAD.encode(bytes));
+    assert!(matches!(page(&executor, &forged), Err(ExecutionError::InvalidStatement(_))));
+    assert!(page(&executor, &stmt.with_after(token)).is_ok());
+}
+
+fn cluster_keys(ids: &[&str]) -> Vec<ClusterKey> {
+    ids.iter().map(|id| ClusterKey { id: id.to_string(), token: format!("token-{}", id) }).collect()
+}
+
+#[test]
+fn continuation_tokens_are_signed_with_the_cluster_keys_across_rotations() {
+    let keys = KeyRing::new("k1", cluster_keys(&["k1"])).unwrap();
+    let issuing = executor(false, 64 << 20).with_token_keys(keys.clone());
+    // Another node of the cluster, with its own copy of the ring.
+    let other = executor(false, 64 << 20).with_token_keys(KeyRing::new("k1", cluster_keys(&["k1"])).unwrap());
+    let stranger = executor(false, 64 << 20);
+
+    let stmt = sorted_orders(vec![OrderBy::asc("amount")]).with_limit(3);
+    let old = page(&issuing, &stmt).unwrap().1.unwrap();
+    let bytes = URL_SAFE_NO_PAD.decode(&old).unwrap();
+    let payload: Value = serde_json::from_slice(&bytes[..bytes.len() - 32]).unwrap();
+    assert_eq!(payload["key_id"], "k1");
+
+    let next = stmt.clone().with_after(old.clone());
+    let expected = ids(page(&issuing, &next).unwrap().0);
+    assert_eq!(ids(page(&other, &next).unwrap().0), expected);
+    assert!(matches!(page(&stranger, &next), Err(ExecutionError::InvalidStatement(_))));
+
+    // A rotated ring signs with the new key and still takes the old one.
+    keys.update("k2", cluster_keys(&["k1", "k2"])).unwrap();
+    assert_eq!(ids(page(&issuing, &next).unwrap().0), expected);
+    let new = page(&issuing, &stmt).unwrap().1.unwrap();
+    let bytes = URL_SAFE_NO_PAD.decode(&new).unwrap();
+    let payload: Value = serde_json::from_slice(&bytes[..bytes.len() - 32]).unwrap();
+    assert_eq!(payload["key_id"], "k2");
+
+    // Once the old key is dropped, so are its tokens.
+    keys.update("k2", cluster_keys(&["k2"])).unwrap();
+    match page(&issuing, &next) {
+        Err(ExecutionError::InvalidStatement(message)) => assert!(message.contains("k1"), "{}", message),
+        other => panic!("expected an invalid token, got {:?}", other.map(|(rows, _)| rows.len())),
+    }
+    assert!(page(&issuing, &stmt.with_after(new)).is_ok());
+}
+
+fn orders_by_user(aggregates: Vec<Aggregate>) -> SelectStatement {
+    SelectStatement::new("orders".to_string(), Vec::new(), String::new())
+        .and_then(|stmt| stmt.with_aggregates(aggregates))
+        .and_then(|stmt| stmt.with_group_by(vec!["user_id".to_string()]))
+        .unwrap()
+}
+
+/// How the select aggregated, as EXPLAIN ANALYZE reports it.
+fn aggregate_used(executor: &Executor, stmt: &SelectStatement) -> String {
+    let nodes = analyzed(executor, stmt);
+    let node = nodes.iter().find(|node| !node["aggregate"].is_null()).unwrap();
+    node["aggregate"].as_str().unwrap().to_string()
+}
+
+/// Partitions a spilled aggregation used, across every level.
+fn partitions_used(aggregate: &str) -> usize {
+    let (_, partitions) = aggregate.rsplit_once(", ").unwrap();
+    partitions.trim_end_matches(" partitions)").parse().unwrap()
+}
+
+#[test]
+fn spilled_aggregation_matches_in_memory_aggregation() {
+    let stmt = orders_by_user(vec![
+        Aggregate::count_all().with_alias("n"),
+        Aggregate::sum("amount"),
+        Aggregate::avg("amount"),
+        Aggregate::min("amount"),
+        Aggregate::max("amount"),
+        Aggregate::count_distinct("amount").with_alias("distinct_amounts"),
+    ]);
+    let in_memory = executor(false, 64 << 20);
+    let spilled = executor(false, 1500);
+    assert!(aggregate_used(&in_memory, &stmt).contains("hash (51 groups)"));
+    // Partitions outgrow the budget too and are partitioned again.
+    let used = aggregate_used(&spilled, &stmt);
+    assert!(used.contains("spilled hash (51 groups"), "{}", used);
+    assert!(partitions_used(&used) > 16, "{}", used);
+
+    let rows = select(&in_memory, &stmt).unwrap();
+    assert_eq!(normalized(select(&spilled, &stmt).unwrap()), normalized(rows.clone()));
+    let counted: u64 = rows.iter().map(|row| row["n"].as_u64().unwrap()).sum();
+    assert_eq!(counted, ORDERS as u64);
+}
+
+#[test]
+fn sum_falls_back_to_float_when_integers_overflow() {
+    let engine = StorageEngine::new();
+    engine.create_table(DATABASE, "totals", Vec::new()).unwrap();
+    let totals = [(1, json!(i64::MAX)), (1, json!(1)), (2, json!(1)), (2, json!(2)), (3, json!(0.5)), (3, json!(1))];
+    let rows = totals.iter().map(|(group, value)| row(&[("g", json!(group)), ("v", value.clone())])).collect();
+    engine.insert(DATABASE, "totals", rows).unwrap();
+    let engine = Arc::new(engine);
+    let stmt = SelectStatement::new("totals".to_string(), Vec::new(), String::new())
+        .and_then(|stmt| stmt.with_aggregates(vec![Aggregate::sum("v").with_alias("total")]))
+        .and_then(|stmt| stmt.with_group_by(vec!["g".to_string()]))
+        .and_then(|stmt| stmt.with_order_by(vec![OrderBy::asc("g")]))
+        .unwrap();
+    // With a tiny budget every row spills and the sums overflow as partitions merge.
+    for budget in [64 << 20, 16] {
+        let spill = SpillConfig { memory_budget: budget, directory: std::env::temp_dir().join("zenith-sum-tests") };
+        let executor = Executor::new(engine.clone()).with_spill(spill);
+        let totals: Vec<Value> = select(&executor, &stmt)
+            .unwrap()
+            .into_iter()
+            .map(|row| row["total"].clone())
+            .collect();
+        assert_eq!(totals, vec![json!((i64::MAX as f64) + 1.0), json!(3), json!(1.5)]);
+        assert!(totals[0].is_f64() && totals[1].is_i64());
+    }
+}
+
+#[test]
+fn count_distinct_counts_values_once_across_partitions() {
+    let mut expected: HashMap<String, HashSet<u64>> = HashMap::new();
+    for order in orders() {
+        expected.entry(order["user_id"].to_string()).or_default().insert(order["amount"].as_u64().unwrap());
+    }
+    let stmt = orders_by_user(vec![Aggregate::count_distinct("amount").with_alias("amounts")]);
+    // A budget of 16 bytes spills each row, so a group's values are spread over many partial states.
+    for budget in [64 << 20, 16] {
+        let rows = select(&executor(false, budget), &stmt).unwrap();
+        assert_eq!(rows.len(), expected.len());
+        for row in rows {
+            assert_eq!(row["amounts"], json!(expected[&row["user_id"].to_string()].len()), "{:?}", row);
+        }
+    }
+
+    let all = SelectStatement::new("orders".to_string(), Vec::new(), String::new())
+        .and_then(|stmt| stmt.with_aggregates(vec![Aggregate::count_distinct("amount").with_alias("amounts")]))
+        .unwrap();
+    assert_eq!(select(&executor(false, 16), &all).unwrap()[0]["amounts"], json!(100));
+}
+
+#[test]
+fn having_filters_groups_whether_spilled_or_not() {
+    let mut counts: HashMap<String, u64> = HashMap::new();
+    for order in orders() {
+        *counts.entry(order["user_id"].to_string()).or_default() += 1;
+    }
+    let mut expected: Vec<(String, u64)> = counts.into_iter().filter(|(_, n)| *n > 7).collect();
+    expected.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    assert!(!expected.is_empty() && expected.len() < 51);
+
+    let stmt = orders_by_user(vec![Aggregate::count_all().with_alias("n")]).with_having("n > 7".to_string());
+    for budget in [64 << 20, 1500] {
+        let executor = executor(false, budget);
+        let mut rows: Vec<(String, u64)> = select(&executor, &stmt)
+            .unwrap()
+            .into_iter()
+            .map(|row| (row["user_id"].to_string(), row["n"].as_u64().unwrap()))
+            .collect();
+        rows.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        assert_eq!(rows, expected);
+
+        // Sorted, and cut to a page, as the groups stream out.
+        let sorted = stmt
+            .clone()
+            .with_order_by(vec![OrderBy::desc("n"), OrderBy::asc("user_id")])
+            .unwrap()
+            .with_offset(1)
+            .with_limit(3);
+        let counts: Vec<u64> = page(&executor, &sorted)
+            .unwrap()
+            .0.iter()
+            .map(|row| row["n"].as_u64().unwrap())
+            .collect();
+        let top: Vec<u64> = expected[1..4].iter().map(|(_, n)| *n).collect();
+        assert_eq!(counts, top);
+
+        let unsorted = stmt.clone().with_offset(2).with_limit(4);
+        assert_eq!(select(&executor, &unsorted).unwrap().len(), 4);
+    }
+}
+
+/// The select, update and delete of `orders` filtered by `filter`.
+fn filtered_orders(filter: &str) -> Vec<Box<dyn Statement>> {
+    vec![
+        Box::new(SelectStatement::new("orders".to_string(), Vec::new(), filter.to_string()).unwrap()),
+        Box::new(
+            UpdateStatement::new(
+                "orders".to_string(),
+                HashMap::from([("amount".to_string(), json!(0))]),
+                filter.to_string()
+            ).unwrap()
+        ),
+        Box::new(DeleteStatement::new("orders".to_string(), Some(filter.to_string())).unwrap()),
+    ]
+}
+
+fn orders_where(matches: impl Fn(&Row) -> bool) -> u64 {
+    orders().iter().filter(|order| matches(order)).count() as u64
+}
+
+#[test]
+fn explain_reads_an_index_for_an_indexed_equality_and_scans_otherwise() {
+    let unindexed = executor(false, 64 << 20);
+    let executor = executor(true, 64 << 20);
+
+    for stmt in filtered_orders("user_id = 3") {
+        let nodes = explained(&executor, stmt.as_ref(), false);
+        let scan = nodes.last().unwrap();
+        assert_eq!(scan["operator"], "IndexScan", "{}", stmt.to_string());
+        assert_eq!(scan["index"], "user_id_idx");
+        assert_eq!(scan["index_key"], "3");
+        assert_eq!(scan["pushed_predicate"], Value::Null);
+        assert_eq!(scan["estimated_rows"], json!(orders_where(|order| order["user_id"] == json!(3))));
+        assert_eq!(nodes[0]["actual_rows"], Value::Null);
+    }
+
+    for stmt in filtered_orders("amount = 3") {
+        let nodes = explained(&executor, stmt.as_ref(), false);
+        let scan = nodes.last().unwrap();
+        assert_eq!(scan["operator"], "FullScan", "{}", stmt.to_string());
+        assert_eq!(scan["index"], Value::Null);
+        assert_eq!(scan["pushed_predicate"], "(amount = 3)");
+    }
+
+    // Without the index the equality is a filtered full scan too.
+    for stmt in filtered_orders("user_id = 3") {
+        assert_eq!(explained(&unindexed, stmt.as_ref(), false).last().unwrap()["operator"], "FullScan");
+    }
+
+    // A plain EXPLAIN of a write leaves the table alone.
+    assert_eq!(executor.engine().row_count(DATABASE, "orders").unwrap(), ORDERS);
+}
+
+#[test]
+fn explain_analyze_reports_the_rows_each_operator_handled() {
+    let executor = executor(true, 64 << 20);
+    let by_user = orders_where(|order| order["user_id"] == json!(3));
+    let cheap = orders_where(|order| order["amount"].as_u64().unwrap() < 10);
+    assert!(by_user > 0 && cheap > 0);
+
+    for (stmt, expected) in [
+        (filtered_orders("user_id = 3").remove(0), by_user),
+        (filtered_orders("amount < 10").remove(0), cheap),
+        (filtered_orders("user_id = 3").remove(1), by_user),
+    ] {
+        let nodes = explained(&executor, stmt.as_ref(), true);
+        assert_eq!(nodes[0]["actual_rows"], json!(expected), "{}", stmt.to_string());
+        assert_eq!(nodes.last().unwrap()["actual_rows"], json!(expected), "{}", stmt.to_string());
+        assert!(nodes.iter().all(|node| node["actual_ms"].as_f64().is_some()));
+    }
+
+    // ANALYZE runs the statement, writes included.
+    let updated = select(&executor, &SelectStatement::new("orders".to_string(), Vec::new(), "user_id = 3".to_string()).unwrap());
+    assert!(updated.unwrap().iter().all(|order| order["amount"] == json!(0)));
+    let nodes = explained(&executor, filtered_orders("amount < 10").remove(2).as_ref(), true);
+    // The update zeroed the amounts of the orders of user 3.
+    let deleted = orders_where(|order| order["amount"].as_u64().unwrap() < 10 || order["user_id"] == json!(3));
+    assert_eq!(nodes[0]["actual_rows"], json!(deleted));
+    assert_eq!(nodes.last().unwrap()["operator"], "FullScan");
+    assert_eq!(executor.engine().row_count(DATABASE, "orders").unwrap(), ORDERS - (deleted as usize));
+}
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use futures::{ stream, StreamExt };
+use rmp_serde::decode;
+use serde_json::json;
+use tokio::sync::{ oneshot, Notify };
+use zenith_store::managment::{ MessageClient, MessageConfig };
+use zenith_store::network::{ HandlerResponse, ListenerConfig, MessageHandler, NodeListener, Session, ZenithConnection };
+use zenith_store::network::auth::Principal;
+use zenith_store::protocol::MessageType;
+use zenith_store::statement::{
+    ColumnDefinition,
+    CreateTableStatement,
+    DeleteStatement,
+    ResultStatement,
+    SelectStatement,
+};
+use zenith_store::storage::{ ExecutionContext, ExecutionError, Executor, StorageEngine, StorageError };
+use zenith_store::transport::{ ChunkKind, FlowControl, Message, Row };
+use zenith_store::transport::stream::INITIAL_STREAM_WINDOW;
+use zenith_store::utils::KeyRing;
+
+const TOKEN: &str = "test-cluster-token";
+/// Small enough that the payloads below take many frames.
+const MAX_BODY_SIZE: u32 = 32 * 1024;
+const ROWS: usize = 2000;
+
+fn client_config(server_addr: &str) -> MessageConfig {
+    MessageConfig {
+        server_addr: server_addr.to_string(),
+        keys: KeyRing::single(TOKEN).unwrap(),
+        node_id: "node_1".to_string(),
+        address: "".to_string(),
+        tags: vec!["replica".to_string()],
+        min_conn: 1,
+        max_conn: 1,
+        timeout: Duration::from_secs(1),
+        max_frame_size: MAX_BODY_SIZE,
+        compression: Vec::new(),
+        compression_threshold: 1024,
+        tls: None,
+    }
+}
+
+async fn serve(handler: Arc<dyn MessageHandler>) -> String {
+    let mut config = ListenerConfig::new("127.0.0.1:0".to_string(), "node-0".to_string(), KeyRing::single(TOKEN).unwrap());
+    config.max_body_size = MAX_BODY_SIZE;
+    let listener = NodeListener::bind(config, handler).await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(listener.serve());
+    address
+}
+
+async fn connect(address: &str) -> ZenithConnection {
+    let client = tokio::time
+        ::timeout(Duration::from_secs(5), MessageClient::new(client_config(address))).await
+        .expect("client did not connect")
+        .unwrap();
+    client.allocate_connection().await.unwrap()
+}
+
+fn context() -> ExecutionContext {
+    ExecutionContext { principal: Principal::Node("test".to_string()), database: "default".to_string() }
+}
+
+/// An executor with an empty `notes` table.
+fn executor() -> Arc<Executor> {
+    let executor = Arc::new(Executor::new(Arc::new(StorageEngine::new())));
+    let columns = vec![column("id", "int"), column("note", "text")];
+    let create = CreateTableStatement::new("notes".to_string(), columns, None).unwrap();
+    executor.run(0, &context(), &Message::new(MessageType::CreateTable, &create)).unwrap();
+    executor
+}
+
+fn column(name: &str, col_type: &str) -> ColumnDefinition {
+    ColumnDefinition {
+        name: name.to_string(),
+        col_type: col_type.to_string(),
+        length: 0,
+        primary_key: false,
+        index: false,
+        default_value: String::new(),
+    }
+}
+
+fn note(id: usize) -> Row {
+    HashMap::from([("id".to_string(), json!(id)), ("note".to_string(), json!(format!("{:0>48}", id)))])
+}
+
+fn row_count(executor: &Executor) -> usize {
+    executor.engine().row_count("default", "notes").unwrap()
+}
+
+/// Whether an open transaction, such as that of an upload, holds the table.
+fn is_locked(executor: &Executor) -> bool {
+    let delete = DeleteStatement::new("notes".to_string(), Some("id = -1".to_string())).unwrap();
+    match executor.run(0, &context(), &Message::new(MessageType::Delete, &delete)) {
+        Ok(_) => false,
+        Err(ExecutionError::Storage(StorageError::Locked(_))) => true,
+        Err(e) => panic!("unexpected error: {}", e),
+    }
+}
+
+fn affected(response: &Message) -> u64 {
+    assert_eq!(response.header.message_type, MessageType::BulkInsert, "{:?}", String::from_utf8_lossy(&response.body));
+    decode::from_slice::<ResultStatement>(&response.body).unwrap().affected_rows
+}
+
+fn select_notes() -> SelectStatement {
+    SelectStatement::new("notes".to_string(), vec!["*".to_string()], String::new()).unwrap()
+}
+
+#[tokio::test]
+async fn upload_and_select_larger_than_a_frame_are_chunked() {
+    let executor = executor();
+    let conn = connect(&serve(executor.clone()).await).await;
+
+    let rows = stream::iter((0..ROWS).map(note));
+    let response = conn.bulk_insert_stream("notes", rows, 100).await.unwrap();
+    assert_eq!(affected(&response), ROWS as u64);
+    assert_eq!(row_count(&executor), ROWS);
+
+    let selected: Vec<Row> = conn
+        .select_rows(&select_notes()).await
+        .unwrap()
+        .map(|row| row.unwrap())
+        .collect().await;
+    assert_eq!(selected.len(), ROWS);
+    let payload = rmp_serde::to_vec_named(&selected).unwrap().len();
+    assert!(payload > (MAX_BODY_SIZE as usize) * 2, "only {} bytes", payload);
+
+    // Joined into one response, the result would not fit in a frame.
+    assert!(conn.send(&Message::new(MessageType::Select, &select_notes())).await.is_err());
+}
+
+#[tokio::test]
+async fn failed_upload_leaves_nothing_behind() {
+    let executor = executor();
+    let conn = connect(&serve(executor.clone()).await).await;
+
+    let mut rows: Vec<Row> = (0..ROWS).map(note).collect();
+    rows[ROWS / 2].insert("missing".to_string(), json!(true));
+    let response = conn.bulk_insert_stream("notes", stream::iter(rows), 100).await.unwrap();
+    assert_eq!(response.header.message_type, MessageType::Error);
+    assert!(!is_locked(&executor));
+    assert_eq!(row_count(&executor), 0);
+}
+
+#[tokio::test]
+async fn upload_cut_off_by_a_disconnect_is_rolled_back() {
+    let executor = executor();
+    let address = serve(executor.clone()).await;
+
+    // The client runs on a runtime of its own, which is dropped to close
+    // its connection halfway through the upload.
+    let (stop, stopped) = oneshot::channel::<()>();
+    let client = std::thread::spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async move {
+            let conn = connect(&address).await;
+            let rows = stream::iter((0..300).map(note)).chain(stream::pending());
+            tokio::select! {
+                _ = conn.bulk_insert_stream("notes", rows, 100) => panic!("the upload cannot finish"),
+                _ = stopped => {}
+            }
+        });
+    });
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    while !is_locked(&executor) {
+        assert!(tokio::time::Instant::now() < deadline, "the upload did not start");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    stop.send(()).unwrap();
+    client.join().unwrap();
+
+    while is_locked(&executor) {
+        assert!(tokio::time::Instant::now() < deadline, "the upload was not rolled back");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(row_count(&executor), 0);
+}
+
+#[tokio::test]
+async fn flow_control_blocks_without_credit() {
+    let window = FlowControl::new(2);
+    window.acquire().await.unwrap();
+    window.acquire().await.unwrap();
+    assert!(tokio::time::timeout(Duration::from_millis(50), window.acquire()).await.is_err());
+
+    window.grant(1);
+    window.acquire().await.unwrap();
+    window.cancel();
+    assert!(window.acquire().await.is_err());
+}
+
+/// Holds the first chunk of an upload until released, so no credit comes
+/// back for it.
+struct StallingHandler {
+    release: Arc<Notify>,
+}
+
+#[async_trait]
+impl MessageHandler for StallingHandler {
+    async fn handle(self: Arc<Self>, _session: Arc<Session>, message: Message) -> HandlerResponse {
+        match message.header.chunk_kind() {
+            ChunkKind::First => {
+                self.release.notified().await;
+                HandlerResponse::None
+            }
+            ChunkKind::Last | ChunkKind::Single => {
+                let message_type = message.header.message_type;
+                HandlerResponse::Reply(Message::new_response(&message, message_type, &ResultStatement::new(message_type, 0)))
+            }
+            ChunkKind::Continuation => HandlerResponse::None,
+        }
+    }
+}
+
+#[tokio::test]
+async fn uploader_without_credit_waits() {
+    let release = Arc::new(Notify::new());
+    let conn = connect(&serve(Arc::new(StallingHandler { release: release.clone() })).await).await;
+
+    let pulled = Arc::new(AtomicUsize::new(0));
+    let counter = pulled.clone();
+    let chunks = (INITIAL_STREAM_WINDOW as usize) * 4;
+    let bodies = stream::iter(0..chunks).map(move |_| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        vec![0u8; 64]
+    });
+    let upload = tokio::spawn(async move { conn.send_chunked(MessageType::BulkInsert, bodies).await });
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(!upload.is_finished());
+    // The window, then the chunk waiting for credit and the one looked at
+    // ahead of it to tell whether it is the last.
+    assert_eq!(pulled.load(Ordering::SeqCst), (INITIAL_STREAM_WINDOW as usize) + 2);
+
+    release.notify_one();
+    let response = tokio::time::timeout(Duration::from_secs(5), upload).await.unwrap().unwrap().unwrap();
+    assert_eq!(response.header.message_type, MessageType::BulkInsert);
+    assert_eq!(pulled.load(Ordering::SeqCst), chunks);
+}
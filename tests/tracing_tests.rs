@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use opentelemetry::trace::{ SpanId, TraceId };
+use opentelemetry_sdk::export::trace::SpanData;
+use opentelemetry_sdk::testing::trace::InMemorySpanExporter;
+use opentelemetry_sdk::trace::TracerProvider;
+use serde_json::json;
+use tracing::Instrument;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+use zenith_store::managment::{ MessageClient, MessageConfig };
+use zenith_store::network::{ ListenerConfig, NodeListener };
+use zenith_store::protocol::MessageType;
+use zenith_store::protocol::handshake::DEFAULT_MAX_FRAME_SIZE;
+use zenith_store::statement::{ ColumnDefinition, CreateTableStatement, EmptyStatement, InsertStatement };
+use zenith_store::storage::{ Executor, StorageEngine };
+use zenith_store::transport::{ FrameError, Message };
+use zenith_store::utils::{ telemetry, KeyRing };
+
+const TOKEN: &str = "test-cluster-token";
+const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+fn traced() -> (TracerProvider, InMemorySpanExporter) {
+    let exporter = InMemorySpanExporter::default();
+    let provider = TracerProvider::builder().with_simple_exporter(exporter.clone()).build();
+    (provider, exporter)
+}
+
+fn span<'a>(spans: &'a [SpanData], name: &str) -> &'a SpanData {
+    spans
+        .iter()
+        .find(|span| span.name == name)
+        .unwrap_or_else(|| panic!("no {} span in {:?}", name, spans.iter().map(|s| &s.name).collect::<Vec<_>>()))
+}
+
+fn assert_child_of(child: &SpanData, parent: &SpanData) {
+    assert_eq!(child.span_context.trace_id(), parent.span_context.trace_id());
+    assert_eq!(child.parent_span_id, parent.span_context.span_id());
+}
+
+#[test]
+fn frame_round_trips_trace_context() {
+    let mut message = Message::new(MessageType::Ping, &EmptyStatement::new(MessageType::Ping));
+    message.trace_context = Some(TRACEPARENT.to_string());
+
+    let decoded = Message::deserialize(&message.serialize()).unwrap();
+    assert!(decoded.header.has_trace_context());
+    assert_eq!(decoded.trace_context.as_deref(), Some(TRACEPARENT));
+    assert_eq!(decoded.body, message.body);
+}
+
+#[test]
+fn frame_without_trace_context_is_unchanged() {
+    let message = Message::new(MessageType::Ping, &EmptyStatement::new(MessageType::Ping));
+    let serialized = message.serialize();
+
+    let decoded = Message::deserialize(&serialized).unwrap();
+    assert!(!decoded.header.has_trace_context());
+    assert_eq!(decoded.trace_context, None);
+    assert_eq!(serialized.len(), zenith_store::transport::MESSAGE_HEADER_SIZE + message.body.len());
+}
+
+#[test]
+fn trace_context_is_covered_by_the_checksum() {
+    let mut message = Message::new(MessageType::Ping, &EmptyStatement::new(MessageType::Ping));
+    message.trace_context = Some(TRACEPARENT.to_string());
+    let mut serialized = message.serialize();
+    // Flip a character of the trace id, just past the length byte.
+    let offset = zenith_store::transport::MESSAGE_HEADER_SIZE + 5;
+    serialized[offset] ^= 0x01;
+
+    assert!(matches!(Message::deserialize(&serialized), Err(FrameError::ChecksumMismatch { .. })));
+}
+
+#[test]
+fn traceparent_round_trips_through_spans() {
+    let (provider, exporter) = traced();
+    let subscriber = Registry::default().with(telemetry::layer(&provider));
+
+    tracing::subscriber::with_default(subscriber, || {
+        let span = tracing::info_span!("remote.child");
+        telemetry::set_remote_parent(&span, TRACEPARENT);
+        assert_eq!(
+            telemetry::traceparent(&span).map(|value| value[..36].to_string()),
+            Some(TRACEPARENT[..36].to_string())
+        );
+    });
+
+    let _ = provider.force_flush();
+    let spans = exporter.get_finished_spans().unwrap();
+    let child = span(&spans, "remote.child");
+    assert_eq!(child.span_context.trace_id(), TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap());
+    assert_eq!(child.parent_span_id, SpanId::from_hex("00f067aa0ba902b7").unwrap());
+}
+
+#[test]
+fn untraced_span_has_no_traceparent() {
+    let span = tracing::info_span!("untraced");
+    assert_eq!(telemetry::traceparent(&span), None);
+}
+
+fn client_config(server_addr: &str) -> MessageConfig {
+    MessageConfig {
+        server_addr: server_addr.to_string(),
+        keys: KeyRing::single(TOKEN).unwrap(),
+        node_id: "node_1".to_string(),
+        address: "".to_string(),
+        tags: vec!["replica".to_string()],
+        min_conn: 1,
+        max_conn: 1,
+        timeout: Duration::from_secs(1),
+        max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        compression: Vec::new(),
+        compression_threshold: 1024,
+        tls: None,
+    }
+}
+
+fn column(name: &str, col_type: &str) -> ColumnDefinition {
+    ColumnDefinition {
+        name: name.to_string(),
+        col_type: col_type.to_string(),
+        length: 0,
+        primary_key: false,
+        index: false,
+        default_value: String::new(),
+    }
+}
+
+// Single threaded, so every task of the client and the listener runs with
+// the subscriber set for this test.
+#[tokio::test]
+async fn request_is_traced_from_send_to_storage() {
+    let (provider, exporter) = traced();
+    let _guard = tracing::subscriber::set_default(Registry::default().with(telemetry::layer(&provider)));
+
+    let executor = Arc::new(Executor::new(Arc::new(StorageEngine::new())));
+    let config = ListenerConfig::new("127.0.0.1:0".to_string(), "node-0".to_string(), KeyRing::single(TOKEN).unwrap());
+    let listener = NodeListener::bind(config, executor).await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(listener.serve());
+
+    let client = tokio::time
+        ::timeout(Duration::from_secs(5), MessageClient::new(client_config(&address))).await
+        .expect("client did not connect")
+        .unwrap();
+    let conn = client.allocate_connection().await.unwrap();
+    assert!(conn.protocol().supports_trace_context());
+
+    let create = CreateTableStatement::new(
+        "orders".to_string(),
+        vec![column("id", "int"), column("item", "text")],
+        None
+    ).unwrap();
+    let response = conn.send(&Message::new(MessageType::CreateTable, &create)).await.unwrap();
+    assert_ne!(response.header.message_type, MessageType::Error);
+
+    let values = HashMap::from([("id".to_string(), json!(1)), ("item".to_string(), json!("book"))]);
+    let insert = InsertStatement::new("orders".to_string(), values).unwrap();
+    let response = conn
+        .send(&Message::new(MessageType::Insert, &insert))
+        .instrument(tracing::info_span!("checkout"))
+        .await
+        .unwrap();
+    assert_ne!(response.header.message_type, MessageType::Error);
+
+    // The server may still be closing its spans after the response is out.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let _ = provider.force_flush();
+    let spans = exporter.get_finished_spans().unwrap();
+
+    let root = span(&spans, "checkout");
+    let sends: Vec<&SpanData> = spans
+        .iter()
+        .filter(|span| span.name == "zenith.send" && span.parent_span_id == root.span_context.span_id())
+        .collect();
+    assert_eq!(sends.len(), 1);
+    let send = sends[0];
+
+    let handle = spans
+        .iter()
+        .find(|span| span.name == "zenith.handle" && span.parent_span_id == send.span_context.span_id())
+        .expect("the listener did not continue the trace");
+    let execute = spans
+        .iter()
+        .find(|span| span.name == "executor.execute" && span.parent_span_id == handle.span_context.span_id())
+        .expect("no executor span under the handler");
+    assert_child_of(handle, send);
+    assert_child_of(execute, handle);
+    assert_child_of(span(&spans, "storage.insert"), execute);
+}
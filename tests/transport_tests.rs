@@ -0,0 +1,197 @@
+use zenith_store::protocol::MessageType;
+use zenith_store::statement::EmptyStatement;
+use zenith_store::transport::header::{ HEADER_VERSION, LEGACY_HEADER_SIZE };
+use zenith_store::transport::{
+    AssemblyError,
+    ChunkAssembler,
+    ChunkKind,
+    Codec,
+    FrameError,
+    Framing,
+    Message,
+    MessageTypeFlag,
+};
+
+fn ping() -> Message {
+    Message::new(MessageType::Ping, &EmptyStatement::new(MessageType::Ping))
+}
+
+#[test]
+fn header_carries_current_version() {
+    let serialized = ping().serialize();
+    assert_eq!(serialized[4], HEADER_VERSION);
+    assert!(Message::deserialize(&serialized).is_ok());
+}
+
+#[test]
+fn header_without_flags_byte_is_rejected_by_version() {
+    let mut serialized = ping().serialize();
+    serialized[4] = 1;
+
+    match Message::deserialize(&serialized) {
+        Err(FrameError::UnsupportedVersion(1)) => {}
+        other => panic!("expected an unsupported version, got {:?}", other.map(|m| m.header)),
+    }
+}
+
+fn pong(body: Vec<u8>) -> Message {
+    let mut message = ping();
+    message.header.message_type = MessageType::Pong;
+    message.body = body;
+    message
+}
+
+#[tokio::test]
+async fn read_skips_garbage_before_a_frame() {
+    let message = pong(b"after garbage".to_vec());
+    let mut bytes = vec![0xde, 0xad, 0x00, 0x13, 0x37];
+    bytes.extend(message.serialize());
+
+    let decoded = Message::read_from(&mut bytes.as_slice(), 1024, Framing::Current).await.unwrap();
+    assert_eq!(decoded.header.message_id, message.header.message_id);
+    assert_eq!(decoded.body, b"after garbage");
+}
+
+#[tokio::test]
+async fn corrupted_frame_is_reported_and_the_next_one_is_read() {
+    let corrupted = pong(b"corrupted".to_vec());
+    let intact = pong(b"intact".to_vec());
+    let mut bytes = corrupted.serialize();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 0xff;
+    bytes.extend(intact.serialize());
+
+    let mut reader = bytes.as_slice();
+    match Message::read_from(&mut reader, 1024, Framing::Current).await {
+        Err(e @ FrameError::ChecksumMismatch { message_id, .. }) => {
+            assert!(e.is_recoverable());
+            assert_eq!(message_id, corrupted.header.message_id);
+        }
+        other => panic!("expected a checksum mismatch, got {:?}", other.map(|m| m.header)),
+    }
+
+    let decoded = Message::read_from(&mut reader, 1024, Framing::Current).await.unwrap();
+    assert_eq!(decoded.body, b"intact");
+}
+
+#[tokio::test]
+async fn oversized_body_is_refused_before_reading_it() {
+    // A frame hidden in the body must not be read once the outer one is refused.
+    let mut body = pong(b"forged".to_vec()).serialize();
+    body.resize(2048, 7);
+    let bytes = pong(body).serialize();
+
+    match Message::read_from(&mut bytes.as_slice(), 1024, Framing::Current).await {
+        Err(e @ FrameError::BodyTooLarge { body_size: 2048, max_body_size: 1024 }) => assert!(!e.is_recoverable()),
+        other => panic!("expected an oversized body, got {:?}", other.map(|m| m.header)),
+    }
+}
+
+#[tokio::test]
+async fn header_of_another_version_is_not_recoverable() {
+    let mut bytes = ping().serialize();
+    bytes[4] = HEADER_VERSION + 1;
+
+    match Message::read_from(&mut bytes.as_slice(), 1024, Framing::Current).await {
+        Err(e @ FrameError::UnsupportedVersion(_)) => assert!(!e.is_recoverable()),
+        other => panic!("expected an unsupported version, got {:?}", other.map(|m| m.header)),
+    }
+}
+
+#[tokio::test]
+async fn truncated_frame_is_not_recoverable() {
+    let bytes = pong(b"truncated".to_vec()).serialize();
+
+    let error = Message::read_from(&mut &bytes[..bytes.len() - 3], 1024, Framing::Current).await.unwrap_err();
+    assert!(!error.is_recoverable());
+}
+
+#[tokio::test]
+async fn legacy_frames_round_trip() {
+    let message = pong(b"legacy".to_vec());
+    let bytes = message.serialize_for(Framing::Legacy).unwrap();
+    assert_eq!(bytes.len(), LEGACY_HEADER_SIZE + 6);
+
+    let (decoded, framing) = Message::read_with(&mut bytes.as_slice(), 1024, Framing::detect).await.unwrap();
+    assert_eq!(framing, Framing::Legacy);
+    assert_eq!(decoded.header.message_id, message.header.message_id);
+    assert_eq!(decoded.header.message_type, MessageType::Pong);
+    assert_eq!(decoded.body, b"legacy");
+}
+
+#[tokio::test]
+async fn current_frames_are_detected() {
+    let bytes = pong(b"current".to_vec()).serialize();
+
+    let (decoded, framing) = Message::read_with(&mut bytes.as_slice(), 1024, Framing::detect).await.unwrap();
+    assert_eq!(framing, Framing::Current);
+    assert_eq!(decoded.body, b"current");
+}
+
+#[test]
+fn legacy_framing_refuses_chunks_and_compression() {
+    let chunk = Message::chunk(MessageType::Pong, MessageTypeFlag::ResponseMessage, [1; 16], ChunkKind::First, vec![1]);
+    assert!(matches!(chunk.serialize_for(Framing::Legacy), Err(FrameError::UnsupportedByLegacyFraming(_))));
+
+    let mut compressed = pong(vec![0; 4096]);
+    compressed.compress(Codec::Lz4, 0).unwrap();
+    assert!(matches!(compressed.serialize_for(Framing::Legacy), Err(FrameError::UnsupportedByLegacyFraming(_))));
+}
+
+fn chunk(id: u8, kind: ChunkKind, body: &[u8]) -> Message {
+    Message::chunk(MessageType::BulkInsert, MessageTypeFlag::RequestMessage, [id; 16], kind, body.to_vec())
+}
+
+#[test]
+fn chunks_are_reassembled_in_order() {
+    let mut assembler = ChunkAssembler::new(1024, 4);
+    assert!(assembler.push(chunk(1, ChunkKind::First, b"ab")).unwrap().is_none());
+    assert!(assembler.push(chunk(2, ChunkKind::First, b"xy")).unwrap().is_none());
+    assert!(assembler.push(chunk(1, ChunkKind::Continuation, b"cd")).unwrap().is_none());
+    assert_eq!(assembler.pending_streams(), 2);
+
+    let chunks = assembler.push(chunk(1, ChunkKind::Last, b"ef")).unwrap().unwrap();
+    let message = Message::assemble(chunks).unwrap();
+    assert_eq!(message.body, b"abcdef");
+    assert_eq!(message.header.chunk_kind(), ChunkKind::Single);
+    assert_eq!(assembler.pending_streams(), 1);
+
+    let single = assembler.push(chunk(3, ChunkKind::Single, b"one")).unwrap().unwrap();
+    assert_eq!(single.len(), 1);
+}
+
+#[test]
+fn chunks_without_a_first_are_dropped() {
+    let mut assembler = ChunkAssembler::new(1024, 4);
+    assert_eq!(assembler.push(chunk(1, ChunkKind::Continuation, b"ab")).err(), Some(AssemblyError::Orphan));
+    assert_eq!(assembler.push(chunk(1, ChunkKind::Last, b"cd")).err(), Some(AssemblyError::Orphan));
+    assert_eq!(assembler.pending_streams(), 0);
+}
+
+#[test]
+fn assembled_body_is_held_to_the_maximum() {
+    let mut assembler = ChunkAssembler::new(8, 4);
+    assembler.push(chunk(1, ChunkKind::First, b"12345")).unwrap();
+    assert_eq!(
+        assembler.push(chunk(1, ChunkKind::Continuation, b"6789")).err(),
+        Some(AssemblyError::TooLarge { size: 9, max_body_size: 8 })
+    );
+    // The partial message is gone, its remaining chunks are orphans.
+    assert_eq!(assembler.pending_streams(), 0);
+    assert_eq!(assembler.push(chunk(1, ChunkKind::Last, b"0")).err(), Some(AssemblyError::Orphan));
+}
+
+#[test]
+fn open_streams_are_capped() {
+    let mut assembler = ChunkAssembler::new(1024, 2);
+    assembler.push(chunk(1, ChunkKind::First, b"a")).unwrap();
+    assembler.push(chunk(2, ChunkKind::First, b"b")).unwrap();
+    assert_eq!(assembler.push(chunk(3, ChunkKind::First, b"c")).err(), Some(AssemblyError::TooManyStreams(2)));
+
+    // Restarting an open message does not count as another stream.
+    assembler.push(chunk(2, ChunkKind::First, b"B")).unwrap();
+    assembler.discard(&[1; 16]);
+    assembler.push(chunk(3, ChunkKind::First, b"c")).unwrap();
+    let chunks = assembler.push(chunk(2, ChunkKind::Last, b"!")).unwrap().unwrap();
+    assert_eq!(Message::assemble(chunks).unwrap().body, b"B!");
+}